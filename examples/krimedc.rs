@@ -1,9 +1,14 @@
 use clap::{Parser, Subcommand};
 use futures::{SinkExt, StreamExt};
+use libkrime::clock::{Clock, SystemClock};
+use libkrime::error::KrbError;
 use libkrime::proto::{
-    AuthenticationRequest, DerivedKey, KdcPrimaryKey, KerberosReply, KerberosRequest,
+    default_salt, AuthenticationRequest, DerivedKey, KdcPrimaryKey, KerberosReply, KerberosRequest,
+    Name,
 };
-use libkrime::KdcTcpCodec;
+use libkrime::replay_cache::{MemoryReplayCache, ReplayCache};
+use libkrime::reply_cache::MemoryReplyCache;
+use libkrime::{KdcTcpCodec, DEFAULT_MAX_SKEW};
 use serde::Deserialize;
 use std::collections::BTreeMap;
 use std::fs;
@@ -12,7 +17,7 @@ use std::io::Read;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream};
 use tokio_util::codec::Framed;
 use tracing::{debug, error, info, instrument, trace};
@@ -22,7 +27,7 @@ async fn process_authentication(
     auth_req: AuthenticationRequest,
     server_state: &ServerState,
 ) -> Result<KerberosReply, KerberosReply> {
-    let stime = SystemTime::now();
+    let stime = server_state.clock.now();
 
     // Got any etypes?
     if auth_req.etypes.is_empty() {
@@ -76,36 +81,36 @@ async fn process_authentication(
 
     // Start to process and validate the enc timestamp.
 
-    let pa_timestamp = pre_enc_timestamp
-        .decrypt_pa_enc_timestamp(&user_record.base_key)
-        .map_err(|err| {
-            error!(?err, "pre_enc_timestamp.decrypt");
-            KerberosReply::error_preauth_failed(auth_req.service_name.clone(), stime)
+    let (pa_ctime, pa_cusec) = pre_enc_timestamp
+        .decrypt_pa_enc_timestamp_checked_with_skew(
+            &user_record.base_key,
+            stime,
+            server_state.max_skew,
+        )
+        .map_err(|err| match err {
+            // Check for the timestamp being in a valid range. If not, reject for clock skew.
+            KrbError::ClockSkew => {
+                error!(?err, "clock skew");
+                KerberosReply::error_clock_skew(auth_req.service_name.clone(), stime)
+            }
+            _ => {
+                error!(?err, "pre_enc_timestamp.decrypt");
+                KerberosReply::error_preauth_failed(auth_req.service_name.clone(), stime)
+            }
         })?;
+    let pa_cusec = pa_cusec.unwrap_or_default();
 
-    trace!(?pa_timestamp, ?stime);
+    trace!(?pa_ctime, ?pa_cusec, ?stime);
 
-    let abs_offset = if pa_timestamp > stime {
-        pa_timestamp.duration_since(stime)
-    } else {
-        stime.duration_since(pa_timestamp)
-    }
-    // This error shouldn't be possible. The error condition on duration_since is
-    // when the right side of the term is actually *before* the left. Our if
-    // condition should guard against thin.
-    // However, let's do the right thing and check it anyway.
-    .map_err(|kdc_err| {
-        error!(?kdc_err);
-        KerberosReply::error_internal(auth_req.service_name.clone(), stime)
-    })?;
-
-    trace!(?abs_offset);
-
-    // Check for the timestamp being in a valid range. If not, reject for clock skew.
-    if abs_offset > Duration::from_secs(300) {
-        error!(?abs_offset, "clock skew");
-        // ClockSkew
-        return Err(KerberosReply::error_clock_skew(
+    // Each timestamp may only be used once, otherwise anyone who captured the request
+    // could replay it for a ticket.
+    if let Err(err) =
+        server_state
+            .replay_cache
+            .check_and_insert(&auth_req.client_name, pa_ctime, pa_cusec)
+    {
+        error!(?err, "preauth timestamp replayed");
+        return Err(KerberosReply::error_preauth_failed(
             auth_req.service_name.clone(),
             stime,
         ));
@@ -137,9 +142,27 @@ async fn process(socket: TcpStream, info: SocketAddr, server_state: Arc<ServerSt
     while let Some(Ok(kdc_req)) = kdc_stream.next().await {
         match kdc_req {
             KerberosRequest::AS(auth_req) => {
-                let reply = match process_authentication(auth_req, &server_state).await {
-                    Ok(rep) => rep,
-                    Err(krb_err) => krb_err,
+                // A retransmitted request gets the reply that was already sent, rather
+                // than having its preauth timestamp rejected as a replay.
+                let cached = server_state
+                    .reply_cache
+                    .as_ref()
+                    .and_then(|reply_cache| reply_cache.get_as_rep(&auth_req));
+
+                let reply = if let Some(reply) = cached {
+                    debug!("retransmitted request, replying from cache");
+                    reply
+                } else {
+                    let reply = match process_authentication(auth_req.clone(), &server_state).await
+                    {
+                        Ok(rep) => rep,
+                        Err(krb_err) => krb_err,
+                    };
+
+                    if let Some(reply_cache) = &server_state.reply_cache {
+                        reply_cache.insert_as_rep(&auth_req, &reply);
+                    }
+                    reply
                 };
 
                 if let Err(err) = kdc_stream.send(reply).await {
@@ -184,6 +207,11 @@ struct Config {
     address: SocketAddr,
     #[serde(deserialize_with = "hex::serde::deserialize")]
     primary_key: Vec<u8>,
+    // How far a client's preauthentication timestamp may be from our time, in seconds.
+    max_skew: Option<u64>,
+    // How long to keep replies to answer retransmitted requests, in seconds. Replies
+    // aren't cached if this is not set.
+    reply_cache_ttl: Option<u64>,
 
     user: BTreeMap<String, UserPrincipal>,
     // services: BTreeMap<String, Service>,
@@ -195,15 +223,21 @@ impl From<Config> for ServerState {
             realm,
             address,
             primary_key,
+            max_skew,
+            reply_cache_ttl,
             user,
         } = cr;
 
+        let max_skew = max_skew
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_MAX_SKEW);
+
         let primary_key = KdcPrimaryKey::try_from(primary_key.as_slice()).unwrap();
 
         let users = user
             .into_iter()
             .map(|(n, u)| {
-                let salt = format!("{}{}", realm, n);
+                let salt = default_salt(&realm, &Name::principal(&n, &realm));
 
                 let base_key = DerivedKey::new_aes256_cts_hmac_sha1_96(&u.password, &salt).unwrap();
 
@@ -215,6 +249,11 @@ impl From<Config> for ServerState {
             realm,
             primary_key,
             users,
+            clock: Box::new(SystemClock),
+            max_skew,
+            replay_cache: MemoryReplayCache::new(max_skew, SystemClock),
+            reply_cache: reply_cache_ttl
+                .map(|ttl| MemoryReplyCache::new(Duration::from_secs(ttl), SystemClock)),
         }
     }
 }
@@ -232,6 +271,10 @@ struct ServerState {
 
     users: BTreeMap<String, UserRecord>,
     // services: BTreeMap<String, Service>,
+    clock: Box<dyn Clock + Send + Sync>,
+    max_skew: Duration,
+    replay_cache: MemoryReplayCache,
+    reply_cache: Option<MemoryReplyCache>,
 }
 
 async fn main_run(config: Config) -> io::Result<()> {
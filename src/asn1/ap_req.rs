@@ -0,0 +1,67 @@
+use super::encrypted_data::EncryptedData;
+use super::tagged_ticket::TaggedTicket;
+use der::asn1::BitString;
+use der::{Decode, DecodeValue, Encode, EncodeValue, FixedTag, Sequence, Tag, TagNumber};
+
+/// ```text
+/// AP-REQ          ::= [APPLICATION 14] SEQUENCE {
+///         pvno            [0] INTEGER (5),
+///         msg-type        [1] INTEGER (14),
+///         ap-options      [2] APOptions,
+///         ticket          [3] Ticket,
+///         authenticator   [4] EncryptedData -- Authenticator
+/// }
+///
+/// APOptions       ::= KerberosFlags
+///         -- reserved(0),
+///         -- use-session-key(1),
+///         -- mutual-required(2)
+/// ````
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct ApReq {
+    #[asn1(context_specific = "0")]
+    pub(crate) pvno: u8,
+    #[asn1(context_specific = "1")]
+    pub(crate) msg_type: u8,
+    // As with the KdcOptions, the FlagSet encoder does not produce the full 32 bits
+    // that krb expects, so we hold the raw BitString here.
+    #[asn1(context_specific = "2")]
+    pub(crate) ap_options: BitString,
+    #[asn1(context_specific = "3")]
+    pub(crate) ticket: TaggedTicket,
+    #[asn1(context_specific = "4")]
+    pub(crate) authenticator: EncryptedData,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct TaggedApReq(pub ApReq);
+
+impl TaggedApReq {
+    pub fn new(ap_req: ApReq) -> Self {
+        Self(ap_req)
+    }
+}
+
+impl FixedTag for TaggedApReq {
+    const TAG: Tag = Tag::Application {
+        constructed: true,
+        number: TagNumber::N14,
+    };
+}
+
+impl<'a> DecodeValue<'a> for TaggedApReq {
+    fn decode_value<R: der::Reader<'a>>(reader: &mut R, _header: der::Header) -> der::Result<Self> {
+        let a: ApReq = ApReq::decode(reader)?;
+        Ok(Self(a))
+    }
+}
+
+impl EncodeValue for TaggedApReq {
+    fn value_len(&self) -> der::Result<der::Length> {
+        self.0.encoded_len()
+    }
+    fn encode_value(&self, encoder: &mut impl der::Writer) -> der::Result<()> {
+        self.0.encode(encoder)?;
+        Ok(())
+    }
+}
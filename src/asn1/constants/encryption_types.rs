@@ -1,7 +1,11 @@
+use crate::error::KrbError;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::fmt;
+use std::str::FromStr;
 
 #[allow(non_camel_case_types)]
 #[derive(Debug, TryFromPrimitive, IntoPrimitive, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(i32)]
 pub enum EncryptionType {
     DES_CBC_CRC = 1,
@@ -28,3 +32,94 @@ pub enum EncryptionType {
     CAMELLIA128_CTS_CMAC = 25,
     CAMELLIA256_CTS_CMAC = 26,
 }
+
+// The names and aliases that an etype may be given by, such as in the configuration of
+// MIT Kerberos. The first name of each etype is its canonical name.
+const ETYPE_NAMES: &[(&str, EncryptionType)] = &[
+    ("des-cbc-crc", EncryptionType::DES_CBC_CRC),
+    ("des-cbc-md4", EncryptionType::DES_CBC_MD4),
+    ("des-cbc-md5", EncryptionType::DES_CBC_MD5),
+    ("des3-cbc-md5", EncryptionType::DES3_CBC_MD5),
+    ("des3-cbc-sha1", EncryptionType::DES3_CBC_SHA1),
+    ("dsaWithSHA1-CmsOID", EncryptionType::DSA_SHA1_CMS),
+    ("md5WithRSAEncryption-CmsOID", EncryptionType::MD5_RSA_CMS),
+    ("sha1WithRSAEncryption-CmsOID", EncryptionType::SHA1_RSA_CMS),
+    ("rc2CBC-EnvOID", EncryptionType::RC2_CBC_ENV),
+    ("rsaEncryption-EnvOID", EncryptionType::RSA_ENV),
+    ("rsaES-OAEP-ENV-OID", EncryptionType::RSA_ES_OAEP_ENV),
+    ("des-ede3-cbc-Env-OID", EncryptionType::DES3_CBC_ENV),
+    ("des3-cbc-sha1-kd", EncryptionType::DES3_CBC_SHA1_KD),
+    ("des3-hmac-sha1", EncryptionType::DES3_CBC_SHA1_KD),
+    (
+        "aes128-cts-hmac-sha1-96",
+        EncryptionType::AES128_CTS_HMAC_SHA1_96,
+    ),
+    ("aes128-cts", EncryptionType::AES128_CTS_HMAC_SHA1_96),
+    ("aes128-sha1", EncryptionType::AES128_CTS_HMAC_SHA1_96),
+    (
+        "aes256-cts-hmac-sha1-96",
+        EncryptionType::AES256_CTS_HMAC_SHA1_96,
+    ),
+    ("aes256-cts", EncryptionType::AES256_CTS_HMAC_SHA1_96),
+    ("aes256-sha1", EncryptionType::AES256_CTS_HMAC_SHA1_96),
+    (
+        "aes128-cts-hmac-sha256-128",
+        EncryptionType::AES128_CTS_HMAC_SHA256_128,
+    ),
+    ("aes128-sha2", EncryptionType::AES128_CTS_HMAC_SHA256_128),
+    (
+        "aes256-cts-hmac-sha384-192",
+        EncryptionType::AES256_CTS_HMAC_SHA384_192,
+    ),
+    ("aes256-sha2", EncryptionType::AES256_CTS_HMAC_SHA384_192),
+    ("rc4-hmac", EncryptionType::RC4_HMAC),
+    ("arcfour-hmac", EncryptionType::RC4_HMAC),
+    ("arcfour-hmac-md5", EncryptionType::RC4_HMAC),
+    ("rc4-hmac-exp", EncryptionType::RC4_HMAC_EXP),
+    ("arcfour-hmac-exp", EncryptionType::RC4_HMAC_EXP),
+    ("arcfour-hmac-md5-exp", EncryptionType::RC4_HMAC_EXP),
+    ("camellia128-cts-cmac", EncryptionType::CAMELLIA128_CTS_CMAC),
+    ("camellia128-cts", EncryptionType::CAMELLIA128_CTS_CMAC),
+    ("camellia256-cts-cmac", EncryptionType::CAMELLIA256_CTS_CMAC),
+    ("camellia256-cts", EncryptionType::CAMELLIA256_CTS_CMAC),
+];
+
+impl EncryptionType {
+    /// The canonical name of the etype, as registered with IANA.
+    pub fn name(self) -> &'static str {
+        ETYPE_NAMES
+            .iter()
+            .find(|(_, etype)| *etype == self)
+            .map(|(name, _)| *name)
+            // Every etype has a canonical name in ETYPE_NAMES.
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for EncryptionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for EncryptionType {
+    type Err = KrbError;
+
+    /// Parse an etype from its canonical name or one of its common aliases, ignoring
+    /// case.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        ETYPE_NAMES
+            .iter()
+            .find(|(etype_name, _)| etype_name.eq_ignore_ascii_case(name))
+            .map(|(_, etype)| *etype)
+            .ok_or_else(|| KrbError::InvalidEncryptionTypeName(name.to_string()))
+    }
+}
+
+impl TryFrom<&str> for EncryptionType {
+    type Error = KrbError;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
@@ -1,6 +1,7 @@
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::fmt;
 
-#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
+#[derive(Debug, TryFromPrimitive, IntoPrimitive, PartialEq, Eq, Clone, Copy)]
 #[repr(i32)]
 pub enum KrbErrorCode {
     KdcErrNone = 0,                         // No error
@@ -72,3 +73,110 @@ pub enum KrbErrorCode {
     KdcErrClientNameMismatch = 75,          // Reserved for PKINIT
     KdcErrKdcNameMismatch = 76,             // Reserved for PKINIT
 }
+
+impl KrbErrorCode {
+    /// The description of the error code from RFC 4120 7.5.9 and RFC 4556.
+    pub fn description(self) -> &'static str {
+        match self {
+            KrbErrorCode::KdcErrNone => "No error",
+            KrbErrorCode::KdcErrNameExp => "Client's entry in database has expired",
+            KrbErrorCode::KdcErrServiceExp => "Server's entry in database has expired",
+            KrbErrorCode::KdcErrBadPvno => "Requested protocol version number not supported",
+            KrbErrorCode::KdcErrCOldMastKvno => "Client's key encrypted in old master key",
+            KrbErrorCode::KdcErrSOldMastKvno => "Server's key encrypted in old master key",
+            KrbErrorCode::KdcErrCPrincipalUnknown => "Client not found in Kerberos database",
+            KrbErrorCode::KdcErrSPrincipalUnknown => "Server not found in Kerberos database",
+            KrbErrorCode::KdcErrPrincipalNotUnique => "Multiple principal entries in database",
+            KrbErrorCode::KdcErrNullKey => "The client or server has a null key",
+            KrbErrorCode::KdcErrCannotPostdate => "Ticket not eligible for postdating",
+            KrbErrorCode::KdcErrNeverValid => "Requested starttime is later than end time",
+            KrbErrorCode::KdcErrPolicy => "KDC policy rejects request",
+            KrbErrorCode::KdcErrBadoption => "KDC cannot accommodate requested option",
+            KrbErrorCode::KdcErrEtypeNosupp => "KDC has no support for encryption type",
+            KrbErrorCode::KdcErrSumtypeNosupp => "KDC has no support for checksum type",
+            KrbErrorCode::KdcErrPadataTypeNosupp => "KDC has no support for padata type",
+            KrbErrorCode::KdcErrTrtypeNosupp => "KDC has no support for transited type",
+            KrbErrorCode::KdcErrClientRevoked => "Client's credentials have been revoked",
+            KrbErrorCode::KdcErrServiceRevoked => "Credentials for server have been revoked",
+            KrbErrorCode::KdcErrTgtRevoked => "TGT has been revoked",
+            KrbErrorCode::KdcErrClientNotyet => "Client not yet valid; try again later",
+            KrbErrorCode::KdcErrServiceNotyet => "Server not yet valid; try again later",
+            KrbErrorCode::KdcErrKeyExpired => "Password has expired; change password to reset",
+            KrbErrorCode::KdcErrPreauthFailed => "Pre-authentication information was invalid",
+            KrbErrorCode::KdcErrPreauthRequired => "Additional pre-authentication required",
+            KrbErrorCode::KdcErrServerNomatch => "Requested server and ticket don't match",
+            KrbErrorCode::KdcErrMustUseUser2User => "Server principal valid for user2user only",
+            KrbErrorCode::KdcErrPathNotAccepted => "KDC Policy rejects transited path",
+            KrbErrorCode::KdcErrSvcUnavailable => "A service is not available",
+            KrbErrorCode::KrbApErrBadIntegrity => "Integrity check on decrypted field failed",
+            KrbErrorCode::KrbApErrTktExpired => "Ticket expired",
+            KrbErrorCode::KrbApErrTktNyv => "Ticket not yet valid",
+            KrbErrorCode::KrbApErrRepeat => "Request is a replay",
+            KrbErrorCode::KrbApErrNotUs => "The ticket isn't for us",
+            KrbErrorCode::KrbApErrBadmatch => "Ticket and authenticator don't match",
+            KrbErrorCode::KrbApErrSkew => "Clock skew too great",
+            KrbErrorCode::KrbApErrBadaddr => "Incorrect net address",
+            KrbErrorCode::KrbApErrBadversion => "Protocol version mismatch",
+            KrbErrorCode::KrbApErrMsgType => "Invalid msg type",
+            KrbErrorCode::KrbApErrModified => "Message stream modified",
+            KrbErrorCode::KrbApErrBadorder => "Message out of order",
+            KrbErrorCode::KrbApErrBadkeyver => "Specified version of key is not available",
+            KrbErrorCode::KrbApErrNokey => "Service key not available",
+            KrbErrorCode::KrbApErrMutFail => "Mutual authentication failed",
+            KrbErrorCode::KrbApErrBaddirection => "Incorrect message direction",
+            KrbErrorCode::KrbApErrMethod => "Alternative authentication method required",
+            KrbErrorCode::KrbApErrBadseq => "Incorrect sequence number in message",
+            KrbErrorCode::KrbApErrInappCksum => "Inappropriate type of checksum in message",
+            KrbErrorCode::KrbApPathNotAccepted => "Policy rejects transited path",
+            KrbErrorCode::KrbErrResponseTooBig => "Response too big for UDP; retry with TCP",
+            KrbErrorCode::KrbErrGeneric => "Generic error (description in e-text)",
+            KrbErrorCode::KrbErrFieldToolong => "Field is too long for this implementation",
+            KrbErrorCode::KdcErrorClientNotTrusted => "Client not trusted",
+            KrbErrorCode::KdcErrorKdcNotTrusted => "KDC not trusted",
+            KrbErrorCode::KdcErrorInvalidSig => "Invalid signature",
+            KrbErrorCode::KdcErrKeyTooWeak => "Key parameters not accepted",
+            KrbErrorCode::KdcErrCertificateMismatch => "Certificate mismatch",
+            KrbErrorCode::KrbApErrNoTgt => "No TGT available to validate USER-TO-USER",
+            KrbErrorCode::KdcErrWrongRealm => "Wrong realm",
+            KrbErrorCode::KrbApErrUserToUserRequired => "Ticket must be for USER-TO-USER",
+            KrbErrorCode::KdcErrCantVerifyCertificate => "Cannot verify certificate",
+            KrbErrorCode::KdcErrInvalidCertificate => "Invalid certificate",
+            KrbErrorCode::KdcErrRevokedCertificate => "Revoked certificate",
+            KrbErrorCode::KdcErrRevocationStatusUnknown => "Revocation status unknown",
+            KrbErrorCode::KdcErrRevocationStatusUnavailable => "Revocation status unavailable",
+            KrbErrorCode::KdcErrClientNameMismatch => "Client name mismatch",
+            KrbErrorCode::KdcErrKdcNameMismatch => "KDC name mismatch",
+        }
+    }
+}
+
+impl fmt::Display for KrbErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.description(), *self as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KrbErrorCode;
+
+    #[test]
+    fn test_error_code_description() {
+        assert_eq!(
+            KrbErrorCode::KdcErrCPrincipalUnknown.description(),
+            "Client not found in Kerberos database"
+        );
+        assert_eq!(
+            KrbErrorCode::KdcErrKeyExpired.description(),
+            "Password has expired; change password to reset"
+        );
+        assert_eq!(
+            KrbErrorCode::KrbApErrSkew.to_string(),
+            "Clock skew too great (37)"
+        );
+        assert_eq!(
+            KrbErrorCode::KdcErrPreauthRequired.to_string(),
+            "Additional pre-authentication required (25)"
+        );
+    }
+}
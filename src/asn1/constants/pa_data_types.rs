@@ -1,6 +1,6 @@
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 
-#[derive(Debug, Clone, Copy, TryFromPrimitive, IntoPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
 #[repr(u32)]
 pub enum PaDataType {
     PaTgsReq = 1,
@@ -39,9 +39,13 @@ pub enum PaDataType {
     TdReqNonce = 107,              // Integer
     TdReqSeq = 108,                // Integer
     PaPacRequest = 128,            // Include Windows PAC
+    PaForUser = 129,               // MS-SFU S4U2Self
     PaFxCookie = 133,              // RFC6113 FAST Cookie
     PaFxFast = 136,                // RFC6113 FAST
+    PaOtpChallenge = 141,          // RFC 6560 OTP challenge
+    PaOtpRequest = 142,            // RFC 6560 OTP request
     EncpadataReqEncPaRep = 149,    // RFC 6806
     PadataAsFreshness = 150,       // RFC 8070
     PadataSpake = 151,             // draft-ietf-kitten-krb-spake-preauth-13
+    PaSupportedEtypes = 165,       // MS-KILE supported encryption types
 }
@@ -0,0 +1,64 @@
+use super::host_address::HostAddress;
+use super::kerberos_time::KerberosTime;
+use super::krb_cred_info::KrbCredInfo;
+use super::microseconds::Microseconds;
+use der::{Decode, DecodeValue, Encode, EncodeValue, FixedTag, Sequence, Tag, TagNumber};
+
+/// ```text
+/// EncKrbCredPart  ::= [APPLICATION 29] SEQUENCE {
+///         ticket-info     [0] SEQUENCE OF KrbCredInfo,
+///         nonce           [1] UInt32 OPTIONAL,
+///         timestamp       [2] KerberosTime OPTIONAL,
+///         usec            [3] Microseconds OPTIONAL,
+///         s-address       [4] HostAddress OPTIONAL,
+///         r-address       [5] HostAddress OPTIONAL
+/// }
+/// ````
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct EncKrbCredPart {
+    #[asn1(context_specific = "0")]
+    pub(crate) ticket_info: Vec<KrbCredInfo>,
+    #[asn1(context_specific = "1", optional = "true")]
+    pub(crate) nonce: Option<u32>,
+    #[asn1(context_specific = "2", optional = "true")]
+    pub(crate) timestamp: Option<KerberosTime>,
+    #[asn1(context_specific = "3", optional = "true")]
+    pub(crate) usec: Option<Microseconds>,
+    #[asn1(context_specific = "4", optional = "true")]
+    pub(crate) s_address: Option<HostAddress>,
+    #[asn1(context_specific = "5", optional = "true")]
+    pub(crate) r_address: Option<HostAddress>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct TaggedEncKrbCredPart(pub EncKrbCredPart);
+
+impl TaggedEncKrbCredPart {
+    pub fn new(enc_krb_cred_part: EncKrbCredPart) -> Self {
+        Self(enc_krb_cred_part)
+    }
+}
+
+impl FixedTag for TaggedEncKrbCredPart {
+    const TAG: Tag = Tag::Application {
+        constructed: true,
+        number: TagNumber::N29,
+    };
+}
+
+impl<'a> DecodeValue<'a> for TaggedEncKrbCredPart {
+    fn decode_value<R: der::Reader<'a>>(reader: &mut R, _header: der::Header) -> der::Result<Self> {
+        let p: EncKrbCredPart = EncKrbCredPart::decode(reader)?;
+        Ok(Self(p))
+    }
+}
+
+impl EncodeValue for TaggedEncKrbCredPart {
+    fn value_len(&self) -> der::Result<der::Length> {
+        self.0.encoded_len()
+    }
+    fn encode_value(&self, encoder: &mut impl der::Writer) -> der::Result<()> {
+        self.0.encode(encoder)?;
+        Ok(())
+    }
+}
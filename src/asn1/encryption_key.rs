@@ -1,5 +1,6 @@
 use der::asn1::OctetString;
 use der::Sequence;
+use std::fmt;
 
 /// ```text
 /// EncryptionKey   ::= SEQUENCE {
@@ -7,10 +8,20 @@ use der::Sequence;
 ///         keyvalue        [1] OCTET STRING
 /// }
 /// ````
-#[derive(Debug, Eq, PartialEq, Sequence, Clone)]
+#[derive(Eq, PartialEq, Sequence, Clone)]
 pub(crate) struct EncryptionKey {
     #[asn1(context_specific = "0")]
     pub key_type: i32,
     #[asn1(context_specific = "1")]
     pub key_value: OctetString,
 }
+
+// The key value is never shown, so that the parts of replies and tickets that carry a
+// session key can be traced.
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("key_type", &self.key_type)
+            .finish_non_exhaustive()
+    }
+}
@@ -1,4 +1,4 @@
-use super::kerberos_string::KerberosString;
+use super::kerberos_string::RawKerberosString;
 use der::asn1::OctetString;
 use der::Sequence;
 
@@ -14,7 +14,7 @@ pub(crate) struct ETypeInfo2Entry {
     #[asn1(context_specific = "0")]
     pub(crate) etype: i32,
     #[asn1(context_specific = "1", optional = "true")]
-    pub(crate) salt: Option<KerberosString>,
+    pub(crate) salt: Option<RawKerberosString>,
     #[asn1(context_specific = "2", optional = "true")]
     pub(crate) s2kparams: Option<OctetString>,
 }
@@ -36,7 +36,7 @@ mod tests {
         let blob = hex::decode(&blob).expect("Failed to decode sample");
         let info2 = ETypeInfo2Entry::from_der(&blob).expect("Failed to decode");
         assert_eq!(info2.etype, EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32);
-        assert_eq!(info2.salt.unwrap().0.to_string(), "AFOREST.ADuser1");
+        assert_eq!(info2.salt.unwrap().0, b"AFOREST.ADuser1");
         assert!(info2.s2kparams.is_none());
     }
 }
@@ -22,7 +22,7 @@ flags! {
         OptHardwareAuth = 1 << 11,
         Unused12        = 1 << 12,
         Unused13        = 1 << 13,
-        Unused14        = 1 << 14,
+        CnameInAddlTkt  = 1 << 14,
         Canonicalize    = 1 << 15,
         Unused16        = 1 << 16,
         Unused17        = 1 << 17,
@@ -7,7 +7,7 @@ use der::Tag;
 /// ```text
 /// KerberosString  ::= GeneralString (IA5String)
 /// ````
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub(crate) struct KerberosString(pub(crate) Ia5String);
 
 impl FixedTag for KerberosString {
@@ -47,3 +47,28 @@ impl KerberosString {
         self.0.as_str()
     }
 }
+
+/// A KerberosString whose bytes are kept as they were sent, rather than checked to be
+/// IA5. Some KDCs send a salt that isn't IA5 or even utf-8, and the key can only be
+/// derived from the exact bytes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct RawKerberosString(pub(crate) Vec<u8>);
+
+impl FixedTag for RawKerberosString {
+    const TAG: Tag = Tag::GeneralString;
+}
+
+impl<'a> DecodeValue<'a> for RawKerberosString {
+    fn decode_value<R: der::Reader<'a>>(reader: &mut R, header: der::Header) -> der::Result<Self> {
+        reader.read_vec(header.length).map(Self)
+    }
+}
+
+impl EncodeValue for RawKerberosString {
+    fn value_len(&self) -> der::Result<der::Length> {
+        der::Length::try_from(self.0.len())
+    }
+    fn encode_value(&self, encoder: &mut impl der::Writer) -> der::Result<()> {
+        encoder.write(&self.0)
+    }
+}
@@ -3,4 +3,55 @@ use der::asn1::GeneralizedTime;
 /// ```text
 /// KerberosTime    ::= GeneralizedTime
 /// ````
+///
+/// RFC 4120 5.2.3 forbids fractional seconds, so a time is always floored to the
+/// whole second when it's converted. Where more precision is needed it's carried in a
+/// separate Microseconds field, such as cusec or pausec.
 pub(crate) type KerberosTime = GeneralizedTime;
+
+#[cfg(test)]
+mod tests {
+    use super::KerberosTime;
+    use crate::asn1::pa_enc_ts_enc::PaEncTsEnc;
+    use der::{Decode, Encode};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_kerberos_time_second_precision() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let kerberos_time = KerberosTime::from_system_time(time).unwrap();
+        assert_eq!(kerberos_time.to_system_time(), time);
+
+        let der = kerberos_time.to_der().unwrap();
+        assert_eq!(&der[2..], b"20231114221320Z");
+        let kerberos_time = KerberosTime::from_der(&der).unwrap();
+        assert_eq!(kerberos_time.to_system_time(), time);
+
+        // Just short of the next second is still floored, not rounded up.
+        for nanos in [1, 500_000_000, 999_999_999] {
+            let kerberos_time =
+                KerberosTime::from_system_time(time + Duration::from_nanos(nanos)).unwrap();
+            assert_eq!(kerberos_time.to_system_time(), time);
+            assert_eq!(kerberos_time.to_der().unwrap(), der);
+        }
+    }
+
+    #[test]
+    fn test_pa_enc_ts_enc_microseconds() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let paenctsenc =
+            PaEncTsEnc::from_system_time(time + Duration::from_nanos(999_999_999)).unwrap();
+        assert_eq!(paenctsenc.patimestamp.to_system_time(), time);
+        assert_eq!(paenctsenc.pausec, Some(999_999));
+
+        // The seconds and microseconds put back together drift by less than a microsecond.
+        let paenctsenc =
+            PaEncTsEnc::from_system_time(time + Duration::from_nanos(123_456_789)).unwrap();
+        let paenctsenc = PaEncTsEnc::from_der(&paenctsenc.to_der().unwrap()).unwrap();
+        let rebuilt = paenctsenc.patimestamp.to_system_time()
+            + Duration::from_micros(paenctsenc.pausec.unwrap_or_default() as u64);
+        assert_eq!(rebuilt, time + Duration::from_micros(123_456));
+    }
+}
@@ -0,0 +1,56 @@
+use super::encrypted_data::EncryptedData;
+use super::tagged_ticket::TaggedTicket;
+use der::{Decode, DecodeValue, Encode, EncodeValue, FixedTag, Sequence, Tag, TagNumber};
+
+/// ```text
+/// KRB-CRED        ::= [APPLICATION 22] SEQUENCE {
+///         pvno            [0] INTEGER (5),
+///         msg-type        [1] INTEGER (22),
+///         tickets         [2] SEQUENCE OF Ticket,
+///         enc-part        [3] EncryptedData -- EncKrbCredPart
+/// }
+/// ````
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct KrbCred {
+    #[asn1(context_specific = "0")]
+    pub(crate) pvno: u8,
+    #[asn1(context_specific = "1")]
+    pub(crate) msg_type: u8,
+    #[asn1(context_specific = "2")]
+    pub(crate) tickets: Vec<TaggedTicket>,
+    #[asn1(context_specific = "3")]
+    pub(crate) enc_part: EncryptedData,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct TaggedKrbCred(pub KrbCred);
+
+impl TaggedKrbCred {
+    pub fn new(krb_cred: KrbCred) -> Self {
+        Self(krb_cred)
+    }
+}
+
+impl FixedTag for TaggedKrbCred {
+    const TAG: Tag = Tag::Application {
+        constructed: true,
+        number: TagNumber::N22,
+    };
+}
+
+impl<'a> DecodeValue<'a> for TaggedKrbCred {
+    fn decode_value<R: der::Reader<'a>>(reader: &mut R, _header: der::Header) -> der::Result<Self> {
+        let c: KrbCred = KrbCred::decode(reader)?;
+        Ok(Self(c))
+    }
+}
+
+impl EncodeValue for TaggedKrbCred {
+    fn value_len(&self) -> der::Result<der::Length> {
+        self.0.encoded_len()
+    }
+    fn encode_value(&self, encoder: &mut impl der::Writer) -> der::Result<()> {
+        self.0.encode(encoder)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use super::encryption_key::EncryptionKey;
+use super::host_addresses::HostAddresses;
+use super::kerberos_time::KerberosTime;
+use super::principal_name::PrincipalName;
+use super::realm::Realm;
+use super::ticket_flags::TicketFlags;
+use der::flagset::FlagSet;
+use der::Sequence;
+
+/// ```text
+/// KrbCredInfo     ::= SEQUENCE {
+///         key             [0] EncryptionKey,
+///         prealm          [1] Realm OPTIONAL,
+///         pname           [2] PrincipalName OPTIONAL,
+///         flags           [3] TicketFlags OPTIONAL,
+///         authtime        [4] KerberosTime OPTIONAL,
+///         starttime       [5] KerberosTime OPTIONAL,
+///         endtime         [6] KerberosTime OPTIONAL,
+///         renew-till      [7] KerberosTime OPTIONAL,
+///         srealm          [8] Realm OPTIONAL,
+///         sname           [9] PrincipalName OPTIONAL,
+///         caddr           [10] HostAddresses OPTIONAL
+/// }
+/// ````
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct KrbCredInfo {
+    #[asn1(context_specific = "0")]
+    pub(crate) key: EncryptionKey,
+    #[asn1(context_specific = "1", optional = "true")]
+    pub(crate) prealm: Option<Realm>,
+    #[asn1(context_specific = "2", optional = "true")]
+    pub(crate) pname: Option<PrincipalName>,
+    #[asn1(context_specific = "3", optional = "true")]
+    pub(crate) flags: Option<FlagSet<TicketFlags>>,
+    #[asn1(context_specific = "4", optional = "true")]
+    pub(crate) auth_time: Option<KerberosTime>,
+    #[asn1(context_specific = "5", optional = "true")]
+    pub(crate) start_time: Option<KerberosTime>,
+    #[asn1(context_specific = "6", optional = "true")]
+    pub(crate) end_time: Option<KerberosTime>,
+    #[asn1(context_specific = "7", optional = "true")]
+    pub(crate) renew_till: Option<KerberosTime>,
+    #[asn1(context_specific = "8", optional = "true")]
+    pub(crate) srealm: Option<Realm>,
+    #[asn1(context_specific = "9", optional = "true")]
+    pub(crate) sname: Option<PrincipalName>,
+    #[asn1(context_specific = "10", optional = "true")]
+    pub(crate) caddr: Option<HostAddresses>,
+}
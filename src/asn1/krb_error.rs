@@ -119,7 +119,7 @@ mod tests {
         let e = TaggedKrbError::from_der(&blob).expect("Failed to decode");
 
         assert_eq!(e.0.pvno, 5);
-        assert_eq!(e.0.msg_type, KrbMessageType::KrbError.into());
+        assert_eq!(e.0.msg_type, u8::from(KrbMessageType::KrbError));
         assert_eq!(
             e.0.stime,
             KerberosTime::from_date_time(
@@ -127,7 +127,10 @@ mod tests {
             )
         );
         assert_eq!(e.0.susec, 121958);
-        assert_eq!(e.0.error_code, KrbErrorCode::KrbErrResponseTooBig.into());
+        assert_eq!(
+            e.0.error_code,
+            i32::from(KrbErrorCode::KrbErrResponseTooBig)
+        );
         assert_eq!(e.0.service_realm.0.as_str(), "AFOREST.AD");
         assert_eq!(e.0.service_name.name_type, 2);
         assert_eq!(e.0.service_name.name_string[0].0.as_str(), "krbtgt");
@@ -141,7 +144,7 @@ mod tests {
         let e = TaggedKrbError::from_der(&blob).expect("Failed to decode");
 
         assert_eq!(e.0.pvno, 5);
-        assert_eq!(e.0.msg_type, KrbMessageType::KrbError.into());
+        assert_eq!(e.0.msg_type, u8::from(KrbMessageType::KrbError));
         assert_eq!(
             e.0.stime,
             KerberosTime::from_date_time(
@@ -149,7 +152,10 @@ mod tests {
             )
         );
         assert_eq!(e.0.susec, 121958);
-        assert_eq!(e.0.error_code, KrbErrorCode::KdcErrPreauthRequired.into());
+        assert_eq!(
+            e.0.error_code,
+            i32::from(KrbErrorCode::KdcErrPreauthRequired)
+        );
         assert_eq!(e.0.service_realm.0.as_str(), "AFOREST.AD");
         assert_eq!(e.0.service_name.name_type, 2);
         assert_eq!(e.0.service_name.name_string[0].0.as_str(), "krbtgt");
@@ -133,7 +133,7 @@ mod tests {
 
     fn verify_as_req(asreq: &KdcReq, tasreq: &TestAsReq) {
         assert_eq!(asreq.pvno, 5);
-        assert_eq!(asreq.msg_type, KrbMessageType::KrbAsReq.into());
+        assert_eq!(asreq.msg_type, u8::from(KrbMessageType::KrbAsReq));
 
         let pa = asreq.padata.as_ref().unwrap();
         assert_eq!(pa.len(), tasreq.padata.len());
@@ -1,6 +1,10 @@
+pub mod ap_req;
+pub mod authenticator;
 pub mod authorization_data;
+pub mod checksum;
 pub mod constants;
 pub mod enc_kdc_rep_part;
+pub mod enc_krb_cred_part;
 pub mod enc_ticket_part;
 pub mod encrypted_data;
 pub mod encryption_key;
@@ -14,6 +18,8 @@ pub mod kdc_req_body;
 pub mod kerberos_flags;
 pub mod kerberos_string;
 pub mod kerberos_time;
+pub mod krb_cred;
+pub mod krb_cred_info;
 pub mod krb_error;
 pub mod krb_kdc_rep;
 pub mod krb_kdc_req;
@@ -21,6 +27,10 @@ pub mod last_req;
 pub mod microseconds;
 pub mod pa_data;
 pub mod pa_enc_ts_enc;
+pub mod pa_for_user;
+pub mod pa_otp;
+pub mod pa_pac_request;
+pub mod pa_spake;
 pub mod principal_name;
 pub mod realm;
 pub mod tagged_enc_kdc_rep_part;
@@ -1,6 +1,8 @@
 use super::kerberos_time::KerberosTime;
 use super::microseconds::Microseconds;
+use crate::error::KrbError;
 use der::Sequence;
+use std::time::{Duration, SystemTime};
 
 /// ```text
 /// PA-ENC-TS-ENC           ::= SEQUENCE {
@@ -15,3 +17,22 @@ pub(crate) struct PaEncTsEnc {
     #[asn1(context_specific = "1", optional = "true")]
     pub(crate) pausec: Option<Microseconds>,
 }
+
+impl PaEncTsEnc {
+    /// Split `time` into the whole seconds of the timestamp and the microseconds of
+    /// pausec. Any precision below a microsecond is discarded.
+    pub(crate) fn from_system_time(time: SystemTime) -> Result<Self, KrbError> {
+        let since_epoch = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|_| KrbError::PreauthInvalidUnixTs)?;
+
+        let patimestamp =
+            KerberosTime::from_unix_duration(Duration::from_secs(since_epoch.as_secs()))
+                .map_err(|_| KrbError::PreauthInvalidUnixTs)?;
+
+        Ok(PaEncTsEnc {
+            patimestamp,
+            pausec: Some(since_epoch.subsec_micros()),
+        })
+    }
+}
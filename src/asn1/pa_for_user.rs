@@ -0,0 +1,26 @@
+use super::checksum::Checksum;
+use super::kerberos_string::KerberosString;
+use super::principal_name::PrincipalName;
+use super::realm::Realm;
+use der::Sequence;
+
+/// ```text
+/// PA-FOR-USER ::= SEQUENCE {
+///         -- PA TYPE 129
+///         userName        [0] PrincipalName,
+///         userRealm       [1] Realm,
+///         cksum           [2] Checksum,
+///         auth-package    [3] KerberosString
+/// }
+/// ````
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct PaForUser {
+    #[asn1(context_specific = "0")]
+    pub(crate) user_name: PrincipalName,
+    #[asn1(context_specific = "1")]
+    pub(crate) user_realm: Realm,
+    #[asn1(context_specific = "2")]
+    pub(crate) cksum: Checksum,
+    #[asn1(context_specific = "3")]
+    pub(crate) auth_package: KerberosString,
+}
@@ -0,0 +1,76 @@
+use super::kerberos_string::RawKerberosString;
+use der::asn1::{Any, BitString, OctetString};
+use der::Sequence;
+
+/// ```text
+/// PA-OTP-CHALLENGE ::= SEQUENCE {
+///         nonce            [0] OCTET STRING,
+///         otp-service      [1] UTF8String               OPTIONAL,
+///         otp-tokenInfo    [2] SEQUENCE (SIZE(1..MAX)) OF OTP-TOKENINFO,
+///         salt             [3] KerberosString           OPTIONAL,
+///         s2kparams        [4] OCTET STRING             OPTIONAL,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct PaOtpChallenge {
+    #[asn1(context_specific = "0")]
+    pub(crate) nonce: OctetString,
+    #[asn1(context_specific = "1", optional = "true")]
+    pub(crate) otp_service: Option<String>,
+    #[asn1(context_specific = "2")]
+    pub(crate) otp_token_info: Vec<OtpTokenInfo>,
+    #[asn1(context_specific = "3", optional = "true")]
+    pub(crate) salt: Option<RawKerberosString>,
+    #[asn1(context_specific = "4", optional = "true")]
+    pub(crate) s2kparams: Option<OctetString>,
+}
+
+/// ```text
+/// OTP-TOKENINFO ::= SEQUENCE {
+///         flags            [0] OTPFlags,
+///         otp-vendor       [1] UTF8String               OPTIONAL,
+///         otp-challenge    [2] OCTET STRING (SIZE(1..MAX)) OPTIONAL,
+///         otp-length       [3] Int32                    OPTIONAL,
+///         otp-format       [4] OTPFormat                OPTIONAL,
+///         otp-tokenID      [5] OCTET STRING             OPTIONAL,
+///         otp-algID        [6] AnyURI                   OPTIONAL,
+///         supportedHashAlg [7] SEQUENCE OF AlgorithmIdentifier OPTIONAL,
+///         iterationCount   [8] Int32                    OPTIONAL,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct OtpTokenInfo {
+    #[asn1(context_specific = "0")]
+    pub(crate) flags: BitString,
+    #[asn1(context_specific = "1", optional = "true")]
+    pub(crate) otp_vendor: Option<String>,
+    #[asn1(context_specific = "2", optional = "true")]
+    pub(crate) otp_challenge: Option<OctetString>,
+    #[asn1(context_specific = "3", optional = "true")]
+    pub(crate) otp_length: Option<i32>,
+    #[asn1(context_specific = "4", optional = "true")]
+    pub(crate) otp_format: Option<i32>,
+    #[asn1(context_specific = "5", optional = "true")]
+    pub(crate) otp_token_id: Option<OctetString>,
+    #[asn1(context_specific = "6", optional = "true")]
+    pub(crate) otp_alg_id: Option<String>,
+    #[asn1(context_specific = "7", optional = "true")]
+    pub(crate) supported_hash_alg: Option<Vec<AlgorithmIdentifier>>,
+    #[asn1(context_specific = "8", optional = "true")]
+    pub(crate) iteration_count: Option<i32>,
+}
+
+/// ```text
+/// AlgorithmIdentifier ::= SEQUENCE {
+///         algorithm        OBJECT IDENTIFIER,
+///         parameters       ANY DEFINED BY algorithm OPTIONAL
+/// }
+/// ```
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct AlgorithmIdentifier {
+    pub(crate) algorithm: Any,
+    #[asn1(optional = "true")]
+    pub(crate) parameters: Option<Any>,
+}
@@ -0,0 +1,29 @@
+use der::Sequence;
+
+/// ```text
+/// KERB-PA-PAC-REQUEST ::= SEQUENCE {
+///         include-pac     [0] BOOLEAN -- if TRUE, and no pac present, include PAC.
+///                                     -- If FALSE, and PAC present, remove PAC
+/// }
+/// ````
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct PaPacRequest {
+    #[asn1(context_specific = "0")]
+    pub(crate) include_pac: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PaPacRequest;
+    use der::{Decode, Encode};
+
+    #[test]
+    fn krb_pa_pac_request_parse() {
+        // From the PA-PAC-REQUEST of a Windows AS-REQ.
+        let blob = [0x30, 0x05, 0xa0, 0x03, 0x01, 0x01, 0xff];
+
+        let pac_request = PaPacRequest::from_der(&blob).expect("Failed to decode");
+        assert!(pac_request.include_pac);
+        assert_eq!(pac_request.to_der().expect("Failed to encode"), blob);
+    }
+}
@@ -0,0 +1,158 @@
+use super::encrypted_data::EncryptedData;
+use der::asn1::{ContextSpecificRef, OctetString};
+use der::{Sequence, Tag, TagMode, TagNumber, Writer};
+
+/// ```text
+/// PA-SPAKE ::= CHOICE {
+///         support [0] SPAKESupport,
+///         challenge [1] SPAKEChallenge,
+///         response [2] SPAKEResponse,
+///         encdata [3] EncryptedData,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum PaSpake {
+    Support(SpakeSupport),
+    Challenge(SpakeChallenge),
+    Response(SpakeResponse),
+    EncData(EncryptedData),
+}
+
+// The derived Choice doesn't mark an explicitly tagged alternative as constructed, so
+// the tags are handled here, as for the application tags of KrbKdcReq.
+impl<'a> ::der::Decode<'a> for PaSpake {
+    fn decode<R: der::Reader<'a>>(decoder: &mut R) -> der::Result<Self> {
+        let tag: Tag = decoder.decode()?;
+        let _len: der::Length = decoder.decode()?;
+
+        match tag {
+            Tag::ContextSpecific {
+                constructed: true,
+                number: TagNumber::N0,
+            } => decoder.decode().map(PaSpake::Support),
+            Tag::ContextSpecific {
+                constructed: true,
+                number: TagNumber::N1,
+            } => decoder.decode().map(PaSpake::Challenge),
+            Tag::ContextSpecific {
+                constructed: true,
+                number: TagNumber::N2,
+            } => decoder.decode().map(PaSpake::Response),
+            Tag::ContextSpecific {
+                constructed: true,
+                number: TagNumber::N3,
+            } => decoder.decode().map(PaSpake::EncData),
+            _ => Err(der::Error::from(der::ErrorKind::TagUnexpected {
+                expected: None,
+                actual: tag,
+            })),
+        }
+    }
+}
+
+impl ::der::Encode for PaSpake {
+    fn encoded_len(&self) -> Result<der::Length, der::Error> {
+        match self {
+            PaSpake::Support(support) => explicit(TagNumber::N0, support).encoded_len(),
+            PaSpake::Challenge(challenge) => explicit(TagNumber::N1, challenge).encoded_len(),
+            PaSpake::Response(response) => explicit(TagNumber::N2, response).encoded_len(),
+            PaSpake::EncData(enc_data) => explicit(TagNumber::N3, enc_data).encoded_len(),
+        }
+    }
+
+    fn encode(&self, writer: &mut impl Writer) -> der::Result<()> {
+        match self {
+            PaSpake::Support(support) => explicit(TagNumber::N0, support).encode(writer),
+            PaSpake::Challenge(challenge) => explicit(TagNumber::N1, challenge).encode(writer),
+            PaSpake::Response(response) => explicit(TagNumber::N2, response).encode(writer),
+            PaSpake::EncData(enc_data) => explicit(TagNumber::N3, enc_data).encode(writer),
+        }
+    }
+}
+
+fn explicit<T>(tag_number: TagNumber, value: &T) -> ContextSpecificRef<'_, T> {
+    ContextSpecificRef {
+        tag_number,
+        tag_mode: TagMode::Explicit,
+        value,
+    }
+}
+
+/// ```text
+/// SPAKESupport ::= SEQUENCE {
+///         groups [0] SEQUENCE (SIZE(1..MAX)) OF Int32,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct SpakeSupport {
+    #[asn1(context_specific = "0")]
+    pub(crate) groups: Vec<i32>,
+}
+
+/// ```text
+/// SPAKEChallenge ::= SEQUENCE {
+///         group [0] Int32,
+///         pubkey [1] OCTET STRING,
+///         factors [2] SEQUENCE (SIZE(1..MAX)) OF SPAKESecondFactor,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct SpakeChallenge {
+    #[asn1(context_specific = "0")]
+    pub(crate) group: i32,
+    #[asn1(context_specific = "1")]
+    pub(crate) pubkey: OctetString,
+    #[asn1(context_specific = "2")]
+    pub(crate) factors: Vec<SpakeSecondFactor>,
+}
+
+/// ```text
+/// SPAKESecondFactor ::= SEQUENCE {
+///         type [0] Int32,
+///         data [1] OCTET STRING OPTIONAL
+/// }
+/// ```
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct SpakeSecondFactor {
+    #[asn1(context_specific = "0")]
+    pub(crate) factor_type: i32,
+    #[asn1(context_specific = "1", optional = "true")]
+    pub(crate) data: Option<OctetString>,
+}
+
+/// ```text
+/// SPAKEResponse ::= SEQUENCE {
+///         pubkey [0] OCTET STRING,
+///         factor [1] EncryptedData, -- SPAKESecondFactor
+///         ...
+/// }
+/// ```
+#[derive(Debug, Eq, PartialEq, Sequence)]
+pub(crate) struct SpakeResponse {
+    #[asn1(context_specific = "0")]
+    pub(crate) pubkey: OctetString,
+    #[asn1(context_specific = "1")]
+    pub(crate) factor: EncryptedData,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PaSpake, SpakeSupport};
+    use der::{Decode, Encode};
+
+    #[test]
+    fn pa_spake_support_encode() {
+        // The support message of a client that only offers edwards25519.
+        let support = PaSpake::Support(SpakeSupport { groups: vec![1] });
+
+        let blob = support.to_der().expect("Failed to encode");
+        assert_eq!(
+            blob,
+            [0xa0, 0x09, 0x30, 0x07, 0xa0, 0x05, 0x30, 0x03, 0x02, 0x01, 0x01]
+        );
+        assert_eq!(PaSpake::from_der(&blob).expect("Failed to decode"), support);
+    }
+}
@@ -0,0 +1,1181 @@
+use crate::asn1::constants::errors::KrbErrorCode;
+use crate::clock::Clock;
+use crate::constants::DEFAULT_MAX_REFERRALS;
+use crate::error::KrbError;
+use crate::proto::{
+    AuthenticationReply, DerivedKey, EtypePolicy, KdcReplyPart, KerberosReply, KerberosRequest,
+    Name, SessionKey, Ticket, TicketGrantReply,
+};
+use std::future::Future;
+use std::time::SystemTime;
+use tracing::{debug, instrument, trace};
+
+/// Obtain a ticket granting ticket for `client_name`, which must be a principal.
+///
+/// The first AS-REQ is sent without preauthentication. If the KDC doesn't require it
+/// for this principal the AS-REP is returned straight away, otherwise the request is
+/// sent again with a PA-ENC-TIMESTAMP in the key the KDC asked for. Either way the
+/// `passphrase` is only called once the KDC has replied, and only once. If the KDC
+/// rejects the timestamp with KDC_ERR_PREAUTH_FAILED, [KrbError::PreauthFailed] is
+/// returned so that the caller can ask for the passphrase again.
+///
+/// The `exchange` function is called with the realm of the KDC that the request must be
+/// sent to, and is responsible for locating and communicating with that KDC.
+pub async fn get_initial_ticket<F, Fut, P>(
+    client_name: &Name,
+    until: SystemTime,
+    clock: &impl Clock,
+    passphrase: P,
+    exchange: F,
+) -> Result<(Ticket, KdcReplyPart), KrbError>
+where
+    F: FnMut(String, KerberosRequest) -> Fut,
+    Fut: Future<Output = Result<KerberosReply, KrbError>>,
+    P: FnOnce() -> Result<String, KrbError>,
+{
+    get_initial_ticket_with_policy(
+        client_name,
+        until,
+        clock,
+        &EtypePolicy::default(),
+        passphrase,
+        exchange,
+    )
+    .await
+}
+
+/// As [get_initial_ticket], but only advertising the etypes that `policy` permits. A
+/// reply encrypted in, or issuing a session key of, any other etype is rejected with
+/// [KrbError::EtypeNotPermitted].
+#[instrument(level = "trace", skip_all)]
+pub async fn get_initial_ticket_with_policy<F, Fut, P>(
+    client_name: &Name,
+    until: SystemTime,
+    clock: &impl Clock,
+    policy: &EtypePolicy,
+    passphrase: P,
+    mut exchange: F,
+) -> Result<(Ticket, KdcReplyPart), KrbError>
+where
+    F: FnMut(String, KerberosRequest) -> Fut,
+    Fut: Future<Output = Result<KerberosReply, KrbError>>,
+    P: FnOnce() -> Result<String, KrbError>,
+{
+    let (username, realm) = client_name.principal_name()?;
+
+    let build_as = || {
+        KerberosRequest::build_as(client_name.clone(), Name::service_krbtgt(realm), until)
+            .etype_policy(policy)
+    };
+
+    let request = build_as().build(clock)?;
+    let nonce = request.nonce();
+
+    let (as_rep, user_key, nonce) = match exchange(realm.to_string(), request).await? {
+        KerberosReply::AS(as_rep) => {
+            debug!("KDC did not require preauthentication");
+
+            policy.check(as_rep.enc_part.etype())?;
+
+            let etype_info2 = as_rep
+                .pa_data
+                .as_ref()
+                .map(|pa_data| pa_data.etype_info2.as_slice());
+
+            let user_key = DerivedKey::from_encrypted_reply(
+                &as_rep.enc_part,
+                etype_info2,
+                realm,
+                username,
+                &passphrase()?,
+            )?;
+
+            (as_rep, user_key, nonce)
+        }
+        KerberosReply::PA(pa_rep) => {
+            debug!("KDC requires preauthentication");
+
+            let epoch_seconds = clock
+                .now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_err(|_| KrbError::PreauthInvalidUnixTs)?;
+
+            let passphrase = passphrase()?;
+
+            let (builder, user_key) = build_as().preauth_enc_ts_with_passphrase(
+                &pa_rep.pa_data,
+                epoch_seconds,
+                &passphrase,
+            )?;
+
+            let request = builder.build(clock)?;
+            let nonce = request.nonce();
+
+            let (as_rep, user_key) = match exchange(realm.to_string(), request).await? {
+                KerberosReply::AS(as_rep) if !policy.permits(as_rep.enc_part.etype()) => {
+                    return Err(KrbError::EtypeNotPermitted(as_rep.enc_part.etype()));
+                }
+                // The timestamp was encrypted in the strongest etype the KDC offered, but
+                // it may still reply in another of the etypes we requested.
+                KerberosReply::AS(as_rep) if as_rep.enc_part.etype() != user_key.etype() => {
+                    debug!(etype = ?as_rep.enc_part.etype(), "KDC replied in another etype");
+
+                    let user_key = DerivedKey::from_encrypted_reply(
+                        &as_rep.enc_part,
+                        Some(&pa_rep.pa_data.etype_info2),
+                        realm,
+                        username,
+                        &passphrase,
+                    )?;
+
+                    (as_rep, user_key)
+                }
+                KerberosReply::AS(as_rep) => (as_rep, user_key),
+                KerberosReply::ERR(err_rep)
+                    if err_rep.code() == KrbErrorCode::KdcErrPreauthFailed =>
+                {
+                    debug!(error_text = ?err_rep.error_text(), "KDC rejected preauthentication");
+                    return Err(KrbError::PreauthFailed);
+                }
+                KerberosReply::ERR(err_rep) => {
+                    debug!(error_text = ?err_rep.error_text(), "KDC refused the request");
+                    return Err(KrbError::KdcError(err_rep.code()));
+                }
+                reply => {
+                    trace!(?reply);
+                    return Err(KrbError::UnexpectedReply);
+                }
+            };
+
+            (as_rep, user_key, nonce)
+        }
+        KerberosReply::ERR(err_rep) => {
+            debug!(error_text = ?err_rep.error_text(), "KDC refused the ticket request");
+            return Err(KrbError::KdcError(err_rep.code()));
+        }
+        reply => {
+            trace!(?reply);
+            return Err(KrbError::UnexpectedReply);
+        }
+    };
+
+    let reply_part = as_rep.decrypt(client_name, &user_key)?;
+    if reply_part.nonce() != nonce {
+        return Err(KrbError::NonceMismatch);
+    }
+    policy.check(reply_part.session_key().etype())?;
+    let AuthenticationReply { ticket, .. } = as_rep;
+
+    Ok((ticket, reply_part))
+}
+
+/// Obtain a ticket for `service` using a ticket granting ticket and its session key.
+///
+/// If the service is in a different realm to the one the TGT is valid for, a cross realm
+/// TGT (`krbtgt/TARGET@LOCAL`) is requested first. The KDC may refer us to an intermediate
+/// realm on the trust path, in which case we present that referral TGT to the next KDC
+/// and continue, until the final service ticket is issued or more than `max_referrals`
+/// hops have been taken.
+///
+/// The `exchange` function is called with the realm of the KDC that the request must be
+/// sent to, and is responsible for locating and communicating with that KDC.
+#[allow(clippy::too_many_arguments)]
+pub async fn get_service_ticket<F, Fut>(
+    service: &Name,
+    tgt: &Ticket,
+    session_key: &SessionKey,
+    client_name: &Name,
+    until: SystemTime,
+    max_referrals: usize,
+    clock: &impl Clock,
+    exchange: F,
+) -> Result<(Ticket, KdcReplyPart), KrbError>
+where
+    F: FnMut(String, KerberosRequest) -> Fut,
+    Fut: Future<Output = Result<KerberosReply, KrbError>>,
+{
+    get_service_ticket_with_policy(
+        service,
+        tgt,
+        session_key,
+        client_name,
+        until,
+        max_referrals,
+        clock,
+        &EtypePolicy::default(),
+        exchange,
+    )
+    .await
+}
+
+/// As [get_service_ticket], but only advertising the etypes that `policy` permits. A
+/// reply encrypted in, or issuing a session key of, any other etype is rejected with
+/// [KrbError::EtypeNotPermitted].
+#[allow(clippy::too_many_arguments)]
+#[instrument(level = "trace", skip_all)]
+pub async fn get_service_ticket_with_policy<F, Fut>(
+    service: &Name,
+    tgt: &Ticket,
+    session_key: &SessionKey,
+    client_name: &Name,
+    until: SystemTime,
+    max_referrals: usize,
+    clock: &impl Clock,
+    policy: &EtypePolicy,
+    mut exchange: F,
+) -> Result<(Ticket, KdcReplyPart), KrbError>
+where
+    F: FnMut(String, KerberosRequest) -> Fut,
+    Fut: Future<Output = Result<KerberosReply, KrbError>>,
+{
+    let target_realm = service.realm();
+
+    let mut ticket = tgt.clone();
+    let mut session_key = session_key.clone();
+    let mut referrals = 0;
+
+    loop {
+        let kdc_realm = ticket
+            .service()
+            .krbtgt_target_realm()
+            .ok_or(KrbError::NameNotKrbtgt)?
+            .to_string();
+
+        // Once we hold a TGT for the target realm we can ask for the service itself,
+        // otherwise ask our current KDC for a path towards the target realm.
+        let request_service = if kdc_realm == target_realm {
+            service.clone()
+        } else {
+            Name::service_krbtgt_cross_realm(target_realm, &kdc_realm)
+        };
+
+        debug!(?request_service, %kdc_realm, "requesting ticket");
+
+        let request = KerberosRequest::build_tgs(request_service, until)
+            .etype_policy(policy)
+            .build(client_name, &ticket, &session_key, clock)?;
+        let nonce = request.nonce();
+
+        let (next_ticket, enc_part) = match exchange(kdc_realm, request).await? {
+            KerberosReply::TGS(TicketGrantReply {
+                ticket, enc_part, ..
+            }) => (ticket, enc_part),
+            KerberosReply::ERR(err_rep) => {
+                debug!(error_text = ?err_rep.error_text(), "KDC refused the ticket request");
+                return Err(KrbError::KdcError(err_rep.code()));
+            }
+            reply => {
+                trace!(?reply);
+                return Err(KrbError::UnexpectedReply);
+            }
+        };
+
+        policy.check(enc_part.etype())?;
+        let reply_part = enc_part.decrypt_enc_tgs_rep(&session_key)?;
+        policy.check(reply_part.session_key().etype())?;
+
+        if reply_part.nonce() != nonce {
+            return Err(KrbError::NonceMismatch);
+        }
+
+        // The ticket's service name is sent in the clear, so only the server name of the
+        // encrypted part can be trusted, and the two must agree.
+        if reply_part.server() != next_ticket.service() {
+            return Err(KrbError::UnexpectedTicketService);
+        }
+
+        if reply_part.server() == service {
+            return Ok((next_ticket, reply_part));
+        }
+
+        if reply_part.server().krbtgt_target_realm().is_none() {
+            return Err(KrbError::UnexpectedTicketService);
+        }
+
+        referrals += 1;
+        if referrals > max_referrals {
+            return Err(KrbError::TooManyReferrals);
+        }
+
+        trace!(referral = ?reply_part.server());
+
+        session_key = reply_part.session_key().clone();
+        ticket = next_ticket;
+    }
+}
+
+/// Obtain a ticket for `service` with the passphrase of `client_name`, which must be a
+/// principal. A ticket granting ticket is obtained as by [get_initial_ticket] and is
+/// then presented for the service ticket as by [get_service_ticket], following up to
+/// [DEFAULT_MAX_REFERRALS] referrals. Both tickets are requested to be valid until
+/// `until`.
+///
+/// The `exchange` function is called with the realm of the KDC that each request must
+/// be sent to, and is responsible for locating and communicating with that KDC.
+#[instrument(level = "trace", skip_all)]
+pub async fn obtain_service_ticket<F, Fut>(
+    client_name: &Name,
+    passphrase: &str,
+    service: &Name,
+    until: SystemTime,
+    clock: &impl Clock,
+    mut exchange: F,
+) -> Result<(Ticket, SessionKey), KrbError>
+where
+    F: FnMut(String, KerberosRequest) -> Fut,
+    Fut: Future<Output = Result<KerberosReply, KrbError>>,
+{
+    let (tgt, tgt_part) = get_initial_ticket(
+        client_name,
+        until,
+        clock,
+        || Ok(passphrase.to_string()),
+        &mut exchange,
+    )
+    .await?;
+
+    let (ticket, reply_part) = get_service_ticket(
+        service,
+        &tgt,
+        tgt_part.session_key(),
+        client_name,
+        until,
+        DEFAULT_MAX_REFERRALS,
+        clock,
+        exchange,
+    )
+    .await?;
+
+    Ok((ticket, reply_part.session_key().clone()))
+}
+
+/// Obtain a ticket for each of `services` with the same ticket granting ticket, as by
+/// [get_service_ticket] with up to [DEFAULT_MAX_REFERRALS] referrals. The requests are
+/// made one after another, so an `exchange` function that holds a connection to the KDC
+/// can reuse it for all of them.
+///
+/// The result for each service is returned in the order of `services`, and a service
+/// that can't be obtained doesn't prevent the others from being requested. A service
+/// that the KDC refused fails with [KrbError::KdcError] and the code of its KRB-ERROR.
+pub async fn get_service_tickets<F, Fut>(
+    tgt: &Ticket,
+    session_key: &SessionKey,
+    services: &[Name],
+    client_name: &Name,
+    until: SystemTime,
+    clock: &impl Clock,
+    exchange: F,
+) -> Vec<Result<(Ticket, SessionKey), KrbError>>
+where
+    F: FnMut(String, KerberosRequest) -> Fut,
+    Fut: Future<Output = Result<KerberosReply, KrbError>>,
+{
+    get_service_tickets_with_policy(
+        tgt,
+        session_key,
+        services,
+        client_name,
+        until,
+        clock,
+        &EtypePolicy::default(),
+        exchange,
+    )
+    .await
+}
+
+/// As [get_service_tickets], but only advertising the etypes that `policy` permits, as
+/// by [get_service_ticket_with_policy].
+#[allow(clippy::too_many_arguments)]
+#[instrument(level = "trace", skip_all)]
+pub async fn get_service_tickets_with_policy<F, Fut>(
+    tgt: &Ticket,
+    session_key: &SessionKey,
+    services: &[Name],
+    client_name: &Name,
+    until: SystemTime,
+    clock: &impl Clock,
+    policy: &EtypePolicy,
+    mut exchange: F,
+) -> Vec<Result<(Ticket, SessionKey), KrbError>>
+where
+    F: FnMut(String, KerberosRequest) -> Fut,
+    Fut: Future<Output = Result<KerberosReply, KrbError>>,
+{
+    let mut results = Vec::with_capacity(services.len());
+
+    for service in services {
+        let result = get_service_ticket_with_policy(
+            service,
+            tgt,
+            session_key,
+            client_name,
+            until,
+            DEFAULT_MAX_REFERRALS,
+            clock,
+            policy,
+            &mut exchange,
+        )
+        .await
+        .map(|(ticket, reply_part)| (ticket, reply_part.session_key().clone()));
+
+        if let Err(err) = &result {
+            debug!(?service, ?err, "failed to obtain service ticket");
+        }
+        results.push(result);
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_initial_ticket, get_initial_ticket_with_policy, get_service_ticket,
+        get_service_tickets, get_service_tickets_with_policy, obtain_service_ticket,
+    };
+    use crate::asn1::{
+        constants::{encryption_types::EncryptionType, errors::KrbErrorCode},
+        enc_kdc_rep_part::EncKdcRepPart,
+        encryption_key::EncryptionKey,
+        kerberos_time::KerberosTime,
+        ticket_flags::TicketFlags,
+        OctetString,
+    };
+    use crate::clock::{Clock, SystemClock};
+    use crate::crypto::supported_etypes;
+    use crate::error::KrbError;
+    use crate::proto::{
+        AuthenticationRequest, DerivedKey, EtypePolicy, KerberosReply, KerberosRequest, Name,
+        SessionKey, Ticket, TicketGrantReply, TicketGrantRequest,
+    };
+    use crate::test_helpers::{primary_key, session_key, ticket};
+    use der::flagset::FlagSet;
+    use std::time::{Duration, SystemTime};
+
+    fn mock_tgs_reply(
+        request: KerberosRequest,
+        client_name: &Name,
+        server: Name,
+        presented_key: &SessionKey,
+        issued_key: [u8; 32],
+    ) -> KerberosReply {
+        let KerberosRequest::TGS(TicketGrantRequest { nonce, until, .. }) = request else {
+            unreachable!();
+        };
+
+        let now = KerberosTime::from_system_time(SystemTime::UNIX_EPOCH).unwrap();
+        let (server_name, server_realm) = (&server).try_into().unwrap();
+
+        let enc_part = presented_key
+            .encrypt_enc_kdc_rep_part(EncKdcRepPart {
+                key: EncryptionKey {
+                    key_type: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                    key_value: OctetString::new(issued_key).unwrap(),
+                },
+                last_req: Vec::with_capacity(0),
+                nonce,
+                key_expiration: None,
+                flags: FlagSet::<TicketFlags>::new(0).unwrap(),
+                auth_time: now,
+                start_time: None,
+                end_time: KerberosTime::from_system_time(until).unwrap(),
+                renew_till: None,
+                server_realm,
+                server_name,
+                client_addresses: None,
+            })
+            .unwrap();
+
+        let ticket = ticket(server);
+
+        KerberosReply::TGS(TicketGrantReply {
+            name: client_name.clone(),
+            enc_part,
+            ticket,
+        })
+    }
+
+    fn local_tgt() -> (Ticket, SessionKey) {
+        let ticket = ticket(Name::service_krbtgt("A.COM"));
+
+        (ticket, session_key())
+    }
+
+    fn mock_as_reply(auth_req: AuthenticationRequest, user_key: &DerivedKey) -> KerberosReply {
+        let primary_key = primary_key();
+
+        KerberosReply::authentication_builder(
+            auth_req.client_name,
+            auth_req.service_name,
+            SystemClock.now(),
+            auth_req.nonce,
+        )
+        .build(user_key, &primary_key)
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_initial_ticket_no_preauth() {
+        let client_name = Name::principal("user", "A.COM");
+        let user_key = DerivedKey::new_aes256_cts_hmac_sha1_96("password", "A.COMuser").unwrap();
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        // A KDC that doesn't require preauth for this principal, and issues the ticket in
+        // reply to the first AS-REQ.
+        let mut requests = 0;
+        let mut passphrase_calls = 0;
+        let (ticket, reply_part) = get_initial_ticket(
+            &client_name,
+            until,
+            &SystemClock,
+            || {
+                passphrase_calls += 1;
+                Ok("password".to_string())
+            },
+            |kdc_realm, request| {
+                requests += 1;
+                assert_eq!(kdc_realm, "A.COM");
+                let KerberosRequest::AS(auth_req) = request else {
+                    unreachable!();
+                };
+                assert!(auth_req.preauth.enc_timestamp().is_none());
+                let reply = mock_as_reply(auth_req, &user_key);
+                async move { Ok(reply) }
+            },
+        )
+        .await
+        .expect("Failed to get initial ticket");
+
+        assert_eq!(requests, 1);
+        assert_eq!(passphrase_calls, 1);
+        assert_eq!(ticket.service(), &Name::service_krbtgt("A.COM"));
+        assert_eq!(reply_part.server(), &Name::service_krbtgt("A.COM"));
+    }
+
+    #[tokio::test]
+    async fn test_get_initial_ticket_etype_policy() {
+        let client_name = Name::principal("user", "A.COM");
+        let user_key = DerivedKey::new_aes256_cts_hmac_sha1_96("password", "A.COMuser").unwrap();
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        let request_ticket = |policy: EtypePolicy, advertised: Vec<EncryptionType>| {
+            let client_name = client_name.clone();
+            let user_key = &user_key;
+            async move {
+                get_initial_ticket_with_policy(
+                    &client_name,
+                    until,
+                    &SystemClock,
+                    &policy,
+                    || Ok("password".to_string()),
+                    |_, request| {
+                        let KerberosRequest::AS(auth_req) = request else {
+                            unreachable!();
+                        };
+                        let advertised: Vec<i32> =
+                            advertised.iter().map(|etype| *etype as i32).collect();
+                        assert_eq!(auth_req.etypes(), advertised);
+                        let reply = mock_as_reply(auth_req, user_key);
+                        async move { Ok(reply) }
+                    },
+                )
+                .await
+            }
+        };
+
+        // The default policy advertises and accepts everything we support.
+        request_ticket(EtypePolicy::default(), supported_etypes().to_vec())
+            .await
+            .expect("Failed to get initial ticket");
+
+        // Only the permitted etypes that we support are advertised, in the order of the
+        // policy.
+        let policy = EtypePolicy::new(&[
+            EncryptionType::AES256_CTS_HMAC_SHA384_192,
+            EncryptionType::AES256_CTS_HMAC_SHA1_96,
+        ]);
+        request_ticket(policy, vec![EncryptionType::AES256_CTS_HMAC_SHA1_96])
+            .await
+            .expect("Failed to get initial ticket");
+
+        // A policy that leaves nothing we can use can't build a request.
+        let policy = EtypePolicy::new(&[EncryptionType::AES256_CTS_HMAC_SHA384_192]);
+        assert!(matches!(
+            request_ticket(policy, Vec::new()).await,
+            Err(KrbError::UnsupportedEncryption)
+        ));
+
+        // The mock KDC only replies in aes256, which this policy forbids.
+        #[cfg(feature = "des3")]
+        {
+            let policy = EtypePolicy::new(&[EncryptionType::DES3_CBC_SHA1_KD]);
+            assert!(matches!(
+                request_ticket(policy, vec![EncryptionType::DES3_CBC_SHA1_KD]).await,
+                Err(KrbError::EtypeNotPermitted(
+                    EncryptionType::AES256_CTS_HMAC_SHA1_96
+                ))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_initial_ticket_preauth() {
+        let client_name = Name::principal("user", "A.COM");
+        let user_key = DerivedKey::new_aes256_cts_hmac_sha1_96("password", "A.COMuser").unwrap();
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        let mut requests = 0;
+        let (ticket, _) = get_initial_ticket(
+            &client_name,
+            until,
+            &SystemClock,
+            || Ok("password".to_string()),
+            |_, request| {
+                requests += 1;
+                let KerberosRequest::AS(auth_req) = request else {
+                    unreachable!();
+                };
+                let reply = match auth_req.preauth.enc_timestamp() {
+                    Some(enc_timestamp) => {
+                        enc_timestamp
+                            .decrypt_pa_enc_timestamp(&user_key)
+                            .expect("Failed to decrypt timestamp");
+                        mock_as_reply(auth_req, &user_key)
+                    }
+                    None => {
+                        KerberosReply::preauth_builder(auth_req.service_name, SystemClock.now())
+                            .set_key_params(&user_key)
+                            .build()
+                    }
+                };
+                async move { Ok(reply) }
+            },
+        )
+        .await
+        .expect("Failed to get initial ticket");
+
+        assert_eq!(requests, 2);
+        assert_eq!(ticket.service(), &Name::service_krbtgt("A.COM"));
+    }
+
+    #[tokio::test]
+    async fn test_get_initial_ticket_preauth_failed() {
+        let client_name = Name::principal("user", "A.COM");
+        let user_key = DerivedKey::new_aes256_cts_hmac_sha1_96("password", "A.COMuser").unwrap();
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        // The KDC can't decrypt the timestamp, as it was encrypted with a key derived from
+        // the wrong password.
+        let result = get_initial_ticket(
+            &client_name,
+            until,
+            &SystemClock,
+            || Ok("wrong password".to_string()),
+            |_, request| {
+                let KerberosRequest::AS(auth_req) = request else {
+                    unreachable!();
+                };
+                let reply = match auth_req.preauth.enc_timestamp() {
+                    Some(_) => KerberosReply::error_preauth_failed(
+                        auth_req.service_name,
+                        SystemClock.now(),
+                    ),
+                    None => {
+                        KerberosReply::preauth_builder(auth_req.service_name, SystemClock.now())
+                            .set_key_params(&user_key)
+                            .build()
+                    }
+                };
+                async move { Ok(reply) }
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(KrbError::PreauthFailed)));
+    }
+
+    #[tokio::test]
+    async fn test_get_initial_ticket_nonce_mismatch() {
+        let client_name = Name::principal("user", "A.COM");
+        let user_key = DerivedKey::new_aes256_cts_hmac_sha1_96("password", "A.COMuser").unwrap();
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        // A KDC that doesn't require preauth, but answers with another nonce.
+        let result = get_initial_ticket(
+            &client_name,
+            until,
+            &SystemClock,
+            || Ok("password".to_string()),
+            |_, request| {
+                let KerberosRequest::AS(mut auth_req) = request else {
+                    unreachable!();
+                };
+                auth_req.nonce = auth_req.nonce.wrapping_add(1);
+                let reply = mock_as_reply(auth_req, &user_key);
+                async move { Ok(reply) }
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(KrbError::NonceMismatch)));
+
+        // A KDC that requires preauth, and answers the preauthenticated request with the
+        // nonce of the first one.
+        let mut first_nonce = None;
+        let result = get_initial_ticket(
+            &client_name,
+            until,
+            &SystemClock,
+            || Ok("password".to_string()),
+            |_, request| {
+                let KerberosRequest::AS(mut auth_req) = request else {
+                    unreachable!();
+                };
+                let reply = match first_nonce {
+                    Some(nonce) => {
+                        assert_ne!(auth_req.nonce, nonce);
+                        auth_req.nonce = nonce;
+                        mock_as_reply(auth_req, &user_key)
+                    }
+                    None => {
+                        first_nonce = Some(auth_req.nonce);
+                        KerberosReply::preauth_builder(auth_req.service_name, SystemClock.now())
+                            .set_key_params(&user_key)
+                            .build()
+                    }
+                };
+                async move { Ok(reply) }
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(KrbError::NonceMismatch)));
+    }
+
+    #[tokio::test]
+    async fn test_get_initial_ticket_kdc_error() {
+        let client_name = Name::principal("user", "A.COM");
+        let user_key = DerivedKey::new_aes256_cts_hmac_sha1_96("password", "A.COMuser").unwrap();
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        // The KDC refuses the preauthenticated request with an error other than
+        // KDC_ERR_PREAUTH_FAILED, which is returned to the caller.
+        let result = get_initial_ticket(
+            &client_name,
+            until,
+            &SystemClock,
+            || Ok("password".to_string()),
+            |_, request| {
+                let KerberosRequest::AS(auth_req) = request else {
+                    unreachable!();
+                };
+                let reply = match auth_req.preauth.enc_timestamp() {
+                    Some(_) => {
+                        KerberosReply::error_clock_skew(auth_req.service_name, SystemClock.now())
+                    }
+                    None => {
+                        KerberosReply::preauth_builder(auth_req.service_name, SystemClock.now())
+                            .set_key_params(&user_key)
+                            .build()
+                    }
+                };
+                async move { Ok(reply) }
+            },
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(KrbError::KdcError(KrbErrorCode::KrbApErrSkew))
+        ));
+    }
+
+    #[cfg(feature = "des3")]
+    #[tokio::test]
+    async fn test_get_initial_ticket_reply_in_other_etype() {
+        let client_name = Name::principal("user", "A.COM");
+        let aes_key = DerivedKey::new_aes256_cts_hmac_sha1_96("password", "A.COMuser").unwrap();
+        let des3_key = DerivedKey::new_des3_cbc_sha1("password", "A.COMuser").unwrap();
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        // The KDC offers both etypes, so the timestamp is encrypted with aes256, but the
+        // reply comes back in des3 and must be decrypted with a key derived for it.
+        let (ticket, _) = get_initial_ticket(
+            &client_name,
+            until,
+            &SystemClock,
+            || Ok("password".to_string()),
+            |_, request| {
+                let KerberosRequest::AS(auth_req) = request else {
+                    unreachable!();
+                };
+                let reply = match auth_req.preauth.enc_timestamp() {
+                    Some(enc_timestamp) => {
+                        enc_timestamp
+                            .decrypt_pa_enc_timestamp(&aes_key)
+                            .expect("Failed to decrypt timestamp");
+
+                        // Our KDC only encrypts replies with aes256, so re-encrypt the
+                        // reply part in the des3 key.
+                        let now = KerberosTime::from_system_time(SystemClock.now()).unwrap();
+                        let nonce = auth_req.nonce;
+                        let (server_name, server_realm) =
+                            (&Name::service_krbtgt("A.COM")).try_into().unwrap();
+                        let KerberosReply::AS(mut as_rep) = mock_as_reply(auth_req, &aes_key)
+                        else {
+                            unreachable!();
+                        };
+
+                        as_rep.enc_part = des3_key
+                            .encrypt_enc_kdc_rep_part(EncKdcRepPart {
+                                key: EncryptionKey {
+                                    key_type: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                                    key_value: OctetString::new([0xaa; 32]).unwrap(),
+                                },
+                                last_req: Vec::with_capacity(0),
+                                nonce,
+                                key_expiration: None,
+                                flags: FlagSet::<TicketFlags>::new(0).unwrap(),
+                                auth_time: now,
+                                start_time: None,
+                                end_time: now,
+                                renew_till: None,
+                                server_realm,
+                                server_name,
+                                client_addresses: None,
+                            })
+                            .unwrap();
+
+                        KerberosReply::AS(as_rep)
+                    }
+                    None => KerberosReply::preauth_required(
+                        auth_req.service_name,
+                        SystemClock.now(),
+                        "A.COMuser",
+                        &[
+                            EncryptionType::AES256_CTS_HMAC_SHA1_96,
+                            EncryptionType::DES3_CBC_SHA1_KD,
+                        ],
+                        vec![],
+                    ),
+                };
+                async move { Ok(reply) }
+            },
+        )
+        .await
+        .expect("Failed to get initial ticket");
+
+        assert_eq!(ticket.service(), &Name::service_krbtgt("A.COM"));
+    }
+
+    #[tokio::test]
+    async fn test_obtain_service_ticket() {
+        let client_name = Name::principal("user", "A.COM");
+        let user_key = DerivedKey::new_aes256_cts_hmac_sha1_96("password", "A.COMuser").unwrap();
+        let service = Name::SrvHst {
+            service: "HTTP".to_string(),
+            host: "www.a.com".to_string(),
+            realm: "A.COM".to_string(),
+        };
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        // The TGS-REQ must be made with the session key of the ticket granting ticket.
+        let mut tgt_session_key = None;
+        let (ticket, session_key) = obtain_service_ticket(
+            &client_name,
+            "password",
+            &service,
+            until,
+            &SystemClock,
+            |kdc_realm, request| {
+                assert_eq!(kdc_realm, "A.COM");
+                let reply = match request {
+                    KerberosRequest::AS(auth_req) => {
+                        let reply = mock_as_reply(auth_req, &user_key);
+                        let KerberosReply::AS(as_rep) = &reply else {
+                            unreachable!();
+                        };
+                        let reply_part = as_rep.decrypt(&client_name, &user_key).unwrap();
+                        tgt_session_key = Some(reply_part.session_key().clone());
+                        reply
+                    }
+                    request => mock_tgs_reply(
+                        request,
+                        &client_name,
+                        service.clone(),
+                        tgt_session_key.as_ref().expect("TGS-REQ before AS-REQ"),
+                        [0xdd; 32],
+                    ),
+                };
+                async move { Ok(reply) }
+            },
+        )
+        .await
+        .expect("Failed to obtain service ticket");
+
+        assert_eq!(ticket.service(), &service);
+        assert!(matches!(
+            session_key,
+            SessionKey::Aes256CtsHmacSha196 { k } if k == [0xdd; 32]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_service_ticket_two_hop_referral() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let client_name = Name::principal("user", "A.COM");
+        let service = Name::SrvHst {
+            service: "HTTP".to_string(),
+            host: "www.c.com".to_string(),
+            realm: "C.COM".to_string(),
+        };
+        let (tgt, session_key) = local_tgt();
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        // A.COM only trusts B.COM, and B.COM trusts C.COM.
+        let mut seen = Vec::new();
+        let (ticket, reply_part) = get_service_ticket(
+            &service,
+            &tgt,
+            &session_key,
+            &client_name,
+            until,
+            2,
+            &SystemClock,
+            |kdc_realm, request| {
+                seen.push(kdc_realm.clone());
+                let reply = match kdc_realm.as_str() {
+                    "A.COM" => mock_tgs_reply(
+                        request,
+                        &client_name,
+                        Name::service_krbtgt_cross_realm("B.COM", "A.COM"),
+                        &session_key,
+                        [0xbb; 32],
+                    ),
+                    "B.COM" => mock_tgs_reply(
+                        request,
+                        &client_name,
+                        Name::service_krbtgt_cross_realm("C.COM", "B.COM"),
+                        &SessionKey::Aes256CtsHmacSha196 { k: [0xbb; 32] },
+                        [0xcc; 32],
+                    ),
+                    "C.COM" => mock_tgs_reply(
+                        request,
+                        &client_name,
+                        service.clone(),
+                        &SessionKey::Aes256CtsHmacSha196 { k: [0xcc; 32] },
+                        [0xdd; 32],
+                    ),
+                    _ => unreachable!(),
+                };
+                async move { Ok(reply) }
+            },
+        )
+        .await
+        .expect("Failed to follow referrals");
+
+        assert_eq!(seen, vec!["A.COM", "B.COM", "C.COM"]);
+        assert_eq!(ticket.service(), &service);
+        assert_eq!(reply_part.server(), &service);
+        assert!(matches!(
+            reply_part.session_key(),
+            SessionKey::Aes256CtsHmacSha196 { k } if k == &[0xdd; 32]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_service_ticket_reply_mismatch() {
+        let client_name = Name::principal("user", "A.COM");
+        let service = Name::service_host("HTTP", "www.a.com", "A.COM");
+        let other = Name::service_host("HTTP", "other.a.com", "A.COM");
+        let (tgt, session_key) = local_tgt();
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        // The KDC answers with another nonce.
+        let result = get_service_ticket(
+            &service,
+            &tgt,
+            &session_key,
+            &client_name,
+            until,
+            0,
+            &SystemClock,
+            |_, request| {
+                let KerberosRequest::TGS(mut tgs_req) = request else {
+                    unreachable!();
+                };
+                tgs_req.nonce = tgs_req.nonce.wrapping_add(1);
+                let reply = mock_tgs_reply(
+                    KerberosRequest::TGS(tgs_req),
+                    &client_name,
+                    service.clone(),
+                    &session_key,
+                    [0xbb; 32],
+                );
+                async move { Ok(reply) }
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(KrbError::NonceMismatch)));
+
+        // The ticket in the clear claims to be for the requested service, but the reply
+        // was issued for another.
+        let result = get_service_ticket(
+            &service,
+            &tgt,
+            &session_key,
+            &client_name,
+            until,
+            0,
+            &SystemClock,
+            |_, request| {
+                let KerberosReply::TGS(mut tgs_rep) = mock_tgs_reply(
+                    request,
+                    &client_name,
+                    other.clone(),
+                    &session_key,
+                    [0xbb; 32],
+                ) else {
+                    unreachable!();
+                };
+                tgs_rep.ticket = ticket(service.clone());
+                async move { Ok(KerberosReply::TGS(tgs_rep)) }
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(KrbError::UnexpectedTicketService)));
+    }
+
+    #[tokio::test]
+    async fn test_get_service_tickets_partial() {
+        let client_name = Name::principal("user", "A.COM");
+        let known = Name::service_host("HTTP", "www.a.com", "A.COM");
+        let unknown = Name::service_host("HTTP", "missing.a.com", "A.COM");
+        let (tgt, session_key) = local_tgt();
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        let mut requests = 0;
+        let results = get_service_tickets(
+            &tgt,
+            &session_key,
+            &[unknown.clone(), known.clone()],
+            &client_name,
+            until,
+            &SystemClock,
+            |kdc_realm, request| {
+                requests += 1;
+                assert_eq!(kdc_realm, "A.COM");
+                let KerberosRequest::TGS(tgs_req) = &request else {
+                    unreachable!();
+                };
+                let reply = if tgs_req.service_name == known {
+                    mock_tgs_reply(
+                        request,
+                        &client_name,
+                        known.clone(),
+                        &session_key,
+                        [0xbb; 32],
+                    )
+                } else {
+                    KerberosReply::error_service_unknown(
+                        tgs_req.service_name.clone(),
+                        SystemClock.now(),
+                    )
+                };
+                async move { Ok(reply) }
+            },
+        )
+        .await;
+
+        // The unknown service doesn't stop the known one from being requested.
+        assert_eq!(requests, 2);
+        assert_eq!(results.len(), 2);
+        assert!(matches!(
+            results[0],
+            Err(KrbError::KdcError(KrbErrorCode::KdcErrSPrincipalUnknown))
+        ));
+        let Ok((ticket, session_key)) = &results[1] else {
+            unreachable!();
+        };
+        assert_eq!(ticket.service(), &known);
+        assert!(matches!(
+            session_key,
+            SessionKey::Aes256CtsHmacSha196 { k } if *k == [0xbb; 32]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_service_tickets_with_policy() {
+        let client_name = Name::principal("user", "A.COM");
+        let service = Name::service_host("HTTP", "www.a.com", "A.COM");
+        let (tgt, session_key) = local_tgt();
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        // A policy that permits nothing we support fails each service without a request.
+        let policy = EtypePolicy::new(&[EncryptionType::AES256_CTS_HMAC_SHA384_192]);
+        let results = get_service_tickets_with_policy(
+            &tgt,
+            &session_key,
+            &[service.clone(), service],
+            &client_name,
+            until,
+            &SystemClock,
+            &policy,
+            |_, _| async { unreachable!() },
+        )
+        .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Err(KrbError::UnsupportedEncryption))));
+    }
+
+    #[tokio::test]
+    async fn test_get_service_ticket_too_many_referrals() {
+        let client_name = Name::principal("user", "A.COM");
+        let service = Name::SrvHst {
+            service: "HTTP".to_string(),
+            host: "www.c.com".to_string(),
+            realm: "C.COM".to_string(),
+        };
+        let (tgt, session_key) = local_tgt();
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        let result = get_service_ticket(
+            &service,
+            &tgt,
+            &session_key,
+            &client_name,
+            until,
+            1,
+            &SystemClock,
+            |kdc_realm, request| {
+                let reply = match kdc_realm.as_str() {
+                    "A.COM" => mock_tgs_reply(
+                        request,
+                        &client_name,
+                        Name::service_krbtgt_cross_realm("B.COM", "A.COM"),
+                        &session_key,
+                        [0xbb; 32],
+                    ),
+                    "B.COM" => mock_tgs_reply(
+                        request,
+                        &client_name,
+                        Name::service_krbtgt_cross_realm("C.COM", "B.COM"),
+                        &SessionKey::Aes256CtsHmacSha196 { k: [0xbb; 32] },
+                        [0xcc; 32],
+                    ),
+                    _ => unreachable!(),
+                };
+                async move { Ok(reply) }
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(KrbError::TooManyReferrals)));
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// A source of the current time. Protocol code takes the time from a `Clock`
+/// rather than the system directly, so that time dependent behaviour such as
+/// authenticator timestamps and clock skew checks can be tested deterministically.
+pub trait Clock: fmt::Debug {
+    fn now(&self) -> SystemTime;
+}
+
+/// The default `Clock`, backed by the system time.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// How far the clock of a peer, such as a KDC, is from our own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockOffset {
+    /// The peer's clock is ahead of ours by this much.
+    Ahead(Duration),
+    /// The peer's clock is behind ours by this much.
+    Behind(Duration),
+}
+
+impl ClockOffset {
+    /// The offset of `peer_time` from `now`, both read at the same moment.
+    pub fn between(now: SystemTime, peer_time: SystemTime) -> Self {
+        match peer_time.duration_since(now) {
+            Ok(ahead) => ClockOffset::Ahead(ahead),
+            Err(err) => ClockOffset::Behind(err.duration()),
+        }
+    }
+
+    /// Convert `time` from our clock to the peer's. The offset comes from the peer, so
+    /// an offset that would take the time out of range is ignored and `time` is returned
+    /// as is.
+    pub fn apply(&self, time: SystemTime) -> SystemTime {
+        match self {
+            ClockOffset::Ahead(offset) => time.checked_add(*offset),
+            ClockOffset::Behind(offset) => time.checked_sub(*offset),
+        }
+        .unwrap_or(time)
+    }
+}
+
+/// A `Clock` corrected by the offset of a peer's clock, so that a request can be retried
+/// with timestamps that the peer will accept after it replied with KRB_AP_ERR_SKEW.
+#[derive(Debug, Clone)]
+pub struct OffsetClock<C> {
+    clock: C,
+    offset: ClockOffset,
+}
+
+impl<C: Clock> OffsetClock<C> {
+    /// Read the time from `clock`, moved by `offset`, such as the
+    /// [ErrorReply::clock_offset](crate::proto::ErrorReply::clock_offset) of a skew error.
+    pub fn new(clock: C, offset: ClockOffset) -> Self {
+        OffsetClock { clock, offset }
+    }
+}
+
+impl<C: Clock> Clock for OffsetClock<C> {
+    fn now(&self) -> SystemTime {
+        self.offset.apply(self.clock.now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, ClockOffset};
+    use crate::asn1::{
+        authenticator::TaggedAuthenticator, kerberos_time::KerberosTime, krb_kdc_req::KrbKdcReq,
+    };
+    use crate::proto::{KerberosRequest, Name, TicketGrantRequest};
+    use crate::test_helpers::{session_key, ticket, FixedClock};
+    use der::Decode;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_clock_offset_out_of_range() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            ClockOffset::Ahead(Duration::from_secs(300)).apply(now),
+            now + Duration::from_secs(300)
+        );
+        assert_eq!(
+            ClockOffset::Behind(Duration::from_secs(300)).apply(now),
+            now - Duration::from_secs(300)
+        );
+
+        // A peer can claim any time at all, which must not make us panic.
+        assert_eq!(ClockOffset::Ahead(Duration::MAX).apply(now), now);
+        assert_eq!(ClockOffset::Behind(Duration::MAX).apply(now), now);
+    }
+
+    #[test]
+    fn test_fixed_clock_authenticator_ctime() {
+        let clock =
+            FixedClock(SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456));
+
+        let session_key = session_key();
+        let tgt = ticket(Name::service_krbtgt("EXAMPLE.COM"));
+
+        let request = KerberosRequest::build_tgs(
+            Name::service_krbtgt("EXAMPLE.COM"),
+            clock.now() + Duration::from_secs(3600),
+        )
+        .build(
+            &Name::principal("testuser", "EXAMPLE.COM"),
+            &tgt,
+            &session_key,
+            &clock,
+        )
+        .expect("Failed to build request");
+
+        let KerberosRequest::TGS(TicketGrantRequest { authenticator, .. }) = request else {
+            unreachable!();
+        };
+
+        let authenticator = session_key
+            .decrypt_data(&authenticator, 7)
+            .expect("Failed to decrypt authenticator");
+        let TaggedAuthenticator(authenticator) =
+            TaggedAuthenticator::from_der(&authenticator).expect("Failed to decode authenticator");
+
+        assert_eq!(
+            authenticator.ctime,
+            KerberosTime::from_unix_duration(Duration::from_secs(1_700_000_000)).unwrap()
+        );
+        assert_eq!(authenticator.cusec, 123_456);
+    }
+
+    #[test]
+    fn test_relative_lifetime() {
+        let clock = FixedClock(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+
+        let request = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            SystemTime::UNIX_EPOCH,
+        )
+        .lifetime(Duration::from_secs(10 * 3600), &clock)
+        .renewable_for(Duration::from_secs(7 * 86400), &clock)
+        .build(&clock)
+        .expect("Failed to build request");
+
+        let KrbKdcReq::AsReq(kdc_req) = request.try_into().expect("Failed to encode request")
+        else {
+            unreachable!();
+        };
+
+        assert_eq!(
+            kdc_req.req_body.till.to_system_time(),
+            clock.now() + Duration::from_secs(10 * 3600)
+        );
+        assert_eq!(
+            kdc_req.req_body.rtime.map(|t| t.to_system_time()),
+            Some(clock.now() + Duration::from_secs(7 * 86400))
+        );
+
+        // A postdated ticket's lifetime starts from its start time.
+        let from = clock.now() + Duration::from_secs(3600);
+        let request = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            SystemTime::UNIX_EPOCH,
+        )
+        .from(Some(from))
+        .lifetime(Duration::from_secs(10 * 3600), &clock)
+        .build(&clock)
+        .expect("Failed to build request");
+
+        let KerberosRequest::AS(request) = request else {
+            unreachable!();
+        };
+
+        assert_eq!(request.until, from + Duration::from_secs(10 * 3600));
+        assert_eq!(request.renew, None);
+    }
+}
@@ -0,0 +1,1013 @@
+use bytes::Buf;
+// use bytes::BufMut;
+use crate::proto::{decode_token, encode_token, KerberosReply, KerberosRequest};
+use bytes::BytesMut;
+use der::{Decode, Header, Reader, SliceReader};
+use futures::{SinkExt, StreamExt};
+use std::io::{self};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_util::codec::{Decoder, Encoder, Framed};
+use tracing::{debug, instrument};
+
+use crate::constants::{DEFAULT_IO_MAX_SIZE, DEFAULT_KDC_RETRIES, DEFAULT_KDC_TIMEOUT};
+#[cfg(feature = "dns")]
+use crate::discovery::{locate_kdcs, DnsResolver};
+use crate::error::KrbError;
+
+// The high bit of the record mark flags the last fragment of the record, and the low
+// 31 bits are the length of the fragment.
+const RECORD_MARK_LAST_FRAGMENT: u32 = 0x8000_0000;
+const RECORD_MARK_LEN: usize = 4;
+
+/// Read a single record marked record from `buf`, returning `None` if the record has
+/// not been fully received yet. Nothing is consumed from `buf` until the whole record
+/// is there, so a record mark split across reads is read again once it is complete.
+/// MIT krb5 never sets the last fragment flag, so a record without it ends once its
+/// fragments hold the whole DER message whose header starts the first fragment. Only
+/// if the first fragment doesn't start with a DER header is the end of what has been
+/// buffered taken as the end of the record. This leaves the next record in `buf` when
+/// a peer reuses the connection for another exchange.
+///
+/// The length of the record is checked against `max_size` as each record mark is read,
+/// so that a peer can't cause a large allocation, or cause us to buffer a record that
+/// we would reject anyway. The record marks of any fragments after the first count
+/// towards the limit, as a record of many tiny or empty fragments is buffered all the
+/// same. A record over the limit is discarded from `buf` with the error.
+fn read_record(buf: &mut BytesMut, max_size: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut fragments = Vec::with_capacity(1);
+    let mut record_len: usize = 0;
+    let mut offset: usize = 0;
+
+    loop {
+        let Some(record_mark) = buf.get(offset..offset + RECORD_MARK_LEN) else {
+            return Ok(None);
+        };
+
+        let mut record_mark_bytes = [0u8; RECORD_MARK_LEN];
+        record_mark_bytes.copy_from_slice(record_mark);
+        let record_mark = u32::from_be_bytes(record_mark_bytes);
+
+        let last_fragment = record_mark & RECORD_MARK_LAST_FRAGMENT != 0;
+        let fragment_len = usize::try_from(record_mark & !RECORD_MARK_LAST_FRAGMENT)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "fragment too large"))?;
+
+        let start = offset + RECORD_MARK_LEN;
+        let end = start
+            .checked_add(fragment_len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "fragment too large"))?;
+
+        // Everything buffered for the record but its first record mark.
+        if end - RECORD_MARK_LEN > max_size {
+            buf.clear();
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "request limit",
+            ));
+        }
+
+        // Never more than what is buffered, so this can't overflow.
+        record_len += fragment_len;
+
+        if buf.len() < end {
+            return Ok(None);
+        }
+
+        fragments.push(start..end);
+        offset = end;
+
+        if last_fragment {
+            break;
+        }
+
+        let message_len = fragments
+            .first()
+            .and_then(|first| der_message_len(&buf[first.clone()]));
+
+        let complete = match message_len {
+            Some(message_len) => record_len >= message_len,
+            None => offset == buf.len(),
+        };
+
+        if complete {
+            break;
+        }
+    }
+
+    let mut record = Vec::with_capacity(record_len);
+    for fragment in fragments {
+        record.extend_from_slice(&buf[fragment]);
+    }
+    buf.advance(offset);
+
+    Ok(Some(record))
+}
+
+// The length of the DER message that starts `bytes`, including its header, or `None` if
+// `bytes` doesn't start with a DER header.
+fn der_message_len(bytes: &[u8]) -> Option<usize> {
+    let mut reader = SliceReader::new(bytes).ok()?;
+    let header = Header::decode(&mut reader).ok()?;
+    (reader.position() + header.length)
+        .ok()
+        .and_then(|len| usize::try_from(len).ok())
+}
+
+/// Append `record` to `buf` as a single record fragment. A record longer than `max_size`
+/// is refused before anything is written, as the peer would reject it once it had read
+/// the record mark.
+fn write_record(buf: &mut BytesMut, record: &[u8], max_size: usize) -> io::Result<()> {
+    let record_len = u32::try_from(record.len())
+        .ok()
+        .filter(|_| record.len() <= max_size)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "encoded message of {} bytes exceeds the maximum record size of {} bytes",
+                    record.len(),
+                    max_size
+                ),
+            )
+        })?;
+
+    buf.reserve(RECORD_MARK_LEN + record.len());
+    buf.extend_from_slice(&record_len.to_be_bytes());
+    buf.extend_from_slice(record);
+
+    Ok(())
+}
+
+/// The codec for the KDC side of a TCP connection, which decodes the requests of
+/// clients and encodes the replies to them. This is the mirror of [KerberosTcpCodec],
+/// and uses the same record marking.
+pub struct KdcTcpCodec {
+    max_size: usize,
+}
+
+/// The codec for the client side of a TCP connection to a KDC, which encodes
+/// requests and decodes the replies to them. Each message is sent as a single record
+/// fragment, without the last fragment flag, as MIT krb5 does.
+pub struct KerberosTcpCodec {
+    max_size: usize,
+}
+
+impl Default for KerberosTcpCodec {
+    fn default() -> Self {
+        KerberosTcpCodec {
+            max_size: DEFAULT_IO_MAX_SIZE,
+        }
+    }
+}
+
+impl Decoder for KerberosTcpCodec {
+    type Item = KerberosReply;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(record) = read_record(buf, self.max_size)? else {
+            return Ok(None);
+        };
+
+        decode_token::<KerberosReply>(&record)
+            .map(Some)
+            .map_err(io::Error::from)
+    }
+}
+
+impl Encoder<KerberosRequest> for KerberosTcpCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: KerberosRequest, buf: &mut BytesMut) -> io::Result<()> {
+        let der_bytes = encode_token(msg)?;
+
+        /* RFC1831 section 10
+        *
+        * When RPC messages are passed on top of a byte stream transport
+        * protocol (like TCP), it is necessary to delimit one message from
+        * another in order to detect and possibly recover from protocol errors.
+        * This is called record marking (RM).  One RPC message fits into one RM
+        * record.
+
+        * A record is composed of one or more record fragments.  A record
+        * fragment is a four-byte header followed by 0 to (2**31) - 1 bytes of
+        * fragment data.  The bytes encode an unsigned binary number; as with
+        * XDR integers, the byte order is from highest to lowest.  The number
+        * encodes two values -- a boolean which indicates whether the fragment
+        * is the last fragment of the record (bit value 1 implies the fragment
+        * is the last fragment) and a 31-bit unsigned binary value which is the
+        * length in bytes of the fragment's data.  The boolean value is the
+        * highest-order bit of the header; the length is the 31 low-order bits.
+        * (Note that this record specification is NOT in XDR standard form!)
+        */
+
+        // Something is certainly wrong here with the xdr writer, as doing it by
+        // hand works. given how simple xdr is, maybe we just take this approach?
+
+        /*
+        // buf.resize(der_bytes.len() + 4, 0);
+        let mut w = XdrRecordWriter::new(buf.writer());
+        w.set_implicit_eor(true);
+        w.write_all(&der_bytes)
+        */
+
+        write_record(buf, &der_bytes, self.max_size)
+    }
+}
+
+impl Default for KdcTcpCodec {
+    fn default() -> Self {
+        KdcTcpCodec {
+            max_size: DEFAULT_IO_MAX_SIZE,
+        }
+    }
+}
+
+impl Decoder for KdcTcpCodec {
+    type Item = KerberosRequest;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(record) = read_record(buf, self.max_size)? else {
+            return Ok(None);
+        };
+
+        decode_token::<KerberosRequest>(&record)
+            .map(Some)
+            .map_err(io::Error::from)
+    }
+}
+
+impl Encoder<KerberosReply> for KdcTcpCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: KerberosReply, buf: &mut BytesMut) -> io::Result<()> {
+        let der_bytes = encode_token(msg)?;
+
+        // Something is certainly wrong here with the xdr writer, as doing it by
+        // hand works. given how simple xdr is, maybe we just take this approach?
+
+        /*
+        // buf.resize(der_bytes.len() + 4, 0);
+        let mut w = XdrRecordWriter::new(buf.writer());
+        w.set_implicit_eor(true);
+        w.write_all(&der_bytes)
+        */
+
+        write_record(buf, &der_bytes, self.max_size)
+    }
+}
+
+/// Sends requests to the KDCs of a realm over TCP, failing over to the next KDC when
+/// one can not be reached or does not reply in time.
+#[derive(Debug, Clone)]
+pub struct KdcTransport {
+    addresses: Vec<SocketAddr>,
+    timeout: Duration,
+    retries: usize,
+}
+
+impl KdcTransport {
+    /// A transport for the KDCs at `addresses`, which are tried in order.
+    pub fn new(addresses: Vec<SocketAddr>) -> Self {
+        KdcTransport {
+            addresses,
+            timeout: DEFAULT_KDC_TIMEOUT,
+            retries: DEFAULT_KDC_RETRIES,
+        }
+    }
+
+    /// A transport for the KDCs of `realm`, located from DNS with the system's resolver
+    /// configuration.
+    #[cfg(feature = "dns")]
+    pub async fn locate(realm: &str) -> Result<Self, KrbError> {
+        let resolver = DnsResolver::from_system_conf()?;
+        locate_kdcs(&resolver, realm).await.map(KdcTransport::new)
+    }
+
+    /// How long to wait for each KDC to accept the connection and reply.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many times to try the list of KDCs before giving up.
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Send `request` to the first KDC that replies. An error reply from a KDC is a
+    /// reply, and is returned rather than trying the next KDC.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn exchange(&self, request: KerberosRequest) -> Result<KerberosReply, KrbError> {
+        for attempt in 0..self.retries {
+            for address in &self.addresses {
+                match timeout(self.timeout, Self::exchange_with(address, request.clone())).await {
+                    Ok(Ok(reply)) => return Ok(reply),
+                    Ok(Err(err)) => debug!(%address, attempt, ?err, "KDC exchange failed"),
+                    Err(_) => debug!(%address, attempt, "KDC exchange timed out"),
+                }
+            }
+        }
+
+        Err(KrbError::NoKdcReachable)
+    }
+
+    async fn exchange_with(
+        address: &SocketAddr,
+        request: KerberosRequest,
+    ) -> io::Result<KerberosReply> {
+        let stream = TcpStream::connect(address).await?;
+        let mut krb_stream = Framed::new(stream, KerberosTcpCodec::default());
+
+        krb_stream.send(request).await?;
+
+        krb_stream.next().await.unwrap_or_else(|| {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed before reply",
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KerberosReply;
+    use bytes::BytesMut;
+    use der::Decode;
+    use futures::SinkExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_util::codec::{Decoder, Encoder, Framed};
+
+    use std::io;
+    use std::time::Duration;
+    #[cfg(feature = "integration")]
+    use std::time::SystemTime;
+
+    use super::{
+        read_record, KdcTcpCodec, KdcTransport, KerberosTcpCodec, RECORD_MARK_LAST_FRAGMENT,
+    };
+    use crate::asn1::constants::encryption_types::EncryptionType;
+    use crate::asn1::constants::errors::KrbErrorCode;
+    use crate::asn1::constants::PaDataType;
+    use crate::asn1::krb_kdc_req::KrbKdcReq;
+    use crate::clock::{Clock, SystemClock};
+    use crate::constants::DEFAULT_IO_MAX_SIZE;
+    use crate::error::KrbError;
+    #[cfg(feature = "integration")]
+    use crate::proto::{AuthenticationReply, DerivedKey, PreauthReply};
+    use crate::proto::{KerberosRequest, Name};
+    use futures::StreamExt;
+    #[cfg(feature = "integration")]
+    use tracing::trace;
+
+    #[cfg(feature = "integration")]
+    #[tokio::test]
+    async fn test_localhost_kdc_no_preauth() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let stream = TcpStream::connect("127.0.0.1:55000")
+            .await
+            .expect("Unable to connect to localhost:55000");
+
+        let mut krb_stream = Framed::new(stream, KerberosTcpCodec::default());
+
+        let now = SystemClock.now();
+        let as_req = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now + Duration::from_secs(3600),
+        )
+        .renew_until(Some(now + Duration::from_secs(86400 * 7)))
+        .build(&SystemClock)
+        .expect("Failed to build request");
+
+        // Write a request
+        krb_stream
+            .send(as_req)
+            .await
+            .expect("Failed to transmit request");
+
+        let response = krb_stream.next().await;
+
+        let cleartext = match response {
+            Some(Ok(KerberosReply::AS(AuthenticationReply {
+                name,
+                enc_part,
+                pa_data,
+                ticket,
+            }))) => {
+                let etype_info = pa_data
+                    .as_ref()
+                    .map(|pa_inner| pa_inner.etype_info2.as_slice());
+
+                let base_key = DerivedKey::from_encrypted_reply(
+                    &enc_part,
+                    etype_info,
+                    "EXAMPLE.COM",
+                    "testuser",
+                    "password",
+                )
+                .expect("Failed to derive base key");
+
+                enc_part
+                    .decrypt_enc_kdc_rep(&base_key)
+                    .expect("Failed to decrypt")
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    // The full PA-ENC-TIMESTAMP exchange, which needs a KDC listening on localhost.
+    #[cfg(feature = "integration")]
+    #[tokio::test]
+    async fn test_localhost_kdc_preauth() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let stream = TcpStream::connect("127.0.0.1:55000")
+            .await
+            .expect("Unable to connect to localhost:55000");
+
+        let mut krb_stream = Framed::new(stream, KerberosTcpCodec::default());
+
+        let now = SystemClock.now();
+
+        let as_req = KerberosRequest::build_as(
+            Name::principal("testuser_preauth", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now + Duration::from_secs(3600),
+        )
+        .renew_until(Some(now + Duration::from_secs(86400 * 7)))
+        .build(&SystemClock)
+        .expect("Failed to build request");
+
+        // Write a request
+        krb_stream
+            .send(as_req)
+            .await
+            .expect("Failed to transmit request");
+
+        let response = krb_stream.next().await;
+
+        trace!(?response);
+        assert!(response.is_some());
+        let response = response.unwrap();
+        assert!(response.is_ok());
+        let response = response.unwrap();
+
+        let (service, pa_data) = match response {
+            KerberosReply::PA(PreauthReply {
+                service,
+                pa_data,
+                stime,
+            }) => (service, pa_data),
+            _ => unreachable!(),
+        };
+
+        // The PA-ENC-TIMESTAMP method MUST be supported by
+        // clients, but whether it is enabled by default MAY be determined on
+        // a realm-by-realm basis.
+        // If the method is not used in the initial request and the error
+        // KDC_ERR_PREAUTH_REQUIRED is returned specifying PA-ENC-TIMESTAMP
+        // as an acceptable method, the client SHOULD retry the initial
+        // request using the PA-ENC-TIMESTAMP pre- authentication method.
+        //
+        // The ETYPE-INFO2 method MUST be supported; this method is used to
+        // communicate the set of supported encryption types, and
+        // corresponding salt and string to key parameters.
+
+        // Assert returned preauth data contains PA-ENC-TIMESTAMP and PA-ETYPE-INFO2
+        assert!(pa_data.enc_timestamp);
+
+        // Assert returned preauth data contains PA-ETYPE-INFO2
+        assert!(!pa_data.etype_info2.is_empty());
+
+        let now = SystemClock.now();
+        let seconds_since_epoch = now.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+
+        // Compute the pre-authentication, with the key for the strongest etype-info2 entry.
+        let (as_req, base_key) = KerberosRequest::build_as(
+            Name::principal("testuser_preauth", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now + Duration::from_secs(3600),
+        )
+        .renew_until(Some(now + Duration::from_secs(86400 * 7)))
+        .preauth_enc_ts_with_passphrase(&pa_data, seconds_since_epoch, "password")
+        .expect("Failed to derive user key");
+
+        let as_req = as_req.build(&SystemClock).expect("Unable to build as req");
+
+        /*
+        let pre_auth = pa_rep
+            .perform_enc_timestamp(
+                password,
+                "EXAMPLE.COM",
+                "testuser_preauth",
+                seconds_since_epoch,
+            )
+            .unwrap();
+
+        let as_req = KerberosRequest::build_asreq(
+            Name::principal("testuser_preauth", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            None,
+            now + Duration::from_secs(3600),
+            Some(now + Duration::from_secs(86400 * 7)),
+        )
+        .add_preauthentication(pre_auth)
+        .build();
+        */
+
+        // Now, because MIT KRB is *silly* we have to re-open the connection. Because apparently
+        // the MIT KRB TCP transport is just "lets pretend to be UDP with with TCP" instead of
+        // doing something sensible. I can only imagine that KKDCP also does similar ... sillyness.
+
+        let stream = TcpStream::connect("127.0.0.1:55000")
+            .await
+            .expect("Unable to connect to localhost:55000");
+
+        let mut krb_stream = Framed::new(stream, KerberosTcpCodec::default());
+
+        // Write a request
+        krb_stream
+            .send(as_req)
+            .await
+            .expect("Failed to transmit request");
+
+        let response = krb_stream.next().await.unwrap().unwrap();
+
+        trace!(?response);
+        let KerberosReply::AS(as_rep) = response else {
+            unreachable!();
+        };
+
+        // Prove the KDC accepted our timestamp by decrypting the reply with the same key.
+        let reply_part = as_rep
+            .decrypt(
+                &Name::principal("testuser_preauth", "EXAMPLE.COM"),
+                &base_key,
+            )
+            .expect("Failed to decrypt reply");
+        trace!(?reply_part);
+        assert_eq!(reply_part.server(), &Name::service_krbtgt("EXAMPLE.COM"));
+    }
+
+    // The AS and TGS exchanges chained by obtain_service_ticket. The localhost KDC has no
+    // service principals of its own, so ask it for a ticket to its ticket granting service.
+    #[cfg(feature = "integration")]
+    #[tokio::test]
+    async fn test_localhost_kdc_obtain_service_ticket() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let transport = KdcTransport::new(vec!["127.0.0.1:55000".parse().unwrap()]);
+        let service = Name::service_krbtgt("EXAMPLE.COM");
+
+        let (ticket, session_key) = crate::client::obtain_service_ticket(
+            &Name::principal("testuser_preauth", "EXAMPLE.COM"),
+            "password",
+            &service,
+            SystemClock.now() + Duration::from_secs(3600),
+            &SystemClock,
+            |_, request| transport.exchange(request),
+        )
+        .await
+        .expect("Failed to obtain service ticket");
+
+        trace!(?ticket, ?session_key);
+        assert_eq!(ticket.service(), &service);
+    }
+
+    #[test]
+    fn test_as_req_nonce_override() {
+        let now = SystemClock.now();
+        let as_req = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now + Duration::from_secs(3600),
+        )
+        .nonce(0x1234_5678)
+        .build(&SystemClock)
+        .expect("Failed to build request");
+
+        let mut buf = BytesMut::new();
+        KerberosTcpCodec::default()
+            .encode(as_req, &mut buf)
+            .expect("Failed to encode request");
+
+        let KrbKdcReq::AsReq(kdc_req) =
+            KrbKdcReq::from_der(&buf[4..]).expect("Failed to decode request")
+        else {
+            unreachable!();
+        };
+
+        assert_eq!(kdc_req.req_body.nonce, 0x1234_5678);
+    }
+
+    #[test]
+    fn test_encode_oversized_request() {
+        // A request padded past the size that the KDC would accept.
+        let as_req = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            SystemClock.now() + Duration::from_secs(3600),
+        )
+        .additional_padata(1000, vec![0; DEFAULT_IO_MAX_SIZE])
+        .build(&SystemClock)
+        .expect("Failed to build request");
+
+        let mut buf = BytesMut::new();
+        let err = KerberosTcpCodec::default()
+            .encode(as_req, &mut buf)
+            .expect_err("Oversized request was encoded");
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_decode_split_record_mark() {
+        let now = SystemClock.now();
+        let as_req = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now + Duration::from_secs(3600),
+        )
+        .nonce(0x1234_5678)
+        .build(&SystemClock)
+        .expect("Failed to build request");
+
+        let mut wire = BytesMut::new();
+        KerberosTcpCodec::default()
+            .encode(as_req, &mut wire)
+            .expect("Failed to encode request");
+
+        let mut codec = KdcTcpCodec::default();
+        let mut buf = BytesMut::new();
+
+        // The record mark arrives one byte at a time, and is left in the buffer.
+        for (i, byte) in wire[..4].iter().enumerate() {
+            buf.extend_from_slice(&[*byte]);
+            assert!(matches!(codec.decode(&mut buf), Ok(None)));
+            assert_eq!(buf.len(), i + 1);
+        }
+
+        let (body_start, body_end) = wire[4..].split_at(wire.len() / 2);
+        buf.extend_from_slice(body_start);
+        assert!(matches!(codec.decode(&mut buf), Ok(None)));
+        assert_eq!(buf.len(), 4 + body_start.len());
+
+        buf.extend_from_slice(body_end);
+        let Some(KerberosRequest::AS(auth_req)) =
+            codec.decode(&mut buf).expect("Failed to decode request")
+        else {
+            unreachable!();
+        };
+        assert_eq!(auth_req.nonce, 0x1234_5678);
+        assert!(buf.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_kdc_codec_framed_pair() {
+        let (client, server) = tokio::io::duplex(4096);
+        let mut client_stream = Framed::new(client, KerberosTcpCodec::default());
+        let mut kdc_stream = Framed::new(server, KdcTcpCodec::default());
+
+        let as_req = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            SystemClock.now() + Duration::from_secs(3600),
+        )
+        .nonce(0x1234_5678)
+        .build(&SystemClock)
+        .expect("Failed to build request");
+
+        client_stream
+            .send(as_req)
+            .await
+            .expect("Failed to transmit request");
+
+        let Some(Ok(KerberosRequest::AS(auth_req))) = kdc_stream.next().await else {
+            unreachable!();
+        };
+        assert_eq!(auth_req.nonce, 0x1234_5678);
+
+        kdc_stream
+            .send(KerberosReply::preauth_required(
+                auth_req.service_name().clone(),
+                SystemClock.now(),
+                "EXAMPLE.COMtestuser",
+                &[EncryptionType::AES256_CTS_HMAC_SHA1_96],
+                b"cookie".to_vec(),
+            ))
+            .await
+            .expect("Failed to transmit reply");
+
+        let Some(Ok(KerberosReply::PA(reply))) = client_stream.next().await else {
+            unreachable!();
+        };
+        assert_eq!(reply.service, Name::service_krbtgt("EXAMPLE.COM"));
+        assert_eq!(
+            reply.pa_data.pa_fx_cookie.as_deref(),
+            Some(b"cookie".as_slice())
+        );
+    }
+
+    #[test]
+    fn test_decode_back_to_back_records() {
+        let mut wire = BytesMut::new();
+        let mut client_codec = KerberosTcpCodec::default();
+        for nonce in [1, 2] {
+            let as_req = KerberosRequest::build_as(
+                Name::principal("testuser", "EXAMPLE.COM"),
+                Name::service_krbtgt("EXAMPLE.COM"),
+                SystemClock.now() + Duration::from_secs(3600),
+            )
+            .nonce(nonce)
+            .build(&SystemClock)
+            .expect("Failed to build request");
+
+            client_codec
+                .encode(as_req, &mut wire)
+                .expect("Failed to encode request");
+        }
+
+        // Neither record has the last fragment flag, so each ends where its DER
+        // message does.
+        let mut codec = KdcTcpCodec::default();
+        for nonce in [1, 2] {
+            let Some(KerberosRequest::AS(auth_req)) =
+                codec.decode(&mut wire).expect("Failed to decode request")
+            else {
+                unreachable!();
+            };
+            assert_eq!(auth_req.nonce, nonce);
+        }
+        assert!(wire.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reuse_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind listener");
+        let address = listener.local_addr().expect("Failed to get local address");
+
+        let kdc = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.expect("Failed to accept");
+            let mut kdc_stream = Framed::new(socket, KdcTcpCodec::default());
+
+            while let Some(request) = kdc_stream.next().await {
+                let Ok(KerberosRequest::AS(auth_req)) = request else {
+                    unreachable!();
+                };
+
+                kdc_stream
+                    .send(KerberosReply::preauth_required(
+                        auth_req.service_name().clone(),
+                        SystemClock.now(),
+                        "EXAMPLE.COMtestuser",
+                        &[EncryptionType::AES256_CTS_HMAC_SHA1_96],
+                        auth_req.nonce.to_be_bytes().to_vec(),
+                    ))
+                    .await
+                    .expect("Failed to transmit reply");
+            }
+        });
+
+        let stream = TcpStream::connect(address)
+            .await
+            .expect("Failed to connect");
+        let mut krb_stream = Framed::new(stream, KerberosTcpCodec::default());
+
+        for nonce in [1u32, 2] {
+            let as_req = KerberosRequest::build_as(
+                Name::principal("testuser", "EXAMPLE.COM"),
+                Name::service_krbtgt("EXAMPLE.COM"),
+                SystemClock.now() + Duration::from_secs(3600),
+            )
+            .nonce(nonce)
+            .build(&SystemClock)
+            .expect("Failed to build request");
+
+            krb_stream
+                .send(as_req)
+                .await
+                .expect("Failed to transmit request");
+
+            let Some(Ok(KerberosReply::PA(reply))) = krb_stream.next().await else {
+                unreachable!();
+            };
+            assert_eq!(
+                reply.pa_data.pa_fx_cookie.as_deref(),
+                Some(nonce.to_be_bytes().as_slice())
+            );
+        }
+
+        drop(krb_stream);
+        kdc.await.expect("Mock KDC failed");
+    }
+
+    #[tokio::test]
+    async fn test_kdc_transport_failover() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        // Nothing listens on this address once the listener is dropped.
+        let unreachable = TcpListener::bind("127.0.0.1:0")
+            .await
+            .and_then(|listener| listener.local_addr())
+            .expect("Failed to bind listener");
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind listener");
+        let reachable = listener.local_addr().expect("Failed to get local address");
+
+        let kdc = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.expect("Failed to accept");
+            let mut kdc_stream = Framed::new(socket, KdcTcpCodec::default());
+
+            let request = kdc_stream.next().await.unwrap().unwrap();
+            assert!(matches!(request, KerberosRequest::AS(_)));
+
+            kdc_stream
+                .send(KerberosReply::error_no_etypes(
+                    Name::service_krbtgt("EXAMPLE.COM"),
+                    SystemClock.now(),
+                ))
+                .await
+                .expect("Failed to transmit reply");
+        });
+
+        let as_req = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            SystemClock.now() + Duration::from_secs(3600),
+        )
+        .build(&SystemClock)
+        .expect("Failed to build request");
+
+        let transport = KdcTransport::new(vec![unreachable, reachable])
+            .timeout(Duration::from_secs(5))
+            .retries(1);
+
+        let reply = transport
+            .exchange(as_req.clone())
+            .await
+            .expect("Failed to fail over to the reachable KDC");
+        kdc.await.expect("Mock KDC failed");
+
+        let KerberosReply::ERR(err_rep) = reply else {
+            unreachable!();
+        };
+        assert_eq!(err_rep.code(), KrbErrorCode::KdcErrEtypeNosupp);
+
+        // Once every KDC has been tried the transport gives up.
+        let transport = KdcTransport::new(vec![unreachable]).retries(2);
+        assert!(matches!(
+            transport.exchange(as_req).await,
+            Err(KrbError::NoKdcReachable)
+        ));
+    }
+
+    #[test]
+    fn test_read_record_max_size() {
+        let max_size = 16;
+
+        // A record of exactly max_size is accepted, and we wait for the rest of it.
+        let mut buf = BytesMut::from(&(RECORD_MARK_LAST_FRAGMENT | 16).to_be_bytes()[..]);
+        assert!(matches!(read_record(&mut buf, max_size), Ok(None)));
+
+        buf.extend_from_slice(&[0x42; 16]);
+        let record = read_record(&mut buf, max_size)
+            .expect("Failed to read record")
+            .expect("Record was incomplete");
+        assert_eq!(record, [0x42; 16]);
+        assert!(buf.is_empty());
+
+        // One byte more is rejected from the record mark alone.
+        let mut buf = BytesMut::from(&17u32.to_be_bytes()[..]);
+        let err = read_record(&mut buf, max_size).expect_err("Oversized record was accepted");
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+
+        // As is a record whose fragments sum to more than max_size.
+        let mut buf = BytesMut::from(&8u32.to_be_bytes()[..]);
+        buf.extend_from_slice(&[0x42; 8]);
+        buf.extend_from_slice(&(RECORD_MARK_LAST_FRAGMENT | 9).to_be_bytes());
+        let err = read_record(&mut buf, max_size).expect_err("Oversized record was accepted");
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_read_record_many_fragments() {
+        let max_size = 16;
+
+        // Three fragments of a byte each, and their record marks, fit.
+        let mut buf = BytesMut::new();
+        for _ in 0..3 {
+            buf.extend_from_slice(&1u32.to_be_bytes());
+            buf.extend_from_slice(&[0x42]);
+        }
+        buf.extend_from_slice(&(RECORD_MARK_LAST_FRAGMENT | 1).to_be_bytes());
+        buf.extend_from_slice(&[0x42]);
+        let record = read_record(&mut buf, max_size)
+            .expect("Failed to read record")
+            .expect("Record was incomplete");
+        assert_eq!(record, [0x42; 4]);
+
+        // Five bytes are under the limit, but not once the record marks of their
+        // fragments are buffered too. The start of a further record mark keeps the
+        // record from ending where the buffer does.
+        let mut buf = BytesMut::new();
+        for _ in 0..5 {
+            buf.extend_from_slice(&1u32.to_be_bytes());
+            buf.extend_from_slice(&[0x42]);
+        }
+        buf.extend_from_slice(&[0, 0]);
+        let err = read_record(&mut buf, max_size).expect_err("Oversized record was accepted");
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(buf.is_empty());
+
+        // Empty fragments add nothing to the record, but are still buffered.
+        let mut buf = BytesMut::new();
+        for _ in 0..6 {
+            buf.extend_from_slice(&0u32.to_be_bytes());
+        }
+        buf.extend_from_slice(&[0, 0]);
+        let err = read_record(&mut buf, max_size).expect_err("Oversized record was accepted");
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_read_record_last_fragment_flag() {
+        // The high bit flags the last fragment, it is not part of the length.
+        let mut buf = BytesMut::from(&(RECORD_MARK_LAST_FRAGMENT | 4).to_be_bytes()[..]);
+        buf.extend_from_slice(&[1, 2, 3, 4]);
+        // The start of the next record is left in the buffer.
+        buf.extend_from_slice(&[0, 0]);
+
+        let record = read_record(&mut buf, 16)
+            .expect("Failed to read record")
+            .expect("Record was incomplete");
+        assert_eq!(record, [1, 2, 3, 4]);
+        assert_eq!(&buf[..], &[0, 0]);
+
+        // A record split over two fragments.
+        let mut buf = BytesMut::from(&2u32.to_be_bytes()[..]);
+        buf.extend_from_slice(&[1, 2]);
+        buf.extend_from_slice(&(RECORD_MARK_LAST_FRAGMENT | 2).to_be_bytes());
+        buf.extend_from_slice(&[3, 4]);
+
+        let record = read_record(&mut buf, 16)
+            .expect("Failed to read record")
+            .expect("Record was incomplete");
+        assert_eq!(record, [1, 2, 3, 4]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_decode_unflagged_fragments() {
+        let now = SystemClock.now();
+        let as_req = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now + Duration::from_secs(3600),
+        )
+        .nonce(0x1234_5678)
+        .build(&SystemClock)
+        .expect("Failed to build request");
+
+        let mut wire = BytesMut::new();
+        KerberosTcpCodec::default()
+            .encode(as_req, &mut wire)
+            .expect("Failed to encode request");
+
+        // The request is sent as two fragments, neither with the last fragment flag.
+        let (first, second) = wire[4..].split_at((wire.len() - 4) / 2);
+
+        let mut codec = KdcTcpCodec::default();
+        let mut buf = BytesMut::new();
+
+        // The first fragment holds only part of the DER message it starts, so the record
+        // doesn't end with it, even though nothing follows it yet.
+        buf.extend_from_slice(&(first.len() as u32).to_be_bytes());
+        buf.extend_from_slice(first);
+        assert!(matches!(codec.decode(&mut buf), Ok(None)));
+        assert_eq!(buf.len(), 4 + first.len());
+
+        buf.extend_from_slice(&(second.len() as u32).to_be_bytes());
+        buf.extend_from_slice(second);
+        let Some(KerberosRequest::AS(auth_req)) =
+            codec.decode(&mut buf).expect("Failed to decode request")
+        else {
+            unreachable!();
+        };
+        assert_eq!(auth_req.nonce, 0x1234_5678);
+        assert!(buf.is_empty());
+    }
+}
@@ -1,12 +1,36 @@
 pub const DEFAULT_IO_MAX_SIZE: usize = 32 * 1024;
 
+// The number of cross realm referrals a client will follow before giving up.
+pub const DEFAULT_MAX_REFERRALS: usize = 5;
+
+// How long a client waits for each KDC to reply, and how many times it tries the list
+// of KDCs before giving up.
+#[cfg(feature = "codec")]
+pub const DEFAULT_KDC_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+#[cfg(feature = "codec")]
+pub const DEFAULT_KDC_RETRIES: usize = 3;
+
+// How far a preauthentication or authenticator timestamp may be from our own time. RFC
+// 4120 section 1.6 suggests five minutes.
+pub const DEFAULT_MAX_SKEW: std::time::Duration = std::time::Duration::from_secs(300);
+
+// The most replies a KDC keeps in its reply cache, so that a flood of requests with
+// distinct nonces can't grow it without bound.
+pub const DEFAULT_REPLY_CACHE_MAX_ENTRIES: usize = 10_000;
+
 pub const AES_BLOCK_SIZE: usize = 16;
 pub const AES_256_KEY_LEN: usize = 32;
+#[cfg(feature = "des3")]
+pub const DES3_KEY_LEN: usize = 24;
 pub const SHA1_HMAC_LEN: usize = 12;
 
 pub const PKBDF2_SHA1_ITER: u32 = 0x8000;
 pub const RFC_PKBDF2_SHA1_ITER: u32 = 0x1000;
 
+// The fewest PBKDF2 iterations a KDC may ask a client to derive its key with. A KDC that
+// asks for fewer may be attempting a downgrade, so this is the RFC 3962 default.
+pub const DEFAULT_MIN_PBKDF2_SHA1_ITER: u32 = RFC_PKBDF2_SHA1_ITER;
+
 pub const IV_ZERO: [u8; AES_BLOCK_SIZE] = [0u8; AES_BLOCK_SIZE];
 
 // In the DK phase of key derivation, a pub constant is used with the nfold algorithm. Since this
@@ -1,3 +1,4 @@
+use crate::asn1::constants::encryption_types::EncryptionType;
 use crate::constants::*;
 use crate::error::KrbError;
 
@@ -9,6 +10,23 @@ use pbkdf2::pbkdf2_hmac;
 use rand::{thread_rng, Rng};
 use sha1::Sha1;
 
+#[cfg(feature = "des3")]
+mod des3;
+
+#[cfg(feature = "des3")]
+pub(crate) use des3::{
+    checksum_hmac_sha1_des3, decrypt_des3_cbc_sha1, derive_key_des3_cbc_sha1, encrypt_des3_cbc_sha1,
+};
+
+/// The encryption types that this build is able to use, in order of preference.
+pub fn supported_etypes() -> &'static [EncryptionType] {
+    &[
+        EncryptionType::AES256_CTS_HMAC_SHA1_96,
+        #[cfg(feature = "des3")]
+        EncryptionType::DES3_CBC_SHA1_KD,
+    ]
+}
+
 type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
 type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
 
@@ -28,8 +46,7 @@ pub(crate) fn derive_key_aes256_cts_hmac_sha1_96(
 ) -> Result<[u8; AES_256_KEY_LEN], KrbError> {
     // Salt is the concatenation of realm + cname.
     // NOTE: Salt may come in AS-REP padata ETYPE-INFO2
-    let mut buf = [0u8; AES_256_KEY_LEN];
-    pbkdf2_hmac::<Sha1>(passphrase, salt, iter_count, &mut buf);
+    let buf = tkey_aes256_cts_hmac_sha1_96(passphrase, salt, iter_count);
 
     // It's unclear what this achieves cryptographically ...
     let mut dk_buf = [0u8; AES_256_KEY_LEN];
@@ -38,6 +55,28 @@ pub(crate) fn derive_key_aes256_cts_hmac_sha1_96(
     Ok(dk_buf)
 }
 
+/// The PBKDF2 output (tkey) that the aes256-cts-hmac-sha1-96 key is derived from, before
+/// the final DK step of RFC 3962 4. This is only exposed for comparing each step of the
+/// derivation against another implementation.
+#[cfg(feature = "test-vectors")]
+pub fn pbkdf2_aes256_cts_hmac_sha1_96(
+    passphrase: &[u8],
+    salt: &[u8],
+    iter_count: u32,
+) -> [u8; AES_256_KEY_LEN] {
+    tkey_aes256_cts_hmac_sha1_96(passphrase, salt, iter_count)
+}
+
+fn tkey_aes256_cts_hmac_sha1_96(
+    passphrase: &[u8],
+    salt: &[u8],
+    iter_count: u32,
+) -> [u8; AES_256_KEY_LEN] {
+    let mut buf = [0u8; AES_256_KEY_LEN];
+    pbkdf2_hmac::<Sha1>(passphrase, salt, iter_count, &mut buf);
+    buf
+}
+
 fn dk_aes_256(out_buf: &mut [u8; AES_256_KEY_LEN], buf: &[u8; AES_256_KEY_LEN]) {
     let (lower, upper) = out_buf.split_at_mut(AES_BLOCK_SIZE);
     debug_assert!(lower.len() == AES_BLOCK_SIZE);
@@ -96,25 +135,49 @@ pub(crate) fn decrypt_aes256_cts_hmac_sha1_96(
     }
 }
 
+/// Compare two secrets without branching on their content, so that the time taken
+/// doesn't reveal how many leading bytes match. Only the lengths, which are not secret,
+/// may end the comparison early.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
 /// Given the [base key](derive_key_aes256_cts_hmac_sha1_96) and the key_usage value
 /// encrypt and authenticate the provided plaintext.
 pub(crate) fn encrypt_aes256_cts_hmac_sha1_96(
     key: &[u8; AES_256_KEY_LEN],
     plaintext: &[u8],
     key_usage: i32,
+) -> Result<Vec<u8>, KrbError> {
+    let mut confuzzler = [0u8; AES_BLOCK_SIZE];
+    thread_rng().fill(&mut confuzzler);
+
+    encrypt_aes256_cts_hmac_sha1_96_with_confounder(key, &confuzzler, plaintext, key_usage)
+}
+
+/// As [encrypt_aes256_cts_hmac_sha1_96], but with the confounder supplied by the
+/// caller so that the ciphertext is deterministic. Outside of tests and [self_test] the
+/// confounder must be random, or equal plaintexts will encrypt to equal ciphertexts.
+fn encrypt_aes256_cts_hmac_sha1_96_with_confounder(
+    key: &[u8; AES_256_KEY_LEN],
+    confuzzler: &[u8; AES_BLOCK_SIZE],
+    plaintext: &[u8],
+    key_usage: i32,
 ) -> Result<Vec<u8>, KrbError> {
     if plaintext.is_empty() {
         return Err(KrbError::PlaintextEmpty);
     };
     let (ki, ke) = dk_ki_ke_aes_256(key, key_usage);
 
-    let mut confuzzler = [0u8; AES_BLOCK_SIZE];
-    thread_rng().fill(&mut confuzzler);
-
     // let mut mac = HmacSha1::new(ki.into());
     let mut mac = HmacSha1::new_from_slice(&ki).map_err(|_| KrbError::InvalidHmacSha1Key)?;
 
-    mac.update(&confuzzler);
+    mac.update(confuzzler);
     mac.update(&plaintext);
 
     let mut buf = [0u8; 20];
@@ -127,16 +190,46 @@ pub(crate) fn encrypt_aes256_cts_hmac_sha1_96(
     ciphertext.resize(ciphertext.capacity(), 0);
     let (cipher, hmac) = ciphertext.split_at_mut(AES_BLOCK_SIZE + plaintext.len());
 
-    encrypt_aes256_cts(&ke, &confuzzler, plaintext, cipher)?;
+    encrypt_aes256_cts(&ke, confuzzler, plaintext, cipher)?;
     hmac.copy_from_slice(&my_hmac);
 
     Ok(ciphertext)
 }
 
+/// Check the crypto backend against known answers before it is trusted with real keys.
+/// This runs the RFC 3962 aes256-cts-hmac-sha1-96 string-to-key vector, and encrypts
+/// and decrypts a message with a fixed confounder, failing with
+/// [KrbError::CryptoSelfTestFailed] if any output differs from the expected value.
+pub fn self_test() -> Result<(), KrbError> {
+    // https://www.rfc-editor.org/rfc/rfc3962#appendix-B
+    let key = derive_key_aes256_cts_hmac_sha1_96(b"password", b"ATHENA.MIT.EDUraeburn", 1200)?;
+    if hex::encode(key) != "55a6ac740ad17b4846941051e1e8b0a7548d93b0ab30a8bc3ff16280382b8c2a" {
+        return Err(KrbError::CryptoSelfTestFailed);
+    }
+
+    let key = derive_key_aes256_cts_hmac_sha1_96(b"admin", b"admin1234", RFC_PKBDF2_SHA1_ITER)?;
+    let plaintext = b"3ahwzt9MG9WVEuBVC5j0oi6sI";
+    let ciphertext = "29737f3db6bcdfe9990fb2136d3efe6f2100e6c4ac75824299d8d3702f5a2e31c7a336747dfd734a1ea0165ebb27c0d7ce9b5aec7a";
+
+    let encrypted =
+        encrypt_aes256_cts_hmac_sha1_96_with_confounder(&key, &[0; AES_BLOCK_SIZE], plaintext, 1)?;
+    if hex::encode(&encrypted) != ciphertext {
+        return Err(KrbError::CryptoSelfTestFailed);
+    }
+
+    let decrypted = decrypt_aes256_cts_hmac_sha1_96(&key, &encrypted, 1)?;
+    if decrypted != plaintext {
+        return Err(KrbError::CryptoSelfTestFailed);
+    }
+
+    Ok(())
+}
+
 fn dk_ki_ke_aes_256(
     buf: &[u8; AES_256_KEY_LEN],
     key_usage: i32,
 ) -> ([u8; AES_256_KEY_LEN], [u8; AES_256_KEY_LEN]) {
+    let (ki_nfold, ke_nfold);
     let (ki_const, ke_const) = match key_usage {
         0 => (&N_FOLD_KEY_USAGE_KI_00, &N_FOLD_KEY_USAGE_KE_00),
         1 => (&N_FOLD_KEY_USAGE_KI_01, &N_FOLD_KEY_USAGE_KE_01),
@@ -170,7 +263,12 @@ fn dk_ki_ke_aes_256(
         29 => (&N_FOLD_KEY_USAGE_KI_29, &N_FOLD_KEY_USAGE_KE_29),
         30 => (&N_FOLD_KEY_USAGE_KI_30, &N_FOLD_KEY_USAGE_KE_30),
         31 => (&N_FOLD_KEY_USAGE_KI_31, &N_FOLD_KEY_USAGE_KE_31),
-        _ => todo!(),
+        // Beyond the precomputed table the constants are n-folded on demand.
+        _ => {
+            ki_nfold = nfold_key_usage(key_usage, 0x55);
+            ke_nfold = nfold_key_usage(key_usage, 0xaa);
+            (&ki_nfold, &ke_nfold)
+        }
     };
 
     let mut ki = [0u8; AES_256_KEY_LEN];
@@ -191,6 +289,103 @@ fn dk_ki_ke_aes_256(
     (ki, ke)
 }
 
+/// Given the [base key](derive_key_aes256_cts_hmac_sha1_96) and the key_usage value
+/// compute the HMAC-SHA1-96-AES256 (cksumtype 16) checksum of the provided data.
+pub(crate) fn checksum_hmac_sha1_96_aes256(
+    key: &[u8; AES_256_KEY_LEN],
+    data: &[u8],
+    key_usage: i32,
+) -> Result<Vec<u8>, KrbError> {
+    let kc = dk_kc_aes_256(key, key_usage);
+
+    let mut mac = HmacSha1::new_from_slice(&kc).map_err(|_| KrbError::InvalidHmacSha1Key)?;
+    mac.update(data);
+
+    let mut buf = [0u8; 20];
+    mac.finalize_into((&mut buf).into());
+
+    // Truncate to 96 bits.
+    Ok(buf[0..SHA1_HMAC_LEN].to_vec())
+}
+
+fn dk_kc_aes_256(buf: &[u8; AES_256_KEY_LEN], key_usage: i32) -> [u8; AES_256_KEY_LEN] {
+    // Unlike Ki/Ke we don't have a precomputed table for the checksum constants, so
+    // they are n-folded on demand.
+    let kc_const = nfold_key_usage(key_usage, 0x99);
+
+    let mut kc = [0u8; AES_256_KEY_LEN];
+    let (lower, upper) = kc.split_at_mut(AES_BLOCK_SIZE);
+    debug_assert!(lower.len() == AES_BLOCK_SIZE);
+    debug_assert!(upper.len() == AES_BLOCK_SIZE);
+    dk_encrypt_aes_256_cbc(buf.into(), (&kc_const).into(), lower.into());
+    dk_encrypt_aes_256_cbc(buf.into(), (&*lower).into(), upper.into());
+
+    kc
+}
+
+// The constant of RFC 3961 5.3 that a key is derived with for `key_usage`, where the
+// suffix selects Kc (0x99), Ke (0xaa) or Ki (0x55).
+fn nfold_key_usage(key_usage: i32, suffix: u8) -> [u8; AES_BLOCK_SIZE] {
+    let mut constant = [0u8; 5];
+    constant[..4].copy_from_slice(&key_usage.to_be_bytes());
+    constant[4] = suffix;
+
+    let mut out = [0u8; AES_BLOCK_SIZE];
+    nfold(&constant, &mut out);
+    out
+}
+
+/// The n-fold operation from RFC3961 section 5.1. This "stretches" or "shrinks" the
+/// input to the length of the output buffer, rotating by 13 bits on each repetition
+/// and combining with ones-complement addition.
+fn nfold(input: &[u8], out: &mut [u8]) {
+    let in_len = input.len();
+    let out_len = out.len();
+
+    if in_len == 0 || out_len == 0 {
+        return;
+    }
+
+    // Find the lcm of the lengths, we need to repeat the input this many bytes.
+    let (mut a, mut b) = (out_len, in_len);
+    while b != 0 {
+        let c = b;
+        b = a % b;
+        a = c;
+    }
+    let lcm = out_len * in_len / a;
+
+    out.fill(0);
+
+    let in_bits = in_len << 3;
+    let mut byte: usize = 0;
+
+    for i in (0..lcm).rev() {
+        // Which bit of the input is the msb of this output byte, after the
+        // 13 bit rotations are accounted for.
+        let msbit =
+            ((in_bits - 1) + ((in_bits + 13) * (i / in_len)) + ((in_len - (i % in_len)) << 3))
+                % in_bits;
+
+        let hi = input[((in_len - 1) - (msbit >> 3)) % in_len] as usize;
+        let lo = input[(in_len - (msbit >> 3)) % in_len] as usize;
+
+        byte += (((hi << 8) | lo) >> ((msbit & 7) + 1)) & 0xff;
+        byte += out[i % out_len] as usize;
+        out[i % out_len] = (byte & 0xff) as u8;
+        byte >>= 8;
+    }
+
+    // Any remaining carry is wrapped around (ones-complement addition).
+    if byte != 0 {
+        for o in out.iter_mut().rev() {
+            byte += *o as usize;
+            *o = (byte & 0xff) as u8;
+            byte >>= 8;
+        }
+    }
+}
+
 fn encrypt_aes256_cts(
     key: &[u8; AES_256_KEY_LEN],
     confuzzler: &[u8],
@@ -454,6 +649,77 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_string_to_key_rfc3962_appendix_b() {
+        // The aes256-cts-hmac-sha1-96 vectors, with the PBKDF2 output (tkey) and the
+        // final key.
+        let g_clef = String::from('\u{1D11E}');
+        let vectors: [(&[u8], &[u8], u32, &str, &str); 7] = [
+            (
+                b"password",
+                b"ATHENA.MIT.EDUraeburn",
+                1,
+                "cdedb5281bb2f801565a1122b25635150ad1f7a04bb9f3a333ecc0e2e1f70837",
+                "fe697b52bc0d3ce14432ba036a92e65bbb52280990a2fa27883998d72af30161",
+            ),
+            (
+                b"password",
+                b"ATHENA.MIT.EDUraeburn",
+                2,
+                "01dbee7f4a9e243e988b62c73cda935da05378b93244ec8f48a99e61ad799d86",
+                "a2e16d16b36069c135d5e9d2e25f896102685618b95914b467c67622225824ff",
+            ),
+            (
+                b"password",
+                b"ATHENA.MIT.EDUraeburn",
+                1200,
+                "5c08eb61fdf71e4e4ec3cf6ba1f5512ba7e52ddbc5e5142f708a31e2e62b1e13",
+                "55a6ac740ad17b4846941051e1e8b0a7548d93b0ab30a8bc3ff16280382b8c2a",
+            ),
+            (
+                b"password",
+                &[0x12, 0x34, 0x56, 0x78, 0x78, 0x56, 0x34, 0x12],
+                5,
+                "d1daa78615f287e6a1c8b120d7062a493f98d203e6be49a6adf4fa574b6e64ee",
+                "97a4e786be20d81a382d5ebc96d5909cabcdadc87ca48f574504159f16c36e31",
+            ),
+            (
+                &[b'X'; 64],
+                b"pass phrase equals block size",
+                1200,
+                "139c30c0966bc32ba55fdbf212530ac9c5ec59f1a452f5cc9ad940fea0598ed1",
+                "89adee3608db8bc71f1bfbfe459486b05618b70cbae22092534e56c553ba4b34",
+            ),
+            (
+                &[b'X'; 65],
+                b"pass phrase exceeds block size",
+                1200,
+                "9ccad6d468770cd51b10e6a68721be611a8b4d282601db3b36be9246915ec82a",
+                "d78c5c9cb872a8c9dad4697f0bb5b2d21496c82beb2caeda2112fceea057401b",
+            ),
+            (
+                g_clef.as_bytes(),
+                b"EXAMPLE.COMpianist",
+                50,
+                "6b9cf26d45455a43a5b8bb276a403b39e7fe37a0c41e02c281ff3069e1e94f52",
+                "4b6d9839f84406df1f09cc166db4b83c571848b784a3d6bdc346589a3e393f9e",
+            ),
+        ];
+
+        for (passphrase, salt, iter_count, tkey, key) in vectors {
+            assert_eq!(
+                hex::encode(tkey_aes256_cts_hmac_sha1_96(passphrase, salt, iter_count)),
+                tkey
+            );
+            assert_eq!(
+                hex::encode(
+                    derive_key_aes256_cts_hmac_sha1_96(passphrase, salt, iter_count).unwrap()
+                ),
+                key
+            );
+        }
+    }
+
     #[test]
     fn test_aes256_cts_hmac_sha1_96_decrypt_1() {
         let out_key = derive_key_aes256_cts_hmac_sha1_96(
@@ -483,6 +749,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_aes256_cts_hmac_sha1_96_encrypt_fixed_confounder() {
+        let out_key = derive_key_aes256_cts_hmac_sha1_96(
+            "admin".as_bytes(),
+            "admin1234".as_bytes(),
+            RFC_PKBDF2_SHA1_ITER,
+        )
+        .unwrap();
+
+        // The vector from test_aes256_cts_hmac_sha1_96_decrypt_1 was encrypted with an
+        // all zero confounder.
+        let confounder = [0u8; AES_BLOCK_SIZE];
+
+        let plaintext = [
+            0x33, 0x61, 0x68, 0x77, 0x7a, 0x74, 0x39, 0x4d, 0x47, 0x39, 0x57, 0x56, 0x45, 0x75,
+            0x42, 0x56, 0x43, 0x35, 0x6a, 0x30, 0x6f, 0x69, 0x36, 0x73, 0x49,
+        ];
+
+        let key_usage = 1;
+
+        let data = encrypt_aes256_cts_hmac_sha1_96_with_confounder(
+            &out_key,
+            &confounder,
+            &plaintext,
+            key_usage,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                0x29, 0x73, 0x7f, 0x3d, 0xb6, 0xbc, 0xdf, 0xe9, 0x99, 0x0f, 0xb2, 0x13, 0x6d, 0x3e,
+                0xfe, 0x6f, 0x21, 0x00, 0xe6, 0xc4, 0xac, 0x75, 0x82, 0x42, 0x99, 0xd8, 0xd3, 0x70,
+                0x2f, 0x5a, 0x2e, 0x31, 0xc7, 0xa3, 0x36, 0x74, 0x7d, 0xfd, 0x73, 0x4a, 0x1e, 0xa0,
+                0x16, 0x5e, 0xbb, 0x27, 0xc0, 0xd7, 0xce, 0x9b, 0x5a, 0xec, 0x7a,
+            ],
+            data
+        );
+    }
+
     #[test]
     fn test_aes256_cts_hmac_sha1_96_decrypt_2() {
         let out_key = derive_key_aes256_cts_hmac_sha1_96(
@@ -595,6 +900,54 @@ mod tests {
         assert_eq!(data, input_data);
     }
 
+    #[test]
+    fn test_nfold_rfc3961_vectors() {
+        // https://www.rfc-editor.org/rfc/rfc3961#appendix-A.1
+        let vectors: [(&str, usize, &str); 6] = [
+            ("012345", 8, "be072631276b1955"),
+            ("password", 7, "78a07b6caf85fa"),
+            ("Rough Consensus, and Running Code", 8, "bb6ed30870b7f0e0"),
+            ("password", 21, "59e4a8ca7c0385c3c37b3f6d2000247cb6e6bd5b3e"),
+            ("Q", 21, "518a54a215a8452a518a54a215a8452a518a54a215"),
+            ("kerberos", 16, "6b65726265726f737b9b5b2b93132b93"),
+        ];
+
+        for (input, out_len, expect) in vectors {
+            let mut out = vec![0u8; out_len];
+            nfold(input.as_bytes(), &mut out);
+            assert_eq!(hex::encode(&out), expect);
+        }
+
+        let mut out = [0u8; 16];
+        nfold(b"kerberos", &mut out);
+        assert_eq!(out, N_FOLD_KERBEROS_16);
+    }
+
+    #[test]
+    fn test_nfold_key_usage_table() {
+        // Assert our runtime nfold agrees with the precomputed table.
+        let mut out = [0u8; 16];
+        nfold(&[0, 0, 0, 3, 0x55], &mut out);
+        assert_eq!(out, N_FOLD_KEY_USAGE_KI_03);
+        nfold(&[0, 0, 0, 3, 0xaa], &mut out);
+        assert_eq!(out, N_FOLD_KEY_USAGE_KE_03);
+        nfold(&[0, 0, 0, 31, 0x55], &mut out);
+        assert_eq!(out, N_FOLD_KEY_USAGE_KI_31);
+    }
+
+    #[test]
+    fn test_checksum_hmac_sha1_96_aes256() {
+        // Generated with MIT krb5's krb5_c_make_checksum.
+        let key = hex::decode("b1ae4cd8462aff1658b3e04062289f643aa189b02f715e6d3bf2c8e2706c8f3f")
+            .unwrap();
+        let mut k = [0u8; AES_256_KEY_LEN];
+        k.copy_from_slice(&key);
+
+        let cksum = checksum_hmac_sha1_96_aes256(&k, b"six seven", 4).unwrap();
+
+        assert_eq!(hex::encode(cksum), "f06df200a629893a38ae60c5");
+    }
+
     #[test]
     fn test_aes256_cts_hmac_sha1_pa_enc_timestamp_decrypt() {
         let enc_data = hex::decode("b736f4dba847718b9f634b7ac94d5d691663164d877a0d875b94f786222ae9dca8cf68a972cfe6b5bec1c29682ec3c507307e7c32eedc032")
@@ -617,4 +970,20 @@ mod tests {
 
         eprintln!("{:?}", pa_enc_ts_enc);
     }
+
+    #[test]
+    fn test_self_test() {
+        assert!(self_test().is_ok());
+    }
+
+    #[test]
+    fn test_supported_etypes() {
+        let etypes = supported_etypes();
+
+        assert_eq!(etypes[0], EncryptionType::AES256_CTS_HMAC_SHA1_96);
+        assert_eq!(
+            etypes.contains(&EncryptionType::DES3_CBC_SHA1_KD),
+            cfg!(feature = "des3")
+        );
+    }
 }
@@ -0,0 +1,383 @@
+//! des3-cbc-sha1-kd (etype 16) as described in RFC 3961 section 6.3. This is deprecated
+//! and only exists to interoperate with legacy realms.
+
+use super::{constant_time_eq, nfold, HmacSha1};
+use crate::constants::DES3_KEY_LEN;
+use crate::error::KrbError;
+
+use des::cipher::generic_array::GenericArray;
+use des::cipher::{BlockDecryptMut, BlockEncrypt, BlockEncryptMut, KeyInit, KeyIvInit};
+use des::TdesEde3;
+use hmac::{digest::FixedOutput, Mac};
+use rand::{thread_rng, Rng};
+
+type TdesCbcEnc = cbc::Encryptor<TdesEde3>;
+type TdesCbcDec = cbc::Decryptor<TdesEde3>;
+
+const DES_BLOCK_SIZE: usize = 8;
+const SHA1_LEN: usize = 20;
+
+// The 168 bits of key material that random-to-key expands into a 24 byte key.
+const DES3_KEY_BYTES: usize = 21;
+
+const DES_IV_ZERO: [u8; DES_BLOCK_SIZE] = [0u8; DES_BLOCK_SIZE];
+
+const DES_WEAK_KEYS: [[u8; DES_BLOCK_SIZE]; 16] = [
+    // Weak keys
+    [0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01],
+    [0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe],
+    [0xe0, 0xe0, 0xe0, 0xe0, 0xf1, 0xf1, 0xf1, 0xf1],
+    [0x1f, 0x1f, 0x1f, 0x1f, 0x0e, 0x0e, 0x0e, 0x0e],
+    // Semi-weak keys
+    [0x01, 0x1f, 0x01, 0x1f, 0x01, 0x0e, 0x01, 0x0e],
+    [0x1f, 0x01, 0x1f, 0x01, 0x0e, 0x01, 0x0e, 0x01],
+    [0x01, 0xe0, 0x01, 0xe0, 0x01, 0xf1, 0x01, 0xf1],
+    [0xe0, 0x01, 0xe0, 0x01, 0xf1, 0x01, 0xf1, 0x01],
+    [0x01, 0xfe, 0x01, 0xfe, 0x01, 0xfe, 0x01, 0xfe],
+    [0xfe, 0x01, 0xfe, 0x01, 0xfe, 0x01, 0xfe, 0x01],
+    [0x1f, 0xe0, 0x1f, 0xe0, 0x0e, 0xf1, 0x0e, 0xf1],
+    [0xe0, 0x1f, 0xe0, 0x1f, 0xf1, 0x0e, 0xf1, 0x0e],
+    [0x1f, 0xfe, 0x1f, 0xfe, 0x0e, 0xfe, 0x0e, 0xfe],
+    [0xfe, 0x1f, 0xfe, 0x1f, 0xfe, 0x0e, 0xfe, 0x0e],
+    [0xe0, 0xfe, 0xe0, 0xfe, 0xf1, 0xfe, 0xf1, 0xfe],
+    [0xfe, 0xe0, 0xfe, 0xe0, 0xfe, 0xf1, 0xfe, 0xf1],
+];
+
+/// Derive the des3 base key from the users passphrase and salt. Unlike the AES
+/// etypes there is no iteration count, this is n-fold and key derivation only.
+pub(crate) fn derive_key_des3_cbc_sha1(
+    passphrase: &[u8],
+    salt: &[u8],
+) -> Result<[u8; DES3_KEY_LEN], KrbError> {
+    // tmpKey = random-to-key(168-fold(passphrase || salt))
+    let mut s = Vec::with_capacity(passphrase.len() + salt.len());
+    s.extend_from_slice(passphrase);
+    s.extend_from_slice(salt);
+
+    let mut folded = [0u8; DES3_KEY_BYTES];
+    nfold(&s, &mut folded);
+
+    let tmp_key = random_to_key(&folded);
+
+    // key = DK(tmpKey, "kerberos")
+    Ok(dk_des3(&tmp_key, b"kerberos"))
+}
+
+/// Given the [base key](derive_key_des3_cbc_sha1) and the key_usage value decrypt
+/// and authenticate the provided ciphertext. Note that the plaintext may retain
+/// trailing zero padding as des3 has no way to express the original length.
+pub(crate) fn decrypt_des3_cbc_sha1(
+    key: &[u8; DES3_KEY_LEN],
+    ciphertext: &[u8],
+    key_usage: i32,
+) -> Result<Vec<u8>, KrbError> {
+    let Some((ciphertext, msg_hmac)) = ciphertext.split_last_chunk::<SHA1_LEN>() else {
+        return Err(KrbError::InsufficientData);
+    };
+
+    if ciphertext.is_empty() {
+        return Err(KrbError::MessageEmpty);
+    }
+
+    // There must be at least the confounder, and whole blocks.
+    if ciphertext.len() < DES_BLOCK_SIZE || ciphertext.len() % DES_BLOCK_SIZE != 0 {
+        return Err(KrbError::InsufficientData);
+    }
+
+    let (ki, ke) = dk_ki_ke_des3(key, key_usage);
+
+    let mut plaintext = ciphertext.to_vec();
+    let mut decryptor = TdesCbcDec::new((&ke).into(), &DES_IV_ZERO.into());
+    for block in plaintext.chunks_exact_mut(DES_BLOCK_SIZE) {
+        decryptor.decrypt_block_mut(GenericArray::from_mut_slice(block));
+    }
+
+    let mut mac =
+        <HmacSha1 as Mac>::new_from_slice(&ki).map_err(|_| KrbError::InvalidHmacSha1Key)?;
+    mac.update(&plaintext);
+
+    let mut buf = [0u8; SHA1_LEN];
+    mac.finalize_into((&mut buf).into());
+
+    if !constant_time_eq(&buf, msg_hmac) {
+        return Err(KrbError::MessageAuthenticationFailed);
+    }
+
+    // Remove the confounder.
+    Ok(plaintext.split_off(DES_BLOCK_SIZE))
+}
+
+/// Given the [base key](derive_key_des3_cbc_sha1) and the key_usage value encrypt
+/// and authenticate the provided plaintext.
+pub(crate) fn encrypt_des3_cbc_sha1(
+    key: &[u8; DES3_KEY_LEN],
+    plaintext: &[u8],
+    key_usage: i32,
+) -> Result<Vec<u8>, KrbError> {
+    if plaintext.is_empty() {
+        return Err(KrbError::PlaintextEmpty);
+    };
+
+    let (ki, ke) = dk_ki_ke_des3(key, key_usage);
+
+    let mut confuzzler = [0u8; DES_BLOCK_SIZE];
+    thread_rng().fill(&mut confuzzler);
+
+    // confounder || plaintext || zero padding to the block size.
+    let padded_len = (DES_BLOCK_SIZE + plaintext.len()).div_ceil(DES_BLOCK_SIZE) * DES_BLOCK_SIZE;
+    let mut ciphertext = Vec::with_capacity(padded_len + SHA1_LEN);
+    ciphertext.extend_from_slice(&confuzzler);
+    ciphertext.extend_from_slice(plaintext);
+    ciphertext.resize(padded_len, 0);
+
+    let mut mac =
+        <HmacSha1 as Mac>::new_from_slice(&ki).map_err(|_| KrbError::InvalidHmacSha1Key)?;
+    mac.update(&ciphertext);
+
+    let mut buf = [0u8; SHA1_LEN];
+    mac.finalize_into((&mut buf).into());
+
+    let mut encryptor = TdesCbcEnc::new((&ke).into(), &DES_IV_ZERO.into());
+    for block in ciphertext.chunks_exact_mut(DES_BLOCK_SIZE) {
+        encryptor.encrypt_block_mut(GenericArray::from_mut_slice(block));
+    }
+
+    ciphertext.extend_from_slice(&buf);
+
+    Ok(ciphertext)
+}
+
+/// Given the [base key](derive_key_des3_cbc_sha1) and the key_usage value compute
+/// the HMAC-SHA1-DES3-KD (cksumtype 12) checksum of the provided data.
+pub(crate) fn checksum_hmac_sha1_des3(
+    key: &[u8; DES3_KEY_LEN],
+    data: &[u8],
+    key_usage: i32,
+) -> Result<Vec<u8>, KrbError> {
+    let mut kc_const = [0u8; 5];
+    kc_const[..4].copy_from_slice(&key_usage.to_be_bytes());
+    kc_const[4] = 0x99;
+
+    let kc = dk_des3(key, &kc_const);
+
+    let mut mac =
+        <HmacSha1 as Mac>::new_from_slice(&kc).map_err(|_| KrbError::InvalidHmacSha1Key)?;
+    mac.update(data);
+
+    let mut buf = [0u8; SHA1_LEN];
+    mac.finalize_into((&mut buf).into());
+
+    Ok(buf.to_vec())
+}
+
+fn dk_ki_ke_des3(
+    key: &[u8; DES3_KEY_LEN],
+    key_usage: i32,
+) -> ([u8; DES3_KEY_LEN], [u8; DES3_KEY_LEN]) {
+    let usage = key_usage.to_be_bytes();
+
+    let mut ki_const = [0u8; 5];
+    ki_const[..4].copy_from_slice(&usage);
+    ki_const[4] = 0x55;
+
+    let mut ke_const = [0u8; 5];
+    ke_const[..4].copy_from_slice(&usage);
+    ke_const[4] = 0xaa;
+
+    (dk_des3(key, &ki_const), dk_des3(key, &ke_const))
+}
+
+/// DK(Key, Constant) = random-to-key(DR(Key, Constant))
+fn dk_des3(key: &[u8; DES3_KEY_LEN], constant: &[u8]) -> [u8; DES3_KEY_LEN] {
+    random_to_key(&dr_des3(key, constant))
+}
+
+/// DR(Key, Constant) = k-truncate(E(Key, Constant, initial-cipher-state)), where the
+/// constant is n-folded to the block size, and the encryption is repeated over its
+/// own output until enough key material is produced.
+fn dr_des3(key: &[u8; DES3_KEY_LEN], constant: &[u8]) -> [u8; DES3_KEY_BYTES] {
+    let cipher = TdesEde3::new(key.into());
+
+    let mut block = [0u8; DES_BLOCK_SIZE];
+    if constant.len() == DES_BLOCK_SIZE {
+        block.copy_from_slice(constant);
+    } else {
+        nfold(constant, &mut block);
+    }
+
+    let mut out = [0u8; DES3_KEY_BYTES];
+    for chunk in out.chunks_mut(DES_BLOCK_SIZE) {
+        cipher.encrypt_block(GenericArray::from_mut_slice(&mut block));
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+
+    out
+}
+
+/// Expand 168 random bits into a 24 byte des3 key. Each 56 bits becomes one DES key,
+/// where the least significant bits of the first seven bytes are moved into the eighth
+/// byte, and the parity bits are then set.
+fn random_to_key(bits: &[u8; DES3_KEY_BYTES]) -> [u8; DES3_KEY_LEN] {
+    let mut key = [0u8; DES3_KEY_LEN];
+
+    for (input, output) in bits.chunks_exact(7).zip(key.chunks_exact_mut(8)) {
+        output[..7].copy_from_slice(input);
+        output[7] = input
+            .iter()
+            .enumerate()
+            .fold(0u8, |acc, (i, b)| acc | ((b & 1) << (i + 1)));
+
+        for b in output.iter_mut() {
+            // Odd parity in the least significant bit.
+            *b &= 0xfe;
+            if b.count_ones() % 2 == 0 {
+                *b |= 1;
+            }
+        }
+
+        if DES_WEAK_KEYS.iter().any(|weak| weak == output) {
+            output[7] ^= 0xf0;
+        }
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_des3_dr_dk_rfc3961_vectors() {
+        // RFC 3961 Appendix A.3
+        let vectors: [(&str, &str, &str, &str); 5] = [
+            (
+                "dce06b1f64c857a11c3db57c51899b2cc1791008ce973b92",
+                "0000000155",
+                "935079d14490a75c3093c4a6e8c3b049c71e6ee705",
+                "925179d04591a79b5d3192c4a7e9c289b049c71f6ee604cd",
+            ),
+            (
+                "5e13d31c70ef765746578531cb51c15bf11ca82c97cee9f2",
+                "00000001aa",
+                "9f58e5a047d894101c469845d67ae3c5249ed812f2",
+                "9e58e5a146d9942a101c469845d67a20e3c4259ed913f207",
+            ),
+            (
+                "98e6fd8a04a4b6859b75a176540b9752bad3ecd610a252bc",
+                "0000000155",
+                "12fff90c773f956d13fc2ca0d0840349dbd39908eb",
+                "13fef80d763e94ec6d13fd2ca1d085070249dad39808eabf",
+            ),
+            (
+                "622aec25a2fe2cad7094680b7c64940280084c1a7cec92b5",
+                "00000001aa",
+                "f8debf05b097e7dc0603686aca35d91fd9a5516a70",
+                "f8dfbf04b097e6d9dc0702686bcb3489d91fd9a4516b703e",
+            ),
+            (
+                "c1081649ada74362e6a1459d01dfd30d67c2234c940704da",
+                "0000000155",
+                "348056ec98fcc517171d2b4d7a9493af482d999175",
+                "348057ec98fdc48016161c2a4c7a943e92ae492c989175f7",
+            ),
+        ];
+
+        for (key, constant, dr, dk) in vectors {
+            let key: [u8; DES3_KEY_LEN] = hex::decode(key).unwrap().try_into().unwrap();
+            let constant = hex::decode(constant).unwrap();
+
+            assert_eq!(hex::encode(dr_des3(&key, &constant)), dr);
+            assert_eq!(hex::encode(dk_des3(&key, &constant)), dk);
+        }
+    }
+
+    #[test]
+    fn test_des3_string_to_key_rfc3961_vectors() {
+        // RFC 3961 Appendix A.4
+        let vectors: [(&str, &str, &str); 5] = [
+            (
+                "password",
+                "ATHENA.MIT.EDUraeburn",
+                "850bb51358548cd05e86768c313e3bfef7511937dcf72c3e",
+            ),
+            (
+                "potatoe",
+                "WHITEHOUSE.GOVdanny",
+                "dfcd233dd0a43204ea6dc437fb15e061b02979c1f74f377a",
+            ),
+            (
+                "penny",
+                "EXAMPLE.COMbuckaroo",
+                "6d2fcdf2d6fbbc3ddcadb5da5710a23489b0d3b69d5d9d4a",
+            ),
+            (
+                "\u{00df}",
+                "ATHENA.MIT.EDUJuri\u{0161}i\u{0107}",
+                "16d5a40e1ce3bacb61b9dce00470324c831973a7b952feb0",
+            ),
+            (
+                "\u{1d11e}",
+                "EXAMPLE.COMpianist",
+                "85763726585dbc1cce6ec43e1f751f07f1c4cbb098f40b19",
+            ),
+        ];
+
+        for (passphrase, salt, key) in vectors {
+            let derived = derive_key_des3_cbc_sha1(passphrase.as_bytes(), salt.as_bytes())
+                .expect("Failed to derive key");
+            assert_eq!(hex::encode(derived), key);
+        }
+    }
+
+    #[test]
+    fn test_des3_cbc_sha1_decrypt() {
+        // Generated with MIT krb5's krb5_c_encrypt.
+        let key: [u8; DES3_KEY_LEN] =
+            hex::decode("850bb51358548cd05e86768c313e3bfef7511937dcf72c3e")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let ciphertext = hex::decode(
+            "5b15cea97668f6a46cea236d21fd8ffb0d920db66785d3b5de8f479fa94fd32e\
+             cd7e7f014c1fbab0d7c3d0f3cd82d14b2c2c2a3a6f4a8ac2ddc67ce7",
+        )
+        .unwrap();
+
+        let plaintext = decrypt_des3_cbc_sha1(&key, &ciphertext, 3).expect("Failed to decrypt");
+
+        // The plaintext retains the zero padding to the block size.
+        assert_eq!(&plaintext[..26], b"kerberos des3 test message");
+        assert!(plaintext[26..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_checksum_hmac_sha1_des3() {
+        // Generated with MIT krb5's krb5_c_make_checksum.
+        let key: [u8; DES3_KEY_LEN] =
+            hex::decode("850bb51358548cd05e86768c313e3bfef7511937dcf72c3e")
+                .unwrap()
+                .try_into()
+                .unwrap();
+
+        let cksum = checksum_hmac_sha1_des3(&key, b"six seven", 6).expect("Failed to checksum");
+        assert_eq!(
+            hex::encode(cksum),
+            "e37b559bc53a2d9c0585e9d11c07bdba0b54e05d"
+        );
+    }
+
+    #[test]
+    fn test_des3_cbc_sha1_reflexive() {
+        let key = derive_key_des3_cbc_sha1(b"password", b"EXAMPLE.COMtestuser")
+            .expect("Failed to derive key");
+        let data = b"hello world";
+
+        let ciphertext = encrypt_des3_cbc_sha1(&key, data, 1).expect("Failed to encrypt");
+        let plaintext = decrypt_des3_cbc_sha1(&key, &ciphertext, 1).expect("Failed to decrypt");
+        assert_eq!(&plaintext[..data.len()], data);
+
+        // The wrong usage must fail to authenticate.
+        assert!(decrypt_des3_cbc_sha1(&key, &ciphertext, 2).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use crate::error::KrbError;
+use rand::Rng;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use tracing::debug;
+
+// The port a KDC listens on when the location does not specify one.
+const KERBEROS_PORT: u16 = 88;
+
+/// A DNS SRV record for a KDC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+/// The DNS lookups needed to locate the KDCs of a realm. The lookups are done through a
+/// `KdcResolver` rather than a resolver library directly, so that discovery can be
+/// tested without a DNS server.
+pub trait KdcResolver {
+    /// The SRV records for `name`, or none if the name does not exist.
+    fn lookup_srv(
+        &self,
+        name: &str,
+    ) -> impl Future<Output = Result<Vec<SrvRecord>, KrbError>> + Send;
+
+    /// The A and AAAA records for `host`, or none if the host does not exist.
+    fn lookup_ip(&self, host: &str) -> impl Future<Output = Result<Vec<IpAddr>, KrbError>> + Send;
+}
+
+/// Locate the KDCs of `realm` from its `_kerberos._tcp` SRV records, in the order they
+/// should be tried. If the realm has no SRV records, the addresses of `_kerberos.REALM`
+/// are used with the default port.
+///
+/// Only TCP KDCs are located, since that is the only transport we support.
+pub async fn locate_kdcs(
+    resolver: &impl KdcResolver,
+    realm: &str,
+) -> Result<Vec<SocketAddr>, KrbError> {
+    let records = resolver
+        .lookup_srv(&format!("_kerberos._tcp.{}.", realm))
+        .await?;
+
+    // RFC 2782 A target of "." means that the service is decidedly not available at
+    // this domain.
+    if records.len() == 1 && records[0].target == "." {
+        return Err(KrbError::KdcNotFound);
+    }
+
+    let mut kdcs = Vec::with_capacity(records.len());
+
+    if records.is_empty() {
+        debug!(%realm, "no SRV records, falling back to _kerberos host");
+        let addresses = resolver.lookup_ip(&format!("_kerberos.{}.", realm)).await?;
+        kdcs.extend(
+            addresses
+                .into_iter()
+                .map(|address| SocketAddr::new(address, KERBEROS_PORT)),
+        );
+    } else {
+        let records = order_srv_records(records, &mut rand::thread_rng());
+
+        for record in records {
+            // One KDC that we can't resolve shouldn't prevent us from using the others.
+            match resolver.lookup_ip(&record.target).await {
+                Ok(addresses) => kdcs.extend(
+                    addresses
+                        .into_iter()
+                        .map(|address| SocketAddr::new(address, record.port)),
+                ),
+                Err(err) => debug!(target = %record.target, ?err, "unable to resolve KDC"),
+            }
+        }
+    }
+
+    if kdcs.is_empty() {
+        return Err(KrbError::KdcNotFound);
+    }
+
+    Ok(kdcs)
+}
+
+/// Order SRV records as described in RFC 2782. Records are tried in order of priority,
+/// and records of the same priority are picked at random in proportion to their weight.
+fn order_srv_records(mut records: Vec<SrvRecord>, rng: &mut impl Rng) -> Vec<SrvRecord> {
+    records.sort_by_key(|record| record.priority);
+
+    let mut ordered = Vec::with_capacity(records.len());
+
+    for group in records.chunk_by(|a, b| a.priority == b.priority) {
+        let mut group = group.to_vec();
+        // Records with a weight of zero are placed first, so that they have a very
+        // small chance of being picked while records with weight remain.
+        group.sort_by_key(|record| record.weight != 0);
+
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|record| u32::from(record.weight)).sum();
+            let choice = rng.gen_range(0..=total);
+
+            let mut running_sum = 0;
+            let index = group
+                .iter()
+                .position(|record| {
+                    running_sum += u32::from(record.weight);
+                    running_sum >= choice
+                })
+                .unwrap_or(0);
+
+            ordered.push(group.remove(index));
+        }
+    }
+
+    ordered
+}
+
+/// The default `KdcResolver`, using the system's DNS configuration.
+#[cfg(feature = "dns")]
+pub struct DnsResolver {
+    resolver: hickory_resolver::TokioAsyncResolver,
+}
+
+#[cfg(feature = "dns")]
+impl DnsResolver {
+    pub fn from_system_conf() -> Result<Self, KrbError> {
+        hickory_resolver::TokioAsyncResolver::tokio_from_system_conf()
+            .map(|resolver| DnsResolver { resolver })
+            .map_err(|_| KrbError::DnsLookupFailed)
+    }
+}
+
+#[cfg(feature = "dns")]
+fn is_no_records(err: &hickory_resolver::error::ResolveError) -> bool {
+    matches!(
+        err.kind(),
+        hickory_resolver::error::ResolveErrorKind::NoRecordsFound { .. }
+    )
+}
+
+#[cfg(feature = "dns")]
+impl KdcResolver for DnsResolver {
+    async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, KrbError> {
+        match self.resolver.srv_lookup(name).await {
+            Ok(lookup) => Ok(lookup
+                .iter()
+                .map(|srv| SrvRecord {
+                    priority: srv.priority(),
+                    weight: srv.weight(),
+                    port: srv.port(),
+                    target: srv.target().to_utf8(),
+                })
+                .collect()),
+            Err(err) if is_no_records(&err) => Ok(Vec::with_capacity(0)),
+            Err(err) => {
+                debug!(?err, %name, "SRV lookup failed");
+                Err(KrbError::DnsLookupFailed)
+            }
+        }
+    }
+
+    async fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>, KrbError> {
+        match self.resolver.lookup_ip(host).await {
+            Ok(lookup) => Ok(lookup.iter().collect()),
+            Err(err) if is_no_records(&err) => Ok(Vec::with_capacity(0)),
+            Err(err) => {
+                debug!(?err, %host, "address lookup failed");
+                Err(KrbError::DnsLookupFailed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{locate_kdcs, KdcResolver, SrvRecord};
+    use crate::error::KrbError;
+    use std::collections::HashMap;
+    use std::future::Future;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    #[derive(Default)]
+    struct MockResolver {
+        srv: HashMap<String, Vec<SrvRecord>>,
+        ip: HashMap<String, Vec<IpAddr>>,
+    }
+
+    impl KdcResolver for MockResolver {
+        fn lookup_srv(
+            &self,
+            name: &str,
+        ) -> impl Future<Output = Result<Vec<SrvRecord>, KrbError>> + Send {
+            let records = self.srv.get(name).cloned().unwrap_or_default();
+            async move { Ok(records) }
+        }
+
+        fn lookup_ip(
+            &self,
+            host: &str,
+        ) -> impl Future<Output = Result<Vec<IpAddr>, KrbError>> + Send {
+            let addresses = self.ip.get(host).cloned().unwrap_or_default();
+            async move { Ok(addresses) }
+        }
+    }
+
+    fn ip(last_octet: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last_octet))
+    }
+
+    #[tokio::test]
+    async fn test_locate_kdcs_srv_priority() {
+        let mut resolver = MockResolver::default();
+        // The backup KDC is listed first, but has a lower priority.
+        resolver.srv.insert(
+            "_kerberos._tcp.EXAMPLE.COM.".to_string(),
+            vec![
+                SrvRecord {
+                    priority: 10,
+                    weight: 0,
+                    port: 8888,
+                    target: "kdc2.example.com.".to_string(),
+                },
+                SrvRecord {
+                    priority: 0,
+                    weight: 100,
+                    port: 88,
+                    target: "kdc1.example.com.".to_string(),
+                },
+            ],
+        );
+        resolver
+            .ip
+            .insert("kdc1.example.com.".to_string(), vec![ip(1)]);
+        resolver
+            .ip
+            .insert("kdc2.example.com.".to_string(), vec![ip(2)]);
+
+        let kdcs = locate_kdcs(&resolver, "EXAMPLE.COM")
+            .await
+            .expect("Failed to locate KDCs");
+
+        assert_eq!(
+            kdcs,
+            vec![SocketAddr::new(ip(1), 88), SocketAddr::new(ip(2), 8888)]
+        );
+
+        // Without SRV records the realm's _kerberos host is used.
+        let mut resolver = MockResolver::default();
+        resolver
+            .ip
+            .insert("_kerberos.EXAMPLE.COM.".to_string(), vec![ip(3)]);
+
+        let kdcs = locate_kdcs(&resolver, "EXAMPLE.COM")
+            .await
+            .expect("Failed to locate KDCs");
+        assert_eq!(kdcs, vec![SocketAddr::new(ip(3), 88)]);
+
+        assert!(matches!(
+            locate_kdcs(&MockResolver::default(), "EXAMPLE.COM").await,
+            Err(KrbError::KdcNotFound)
+        ));
+    }
+}
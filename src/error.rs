@@ -1,3 +1,9 @@
+use crate::asn1::constants::encryption_types::EncryptionType;
+use crate::asn1::constants::errors::KrbErrorCode;
+use crate::asn1::constants::pa_data_types::PaDataType;
+use std::fmt;
+use std::io;
+
 #[derive(Debug, Clone)]
 pub enum KrbError {
     InvalidHmacSha1Key,
@@ -6,30 +12,332 @@ pub enum KrbError {
     InsufficientData,
     PlaintextEmpty,
     CtsCiphertextInvalid,
+    /// The crypto backend produced the wrong output for a known answer test.
+    CryptoSelfTestFailed,
     UnsupportedEncryption,
+    UnsupportedChecksum,
+    UnsupportedGssMech,
     MissingPaData,
     MissingServiceNameWithRealm,
     MissingClientName,
     MissingRealm,
     DerDecodePaData,
     DerDecodeEtypeInfo2,
+    DerEncodeEtypeInfo2,
     DerEncodePaEncTsEnc,
     DerDecodePaEncTsEnc,
     DerDecodeEncKdcRepPart,
     DerEncodeEncKdcRepPart,
     DerEncodeOctetString,
     DerEncodeEncTicketPart,
+    DerDecodeEncTicketPart,
+    DerEncodeKerberosTime,
+    DerEncodeKdcReqBody,
+    DerEncodeAuthenticator,
+    DerEncodeApReq,
+    DerDecodeApReq,
+    DerEncodeKrbCred,
+    DerDecodeKrbCred,
+    DerEncodeEncKrbCredPart,
+    DerDecodeEncKrbCredPart,
+    DerEncodeTicket,
+    DerDecodeTicket,
+    DerEncodePaForUser,
+    DerDecodePaForUser,
+    DerEncodePaSpake,
+    DerEncodeKdcReq,
+    DerDecodeKdcReq,
+    DerEncodeKdcRep,
+    DerDecodeKdcRep,
+    DerEncodeAuthorizationData,
+    DerDecodeAuthorizationData,
 
     PreauthUnsupported,
     PreauthMissingEtypeInfo2,
     PreauthInvalidUnixTs,
     PreauthInvalidS2KParams,
+    /// The KDC rejected the preauthentication (KDC_ERR_PREAUTH_FAILED), which usually
+    /// means the password is wrong.
+    PreauthFailed,
+    /// The KDC asked for a key derived with fewer string-to-key iterations than the
+    /// minimum we accept.
+    IterationCountTooLow,
+    /// The KDC replied with an etype that the client's etype policy doesn't permit.
+    EtypeNotPermitted(EncryptionType),
 
     NameNotPrincipal,
+    NameNotKrbtgt,
+    MalformedPrincipalName,
+    MalformedTransitedEncoding,
+    MalformedStashFile,
+    MalformedPrincipalDump,
+    MalformedBase64,
+    MalformedGssToken,
+    ClientNameMismatch,
+    /// The KDC reply doesn't carry the nonce of the request it should answer.
+    NonceMismatch,
+
+    MissingApReq,
+    TooManyReferrals,
+    NoKdcReachable,
+    KdcNotFound,
+    DnsLookupFailed,
+    UnexpectedReply,
+    /// The KDC refused the request with a KRB-ERROR of this code.
+    KdcError(KrbErrorCode),
+    UnexpectedReplyType,
+    UnexpectedTicketService,
+    Replay,
+    ClockSkew,
 
     InvalidMessageType,
     InvalidMessageDirection,
     InvalidPvno,
     InvalidEncryptionKey,
+    InvalidTicketTimes,
+    InvalidPrincipalName,
+    InvalidAdditionalTickets,
+    InvalidApOptions,
+    InvalidHostAddress,
+    /// The credential info of a KRB-CRED doesn't match the tickets it forwards.
+    InvalidCredentialInfo,
     InvalidEnumValue(String, i32),
+    /// The name isn't one of the names or aliases of an encryption type.
+    InvalidEncryptionTypeName(String),
+    UnsupportedTransitedType(i32),
+    /// The KDC requires preauthentication, but only offered these mechanisms, none of
+    /// which are supported.
+    UnsupportedPreauthMechanism(Vec<PaDataType>),
+    /// The KDC encrypted its reply with this etype, which is not supported.
+    UnsupportedReplyEncryption(i32),
+}
+
+impl fmt::Display for KrbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KrbError::InvalidHmacSha1Key => write!(f, "invalid HMAC-SHA1 key"),
+            KrbError::MessageAuthenticationFailed => write!(f, "message integrity check failed"),
+            KrbError::MessageEmpty => write!(f, "message is empty"),
+            KrbError::InsufficientData => write!(f, "insufficient data"),
+            KrbError::PlaintextEmpty => write!(f, "plaintext is empty"),
+            KrbError::CtsCiphertextInvalid => write!(f, "invalid CTS ciphertext"),
+            KrbError::CryptoSelfTestFailed => write!(f, "crypto self test failed"),
+            KrbError::UnsupportedEncryption => write!(f, "unsupported encryption type"),
+            KrbError::UnsupportedChecksum => write!(f, "unsupported checksum type"),
+            KrbError::UnsupportedGssMech => write!(f, "unsupported GSS-API mechanism"),
+            KrbError::MissingPaData => write!(f, "missing padata"),
+            KrbError::MissingServiceNameWithRealm => write!(f, "missing service name"),
+            KrbError::MissingClientName => write!(f, "missing client name"),
+            KrbError::MissingRealm => write!(f, "missing realm"),
+            KrbError::DerDecodePaData => write!(f, "failed to decode padata"),
+            KrbError::DerDecodeEtypeInfo2 => write!(f, "failed to decode ETYPE-INFO2"),
+            KrbError::DerEncodeEtypeInfo2 => write!(f, "failed to encode ETYPE-INFO2"),
+            KrbError::DerEncodePaEncTsEnc => write!(f, "failed to encode PA-ENC-TS-ENC"),
+            KrbError::DerDecodePaEncTsEnc => write!(f, "failed to decode PA-ENC-TS-ENC"),
+            KrbError::DerDecodeEncKdcRepPart => write!(f, "failed to decode EncKDCRepPart"),
+            KrbError::DerEncodeEncKdcRepPart => write!(f, "failed to encode EncKDCRepPart"),
+            KrbError::DerEncodeOctetString => write!(f, "failed to encode OCTET STRING"),
+            KrbError::DerEncodeEncTicketPart => write!(f, "failed to encode EncTicketPart"),
+            KrbError::DerDecodeEncTicketPart => write!(f, "failed to decode EncTicketPart"),
+            KrbError::DerEncodeKerberosTime => write!(f, "failed to encode KerberosTime"),
+            KrbError::DerEncodeKdcReqBody => write!(f, "failed to encode KDC-REQ-BODY"),
+            KrbError::DerEncodeAuthenticator => write!(f, "failed to encode Authenticator"),
+            KrbError::DerEncodeApReq => write!(f, "failed to encode AP-REQ"),
+            KrbError::DerDecodeApReq => write!(f, "failed to decode AP-REQ"),
+            KrbError::DerEncodeKrbCred => write!(f, "failed to encode KRB-CRED"),
+            KrbError::DerDecodeKrbCred => write!(f, "failed to decode KRB-CRED"),
+            KrbError::DerEncodeEncKrbCredPart => write!(f, "failed to encode EncKrbCredPart"),
+            KrbError::DerDecodeEncKrbCredPart => write!(f, "failed to decode EncKrbCredPart"),
+            KrbError::DerEncodeTicket => write!(f, "failed to encode Ticket"),
+            KrbError::DerDecodeTicket => write!(f, "failed to decode Ticket"),
+            KrbError::DerEncodePaForUser => write!(f, "failed to encode PA-FOR-USER"),
+            KrbError::DerDecodePaForUser => write!(f, "failed to decode PA-FOR-USER"),
+            KrbError::DerEncodePaSpake => write!(f, "failed to encode PA-SPAKE"),
+            KrbError::DerEncodeKdcReq => write!(f, "failed to encode KDC-REQ"),
+            KrbError::DerDecodeKdcReq => write!(f, "failed to decode KDC-REQ"),
+            KrbError::DerEncodeKdcRep => write!(f, "failed to encode KDC-REP"),
+            KrbError::DerDecodeKdcRep => write!(f, "failed to decode KDC-REP"),
+            KrbError::DerEncodeAuthorizationData => {
+                write!(f, "failed to encode AuthorizationData")
+            }
+            KrbError::DerDecodeAuthorizationData => {
+                write!(f, "failed to decode AuthorizationData")
+            }
+            KrbError::PreauthUnsupported => {
+                write!(f, "the KDC offered no supported preauthentication")
+            }
+            KrbError::PreauthMissingEtypeInfo2 => {
+                write!(f, "the KDC sent no ETYPE-INFO2 for a supported etype")
+            }
+            KrbError::PreauthInvalidUnixTs => write!(f, "the time is before the unix epoch"),
+            KrbError::PreauthInvalidS2KParams => write!(f, "invalid string-to-key parameters"),
+            KrbError::IterationCountTooLow => {
+                write!(f, "the KDC asked for too few string-to-key iterations")
+            }
+            KrbError::PreauthFailed => {
+                write!(f, "preauthentication failed, the password may be wrong")
+            }
+            KrbError::EtypeNotPermitted(etype) => {
+                write!(f, "encryption type {} is not permitted by policy", etype)
+            }
+            KrbError::NameNotPrincipal => write!(f, "name is not a principal"),
+            KrbError::NameNotKrbtgt => write!(f, "name is not a ticket granting service"),
+            KrbError::MalformedPrincipalName => write!(f, "malformed principal name"),
+            KrbError::MalformedTransitedEncoding => write!(f, "malformed transited encoding"),
+            KrbError::MalformedStashFile => write!(f, "malformed stash file"),
+            KrbError::MalformedPrincipalDump => write!(f, "malformed principal dump"),
+            KrbError::MalformedBase64 => write!(f, "malformed base64"),
+            KrbError::MalformedGssToken => write!(f, "malformed GSS-API token"),
+            KrbError::ClientNameMismatch => {
+                write!(
+                    f,
+                    "the reply is for a different client to the one requested"
+                )
+            }
+            KrbError::NonceMismatch => {
+                write!(f, "the reply is for a different request to the one sent")
+            }
+            KrbError::MissingApReq => write!(f, "missing AP-REQ"),
+            KrbError::TooManyReferrals => write!(f, "too many referrals"),
+            KrbError::NoKdcReachable => write!(f, "no KDC could be reached"),
+            KrbError::KdcNotFound => write!(f, "no KDC was found for the realm"),
+            KrbError::DnsLookupFailed => write!(f, "DNS lookup failed"),
+            KrbError::UnexpectedReply => write!(f, "unexpected reply"),
+            KrbError::KdcError(code) => write!(f, "the KDC replied with an error: {}", code),
+            KrbError::UnexpectedReplyType => write!(f, "unexpected reply type"),
+            KrbError::UnexpectedTicketService => write!(f, "unexpected ticket service"),
+            KrbError::Replay => write!(f, "replayed message"),
+            KrbError::ClockSkew => write!(f, "clock skew too great"),
+            KrbError::InvalidMessageType => write!(f, "invalid message type"),
+            KrbError::InvalidMessageDirection => write!(f, "invalid message direction"),
+            KrbError::InvalidPvno => write!(f, "invalid protocol version number"),
+            KrbError::InvalidEncryptionKey => write!(f, "invalid encryption key"),
+            KrbError::InvalidTicketTimes => write!(f, "invalid ticket times"),
+            KrbError::InvalidPrincipalName => write!(f, "invalid principal name"),
+            KrbError::InvalidAdditionalTickets => write!(f, "invalid additional tickets"),
+            KrbError::InvalidApOptions => write!(f, "invalid AP options"),
+            KrbError::InvalidHostAddress => write!(f, "invalid host address"),
+            KrbError::InvalidCredentialInfo => {
+                write!(f, "credential info doesn't match the forwarded tickets")
+            }
+            KrbError::InvalidEnumValue(type_name, value) => {
+                write!(f, "invalid value {} for {}", value, type_name)
+            }
+            KrbError::InvalidEncryptionTypeName(name) => {
+                write!(f, "unknown encryption type name {}", name)
+            }
+            KrbError::UnsupportedTransitedType(tr_type) => {
+                write!(f, "unsupported transited encoding type {}", tr_type)
+            }
+            KrbError::UnsupportedPreauthMechanism(mechanisms) => write!(
+                f,
+                "the KDC only offered unsupported preauthentication mechanisms {:?}",
+                mechanisms
+            ),
+            KrbError::UnsupportedReplyEncryption(etype) => {
+                write!(
+                    f,
+                    "the KDC replied with unsupported encryption type {}",
+                    etype
+                )
+            }
+        }
+    }
+}
+
+// No variant wraps the error of another crate, so there is no source.
+impl std::error::Error for KrbError {}
+
+impl From<KrbError> for io::Error {
+    fn from(err: KrbError) -> Self {
+        let kind = match &err {
+            KrbError::UnsupportedEncryption
+            | KrbError::UnsupportedChecksum
+            | KrbError::UnsupportedGssMech
+            | KrbError::UnsupportedTransitedType(_)
+            | KrbError::UnsupportedPreauthMechanism(_)
+            | KrbError::UnsupportedReplyEncryption(_)
+            | KrbError::PreauthUnsupported => io::ErrorKind::Unsupported,
+
+            KrbError::NoKdcReachable => io::ErrorKind::NotConnected,
+
+            KrbError::CryptoSelfTestFailed => io::ErrorKind::Other,
+
+            KrbError::PreauthFailed | KrbError::EtypeNotPermitted(_) | KrbError::KdcError(_) => {
+                io::ErrorKind::PermissionDenied
+            }
+
+            // Errors that occur while we build a message from caller provided values.
+            KrbError::DerEncodeEtypeInfo2
+            | KrbError::DerEncodePaEncTsEnc
+            | KrbError::DerEncodeEncKdcRepPart
+            | KrbError::DerEncodeOctetString
+            | KrbError::DerEncodeEncTicketPart
+            | KrbError::DerEncodeKerberosTime
+            | KrbError::DerEncodeKdcReqBody
+            | KrbError::DerEncodeAuthenticator
+            | KrbError::DerEncodeApReq
+            | KrbError::DerEncodeKrbCred
+            | KrbError::DerEncodeEncKrbCredPart
+            | KrbError::DerEncodeTicket
+            | KrbError::DerEncodePaForUser
+            | KrbError::DerEncodePaSpake
+            | KrbError::DerEncodeKdcReq
+            | KrbError::DerEncodeKdcRep
+            | KrbError::DerEncodeAuthorizationData
+            | KrbError::PreauthInvalidUnixTs
+            | KrbError::NameNotPrincipal
+            | KrbError::NameNotKrbtgt
+            | KrbError::InvalidEncryptionKey
+            | KrbError::InvalidTicketTimes
+            | KrbError::InvalidPrincipalName
+            | KrbError::InvalidAdditionalTickets
+            | KrbError::InvalidApOptions
+            | KrbError::InvalidHostAddress
+            | KrbError::InvalidEncryptionTypeName(_) => io::ErrorKind::InvalidInput,
+
+            // Everything else is a problem with the data we received.
+            _ => io::ErrorKind::InvalidData,
+        };
+
+        io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KrbError;
+    use std::io;
+
+    #[test]
+    fn test_krb_error_into_io_error() {
+        let err: io::Error = KrbError::UnsupportedEncryption.into();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        let err: io::Error = KrbError::DerDecodeEncKdcRepPart.into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err: io::Error = KrbError::DerEncodeKdcReqBody.into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_krb_error_display() {
+        assert_eq!(KrbError::ClockSkew.to_string(), "clock skew too great");
+        assert_eq!(
+            KrbError::InvalidEnumValue("KrbErrorCode".to_string(), 99).to_string(),
+            "invalid value 99 for KrbErrorCode"
+        );
+
+        // The message is kept when converted to an io::Error, as is the KrbError.
+        let err: io::Error = KrbError::UnsupportedReplyEncryption(17).into();
+        assert_eq!(
+            err.to_string(),
+            "the KDC replied with unsupported encryption type 17"
+        );
+        assert!(matches!(
+            err.get_ref().and_then(|err| err.downcast_ref::<KrbError>()),
+            Some(KrbError::UnsupportedReplyEncryption(17))
+        ));
+    }
 }
@@ -0,0 +1,307 @@
+//! The GSS-API framing of the Kerberos V5 mechanism (RFC 4121), as used to carry an
+//! AP-REQ in SPNEGO, HTTP Negotiate or LDAP SASL GSSAPI.
+
+use crate::error::KrbError;
+use crate::proto::{parse_name_components, Name};
+
+// RFC 2743 3.1 The initial context token is an [APPLICATION 0] IMPLICIT SEQUENCE.
+const INITIAL_CONTEXT_TOKEN_TAG: u8 = 0x60;
+const OID_TAG: u8 = 0x06;
+
+// The DER encoding of the Kerberos V5 mechanism OID 1.2.840.113554.1.2.2.
+const KRB5_MECH_OID: [u8; 9] = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02];
+
+// RFC 4121 4.1 The token id of a KRB_AP_REQ.
+const TOK_ID_AP_REQ: [u8; 2] = [0x01, 0x00];
+
+// RFC 2743 3.2 The token id of an exported name.
+const TOK_ID_EXPORTED_NAME: [u8; 2] = [0x04, 0x01];
+
+/// Wrap the DER of an AP-REQ in the GSS-API initial context token framing, with the
+/// Kerberos V5 mechanism OID and the AP-REQ token id.
+pub fn wrap_initial_token(ap_req_der: &[u8]) -> Vec<u8> {
+    let mut inner = Vec::with_capacity(2 + KRB5_MECH_OID.len() + 2 + ap_req_der.len());
+    inner.push(OID_TAG);
+    inner.push(KRB5_MECH_OID.len() as u8);
+    inner.extend_from_slice(&KRB5_MECH_OID);
+    inner.extend_from_slice(&TOK_ID_AP_REQ);
+    inner.extend_from_slice(ap_req_der);
+
+    let mut token = Vec::with_capacity(inner.len() + 6);
+    token.push(INITIAL_CONTEXT_TOKEN_TAG);
+    encode_length(inner.len(), &mut token);
+    token.extend_from_slice(&inner);
+    token
+}
+
+/// Unwrap an initial context token received by an acceptor, checking the mechanism OID
+/// and token id, and returning the DER of the AP-REQ it carries.
+pub fn unwrap_initial_token(token: &[u8]) -> Result<&[u8], KrbError> {
+    let (&tag, rest) = token.split_first().ok_or(KrbError::MalformedGssToken)?;
+    if tag != INITIAL_CONTEXT_TOKEN_TAG {
+        return Err(KrbError::MalformedGssToken);
+    }
+
+    let (len, rest) = decode_length(rest)?;
+    if len != rest.len() {
+        return Err(KrbError::MalformedGssToken);
+    }
+
+    let rest = rest
+        .strip_prefix(&[OID_TAG, KRB5_MECH_OID.len() as u8])
+        .and_then(|rest| rest.strip_prefix(&KRB5_MECH_OID))
+        .ok_or(KrbError::UnsupportedGssMech)?;
+
+    rest.strip_prefix(&TOK_ID_AP_REQ)
+        .ok_or(KrbError::MalformedGssToken)
+}
+
+impl Name {
+    /// Parse a GSS-API exported name token (RFC 2743 3.2) of the Kerberos V5 mechanism,
+    /// as produced by `gss_export_name`.
+    ///
+    /// The token carries the name in its string form (RFC 1964 2.1.1), which doesn't
+    /// say what type of name it is. A single component is a principal, `krbtgt/REALM`
+    /// is the ticket granting service, and any other two components are a host based
+    /// service.
+    pub fn from_gss_exported(token: &[u8]) -> Result<Name, KrbError> {
+        let rest = token
+            .strip_prefix(&TOK_ID_EXPORTED_NAME)
+            .ok_or(KrbError::MalformedGssToken)?;
+
+        let (oid_len, rest) = rest
+            .split_first_chunk::<2>()
+            .ok_or(KrbError::MalformedGssToken)?;
+        let oid_len = u16::from_be_bytes(*oid_len) as usize;
+        if rest.len() < oid_len {
+            return Err(KrbError::MalformedGssToken);
+        }
+
+        let (oid, rest) = rest.split_at(oid_len);
+        let is_krb5 = oid
+            .strip_prefix(&[OID_TAG, KRB5_MECH_OID.len() as u8])
+            .is_some_and(|oid| oid == KRB5_MECH_OID);
+        if !is_krb5 {
+            return Err(KrbError::UnsupportedGssMech);
+        }
+
+        let (name_len, name) = rest
+            .split_first_chunk::<4>()
+            .ok_or(KrbError::MalformedGssToken)?;
+        if u32::from_be_bytes(*name_len) as usize != name.len() {
+            return Err(KrbError::MalformedGssToken);
+        }
+
+        let name = std::str::from_utf8(name).map_err(|_| KrbError::MalformedPrincipalName)?;
+        parse_name(name)
+    }
+
+    /// Encode this name as a GSS-API exported name token (RFC 2743 3.2) of the Kerberos
+    /// V5 mechanism, as `gss_import_name` accepts with GSS_C_NT_EXPORT_NAME.
+    pub fn to_gss_exported(&self) -> Vec<u8> {
+        let name = unparse_name(self);
+
+        let mut token = Vec::with_capacity(4 + 2 + KRB5_MECH_OID.len() + 4 + name.len());
+        token.extend_from_slice(&TOK_ID_EXPORTED_NAME);
+        token.extend_from_slice(&(2 + KRB5_MECH_OID.len() as u16).to_be_bytes());
+        token.push(OID_TAG);
+        token.push(KRB5_MECH_OID.len() as u8);
+        token.extend_from_slice(&KRB5_MECH_OID);
+        token.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        token.extend_from_slice(name.as_bytes());
+        token
+    }
+}
+
+// The string form of a name, `component/component@REALM`, with a backslash before any
+// separator or backslash within a component or the realm.
+fn unparse_name(name: &Name) -> String {
+    let (components, realm): (Vec<&str>, &str) = match name {
+        Name::Principal { name, realm } => (vec![name], realm),
+        Name::SrvInst {
+            service,
+            instance,
+            realm,
+        } => (vec![service, instance], realm),
+        Name::SrvHst {
+            service,
+            host,
+            realm,
+        } => (vec![service, host], realm),
+        Name::SrvXHst { components, realm }
+        | Name::Other {
+            components, realm, ..
+        } => (components.iter().map(String::as_str).collect(), realm),
+    };
+
+    let escape = |s: &str| {
+        s.chars()
+            .fold(String::with_capacity(s.len()), |mut out, c| {
+                if matches!(c, '/' | '@' | '\\') {
+                    out.push('\\');
+                }
+                out.push(c);
+                out
+            })
+    };
+
+    let mut out = components
+        .into_iter()
+        .map(escape)
+        .collect::<Vec<_>>()
+        .join("/");
+    out.push('@');
+    out.push_str(&escape(realm));
+    out
+}
+
+fn parse_name(name: &str) -> Result<Name, KrbError> {
+    let (mut components, realm) = parse_name_components(name)?;
+
+    match components.len() {
+        1 => Ok(Name::Principal {
+            name: components.remove(0),
+            realm,
+        }),
+        2 => {
+            let second = components.remove(1);
+            let service = components.remove(0);
+            if service == "krbtgt" {
+                Ok(Name::SrvInst {
+                    service,
+                    instance: second,
+                    realm,
+                })
+            } else {
+                Ok(Name::SrvHst {
+                    service,
+                    host: second,
+                    realm,
+                })
+            }
+        }
+        // NT-PRINCIPAL, as MIT imports any name it can't otherwise place.
+        _ => Ok(Name::Other {
+            name_type: 1,
+            components,
+            realm,
+        }),
+    }
+}
+
+// A DER definite length, in the short form below 128 and the long form otherwise.
+fn encode_length(len: usize, out: &mut Vec<u8>) {
+    if len < 0x80 {
+        out.push(len as u8);
+        return;
+    }
+
+    let bytes = len.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    out.push(0x80 | (bytes.len() - skip) as u8);
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+fn decode_length(data: &[u8]) -> Result<(usize, &[u8]), KrbError> {
+    let (&first, rest) = data.split_first().ok_or(KrbError::MalformedGssToken)?;
+    if first < 0x80 {
+        return Ok((first as usize, rest));
+    }
+
+    let count = (first & 0x7f) as usize;
+    if count == 0 || count > std::mem::size_of::<usize>() || rest.len() < count {
+        return Err(KrbError::MalformedGssToken);
+    }
+
+    let (len_bytes, rest) = rest.split_at(count);
+    let len = len_bytes
+        .iter()
+        .fold(0usize, |len, b| (len << 8) | *b as usize);
+
+    Ok((len, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unwrap_initial_token, wrap_initial_token};
+    use crate::error::KrbError;
+    use crate::proto::Name;
+
+    #[test]
+    fn test_gss_initial_token() {
+        // Any AP-REQ starts with its [APPLICATION 14] tag.
+        let ap_req = [0x6e, 0x03, 0x30, 0x01, 0x00];
+
+        let token = wrap_initial_token(&ap_req);
+        assert_eq!(
+            &token[..15],
+            &[
+                0x60, 0x12, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02, 0x01,
+                0x00
+            ]
+        );
+        assert_eq!(unwrap_initial_token(&token).unwrap(), &ap_req);
+
+        // A real AP-REQ is long enough to need the long form of the length.
+        let ap_req = vec![0x6e; 1000];
+        let token = wrap_initial_token(&ap_req);
+        assert_eq!(&token[..4], &[0x60, 0x82, 0x03, 0xf5]);
+        assert_eq!(unwrap_initial_token(&token).unwrap(), ap_req.as_slice());
+
+        assert!(matches!(
+            unwrap_initial_token(&token[..token.len() - 1]),
+            Err(KrbError::MalformedGssToken)
+        ));
+
+        // The SPNEGO mechanism OID 1.3.6.1.5.5.2, rather than Kerberos.
+        let spnego = [0x60, 0x08, 0x06, 0x06, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x02];
+        assert!(matches!(
+            unwrap_initial_token(&spnego),
+            Err(KrbError::UnsupportedGssMech)
+        ));
+    }
+
+    #[test]
+    fn test_gss_exported_name() {
+        let name = Name::SrvHst {
+            service: "host".to_string(),
+            host: "www.example.com".to_string(),
+            realm: "EXAMPLE.COM".to_string(),
+        };
+
+        let token = name.to_gss_exported();
+        let mut expected = vec![
+            0x04, 0x01, 0x00, 0x0b, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02,
+            0x02, 0x00, 0x00, 0x00, 0x20,
+        ];
+        expected.extend_from_slice(b"host/www.example.com@EXAMPLE.COM");
+        assert_eq!(token, expected);
+        assert_eq!(Name::from_gss_exported(&token).unwrap(), name);
+
+        // Separators within a component are escaped.
+        let name = Name::principal("odd/user@name", "EXAMPLE.COM");
+        let token = name.to_gss_exported();
+        assert!(token.ends_with(br"odd\/user\@name@EXAMPLE.COM"));
+        assert_eq!(Name::from_gss_exported(&token).unwrap(), name);
+
+        let tgs = Name::service_krbtgt("EXAMPLE.COM");
+        assert_eq!(
+            Name::from_gss_exported(&tgs.to_gss_exported()).unwrap(),
+            tgs
+        );
+
+        assert!(matches!(
+            Name::from_gss_exported(&token[..token.len() - 1]),
+            Err(KrbError::MalformedGssToken)
+        ));
+
+        // A name without a realm.
+        let mut token = expected[..15].to_vec();
+        token.extend_from_slice(&[0x00, 0x00, 0x00, 0x04]);
+        token.extend_from_slice(b"user");
+        assert!(matches!(
+            Name::from_gss_exported(&token),
+            Err(KrbError::MalformedPrincipalName)
+        ));
+    }
+}
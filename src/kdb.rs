@@ -0,0 +1,394 @@
+//! Loading principals and their keys from an MIT krb5 KDC database, as written by
+//! `kdb5_util dump`, with the master key from the KDC's stash file.
+
+use crate::asn1::{principal_name::PrincipalName, realm::Realm};
+#[cfg(feature = "des3")]
+use crate::constants::DES3_KEY_LEN;
+use crate::constants::{AES_256_KEY_LEN, RFC_PKBDF2_SHA1_ITER};
+use crate::crypto::decrypt_aes256_cts_hmac_sha1_96;
+use crate::error::KrbError;
+use crate::proto::{parse_name_components, DerivedKey, KdcPrimaryKey, Name};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::debug;
+
+// The encryption types of the keys we can use.
+const ENCTYPE_AES256_CTS_HMAC_SHA1_96: i32 = 18;
+#[cfg(feature = "des3")]
+const ENCTYPE_DES3_CBC_SHA1_KD: i32 = 16;
+
+// The MIT salt types, which say how the salt of a key is formed from the principal.
+const SALTTYPE_NORMAL: i32 = 0;
+const SALTTYPE_V4: i32 = 1;
+const SALTTYPE_NOREALM: i32 = 2;
+const SALTTYPE_ONLYREALM: i32 = 3;
+const SALTTYPE_SPECIAL: i32 = 4;
+
+// MIT encrypts the keys in the database in the master key with a key usage of 0.
+const MASTER_KEY_USAGE: i32 = 0;
+
+// The number of integer fields of a principal record between the name and the tl-data.
+const PRINC_ATTRIBUTE_FIELDS: usize = 8;
+
+/// A store of principals and their long term keys, which a KDC uses to authenticate
+/// clients and to issue tickets for services.
+pub trait PrincipalDb {
+    /// The master key of the database.
+    fn master_key(&self) -> &KdcPrimaryKey;
+
+    /// The principal with the same name components and realm as `name`. As in MIT krb5
+    /// the name type is not considered.
+    fn lookup(&self, name: &Name) -> Option<&PrincipalEntry>;
+}
+
+/// A long term key of a principal.
+#[derive(Debug)]
+pub struct PrincipalKey {
+    pub kvno: u32,
+    pub key: DerivedKey,
+}
+
+/// A principal from a `PrincipalDb`.
+#[derive(Debug, Default)]
+pub struct PrincipalEntry {
+    keys: Vec<PrincipalKey>,
+}
+
+impl PrincipalEntry {
+    /// The keys of the principal that we support, newest first.
+    pub fn keys(&self) -> &[PrincipalKey] {
+        &self.keys
+    }
+}
+
+/// A `PrincipalDb` loaded from the output of `kdb5_util dump`.
+#[derive(Debug)]
+pub struct DumpPrincipalDb {
+    master_key: KdcPrimaryKey,
+    principals: BTreeMap<(Vec<String>, String), PrincipalEntry>,
+}
+
+impl DumpPrincipalDb {
+    /// Load the database dump at `dump`, decrypting the keys with the master key from
+    /// the stash file at `stash`.
+    pub fn open<P: AsRef<Path>, S: AsRef<Path>>(dump: P, stash: S) -> io::Result<Self> {
+        let master_key = KdcPrimaryKey::from_stash(&fs::read(stash)?)?;
+        let dump = fs::read_to_string(dump)?;
+
+        Ok(Self::from_dump(&dump, master_key)?)
+    }
+
+    /// Parse a database dump, decrypting the keys with `master_key`.
+    ///
+    /// Keys with encryption types that we don't support are skipped, so a principal may
+    /// have no keys.
+    pub fn from_dump(dump: &str, master_key: KdcPrimaryKey) -> Result<Self, KrbError> {
+        let mut lines = dump.lines();
+
+        // Versions 6 and 7 only differ in the format of policies, which we don't read.
+        match lines.next() {
+            Some("kdb5_util load_dump version 6") | Some("kdb5_util load_dump version 7") => {}
+            header => {
+                debug!(?header, "unsupported dump version");
+                return Err(KrbError::MalformedPrincipalDump);
+            }
+        }
+
+        let mut principals = BTreeMap::new();
+
+        for line in lines {
+            let Some(record) = line.strip_prefix("princ\t") else {
+                continue;
+            };
+
+            let (name, entry) = parse_principal(record, &master_key)?;
+            principals.insert(name, entry);
+        }
+
+        Ok(DumpPrincipalDb {
+            master_key,
+            principals,
+        })
+    }
+}
+
+impl PrincipalDb for DumpPrincipalDb {
+    fn master_key(&self) -> &KdcPrimaryKey {
+        &self.master_key
+    }
+
+    fn lookup(&self, name: &Name) -> Option<&PrincipalEntry> {
+        let (principal_name, realm): (PrincipalName, Realm) = name.try_into().ok()?;
+        let components = principal_name
+            .name_string
+            .iter()
+            .map(|component| component.as_str().to_string())
+            .collect();
+
+        self.principals.get(&(components, realm.into()))
+    }
+}
+
+impl KdcPrimaryKey {
+    /// Load the master key from an MIT krb5 stash file. This is either a keytab holding
+    /// the master key, or the older format of the encryption type and key length in host
+    /// (little endian) byte order, followed by the key.
+    pub fn from_stash(data: &[u8]) -> Result<Self, KrbError> {
+        if let Some(entries) = data.strip_prefix(&[0x05, 0x02]) {
+            return stash_keytab_key(entries);
+        }
+
+        let (enctype, rest) = split_at(data, 2)?;
+        let (key_len, key) = split_at(rest, 4)?;
+        let enctype = u16::from_le_bytes([enctype[0], enctype[1]]);
+        let key_len = u32::from_le_bytes([key_len[0], key_len[1], key_len[2], key_len[3]]);
+
+        if i32::from(enctype) != ENCTYPE_AES256_CTS_HMAC_SHA1_96 || key_len as usize != key.len() {
+            return Err(KrbError::MalformedStashFile);
+        }
+
+        KdcPrimaryKey::try_from(key)
+    }
+}
+
+fn split_at(data: &[u8], mid: usize) -> Result<(&[u8], &[u8]), KrbError> {
+    if data.len() < mid {
+        return Err(KrbError::MalformedStashFile);
+    }
+    Ok(data.split_at(mid))
+}
+
+fn read_u16(data: &mut &[u8]) -> Result<u16, KrbError> {
+    let (value, rest) = split_at(data, 2)?;
+    *data = rest;
+    Ok(u16::from_be_bytes([value[0], value[1]]))
+}
+
+fn read_counted<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], KrbError> {
+    let len = read_u16(data)?;
+    let (value, rest) = split_at(data, len as usize)?;
+    *data = rest;
+    Ok(value)
+}
+
+// Find the master key in the entries of a version 2 keytab, which are in network byte
+// order. There is normally only one, but the first AES256 key is used.
+fn stash_keytab_key(mut data: &[u8]) -> Result<KdcPrimaryKey, KrbError> {
+    while !data.is_empty() {
+        let (size, rest) = split_at(data, 4)?;
+        let size = i32::from_be_bytes([size[0], size[1], size[2], size[3]]);
+        let (entry, rest) = split_at(rest, size.unsigned_abs() as usize)?;
+        data = rest;
+
+        // A negative size marks a deleted entry.
+        if size <= 0 {
+            continue;
+        }
+
+        let mut entry = entry;
+        let components = read_u16(&mut entry)?;
+        // The realm, then each component.
+        for _ in 0..=components {
+            read_counted(&mut entry)?;
+        }
+        // The name type, timestamp and 8 bit kvno.
+        let (_, mut entry) = split_at(entry, 9)?;
+        let enctype = read_u16(&mut entry)?;
+        let key = read_counted(&mut entry)?;
+
+        if i32::from(enctype) == ENCTYPE_AES256_CTS_HMAC_SHA1_96 {
+            return KdcPrimaryKey::try_from(key);
+        }
+    }
+
+    Err(KrbError::MalformedStashFile)
+}
+
+fn next_int<'a>(fields: &mut impl Iterator<Item = &'a str>) -> Result<i64, KrbError> {
+    fields
+        .next()
+        .and_then(|field| field.parse().ok())
+        .ok_or(KrbError::MalformedPrincipalDump)
+}
+
+fn parse_hex(field: &str, len: usize) -> Result<Vec<u8>, KrbError> {
+    // Empty contents are written as -1.
+    if len == 0 {
+        return Ok(Vec::with_capacity(0));
+    }
+
+    hex::decode(field)
+        .ok()
+        .filter(|data| data.len() == len)
+        .ok_or(KrbError::MalformedPrincipalDump)
+}
+
+// Parse the tab separated fields of a principal record, after the "princ" tag.
+fn parse_principal(
+    record: &str,
+    master_key: &KdcPrimaryKey,
+) -> Result<((Vec<String>, String), PrincipalEntry), KrbError> {
+    let mut fields = record.trim_end_matches(';').split('\t');
+
+    // The record length, name length, number of tl-data, number of key-data and the
+    // length of the extra data.
+    let _len = next_int(&mut fields)?;
+    let _name_len = next_int(&mut fields)?;
+    let n_tl_data = next_int(&mut fields)?;
+    let n_key_data = next_int(&mut fields)?;
+    let _e_length = next_int(&mut fields)?;
+
+    let name = fields.next().ok_or(KrbError::MalformedPrincipalDump)?;
+    let (components, realm) =
+        parse_name_components(name).map_err(|_| KrbError::MalformedPrincipalDump)?;
+
+    // The attributes, lifetimes, expirations and authentication counters.
+    for _ in 0..PRINC_ATTRIBUTE_FIELDS {
+        next_int(&mut fields)?;
+    }
+
+    for _ in 0..n_tl_data {
+        let _tl_type = next_int(&mut fields)?;
+        let _tl_len = next_int(&mut fields)?;
+        fields.next().ok_or(KrbError::MalformedPrincipalDump)?;
+    }
+
+    let mut keys = Vec::new();
+
+    for _ in 0..n_key_data {
+        let ver = next_int(&mut fields)?;
+        let kvno = next_int(&mut fields)?;
+
+        // The key, then the salt if the version includes it.
+        let mut data = Vec::with_capacity(2);
+        for _ in 0..ver {
+            let data_type = next_int(&mut fields)?;
+            let len = next_int(&mut fields)?;
+            let contents = fields.next().ok_or(KrbError::MalformedPrincipalDump)?;
+            let len = usize::try_from(len).map_err(|_| KrbError::MalformedPrincipalDump)?;
+            data.push((data_type, parse_hex(contents, len)?));
+        }
+
+        let mut data = data.into_iter();
+        let (enctype, key) = data.next().ok_or(KrbError::MalformedPrincipalDump)?;
+        let (salt_type, salt) = data
+            .next()
+            .unwrap_or((i64::from(SALTTYPE_NORMAL), Vec::new()));
+
+        let kvno = u32::try_from(kvno).map_err(|_| KrbError::MalformedPrincipalDump)?;
+        let enctype = i32::try_from(enctype).map_err(|_| KrbError::MalformedPrincipalDump)?;
+        let salt_type = i32::try_from(salt_type).map_err(|_| KrbError::MalformedPrincipalDump)?;
+
+        let salt = match salt_type {
+            SALTTYPE_NORMAL => format!("{}{}", realm, components.concat()).into_bytes(),
+            SALTTYPE_V4 => Vec::new(),
+            SALTTYPE_NOREALM => components.concat().into_bytes(),
+            SALTTYPE_ONLYREALM => realm.clone().into_bytes(),
+            SALTTYPE_SPECIAL => salt,
+            _ => {
+                debug!(%name, %salt_type, "skipping key with unsupported salt type");
+                continue;
+            }
+        };
+
+        let Some(key) = decrypt_key(enctype, &key, salt, master_key)? else {
+            debug!(%name, %enctype, "skipping key with unsupported encryption type");
+            continue;
+        };
+
+        keys.push(PrincipalKey { kvno, key });
+    }
+
+    keys.sort_by_key(|key| std::cmp::Reverse(key.kvno));
+
+    Ok(((components, realm), PrincipalEntry { keys }))
+}
+
+// Decrypt a key from the database. The stored key is the length of the key as a 16 bit
+// little endian integer, followed by the key encrypted in the master key.
+fn decrypt_key(
+    enctype: i32,
+    data: &[u8],
+    salt: Vec<u8>,
+    master_key: &KdcPrimaryKey,
+) -> Result<Option<DerivedKey>, KrbError> {
+    let key_len = match enctype {
+        ENCTYPE_AES256_CTS_HMAC_SHA1_96 => AES_256_KEY_LEN,
+        #[cfg(feature = "des3")]
+        ENCTYPE_DES3_CBC_SHA1_KD => DES3_KEY_LEN,
+        _ => return Ok(None),
+    };
+
+    if data.len() < 2 || usize::from(u16::from_le_bytes([data[0], data[1]])) != key_len {
+        return Err(KrbError::MalformedPrincipalDump);
+    }
+
+    let KdcPrimaryKey::Aes256 { k } = master_key;
+    let key = decrypt_aes256_cts_hmac_sha1_96(k, &data[2..], MASTER_KEY_USAGE)?;
+    let key = key.get(..key_len).ok_or(KrbError::MalformedPrincipalDump)?;
+
+    match enctype {
+        #[cfg(feature = "des3")]
+        ENCTYPE_DES3_CBC_SHA1_KD => {
+            let mut k = [0u8; DES3_KEY_LEN];
+            k.copy_from_slice(key);
+            Ok(Some(DerivedKey::Des3CbcSha1 { k, s: salt }))
+        }
+        _ => {
+            let mut k = [0u8; AES_256_KEY_LEN];
+            k.copy_from_slice(key);
+            Ok(Some(DerivedKey::Aes256CtsHmacSha196 {
+                k,
+                i: RFC_PKBDF2_SHA1_ITER,
+                s: salt,
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DumpPrincipalDb, PrincipalDb};
+    use crate::proto::{DerivedKey, KdcPrimaryKey, Name};
+
+    #[test]
+    fn test_principal_db_from_dump() {
+        let master_key =
+            KdcPrimaryKey::from_stash(include_bytes!("../samples/k5.EXAMPLE.COM.stash"))
+                .expect("Failed to load stash file");
+        let db = DumpPrincipalDb::from_dump(include_str!("../samples/principal.dump"), master_key)
+            .expect("Failed to load dump");
+
+        let entry = db
+            .lookup(&Name::principal("testuser", "EXAMPLE.COM"))
+            .expect("Principal not found");
+        assert_eq!(entry.keys().len(), 2);
+        assert_eq!(entry.keys()[0].kvno, 2);
+
+        let expected = DerivedKey::new_aes256_cts_hmac_sha1_96("password", "EXAMPLE.COMtestuser")
+            .expect("Failed to derive key");
+        match (&entry.keys()[0].key, expected) {
+            (
+                DerivedKey::Aes256CtsHmacSha196 { k, s, .. },
+                DerivedKey::Aes256CtsHmacSha196 { k: expected, .. },
+            ) => {
+                assert_eq!(k, &expected);
+                assert_eq!(s, b"EXAMPLE.COMtestuser");
+            }
+            #[cfg(feature = "des3")]
+            _ => unreachable!(),
+        }
+
+        // The aes128 key of krbtgt is skipped.
+        let krbtgt = db
+            .lookup(&Name::service_krbtgt("EXAMPLE.COM"))
+            .expect("Principal not found");
+        assert_eq!(krbtgt.keys().len(), 1);
+
+        assert!(db
+            .lookup(&Name::principal("nobody", "EXAMPLE.COM"))
+            .is_none());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::asn1::BitString;
+use crate::error::KrbError;
+
+// APOptions are numbered from the most significant bit of the first octet, as the
+// KDCOptions are. Bit 0 is reserved.
+const AP_OPTION_USE_SESSION_KEY: u8 = 0x40;
+const AP_OPTION_MUTUAL_REQUIRED: u8 = 0x20;
+
+/// The options that a client sets on an AP-REQ. Options that are not set are not
+/// requested.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ApOptions {
+    use_session_key: bool,
+    mutual_required: bool,
+}
+
+impl ApOptions {
+    /// Indicate that the ticket is a user-to-user ticket, encrypted in the session key
+    /// of the service's ticket granting ticket.
+    pub fn use_session_key(mut self, use_session_key: bool) -> Self {
+        self.use_session_key = use_session_key;
+        self
+    }
+
+    /// Ask the service to authenticate itself to the client with an AP-REP.
+    pub fn mutual_required(mut self, mutual_required: bool) -> Self {
+        self.mutual_required = mutual_required;
+        self
+    }
+
+    pub(crate) fn is_use_session_key(self) -> bool {
+        self.use_session_key
+    }
+
+    pub(crate) fn is_mutual_required(self) -> bool {
+        self.mutual_required
+    }
+
+    /// Encode as the APOptions bit string, which like all KerberosFlags is at least
+    /// 32 bits.
+    pub(crate) fn to_bit_string(self) -> Result<BitString, KrbError> {
+        let mut options = 0;
+
+        if self.use_session_key {
+            options |= AP_OPTION_USE_SESSION_KEY;
+        }
+
+        if self.mutual_required {
+            options |= AP_OPTION_MUTUAL_REQUIRED;
+        }
+
+        BitString::from_bytes(&[options, 0x00, 0x00, 0x00]).map_err(|_| KrbError::DerEncodeApReq)
+    }
+
+    pub(crate) fn from_bit_string(bit_string: &BitString) -> Self {
+        let options = bit_string.raw_bytes().first().copied().unwrap_or_default();
+
+        ApOptions {
+            use_session_key: options & AP_OPTION_USE_SESSION_KEY != 0,
+            mutual_required: options & AP_OPTION_MUTUAL_REQUIRED != 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ApOptions;
+
+    #[test]
+    fn test_ap_options_bit_string() {
+        // mutual-required is bit 2.
+        let options = ApOptions::default().mutual_required(true);
+
+        let bit_string = options.to_bit_string().unwrap();
+        assert_eq!(bit_string.raw_bytes(), &[0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(ApOptions::from_bit_string(&bit_string), options);
+
+        // And use-session-key is bit 1.
+        let options = options.use_session_key(true);
+
+        let bit_string = options.to_bit_string().unwrap();
+        assert_eq!(bit_string.raw_bytes(), &[0x60, 0x00, 0x00, 0x00]);
+        assert_eq!(ApOptions::from_bit_string(&bit_string), options);
+    }
+}
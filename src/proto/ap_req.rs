@@ -0,0 +1,125 @@
+use super::request::encode_authenticator;
+use super::{ApOptions, EncryptedData, Name, SessionKey, Ticket};
+use crate::asn1::{
+    ap_req::{ApReq, TaggedApReq},
+    constants::message_types::KrbMessageType,
+};
+use crate::clock::Clock;
+use crate::error::KrbError;
+use der::{Decode, Encode};
+
+/// An AP-REQ, authenticating a client to a service with a ticket.
+#[derive(Debug, Clone)]
+pub struct ApRequest {
+    ap_options: ApOptions,
+    ticket: Ticket,
+    authenticator: EncryptedData,
+}
+
+#[derive(Debug)]
+pub struct KerberosApRequestBuilder {
+    ap_options: ApOptions,
+    ticket: Ticket,
+}
+
+impl ApRequest {
+    pub fn builder(ticket: Ticket) -> KerberosApRequestBuilder {
+        KerberosApRequestBuilder {
+            ap_options: ApOptions::default(),
+            ticket,
+        }
+    }
+
+    pub fn ap_options(&self) -> ApOptions {
+        self.ap_options
+    }
+
+    /// If the ticket is encrypted in the session key of the service's ticket granting
+    /// ticket, rather than its long term key, as in user-to-user authentication.
+    pub fn use_session_key(&self) -> bool {
+        self.ap_options.is_use_session_key()
+    }
+
+    /// If the client requires the service to authenticate itself with an AP-REP.
+    pub fn mutual_required(&self) -> bool {
+        self.ap_options.is_mutual_required()
+    }
+
+    pub fn ticket(&self) -> &Ticket {
+        &self.ticket
+    }
+
+    pub fn to_der(&self) -> Result<Vec<u8>, KrbError> {
+        TaggedApReq::new(ApReq {
+            pvno: 5,
+            msg_type: KrbMessageType::KrbApReq as u8,
+            ap_options: self.ap_options.to_bit_string()?,
+            ticket: self.ticket.clone().try_into()?,
+            authenticator: self.authenticator.clone().try_into()?,
+        })
+        .to_der()
+        .map_err(|_| KrbError::DerEncodeApReq)
+    }
+
+    pub fn from_der(data: &[u8]) -> Result<Self, KrbError> {
+        let TaggedApReq(ap_req) =
+            TaggedApReq::from_der(data).map_err(|_| KrbError::DerDecodeApReq)?;
+
+        if ap_req.pvno != 5 {
+            return Err(KrbError::InvalidPvno);
+        }
+
+        if ap_req.msg_type != KrbMessageType::KrbApReq as u8 {
+            return Err(KrbError::InvalidMessageType);
+        }
+
+        Ok(ApRequest {
+            ap_options: ApOptions::from_bit_string(&ap_req.ap_options),
+            ticket: Ticket::try_from(ap_req.ticket)?,
+            authenticator: EncryptedData::try_from(ap_req.authenticator)?,
+        })
+    }
+}
+
+impl KerberosApRequestBuilder {
+    /// Indicate that the ticket is a user-to-user ticket, encrypted in the session key
+    /// of the service's ticket granting ticket.
+    pub fn use_session_key(mut self, use_session_key: bool) -> Self {
+        self.ap_options = self.ap_options.use_session_key(use_session_key);
+        self
+    }
+
+    pub fn ap_options(mut self, ap_options: ApOptions) -> Self {
+        self.ap_options = ap_options;
+        self
+    }
+
+    /// Build the request, authenticating as `client_name` with the session key that
+    /// was issued alongside the ticket. The authenticator timestamp is taken from `clock`.
+    pub fn build(
+        self,
+        client_name: &Name,
+        session_key: &SessionKey,
+        clock: &impl Clock,
+    ) -> Result<ApRequest, KrbError> {
+        let KerberosApRequestBuilder { ap_options, ticket } = self;
+
+        // A kvno is only sent with data encrypted in a long term key (RFC 4120 5.2.9),
+        // so a ticket with one can't be a user-to-user ticket.
+        if ap_options.is_use_session_key() && ticket.kvno().is_some() {
+            return Err(KrbError::InvalidApOptions);
+        }
+
+        let authenticator = encode_authenticator(client_name, None, clock)?;
+
+        // RFC 4120 The AP-REQ authenticator is encrypted in the session key with
+        // key usage 11.
+        let authenticator = session_key.encrypt_data(&authenticator, 11)?;
+
+        Ok(ApRequest {
+            ap_options,
+            ticket,
+            authenticator,
+        })
+    }
+}
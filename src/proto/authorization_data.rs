@@ -0,0 +1,69 @@
+use super::{EncryptedData, SessionKey};
+use crate::asn1::{authorization_data::AuthorizationData as KdcAuthorizationData, OctetString};
+use crate::error::KrbError;
+use der::{Decode, Encode};
+
+// RFC 4120 5.4.1 The enc-authorization-data of a TGS-REQ is encrypted in the session
+// key of the ticket granting ticket with key usage 4, or in the authenticator subkey
+// with key usage 5. We never send a subkey.
+const ENC_AUTHORIZATION_DATA_KEY_USAGE: i32 = 4;
+
+/// An element of authorization data, whose content is interpreted according to its
+/// ad-type, as registered in RFC 4120 7.5.4.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationData {
+    ad_type: i32,
+    ad_data: Vec<u8>,
+}
+
+impl AuthorizationData {
+    pub fn new(ad_type: i32, ad_data: Vec<u8>) -> Self {
+        AuthorizationData { ad_type, ad_data }
+    }
+
+    pub fn ad_type(&self) -> i32 {
+        self.ad_type
+    }
+
+    pub fn ad_data(&self) -> &[u8] {
+        &self.ad_data
+    }
+}
+
+pub(crate) fn encrypt_authorization_data(
+    authorization_data: &[AuthorizationData],
+    session_key: &SessionKey,
+) -> Result<EncryptedData, KrbError> {
+    let data = authorization_data
+        .iter()
+        .map(|element| {
+            OctetString::new(element.ad_data.as_slice()).map(|ad_data| KdcAuthorizationData {
+                ad_type: element.ad_type,
+                ad_data,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|authorization_data| authorization_data.to_der())
+        .map_err(|_| KrbError::DerEncodeAuthorizationData)?;
+
+    session_key.encrypt_data(&data, ENC_AUTHORIZATION_DATA_KEY_USAGE)
+}
+
+pub(crate) fn decrypt_authorization_data(
+    enc_authorization_data: &EncryptedData,
+    session_key: &SessionKey,
+) -> Result<Vec<AuthorizationData>, KrbError> {
+    let data =
+        session_key.decrypt_data(enc_authorization_data, ENC_AUTHORIZATION_DATA_KEY_USAGE)?;
+
+    let authorization_data = Vec::<KdcAuthorizationData>::from_der(&data)
+        .map_err(|_| KrbError::DerDecodeAuthorizationData)?;
+
+    Ok(authorization_data
+        .into_iter()
+        .map(|element| AuthorizationData {
+            ad_type: element.ad_type,
+            ad_data: element.ad_data.into_bytes(),
+        })
+        .collect())
+}
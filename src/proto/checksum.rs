@@ -0,0 +1,107 @@
+//! Keyed checksums (RFC 3961 get_mic/verify_mic) over a session key. The checksum
+//! type is always the mandatory checksum of the key's encryption type.
+
+use super::SessionKey;
+#[cfg(feature = "des3")]
+use crate::crypto::checksum_hmac_sha1_des3;
+use crate::crypto::{checksum_hmac_sha1_96_aes256, constant_time_eq};
+use crate::error::KrbError;
+
+/// hmac-sha1-96-aes256
+const CKSUMTYPE_HMAC_SHA1_96_AES256: i32 = 16;
+/// hmac-sha1-des3-kd
+#[cfg(feature = "des3")]
+const CKSUMTYPE_HMAC_SHA1_DES3_KD: i32 = 12;
+
+/// Compute the keyed checksum of data, returning the checksum type and value.
+pub fn make_checksum(
+    key: &SessionKey,
+    key_usage: i32,
+    data: &[u8],
+) -> Result<(i32, Vec<u8>), KrbError> {
+    match key {
+        SessionKey::Aes256CtsHmacSha196 { k } => checksum_hmac_sha1_96_aes256(k, data, key_usage)
+            .map(|cksum| (CKSUMTYPE_HMAC_SHA1_96_AES256, cksum)),
+        #[cfg(feature = "des3")]
+        SessionKey::Des3CbcSha1 { k } => checksum_hmac_sha1_des3(k, data, key_usage)
+            .map(|cksum| (CKSUMTYPE_HMAC_SHA1_DES3_KD, cksum)),
+    }
+}
+
+/// Verify the keyed checksum of data. The checksum type must be the one that
+/// matches the key's encryption type.
+pub fn verify_checksum(
+    key: &SessionKey,
+    key_usage: i32,
+    data: &[u8],
+    checksum_type: i32,
+    checksum: &[u8],
+) -> Result<(), KrbError> {
+    let (expected_type, expected) = make_checksum(key, key_usage, data)?;
+
+    if checksum_type != expected_type {
+        return Err(KrbError::UnsupportedChecksum);
+    }
+
+    if constant_time_eq(checksum, &expected) {
+        Ok(())
+    } else {
+        Err(KrbError::MessageAuthenticationFailed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{make_checksum, verify_checksum};
+    use crate::error::KrbError;
+    use crate::proto::SessionKey;
+
+    #[test]
+    fn test_checksum_aes256() {
+        // Generated with MIT krb5's krb5_c_make_checksum.
+        let mut k = [0u8; 32];
+        hex::decode_to_slice(
+            "b1ae4cd8462aff1658b3e04062289f643aa189b02f715e6d3bf2c8e2706c8f3f",
+            &mut k,
+        )
+        .unwrap();
+        let key = SessionKey::Aes256CtsHmacSha196 { k };
+
+        let (checksum_type, checksum) = make_checksum(&key, 6, b"kerberos checksum").unwrap();
+
+        assert_eq!(checksum_type, 16);
+        assert_eq!(hex::encode(&checksum), "ac1ef83578f854b87def3177");
+
+        assert!(verify_checksum(&key, 6, b"kerberos checksum", 16, &checksum).is_ok());
+        assert!(matches!(
+            verify_checksum(&key, 7, b"kerberos checksum", 16, &checksum),
+            Err(KrbError::MessageAuthenticationFailed)
+        ));
+        assert!(matches!(
+            verify_checksum(&key, 6, b"kerberos checksun", 16, &checksum),
+            Err(KrbError::MessageAuthenticationFailed)
+        ));
+        assert!(matches!(
+            verify_checksum(&key, 6, b"kerberos checksum", 19, &checksum),
+            Err(KrbError::UnsupportedChecksum)
+        ));
+    }
+
+    #[cfg(feature = "des3")]
+    #[test]
+    fn test_checksum_des3() {
+        // Generated with MIT krb5's krb5_c_make_checksum.
+        let mut k = [0u8; 24];
+        hex::decode_to_slice("850bb51358548cd05e86768c313e3bfef7511937dcf72c3e", &mut k).unwrap();
+        let key = SessionKey::Des3CbcSha1 { k };
+
+        let (checksum_type, checksum) = make_checksum(&key, 6, b"kerberos checksum").unwrap();
+
+        assert_eq!(checksum_type, 12);
+        assert_eq!(
+            hex::encode(&checksum),
+            "562f45addf845e541df75ded00ab5843f8f69eb9"
+        );
+        assert!(verify_checksum(&key, 6, b"kerberos checksum", 12, &checksum).is_ok());
+    }
+}
@@ -0,0 +1,306 @@
+use super::{EncryptedData, KdcReplyPart, Name, SessionKey, Ticket};
+use crate::asn1::{
+    constants::message_types::KrbMessageType,
+    enc_krb_cred_part::{EncKrbCredPart, TaggedEncKrbCredPart},
+    encryption_key::EncryptionKey as KdcEncryptionKey,
+    kerberos_time::KerberosTime,
+    krb_cred::{KrbCred as Asn1KrbCred, TaggedKrbCred},
+    krb_cred_info::KrbCredInfo as Asn1KrbCredInfo,
+    tagged_ticket::TaggedTicket as Asn1Ticket,
+    ticket_flags::TicketFlags,
+};
+use crate::error::KrbError;
+use der::{flagset::FlagSet, Decode, Encode};
+use std::time::SystemTime;
+
+// RFC 4120 The key usage value for encrypting the EncKrbCredPart is 14, using the
+// session key or subkey established with the recipient.
+const KRB_CRED_KEY_USAGE: i32 = 14;
+
+/// A KRB-CRED message, used to forward tickets and their session keys to another
+/// party, such as during GSSAPI credential delegation.
+#[derive(Debug, Clone)]
+pub struct KrbCred {
+    tickets: Vec<Ticket>,
+    enc_part: EncryptedData,
+}
+
+/// The details of a forwarded ticket, carried in the encrypted part of a [KrbCred].
+#[derive(Debug, Clone)]
+pub struct KrbCredInfo {
+    key: SessionKey,
+    client: Option<Name>,
+    flags: FlagSet<TicketFlags>,
+    auth_time: Option<SystemTime>,
+    start_time: Option<SystemTime>,
+    end_time: Option<SystemTime>,
+    renew_until: Option<SystemTime>,
+    server: Option<Name>,
+}
+
+impl KrbCredInfo {
+    /// The credential info for the ticket that was issued to `client` with `reply_part`.
+    pub fn new(client: Name, reply_part: &KdcReplyPart) -> Self {
+        KrbCredInfo {
+            key: reply_part.key.clone(),
+            client: Some(client),
+            flags: reply_part.flags,
+            auth_time: Some(reply_part.auth_time),
+            start_time: reply_part.start_time,
+            end_time: Some(reply_part.end_time),
+            renew_until: reply_part.renew_until,
+            server: Some(reply_part.server.clone()),
+        }
+    }
+
+    /// The session key that must be used with the forwarded ticket.
+    pub fn session_key(&self) -> &SessionKey {
+        &self.key
+    }
+
+    pub fn client(&self) -> Option<&Name> {
+        self.client.as_ref()
+    }
+
+    pub fn server(&self) -> Option<&Name> {
+        self.server.as_ref()
+    }
+
+    pub fn end_time(&self) -> Option<SystemTime> {
+        self.end_time
+    }
+}
+
+impl KrbCred {
+    /// Build a KRB-CRED forwarding each ticket along with its credential info. The
+    /// credential info is encrypted with `key`, which is the session key or subkey
+    /// shared with the recipient.
+    pub fn new(
+        credentials: Vec<(Ticket, KrbCredInfo)>,
+        key: &SessionKey,
+    ) -> Result<Self, KrbError> {
+        let (tickets, ticket_info): (Vec<_>, Vec<_>) = credentials.into_iter().unzip();
+
+        let ticket_info = ticket_info
+            .into_iter()
+            .map(Asn1KrbCredInfo::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let enc_krb_cred_part = TaggedEncKrbCredPart::new(EncKrbCredPart {
+            ticket_info,
+            nonce: None,
+            timestamp: None,
+            usec: None,
+            s_address: None,
+            r_address: None,
+        })
+        .to_der()
+        .map_err(|_| KrbError::DerEncodeEncKrbCredPart)?;
+
+        let enc_part = key.encrypt_data(&enc_krb_cred_part, KRB_CRED_KEY_USAGE)?;
+
+        Ok(KrbCred { tickets, enc_part })
+    }
+
+    /// The forwarded tickets, in the same order as the credential info they were
+    /// sent with.
+    pub fn tickets(&self) -> &[Ticket] {
+        &self.tickets
+    }
+
+    /// Decrypt the credential info of the forwarded tickets with the session key or
+    /// subkey shared with the sender.
+    pub fn decrypt(&self, key: &SessionKey) -> Result<Vec<KrbCredInfo>, KrbError> {
+        let data = key.decrypt_data(&self.enc_part, KRB_CRED_KEY_USAGE)?;
+
+        let TaggedEncKrbCredPart(enc_krb_cred_part) =
+            TaggedEncKrbCredPart::from_der(&data).map_err(|_| KrbError::DerDecodeEncKrbCredPart)?;
+
+        // Each credential info describes the ticket at the same position, so there must
+        // be one for every ticket.
+        if enc_krb_cred_part.ticket_info.len() != self.tickets.len() {
+            return Err(KrbError::InvalidCredentialInfo);
+        }
+
+        enc_krb_cred_part
+            .ticket_info
+            .into_iter()
+            .map(KrbCredInfo::try_from)
+            .collect()
+    }
+
+    pub fn to_der(&self) -> Result<Vec<u8>, KrbError> {
+        let tickets = self
+            .tickets
+            .iter()
+            .cloned()
+            .map(|ticket| ticket.try_into())
+            .collect::<Result<Vec<Asn1Ticket>, _>>()?;
+
+        TaggedKrbCred::new(Asn1KrbCred {
+            pvno: 5,
+            msg_type: KrbMessageType::KrbCred as u8,
+            tickets,
+            enc_part: self.enc_part.clone().try_into()?,
+        })
+        .to_der()
+        .map_err(|_| KrbError::DerEncodeKrbCred)
+    }
+
+    pub fn from_der(data: &[u8]) -> Result<Self, KrbError> {
+        let TaggedKrbCred(krb_cred) =
+            TaggedKrbCred::from_der(data).map_err(|_| KrbError::DerDecodeKrbCred)?;
+
+        if krb_cred.pvno != 5 {
+            return Err(KrbError::InvalidPvno);
+        }
+
+        if krb_cred.msg_type != KrbMessageType::KrbCred as u8 {
+            return Err(KrbError::InvalidMessageType);
+        }
+
+        let tickets = krb_cred
+            .tickets
+            .into_iter()
+            .map(Ticket::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        let enc_part = EncryptedData::try_from(krb_cred.enc_part)?;
+
+        Ok(KrbCred { tickets, enc_part })
+    }
+}
+
+impl TryFrom<KrbCredInfo> for Asn1KrbCredInfo {
+    type Error = KrbError;
+
+    fn try_from(info: KrbCredInfo) -> Result<Self, Self::Error> {
+        let kerberos_time = |time: Option<SystemTime>| {
+            time.map(|t| {
+                KerberosTime::from_system_time(t).map_err(|_| KrbError::DerEncodeKerberosTime)
+            })
+            .transpose()
+        };
+
+        let (pname, prealm) = match &info.client {
+            Some(client) => {
+                let (pname, prealm) = client.try_into()?;
+                (Some(pname), Some(prealm))
+            }
+            None => (None, None),
+        };
+
+        let (sname, srealm) = match &info.server {
+            Some(server) => {
+                let (sname, srealm) = server.try_into()?;
+                (Some(sname), Some(srealm))
+            }
+            None => (None, None),
+        };
+
+        Ok(Asn1KrbCredInfo {
+            key: KdcEncryptionKey::try_from(&info.key)?,
+            prealm,
+            pname,
+            flags: Some(info.flags),
+            auth_time: kerberos_time(info.auth_time)?,
+            start_time: kerberos_time(info.start_time)?,
+            end_time: kerberos_time(info.end_time)?,
+            renew_till: kerberos_time(info.renew_until)?,
+            srealm,
+            sname,
+            caddr: None,
+        })
+    }
+}
+
+impl TryFrom<Asn1KrbCredInfo> for KrbCredInfo {
+    type Error = KrbError;
+
+    fn try_from(info: Asn1KrbCredInfo) -> Result<Self, Self::Error> {
+        let key = SessionKey::try_from(info.key)?;
+
+        let client = match (info.pname, info.prealm) {
+            (Some(pname), Some(prealm)) => Some(Name::try_from((pname, prealm))?),
+            _ => None,
+        };
+
+        let server = match (info.sname, info.srealm) {
+            (Some(sname), Some(srealm)) => Some(Name::try_from((sname, srealm))?),
+            _ => None,
+        };
+
+        Ok(KrbCredInfo {
+            key,
+            client,
+            flags: info.flags.unwrap_or_default(),
+            auth_time: info.auth_time.map(|t| t.to_system_time()),
+            start_time: info.start_time.map(|t| t.to_system_time()),
+            end_time: info.end_time.map(|t| t.to_system_time()),
+            renew_until: info.renew_till.map(|t| t.to_system_time()),
+            server,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::KrbError;
+    use crate::proto::{KrbCred, KrbCredInfo, Name, SessionKey};
+    use crate::test_helpers::{reply_part_valid_between, session_key, ticket};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_krb_cred_round_trip() {
+        let session_key = session_key();
+        let client = Name::principal("testuser", "EXAMPLE.COM");
+        let reply_part = reply_part_valid_between(None, 2_000);
+
+        let ticket = ticket(Name::service_krbtgt("EXAMPLE.COM"));
+
+        let krb_cred = KrbCred::new(
+            vec![(ticket, KrbCredInfo::new(client.clone(), &reply_part))],
+            &session_key,
+        )
+        .expect("Failed to build KRB-CRED");
+
+        let der = krb_cred.to_der().expect("Failed to encode KRB-CRED");
+        // KRB-CRED is [APPLICATION 22]
+        assert_eq!(der[0], 0x76);
+
+        let krb_cred = KrbCred::from_der(&der).expect("Failed to decode KRB-CRED");
+
+        assert_eq!(krb_cred.tickets().len(), 1);
+        assert_eq!(
+            krb_cred.tickets()[0].service(),
+            &Name::service_krbtgt("EXAMPLE.COM")
+        );
+
+        let ticket_info = krb_cred
+            .decrypt(&session_key)
+            .expect("Failed to decrypt KRB-CRED");
+
+        assert_eq!(ticket_info.len(), 1);
+        assert_eq!(ticket_info[0].client(), Some(&client));
+        assert_eq!(ticket_info[0].server(), Some(reply_part.server()));
+        assert_eq!(
+            ticket_info[0].end_time(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(2_000))
+        );
+        assert!(matches!(
+            ticket_info[0].session_key(),
+            SessionKey::Aes256CtsHmacSha196 { k } if k == &[0x55; 32]
+        ));
+
+        // The credential info can only be read with the shared key.
+        let other_key = SessionKey::Aes256CtsHmacSha196 { k: [0xbb; 32] };
+        assert!(krb_cred.decrypt(&other_key).is_err());
+
+        // A ticket without credential info is rejected.
+        let mut krb_cred = krb_cred;
+        krb_cred.tickets.push(krb_cred.tickets[0].clone());
+        assert!(matches!(
+            krb_cred.decrypt(&session_key),
+            Err(KrbError::InvalidCredentialInfo)
+        ));
+    }
+}
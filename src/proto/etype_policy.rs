@@ -0,0 +1,166 @@
+use crate::asn1::constants::encryption_types::EncryptionType;
+use crate::crypto::supported_etypes;
+use crate::error::KrbError;
+use std::str::FromStr;
+
+/// The etypes that a client is willing to use, in order of preference. Requests built
+/// under a policy only advertise the etypes it permits, and replies in any other etype
+/// are rejected with [KrbError::EtypeNotPermitted].
+///
+/// The default policy permits every etype that this build supports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EtypePolicy {
+    etypes: Vec<EncryptionType>,
+}
+
+impl Default for EtypePolicy {
+    fn default() -> Self {
+        EtypePolicy {
+            etypes: supported_etypes().to_vec(),
+        }
+    }
+}
+
+impl EtypePolicy {
+    /// A policy that permits only `etypes`, preferring them in the order given. Etypes
+    /// that this build doesn't support may be named, so that one policy can be shared
+    /// between builds, but are never advertised.
+    pub fn new(etypes: &[EncryptionType]) -> Self {
+        let mut permitted = Vec::with_capacity(etypes.len());
+        for etype in etypes {
+            if !permitted.contains(etype) {
+                permitted.push(*etype);
+            }
+        }
+
+        EtypePolicy { etypes: permitted }
+    }
+
+    /// The etypes to advertise in a request, which are those the policy permits that
+    /// this build supports, in the order of the policy.
+    pub fn etypes(&self) -> Vec<EncryptionType> {
+        self.etypes
+            .iter()
+            .copied()
+            .filter(|etype| supported_etypes().contains(etype))
+            .collect()
+    }
+
+    pub fn permits(&self, etype: EncryptionType) -> bool {
+        self.etypes.contains(&etype)
+    }
+
+    /// Check that a reply in `etype` is permitted by the policy.
+    pub fn check(&self, etype: EncryptionType) -> Result<(), KrbError> {
+        if self.permits(etype) {
+            Ok(())
+        } else {
+            Err(KrbError::EtypeNotPermitted(etype))
+        }
+    }
+}
+
+impl FromStr for EtypePolicy {
+    type Err = KrbError;
+
+    /// Parse a policy from a list of etype names separated by whitespace or commas, such
+    /// as `"aes256-cts aes128-cts"`.
+    fn from_str(etypes: &str) -> Result<Self, Self::Err> {
+        let etypes = etypes
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|name| !name.is_empty())
+            .map(EncryptionType::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(EtypePolicy::new(&etypes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EtypePolicy;
+    use crate::error::KrbError;
+    use crate::proto::EncryptionType;
+
+    #[test]
+    fn test_etype_policy_rejects_unpermitted_etype() {
+        let policy = EtypePolicy::new(&[
+            EncryptionType::AES128_CTS_HMAC_SHA1_96,
+            EncryptionType::AES128_CTS_HMAC_SHA1_96,
+        ]);
+
+        // aes128 is permitted, but not supported, so there is nothing to advertise.
+        assert!(policy.permits(EncryptionType::AES128_CTS_HMAC_SHA1_96));
+        assert!(policy.etypes().is_empty());
+        assert!(policy
+            .check(EncryptionType::AES128_CTS_HMAC_SHA1_96)
+            .is_ok());
+
+        // A reply in aes256 is rejected, even though this build supports it.
+        assert!(!policy.permits(EncryptionType::AES256_CTS_HMAC_SHA1_96));
+        assert!(matches!(
+            policy.check(EncryptionType::AES256_CTS_HMAC_SHA1_96),
+            Err(KrbError::EtypeNotPermitted(
+                EncryptionType::AES256_CTS_HMAC_SHA1_96
+            ))
+        ));
+
+        let policy = EtypePolicy::default();
+        assert!(policy
+            .check(EncryptionType::AES256_CTS_HMAC_SHA1_96)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_etype_names() {
+        use crate::proto::EtypePolicy;
+
+        for name in ["aes256-cts-hmac-sha1-96", "aes256-cts", "AES256-CTS"] {
+            assert_eq!(
+                name.parse::<EncryptionType>()
+                    .expect("Unknown encryption type"),
+                EncryptionType::AES256_CTS_HMAC_SHA1_96
+            );
+        }
+        for name in ["aes128-cts-hmac-sha1-96", "aes128-cts", "aes128-sha1"] {
+            assert_eq!(
+                EncryptionType::try_from(name).expect("Unknown encryption type"),
+                EncryptionType::AES128_CTS_HMAC_SHA1_96
+            );
+        }
+
+        // Every etype is displayed by a name that parses back to it.
+        for value in 0..=26 {
+            if let Ok(etype) = EncryptionType::try_from(value) {
+                assert_eq!(
+                    etype
+                        .to_string()
+                        .parse::<EncryptionType>()
+                        .expect("Unknown encryption type"),
+                    etype
+                );
+            }
+        }
+        assert_eq!(
+            EncryptionType::AES256_CTS_HMAC_SHA1_96.to_string(),
+            "aes256-cts-hmac-sha1-96"
+        );
+
+        assert!(matches!(
+            "aes512-cts".parse::<EncryptionType>(),
+            Err(KrbError::InvalidEncryptionTypeName(name)) if name == "aes512-cts"
+        ));
+
+        let policy: EtypePolicy = "aes256-cts, aes128-cts-hmac-sha1-96"
+            .parse()
+            .expect("Invalid policy");
+        assert_eq!(
+            policy,
+            EtypePolicy::new(&[
+                EncryptionType::AES256_CTS_HMAC_SHA1_96,
+                EncryptionType::AES128_CTS_HMAC_SHA1_96
+            ])
+        );
+        assert!("aes256-cts bogus".parse::<EtypePolicy>().is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::asn1::{kerberos_flags::KerberosFlags, BitString};
+use crate::error::KrbError;
+use der::flagset::FlagSet;
+
+/// The options that a client requests of the KDC when asking for a ticket. Options
+/// that are not set are not requested.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KdcOptions {
+    forwardable: bool,
+    forwarded: bool,
+    proxiable: bool,
+    proxy: bool,
+    allow_postdate: bool,
+    postdated: bool,
+    renewable: bool,
+    cname_in_addl_tkt: bool,
+    disable_transited_check: bool,
+    renewable_ok: bool,
+    enc_tkt_in_skey: bool,
+    renew: bool,
+    validate: bool,
+}
+
+impl KdcOptions {
+    pub fn forwardable(mut self, forwardable: bool) -> Self {
+        self.forwardable = forwardable;
+        self
+    }
+
+    pub fn forwarded(mut self, forwarded: bool) -> Self {
+        self.forwarded = forwarded;
+        self
+    }
+
+    pub fn proxiable(mut self, proxiable: bool) -> Self {
+        self.proxiable = proxiable;
+        self
+    }
+
+    pub fn proxy(mut self, proxy: bool) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    pub fn allow_postdate(mut self, allow_postdate: bool) -> Self {
+        self.allow_postdate = allow_postdate;
+        self
+    }
+
+    pub fn postdated(mut self, postdated: bool) -> Self {
+        self.postdated = postdated;
+        self
+    }
+
+    pub fn renewable(mut self, renewable: bool) -> Self {
+        self.renewable = renewable;
+        self
+    }
+
+    /// Request a ticket for the client named in the additional ticket, rather than the
+    /// client of the ticket granting ticket, as in constrained delegation (S4U2Proxy).
+    pub fn cname_in_addl_tkt(mut self, cname_in_addl_tkt: bool) -> Self {
+        self.cname_in_addl_tkt = cname_in_addl_tkt;
+        self
+    }
+
+    /// Ask the KDC not to check the transited realms of a cross realm ticket, leaving
+    /// the check to the service.
+    pub fn disable_transited_check(mut self, disable_transited_check: bool) -> Self {
+        self.disable_transited_check = disable_transited_check;
+        self
+    }
+
+    /// Accept a renewable ticket if the requested end time can not be granted.
+    pub fn renewable_ok(mut self, renewable_ok: bool) -> Self {
+        self.renewable_ok = renewable_ok;
+        self
+    }
+
+    /// Encrypt the issued ticket in the session key of the additional ticket, rather
+    /// than the service's long term key. This is used for user-to-user authentication.
+    pub fn enc_tkt_in_skey(mut self, enc_tkt_in_skey: bool) -> Self {
+        self.enc_tkt_in_skey = enc_tkt_in_skey;
+        self
+    }
+
+    pub fn renew(mut self, renew: bool) -> Self {
+        self.renew = renew;
+        self
+    }
+
+    pub fn validate(mut self, validate: bool) -> Self {
+        self.validate = validate;
+        self
+    }
+
+    /// If a renewable ticket is requested, and so the renew time of the request applies.
+    pub(crate) fn is_renewable(&self) -> bool {
+        self.renewable
+    }
+
+    pub(crate) fn is_renewable_ok(&self) -> bool {
+        self.renewable_ok
+    }
+
+    pub(crate) fn is_forwardable(&self) -> bool {
+        self.forwardable
+    }
+
+    pub(crate) fn is_proxiable(&self) -> bool {
+        self.proxiable
+    }
+
+    /// If these options tell the KDC to use an additional ticket from the request.
+    pub(crate) fn uses_additional_ticket(&self) -> bool {
+        self.enc_tkt_in_skey || self.cname_in_addl_tkt
+    }
+
+    /// Encode as the KDCOptions bit string. Kerberos numbers the flags from the most
+    /// significant bit of the first octet, so bit 0 of the flag set is 0x80 of octet 0.
+    pub(crate) fn to_bit_string(self) -> Result<BitString, KrbError> {
+        let bits = FlagSet::<KerberosFlags>::from(self).bits();
+        BitString::from_bytes(&bits.reverse_bits().to_be_bytes())
+            .map_err(|_| KrbError::DerEncodeKdcReqBody)
+    }
+
+    pub(crate) fn from_bit_string(bit_string: &BitString) -> Self {
+        let mut bytes = [0u8; 4];
+        let raw_bytes = bit_string.raw_bytes();
+        let len = raw_bytes.len().min(bytes.len());
+        bytes[..len].copy_from_slice(&raw_bytes[..len]);
+
+        FlagSet::<KerberosFlags>::new_truncated(u32::from_be_bytes(bytes).reverse_bits()).into()
+    }
+}
+
+impl From<KdcOptions> for FlagSet<KerberosFlags> {
+    fn from(options: KdcOptions) -> Self {
+        let mut flags = FlagSet::default();
+        let options = [
+            (options.forwardable, KerberosFlags::Forwardable),
+            (options.forwarded, KerberosFlags::Forwarded),
+            (options.proxiable, KerberosFlags::Proxiable),
+            (options.proxy, KerberosFlags::Proxy),
+            (options.allow_postdate, KerberosFlags::AllowPostdate),
+            (options.postdated, KerberosFlags::Postdated),
+            (options.renewable, KerberosFlags::Renewable),
+            (options.cname_in_addl_tkt, KerberosFlags::CnameInAddlTkt),
+            (
+                options.disable_transited_check,
+                KerberosFlags::DisableTransitedCheck,
+            ),
+            (options.renewable_ok, KerberosFlags::RenewableOk),
+            (options.enc_tkt_in_skey, KerberosFlags::EncTktInSkey),
+            (options.renew, KerberosFlags::Renew),
+            (options.validate, KerberosFlags::Validate),
+        ];
+
+        for (set, flag) in options {
+            if set {
+                flags |= flag;
+            }
+        }
+
+        flags
+    }
+}
+
+impl From<FlagSet<KerberosFlags>> for KdcOptions {
+    fn from(flags: FlagSet<KerberosFlags>) -> Self {
+        KdcOptions {
+            forwardable: flags.contains(KerberosFlags::Forwardable),
+            forwarded: flags.contains(KerberosFlags::Forwarded),
+            proxiable: flags.contains(KerberosFlags::Proxiable),
+            proxy: flags.contains(KerberosFlags::Proxy),
+            allow_postdate: flags.contains(KerberosFlags::AllowPostdate),
+            postdated: flags.contains(KerberosFlags::Postdated),
+            renewable: flags.contains(KerberosFlags::Renewable),
+            cname_in_addl_tkt: flags.contains(KerberosFlags::CnameInAddlTkt),
+            disable_transited_check: flags.contains(KerberosFlags::DisableTransitedCheck),
+            renewable_ok: flags.contains(KerberosFlags::RenewableOk),
+            enc_tkt_in_skey: flags.contains(KerberosFlags::EncTktInSkey),
+            renew: flags.contains(KerberosFlags::Renew),
+            validate: flags.contains(KerberosFlags::Validate),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KdcOptions;
+
+    #[test]
+    fn test_kdc_options_bit_string() {
+        // The MIT krb5 default of forwardable, renewable and renewable-ok.
+        let options = KdcOptions::default()
+            .forwardable(true)
+            .renewable(true)
+            .renewable_ok(true);
+
+        let bit_string = options.to_bit_string().unwrap();
+        assert_eq!(bit_string.raw_bytes(), &[0x40, 0x80, 0x00, 0x10]);
+
+        assert_eq!(KdcOptions::from_bit_string(&bit_string), options);
+    }
+}
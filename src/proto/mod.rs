@@ -1,18 +1,57 @@
+mod ap_options;
+mod ap_req;
+mod authorization_data;
+mod checksum;
+mod cred;
+mod etype_policy;
+mod kdc_options;
+mod otp;
 mod reply;
 mod request;
-
-pub use self::reply::{AuthenticationReply, KerberosReply, PreauthReply, TicketGrantReply};
-pub use self::request::{AuthenticationRequest, KerberosRequest, TicketGrantRequest};
+mod s4u;
+mod spake;
+mod token;
+mod transited;
+
+pub use self::ap_options::ApOptions;
+pub use self::ap_req::{ApRequest, KerberosApRequestBuilder};
+pub use self::authorization_data::AuthorizationData;
+pub use self::checksum::{make_checksum, verify_checksum};
+pub use self::cred::{KrbCred, KrbCredInfo};
+pub use self::etype_policy::EtypePolicy;
+pub use self::kdc_options::KdcOptions;
+pub use self::otp::{OtpChallenge, OtpFormat, OtpTokenInfo};
+pub use self::reply::{
+    AuthenticationReply, ErrorReply, KerberosReply, PreauthReply, TicketGrantReply,
+};
+pub use self::request::{
+    AuthenticationRequest, KerberosAuthenticationBuilder, KerberosRequest,
+    KerberosTicketGrantBuilder, TicketGrantRequest,
+};
+pub use self::s4u::ForUser;
+pub use self::spake::{SpakeChallenge, SpakeGroup};
+pub use self::token::{decode_all, decode_token, encode_token, KerberosToken};
+#[cfg(feature = "base64")]
+pub use self::token::{decode_token_base64, encode_token_base64};
+pub use self::transited::TransitedEncoding;
+pub use crate::asn1::constants::encryption_types::EncryptionType;
+pub use crate::asn1::constants::errors::KrbErrorCode;
 
 use crate::asn1::{
-    constants::{encryption_types::EncryptionType, pa_data_types::PaDataType},
+    constants::pa_data_types::PaDataType,
     enc_kdc_rep_part::EncKdcRepPart,
+    enc_ticket_part::{EncTicketPart, TaggedEncTicketPart},
     encrypted_data::EncryptedData as KdcEncryptedData,
     encryption_key::EncryptionKey as KdcEncryptionKey,
-    etype_info2::ETypeInfo2 as KdcETypeInfo2,
-    kerberos_string::KerberosString,
+    etype_info2::{ETypeInfo2 as KdcETypeInfo2, ETypeInfo2Entry as KdcETypeInfo2Entry},
+    host_address::HostAddress as KdcHostAddress,
+    kerberos_string::{KerberosString, RawKerberosString},
+    kerberos_time::KerberosTime,
     pa_data::PaData,
     pa_enc_ts_enc::PaEncTsEnc,
+    pa_otp::PaOtpChallenge,
+    pa_pac_request::PaPacRequest,
+    pa_spake::PaSpake,
     principal_name::PrincipalName,
     realm::Realm,
     tagged_enc_kdc_rep_part::TaggedEncKdcRepPart,
@@ -20,35 +59,72 @@ use crate::asn1::{
     ticket_flags::TicketFlags,
     Ia5String, OctetString,
 };
-use crate::constants::{AES_256_KEY_LEN, PKBDF2_SHA1_ITER, RFC_PKBDF2_SHA1_ITER};
+#[cfg(feature = "des3")]
+use crate::constants::DES3_KEY_LEN;
+use crate::constants::{
+    AES_256_KEY_LEN, DEFAULT_MAX_SKEW, DEFAULT_MIN_PBKDF2_SHA1_ITER, PKBDF2_SHA1_ITER,
+    RFC_PKBDF2_SHA1_ITER,
+};
 use crate::crypto::{
-    decrypt_aes256_cts_hmac_sha1_96, derive_key_aes256_cts_hmac_sha1_96,
-    encrypt_aes256_cts_hmac_sha1_96,
+    constant_time_eq, decrypt_aes256_cts_hmac_sha1_96, derive_key_aes256_cts_hmac_sha1_96,
+    encrypt_aes256_cts_hmac_sha1_96, supported_etypes,
 };
+#[cfg(feature = "des3")]
+use crate::crypto::{decrypt_des3_cbc_sha1, derive_key_des3_cbc_sha1, encrypt_des3_cbc_sha1};
 use crate::error::KrbError;
 use der::{flagset::FlagSet, Decode, Encode};
 use rand::{thread_rng, Rng};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::time::{Duration, SystemTime};
-use tracing::trace;
+use tracing::{instrument, trace};
 
 // Zeroize blocked on https://github.com/RustCrypto/block-ciphers/issues/426
 // use zeroize::Zeroizing;
 
-#[derive(Debug, Default)]
+/// The default salt for the long term keys of `principal` in `realm`, used when the KDC
+/// does not advertise one. RFC 4120 6.3 The salt is the realm followed by each
+/// component of the principal name, in order and without separators.
+pub fn default_salt(realm: &str, principal: &Name) -> String {
+    let components: Vec<&str> = match principal {
+        Name::Principal { name, .. } => vec![name],
+        Name::SrvInst {
+            service, instance, ..
+        } => vec![service, instance],
+        Name::SrvHst { service, host, .. } => vec![service, host],
+        Name::SrvXHst { components, .. } | Name::Other { components, .. } => {
+            components.iter().map(String::as_str).collect()
+        }
+    };
+
+    let mut salt = realm.to_string();
+    salt.extend(components);
+    salt
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Preauth {
     enc_timestamp: Option<EncryptedData>,
     pa_fx_cookie: Option<Vec<u8>>,
+    pa_as_freshness: Option<Vec<u8>>,
+    pa_pac_request: Option<bool>,
+    // Any other padata as (padata-type, padata-value), in the order it was sent.
+    additional_padata: Vec<(u32, Vec<u8>)>,
 }
 
 pub enum DerivedKey {
     Aes256CtsHmacSha196 {
         k: [u8; AES_256_KEY_LEN],
         i: u32,
-        s: String,
+        s: Vec<u8>,
     },
+    #[cfg(feature = "des3")]
+    Des3CbcSha1 { k: [u8; DES3_KEY_LEN], s: Vec<u8> },
 }
 
 impl DerivedKey {
@@ -60,74 +136,98 @@ impl DerivedKey {
             |k| DerivedKey::Aes256CtsHmacSha196 {
                 k,
                 i: iter_count,
-                s: salt.to_string(),
+                s: salt.as_bytes().to_vec(),
             },
         )
     }
 
+    /// The etype of the key, which must match the etype of the data it decrypts.
+    pub fn etype(&self) -> EncryptionType {
+        match self {
+            DerivedKey::Aes256CtsHmacSha196 { .. } => EncryptionType::AES256_CTS_HMAC_SHA1_96,
+            #[cfg(feature = "des3")]
+            DerivedKey::Des3CbcSha1 { .. } => EncryptionType::DES3_CBC_SHA1_KD,
+        }
+    }
+
+    /// The bytes of the derived key, to compare with the keys of a keytab or another
+    /// implementation. This is only exposed with the `test-vectors` feature.
+    #[cfg(feature = "test-vectors")]
+    pub fn key_bytes(&self) -> &[u8] {
+        match self {
+            DerivedKey::Aes256CtsHmacSha196 { k, .. } => k,
+            #[cfg(feature = "des3")]
+            DerivedKey::Des3CbcSha1 { k, .. } => k,
+        }
+    }
+
+    #[cfg(feature = "des3")]
+    pub fn new_des3_cbc_sha1(passphrase: &str, salt: &str) -> Result<Self, KrbError> {
+        derive_key_des3_cbc_sha1(passphrase.as_bytes(), salt.as_bytes()).map(|k| {
+            DerivedKey::Des3CbcSha1 {
+                k,
+                s: salt.as_bytes().to_vec(),
+            }
+        })
+    }
+
     // Used to derive a key for the user. We have to do this to get the correct
     // etype from the enc data as pa_data may have many etype_info2 and the spec
     // doesn't call it an error to have multiple ... yay for confusing poorly
     // structured protocols.
+    //
+    // The KDC chooses the etype of the reply from those we requested, which need not be
+    // the first, so the salt and string-to-key parameters are taken from the etype-info2
+    // entry for the etype the reply is actually encrypted with.
     pub fn from_encrypted_reply(
         encrypted_data: &EncryptedData,
         pa_data_etype_info2: Option<&[EtypeInfo2]>,
         realm: &str,
         username: &str,
         passphrase: &str,
+    ) -> Result<Self, KrbError> {
+        Self::from_encrypted_reply_with_min_iter(
+            encrypted_data,
+            pa_data_etype_info2,
+            realm,
+            username,
+            passphrase,
+            DEFAULT_MIN_PBKDF2_SHA1_ITER,
+        )
+    }
+
+    /// As [Self::from_encrypted_reply], but rejecting string-to-key parameters with
+    /// fewer than `min_iter_count` iterations rather than [DEFAULT_MIN_PBKDF2_SHA1_ITER].
+    /// A lower minimum should only be used for a KDC that is known to use weak keys.
+    pub fn from_encrypted_reply_with_min_iter(
+        encrypted_data: &EncryptedData,
+        pa_data_etype_info2: Option<&[EtypeInfo2]>,
+        realm: &str,
+        username: &str,
+        passphrase: &str,
+        min_iter_count: u32,
     ) -> Result<Self, KrbError> {
         // If only Krb had put the *parameters* with the encrypted data, like any other
         // sane ecosystem.
-        match encrypted_data {
-            EncryptedData::Aes256CtsHmacSha196 { .. } => {
-                // Find if we have an etype info?
-
-                let maybe_etype_info2 = pa_data_etype_info2
-                    .iter()
-                    .map(|slice| slice.iter())
-                    .flatten()
-                    .filter(|etype_info2| {
-                        matches!(&etype_info2.etype, EncryptionType::AES256_CTS_HMAC_SHA1_96)
-                    })
-                    .next();
-
-                let (salt, iter_count) = if let Some(etype_info2) = maybe_etype_info2 {
-                    let salt = etype_info2.salt.as_ref().cloned();
-
-                    let iter_count = if let Some(s2kparams) = &etype_info2.s2kparams {
-                        if s2kparams.len() != 4 {
-                            return Err(KrbError::PreauthInvalidS2KParams);
-                        };
-                        let mut iter_count = [0u8; 4];
-                        iter_count.copy_from_slice(&s2kparams);
-
-                        Some(u32::from_be_bytes(iter_count))
-                    } else {
-                        None
-                    };
-
-                    (salt, iter_count)
-                } else {
-                    (None, None)
-                };
-
-                let salt = salt.unwrap_or_else(|| format!("{}{}", realm, username));
-
-                let iter_count = iter_count.unwrap_or(RFC_PKBDF2_SHA1_ITER);
+        let etype = encrypted_data.etype();
 
-                derive_key_aes256_cts_hmac_sha1_96(
-                    passphrase.as_bytes(),
-                    salt.as_bytes(),
-                    iter_count,
-                )
-                .map(|k| DerivedKey::Aes256CtsHmacSha196 {
-                    k,
-                    i: iter_count,
-                    s: salt,
-                })
-            }
-            _ => Err(KrbError::UnsupportedEncryption),
-        }
+        let etype_info2 = pa_data_etype_info2
+            .unwrap_or_default()
+            .iter()
+            .find(|etype_info2| etype_info2.etype == etype)
+            .cloned()
+            .unwrap_or(EtypeInfo2 {
+                etype,
+                salt: None,
+                s2kparams: None,
+            });
+
+        Self::from_etype_info2_with_min_iter(
+            &etype_info2,
+            &Name::principal(username, realm),
+            passphrase,
+            min_iter_count,
+        )
     }
 
     // This is used in pre-auth timestamp as there is no kvno as I can see?
@@ -136,13 +236,44 @@ impl DerivedKey {
         realm: &str,
         username: &str,
         passphrase: &str,
+    ) -> Result<Self, KrbError> {
+        Self::from_etype_info2_with_min_iter(
+            etype_info2,
+            &Name::principal(username, realm),
+            passphrase,
+            DEFAULT_MIN_PBKDF2_SHA1_ITER,
+        )
+    }
+
+    /// As [Self::from_etype_info2], but rejecting string-to-key parameters with fewer
+    /// than `min_iter_count` iterations rather than [DEFAULT_MIN_PBKDF2_SHA1_ITER].
+    /// A lower minimum should only be used for a KDC that is known to use weak keys.
+    /// Without a salt in `etype_info2`, the [default_salt] of `client_name` is used, so
+    /// the key of a multi-component principal is derived from all of its components.
+    pub fn from_etype_info2_with_min_iter(
+        etype_info2: &EtypeInfo2,
+        client_name: &Name,
+        passphrase: &str,
+        min_iter_count: u32,
     ) -> Result<Self, KrbError> {
         let salt = etype_info2
             .salt
             .as_ref()
             .cloned()
-            .unwrap_or_else(|| format!("{}{}", realm, username));
+            .unwrap_or_else(|| default_salt(client_name.realm(), client_name).into_bytes());
+
+        Self::from_etype_info2_with_salt(etype_info2, &salt, passphrase, min_iter_count)
+    }
 
+    // Derive the key for the etype and string-to-key parameters of `etype_info2`, with
+    // the salt already resolved. The key is derived from, and keeps, the exact bytes of
+    // the salt, which need not be utf-8.
+    pub(crate) fn from_etype_info2_with_salt(
+        etype_info2: &EtypeInfo2,
+        salt: &[u8],
+        passphrase: &str,
+        min_iter_count: u32,
+    ) -> Result<Self, KrbError> {
         match &etype_info2.etype {
             EncryptionType::AES256_CTS_HMAC_SHA1_96 => {
                 // Iter count is from the s2kparams
@@ -159,22 +290,48 @@ impl DerivedKey {
                     RFC_PKBDF2_SHA1_ITER
                 };
 
-                derive_key_aes256_cts_hmac_sha1_96(
-                    passphrase.as_bytes(),
-                    salt.as_bytes(),
-                    iter_count,
+                if iter_count < min_iter_count {
+                    return Err(KrbError::IterationCountTooLow);
+                }
+
+                derive_key_aes256_cts_hmac_sha1_96(passphrase.as_bytes(), salt, iter_count).map(
+                    |k| DerivedKey::Aes256CtsHmacSha196 {
+                        k,
+                        i: iter_count,
+                        s: salt.to_vec(),
+                    },
                 )
-                .map(|k| DerivedKey::Aes256CtsHmacSha196 {
-                    k,
-                    i: iter_count,
-                    s: salt,
+            }
+            #[cfg(feature = "des3")]
+            EncryptionType::DES3_CBC_SHA1_KD => {
+                derive_key_des3_cbc_sha1(passphrase.as_bytes(), salt).map(|k| {
+                    DerivedKey::Des3CbcSha1 {
+                        k,
+                        s: salt.to_vec(),
+                    }
                 })
             }
             _ => Err(KrbError::UnsupportedEncryption),
         }
     }
 
-    pub fn encrypt_pa_enc_timestamp(
+    /// Negotiate the etype to use from the etype-info2 the KDC offered, selecting the
+    /// strongest one of the `etypes` the client requested, and derive the key using its
+    /// salt and string-to-key parameters. A salt sent as PA-PW-SALT or PA-AFS3-SALT is
+    /// used when the etype-info2 has none, and when there is no etype-info2 at all, with
+    /// aes256-cts-hmac-sha1-96 if it was requested.
+    pub fn select_and_derive(
+        preauth: &PreauthData,
+        etypes: &[EncryptionType],
+        realm: &str,
+        username: &str,
+        passphrase: &str,
+    ) -> Result<Self, KrbError> {
+        let etype_info2 = preauth.preferred_etype_info2_of(etypes)?;
+        Self::from_etype_info2(&etype_info2, realm, username, passphrase)
+    }
+
+    pub(crate) fn encrypt_pa_enc_timestamp(
         &self,
         paenctsenc: &PaEncTsEnc,
     ) -> Result<EncryptedData, KrbError> {
@@ -185,15 +342,61 @@ impl DerivedKey {
         // https://www.rfc-editor.org/rfc/rfc4120#section-5.2.7.2
         let key_usage = 1;
 
+        self.encrypt_data(&data, key_usage)
+    }
+
+    /// Encrypt the enc-part of an AS-REP in the client's long term key, as the KDC does
+    /// once it has authenticated the client.
+    pub fn encrypt_enc_as_rep_part(&self, part: &KdcReplyPart) -> Result<EncryptedData, KrbError> {
+        self.encrypt_enc_kdc_rep_part(part.try_into()?)
+    }
+
+    // As encrypt_enc_as_rep_part, for a reply part that the KDC has built itself.
+    pub(crate) fn encrypt_enc_kdc_rep_part(
+        &self,
+        part: EncKdcRepPart,
+    ) -> Result<EncryptedData, KrbError> {
+        let data = TaggedEncKdcRepPart::EncAsRepPart(part)
+            .to_der()
+            .map_err(|_| KrbError::DerEncodeEncKdcRepPart)?;
+
+        // RFC 4120 The key usage value for encrypting this field is 3 in an AS-REP.
+        self.encrypt_data(&data, 3)
+    }
+
+    fn encrypt_data(&self, data: &[u8], key_usage: i32) -> Result<EncryptedData, KrbError> {
         match self {
             DerivedKey::Aes256CtsHmacSha196 { k, .. } => {
-                encrypt_aes256_cts_hmac_sha1_96(k, &data, key_usage)
+                encrypt_aes256_cts_hmac_sha1_96(k, data, key_usage)
                     .map(|data| EncryptedData::Aes256CtsHmacSha196 { kvno: None, data })
             }
+            #[cfg(feature = "des3")]
+            DerivedKey::Des3CbcSha1 { k, .. } => encrypt_des3_cbc_sha1(k, data, key_usage)
+                .map(|data| EncryptedData::Des3CbcSha1 { kvno: None, data }),
         }
     }
 }
 
+// The etype isn't secret, but the key material is, so it's compared in constant time.
+impl PartialEq for DerivedKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                DerivedKey::Aes256CtsHmacSha196 { k, .. },
+                DerivedKey::Aes256CtsHmacSha196 { k: other_k, .. },
+            ) => constant_time_eq(k, other_k),
+            #[cfg(feature = "des3")]
+            (DerivedKey::Des3CbcSha1 { k, .. }, DerivedKey::Des3CbcSha1 { k: other_k, .. }) => {
+                constant_time_eq(k, other_k)
+            }
+            #[cfg(feature = "des3")]
+            _ => false,
+        }
+    }
+}
+
+impl Eq for DerivedKey {}
+
 impl fmt::Debug for DerivedKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut builder = f.debug_struct("DerivedKey");
@@ -201,21 +404,118 @@ impl fmt::Debug for DerivedKey {
             DerivedKey::Aes256CtsHmacSha196 { i, s, .. } => builder
                 .field("k", &"Aes256HmacSha1")
                 .field("i", i)
-                .field("s", s),
+                .field("s", &String::from_utf8_lossy(s)),
+            #[cfg(feature = "des3")]
+            DerivedKey::Des3CbcSha1 { s, .. } => builder
+                .field("k", &"Des3CbcSha1")
+                .field("s", &String::from_utf8_lossy(s)),
         }
         .finish()
     }
 }
 
+#[derive(Clone)]
 pub enum SessionKey {
-    Aes256CtsHmacSha196 { k: [u8; AES_256_KEY_LEN] },
+    Aes256CtsHmacSha196 {
+        k: [u8; AES_256_KEY_LEN],
+    },
+    #[cfg(feature = "des3")]
+    Des3CbcSha1 {
+        k: [u8; DES3_KEY_LEN],
+    },
+}
+
+impl SessionKey {
+    /// The etype of the session key.
+    pub fn etype(&self) -> EncryptionType {
+        match self {
+            SessionKey::Aes256CtsHmacSha196 { .. } => EncryptionType::AES256_CTS_HMAC_SHA1_96,
+            #[cfg(feature = "des3")]
+            SessionKey::Des3CbcSha1 { .. } => EncryptionType::DES3_CBC_SHA1_KD,
+        }
+    }
+
+    /// Encrypt the enc-part of a TGS-REP in the session key of the ticket granting
+    /// ticket that the client presented.
+    pub fn encrypt_enc_tgs_rep_part(&self, part: &KdcReplyPart) -> Result<EncryptedData, KrbError> {
+        self.encrypt_enc_kdc_rep_part(part.try_into()?)
+    }
+
+    // As encrypt_enc_tgs_rep_part, for a reply part that the KDC has built itself.
+    pub(crate) fn encrypt_enc_kdc_rep_part(
+        &self,
+        part: EncKdcRepPart,
+    ) -> Result<EncryptedData, KrbError> {
+        let data = TaggedEncKdcRepPart::EncTgsRepPart(part)
+            .to_der()
+            .map_err(|_| KrbError::DerEncodeEncKdcRepPart)?;
+
+        // RFC 4120 The key usage value for encrypting this field is 8 in a TGS-REP.
+        self.encrypt_data(&data, 8)
+    }
+
+    pub(crate) fn encrypt_data(
+        &self,
+        data: &[u8],
+        key_usage: i32,
+    ) -> Result<EncryptedData, KrbError> {
+        match self {
+            SessionKey::Aes256CtsHmacSha196 { k } => {
+                encrypt_aes256_cts_hmac_sha1_96(k, data, key_usage)
+                    .map(|data| EncryptedData::Aes256CtsHmacSha196 { kvno: None, data })
+            }
+            #[cfg(feature = "des3")]
+            SessionKey::Des3CbcSha1 { k } => encrypt_des3_cbc_sha1(k, data, key_usage)
+                .map(|data| EncryptedData::Des3CbcSha1 { kvno: None, data }),
+        }
+    }
+
+    pub(crate) fn decrypt_data(
+        &self,
+        enc_data: &EncryptedData,
+        key_usage: i32,
+    ) -> Result<Vec<u8>, KrbError> {
+        match (self, enc_data) {
+            (
+                SessionKey::Aes256CtsHmacSha196 { k },
+                EncryptedData::Aes256CtsHmacSha196 { kvno: _, data },
+            ) => decrypt_aes256_cts_hmac_sha1_96(k, data, key_usage),
+            #[cfg(feature = "des3")]
+            (SessionKey::Des3CbcSha1 { k }, EncryptedData::Des3CbcSha1 { kvno: _, data }) => {
+                decrypt_des3_cbc_sha1(k, data, key_usage).and_then(trim_des3_padding)
+            }
+            #[cfg(feature = "des3")]
+            _ => Err(KrbError::UnsupportedEncryption),
+        }
+    }
+}
+
+impl PartialEq for SessionKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                SessionKey::Aes256CtsHmacSha196 { k },
+                SessionKey::Aes256CtsHmacSha196 { k: other_k },
+            ) => constant_time_eq(k, other_k),
+            #[cfg(feature = "des3")]
+            (SessionKey::Des3CbcSha1 { k }, SessionKey::Des3CbcSha1 { k: other_k }) => {
+                constant_time_eq(k, other_k)
+            }
+            #[cfg(feature = "des3")]
+            _ => false,
+        }
+    }
 }
 
+impl Eq for SessionKey {}
+
 impl fmt::Debug for SessionKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut builder = f.debug_struct("SessionKey");
         match self {
             SessionKey::Aes256CtsHmacSha196 { .. } => builder.field("k", &"Aes256"),
+            #[cfg(feature = "des3")]
+            SessionKey::Des3CbcSha1 { .. } => builder.field("k", &"Des3CbcSha1"),
         }
         .finish()
     }
@@ -250,14 +550,246 @@ impl TryFrom<&[u8]> for KdcPrimaryKey {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ticket {
-    tkt_vno: i8,
-    service: Name,
-    enc_part: EncryptedData,
+    pub(crate) tkt_vno: i8,
+    pub(crate) service: Name,
+    pub(crate) enc_part: EncryptedData,
+    // The DER the ticket was decoded from. The ticket is opaque to the client and
+    // integrity protected by the service key, so it must be sent on exactly as received.
+    pub(crate) der: Option<Vec<u8>>,
+}
+
+impl Ticket {
+    /// The service that this ticket grants access to.
+    pub fn service(&self) -> &Name {
+        &self.service
+    }
+
+    /// The key version number of the service key that the ticket is encrypted with,
+    /// if the KDC provided one.
+    pub fn kvno(&self) -> Option<u32> {
+        match &self.enc_part {
+            EncryptedData::Aes256CtsHmacSha196 { kvno, .. } => *kvno,
+            #[cfg(feature = "des3")]
+            EncryptedData::Des3CbcSha1 { kvno, .. } => *kvno,
+        }
+    }
+
+    /// Encode the ticket as the opaque DER `Ticket` that other Kerberos
+    /// implementations expect. A ticket that was received is encoded as the exact bytes
+    /// it was decoded from, so that it can be forwarded unchanged.
+    pub fn to_der(&self) -> Result<Vec<u8>, KrbError> {
+        if let Some(der) = &self.der {
+            return Ok(der.clone());
+        }
+
+        let ticket: Asn1Ticket = self.clone().try_into()?;
+        ticket.to_der().map_err(|_| KrbError::DerEncodeTicket)
+    }
+
+    pub fn from_der(data: &[u8]) -> Result<Self, KrbError> {
+        let ticket = Asn1Ticket::from_der(data).map_err(|_| KrbError::DerDecodeTicket)?;
+        Ticket::try_from(ticket).map(|ticket| Ticket {
+            der: Some(data.to_vec()),
+            ..ticket
+        })
+    }
+
+    /// Issue a ticket to `client` for `service`, as the KDC does, encrypting the
+    /// session key and ticket times in the service's long term key. `flags` are the bits
+    /// of the RFC 4120 TicketFlags. The ticket records that no realms were transited.
+    pub fn new_encrypted(
+        service: &Name,
+        client: &Name,
+        session_key: &SessionKey,
+        flags: u32,
+        times: TicketTimes,
+        service_key: &DerivedKey,
+    ) -> Result<Ticket, KrbError> {
+        let (cname, crealm) = client.try_into()?;
+        let kerberos_time = |time| {
+            KerberosTime::from_system_time(time).map_err(|_| KrbError::DerEncodeKerberosTime)
+        };
+
+        let enc_ticket_part = TaggedEncTicketPart(EncTicketPart {
+            flags: FlagSet::<TicketFlags>::new_truncated(flags),
+            key: session_key.try_into()?,
+            crealm,
+            cname,
+            transited: (&TransitedEncoding::new(Vec::with_capacity(0))).try_into()?,
+            auth_time: kerberos_time(times.auth_time)?,
+            start_time: times.start_time.map(kerberos_time).transpose()?,
+            end_time: kerberos_time(times.end_time)?,
+            renew_till: times.renew_until.map(kerberos_time).transpose()?,
+            client_addresses: None,
+            authorization_data: None,
+        });
+
+        let data = enc_ticket_part
+            .to_der()
+            .map_err(|_| KrbError::DerEncodeEncTicketPart)?;
+
+        // RFC 4120 The key usage value for encrypting the EncTicketPart is 2.
+        let enc_part = service_key.encrypt_data(&data, 2)?;
+
+        Ok(Ticket {
+            tkt_vno: 5,
+            service: service.clone(),
+            enc_part,
+            der: None,
+        })
+    }
+
+    /// Decrypt the ticket with the service's long term key, as the service does when
+    /// the client presents it.
+    #[instrument(level = "trace", skip_all)]
+    pub fn decrypt(&self, service_key: &DerivedKey) -> Result<TicketPart, KrbError> {
+        // RFC 4120 The key usage value for encrypting the EncTicketPart is 2.
+        let data = self.enc_part.decrypt_data(service_key, 2)?;
+
+        let TaggedEncTicketPart(enc_ticket_part) =
+            TaggedEncTicketPart::from_der(&data).map_err(|_| KrbError::DerDecodeEncTicketPart)?;
+
+        TicketPart::try_from(enc_ticket_part)
+    }
+
+    /// Decrypt a user-to-user ticket, which the KDC encrypted in the session key of the
+    /// acceptor's ticket granting ticket rather than the service's long term key.
+    #[instrument(level = "trace", skip_all)]
+    pub fn decrypt_with_session_key(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<TicketPart, KrbError> {
+        // RFC 4120 The key usage value for encrypting the EncTicketPart is 2.
+        let data = session_key.decrypt_data(&self.enc_part, 2)?;
+
+        let TaggedEncTicketPart(enc_ticket_part) =
+            TaggedEncTicketPart::from_der(&data).map_err(|_| KrbError::DerDecodeEncTicketPart)?;
+
+        TicketPart::try_from(enc_ticket_part)
+    }
+}
+
+/// The times of a ticket that the KDC issues.
+#[derive(Debug, Clone, Copy)]
+pub struct TicketTimes {
+    pub auth_time: SystemTime,
+    pub start_time: Option<SystemTime>,
+    pub end_time: SystemTime,
+    pub renew_until: Option<SystemTime>,
+}
+
+/// The decrypted content of a [Ticket].
+#[derive(Debug)]
+pub struct TicketPart {
+    key: SessionKey,
+    client: Name,
+    auth_time: SystemTime,
+    start_time: Option<SystemTime>,
+    end_time: SystemTime,
+    renew_until: Option<SystemTime>,
+    transited: TransitedEncoding,
+    client_addresses: Vec<HostAddress>,
+}
+
+impl TicketPart {
+    /// The session key that the client will use to authenticate to the service.
+    pub fn session_key(&self) -> &SessionKey {
+        &self.key
+    }
+
+    /// The client that the ticket was issued to.
+    pub fn client(&self) -> &Name {
+        &self.client
+    }
+
+    pub fn client_addresses(&self) -> &[HostAddress] {
+        &self.client_addresses
+    }
+
+    pub fn renew_until(&self) -> Option<SystemTime> {
+        self.renew_until
+    }
+
+    /// The realms that were traversed to authenticate the client, which are empty if
+    /// the client is in the realm that issued the ticket.
+    pub fn transited(&self) -> &TransitedEncoding {
+        &self.transited
+    }
+
+    /// Check that the ticket may be used at `now`.
+    pub fn is_valid_at(&self, now: SystemTime) -> bool {
+        let start_time = self.start_time.unwrap_or(self.auth_time);
+        start_time <= now && now < self.end_time
+    }
+}
+
+impl TryFrom<EncTicketPart> for TicketPart {
+    type Error = KrbError;
+
+    fn try_from(enc_ticket_part: EncTicketPart) -> Result<Self, Self::Error> {
+        let key = SessionKey::try_from(enc_ticket_part.key)?;
+        let client = Name::try_from((enc_ticket_part.cname, enc_ticket_part.crealm))?;
+        let transited = TransitedEncoding::try_from(enc_ticket_part.transited)?;
+
+        let client_addresses = enc_ticket_part
+            .client_addresses
+            .unwrap_or_default()
+            .into_iter()
+            .map(HostAddress::from)
+            .collect();
+
+        Ok(TicketPart {
+            key,
+            client,
+            auth_time: enc_ticket_part.auth_time.to_system_time(),
+            start_time: enc_ticket_part.start_time.map(|t| t.to_system_time()),
+            end_time: enc_ticket_part.end_time.to_system_time(),
+            renew_until: enc_ticket_part.renew_till.map(|t| t.to_system_time()),
+            transited,
+            client_addresses,
+        })
+    }
+}
+
+/// An entry of the last-req of a KDC reply, such as the time of the client's last
+/// successful request. The meaning of `lr_value` depends on `lr_type`, RFC 4120 5.4.2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastRequest {
+    pub lr_type: i32,
+    pub lr_value: SystemTime,
+}
+
+/// The decrypted enc-part of a KDC reply as the KDC sent it, which keeps the fields
+/// that a [KdcReplyPart] drops. See [EncryptedData::decrypt_enc_kdc_rep_raw].
+#[derive(Debug)]
+pub struct RawKdcReplyPart(EncKdcRepPart);
+
+impl RawKdcReplyPart {
+    pub fn last_req(&self) -> Vec<LastRequest> {
+        self.0
+            .last_req
+            .iter()
+            .map(|item| LastRequest {
+                lr_type: item.lr_type,
+                lr_value: item.lr_value.to_system_time(),
+            })
+            .collect()
+    }
+
+    pub fn nonce(&self) -> u32 {
+        self.0.nonce
+    }
 }
 
-// pub struct LastRequest
+impl TryFrom<RawKdcReplyPart> for KdcReplyPart {
+    type Error = KrbError;
+
+    fn try_from(raw_part: RawKdcReplyPart) -> Result<Self, Self::Error> {
+        KdcReplyPart::try_from(raw_part.0)
+    }
+}
 
 #[derive(Debug)]
 pub struct KdcReplyPart {
@@ -275,86 +807,543 @@ pub struct KdcReplyPart {
     // Shows the addresses the ticket may be used from. Mostly these are broken
     // by nat, and so aren't used. These are just to display that there are limits
     // to the client, the enforced addrs are in the ticket.
-    // client_addresses: Vec<HostAddress>,
+    client_addresses: Vec<HostAddress>,
 }
 
-#[derive(Debug)]
-pub enum EncryptedData {
-    Aes256CtsHmacSha196 { kvno: Option<u32>, data: Vec<u8> },
+/// An address that a ticket is restricted to be used from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum HostAddress {
+    Ip(IpAddr),
+    /// An address type that we don't interpret, such as netbios.
+    Other {
+        addr_type: i32,
+        address: Vec<u8>,
+    },
 }
 
-#[derive(Debug, Default)]
-pub struct PreauthData {
-    pub(crate) pa_fx_fast: bool,
-    pub(crate) enc_timestamp: bool,
-    pub(crate) pa_fx_cookie: Option<Vec<u8>>,
-    pub(crate) etype_info2: Vec<EtypeInfo2>,
-}
+// RFC 4120 7.5.3 - Address types
+const ADDR_TYPE_IPV4: i32 = 2;
+const ADDR_TYPE_IPV6: i32 = 24;
 
-#[derive(Debug, Clone)]
-pub enum Name {
-    Principal {
-        name: String,
-        realm: String,
-    },
-    SrvInst {
-        service: String,
-        realm: String,
-    },
-    SrvHst {
-        service: String,
-        host: String,
-        realm: String,
-    },
-    /*
-    Uid {
-    }
-    */
-}
+impl TryFrom<IpAddr> for HostAddress {
+    type Error = KrbError;
 
-#[derive(Debug, Clone)]
-pub struct EtypeInfo2 {
-    // The type of encryption for enc ts.
-    etype: EncryptionType,
+    /// An IPv4-mapped IPv6 address is the IPv4 address of the same host, and RFC 4120
+    /// 7.5.3 says it must be sent as an IPv4 address. The deprecated IPv4-compatible
+    /// form is ambiguous, and the unspecified and multicast addresses never identify a
+    /// host, so these are rejected.
+    fn try_from(ip: IpAddr) -> Result<Self, Self::Error> {
+        let ip = match ip {
+            IpAddr::V6(ipv6) => ipv6
+                .to_ipv4_mapped()
+                .map(IpAddr::V4)
+                .unwrap_or(IpAddr::V6(ipv6)),
+            ip => ip,
+        };
 
-    salt: Option<String>,
+        let ipv4_compatible = match ip {
+            IpAddr::V6(ipv6) => {
+                ipv6.segments()[..6] == [0; 6] && !ipv6.is_loopback() && !ipv6.is_unspecified()
+            }
+            IpAddr::V4(_) => false,
+        };
 
-    // For AES HMAC SHA1:
-    //   The number of iterations is specified by the string-to-key parameters
-    //   supplied.  The parameter string is four octets indicating an unsigned
-    //   number in big-endian order.  This is the number of iterations to be
-    //   performed.  If the value is 00 00 00 00, the number of iterations to
-    //   be performed is 4,294,967,296 (2**32).  (Thus the minimum expressible
-    //   iteration count is 1.)
-    s2kparams: Option<Vec<u8>>,
+        if ipv4_compatible || ip.is_unspecified() || ip.is_multicast() {
+            tracing::error!(%ip, "Address can not identify a host");
+            return Err(KrbError::InvalidHostAddress);
+        }
+
+        Ok(HostAddress::Ip(ip))
+    }
 }
 
-fn sort_cryptographic_strength(a: &EtypeInfo2, b: &EtypeInfo2) -> Ordering {
+impl TryFrom<&HostAddress> for KdcHostAddress {
+    type Error = KrbError;
+
+    fn try_from(host_address: &HostAddress) -> Result<Self, Self::Error> {
+        let (addr_type, address) = match host_address {
+            HostAddress::Ip(IpAddr::V4(ipv4)) => (ADDR_TYPE_IPV4, ipv4.octets().to_vec()),
+            HostAddress::Ip(IpAddr::V6(ipv6)) => match ipv6.to_ipv4_mapped() {
+                Some(ipv4) => (ADDR_TYPE_IPV4, ipv4.octets().to_vec()),
+                None => (ADDR_TYPE_IPV6, ipv6.octets().to_vec()),
+            },
+            HostAddress::Other { addr_type, address } => (*addr_type, address.clone()),
+        };
+
+        let address = OctetString::new(address).map_err(|_| KrbError::DerEncodeOctetString)?;
+
+        Ok(KdcHostAddress { addr_type, address })
+    }
+}
+
+impl From<KdcHostAddress> for HostAddress {
+    fn from(host_address: KdcHostAddress) -> Self {
+        let address = host_address.address.as_bytes();
+        match (host_address.addr_type, address.len()) {
+            (ADDR_TYPE_IPV4, 4) => {
+                let mut octets = [0u8; 4];
+                octets.copy_from_slice(address);
+                HostAddress::Ip(IpAddr::V4(Ipv4Addr::from(octets)))
+            }
+            (ADDR_TYPE_IPV6, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(address);
+                let ipv6 = Ipv6Addr::from(octets);
+                // Some peers send IPv4-mapped addresses as IPv6, so compare as IPv4.
+                HostAddress::Ip(
+                    ipv6.to_ipv4_mapped()
+                        .map(IpAddr::V4)
+                        .unwrap_or(IpAddr::V6(ipv6)),
+                )
+            }
+            (addr_type, _) => HostAddress::Other {
+                addr_type,
+                address: address.to_vec(),
+            },
+        }
+    }
+}
+
+impl KdcReplyPart {
+    /// The session key that must be used with the ticket this reply was issued with.
+    pub fn session_key(&self) -> &SessionKey {
+        &self.key
+    }
+
+    pub fn server(&self) -> &Name {
+        &self.server
+    }
+
+    /// The nonce of the request that this reply answers.
+    pub fn nonce(&self) -> u32 {
+        self.nonce
+    }
+
+    /// The addresses the issued ticket may be used from. If this is empty the ticket
+    /// may be used from any address.
+    pub fn client_addresses(&self) -> &[HostAddress] {
+        &self.client_addresses
+    }
+
+    /// Check that the ticket issued with this reply may be used at `now`. A ticket is
+    /// valid from its start time (or auth time if it was not postdated) up until, but not
+    /// including, its end time.
+    pub fn is_valid_at(&self, now: SystemTime) -> bool {
+        let start_time = self.start_time.unwrap_or(self.auth_time);
+        start_time <= now && now < self.end_time
+    }
+
+    /// Compare the ticket issued with this reply to what was asked for in `request`,
+    /// reporting where the KDC granted less than was requested.
+    pub fn downgrade(&self, request: &KerberosRequest) -> TicketDowngrade {
+        let (until, renew, kdc_options) = match request {
+            KerberosRequest::AS(req) => (req.until, req.renew, req.kdc_options),
+            KerberosRequest::TGS(req) => (req.until, req.renew, req.kdc_options),
+        };
+
+        // Ticket times are sent in whole seconds, so the requested times are compared at
+        // that precision.
+        let whole_secs = |time: SystemTime| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .map(|since| SystemTime::UNIX_EPOCH + Duration::from_secs(since.as_secs()))
+                .unwrap_or(time)
+        };
+        let until = whole_secs(until);
+        let renew = renew.map(whole_secs);
+
+        let renewable = self.flags.contains(TicketFlags::Renewable);
+        let end_time_reduced = self.end_time < until;
+
+        // RFC 4120 3.1.3 With renewable-ok, a KDC that can't grant the requested end
+        // time may instead issue a renewable ticket, renewable up to that end time.
+        let renewable_ok = kdc_options.is_renewable_ok() && end_time_reduced && renewable;
+
+        let renew_until_reduced = match (renew, self.renew_until) {
+            (Some(renew), Some(renew_until)) => renew_until < renew,
+            _ => false,
+        };
+
+        TicketDowngrade {
+            end_time_reduced,
+            renewable_ok,
+            renewable_refused: kdc_options.is_renewable() && !renewable,
+            renew_until_reduced,
+            forwardable_refused: kdc_options.is_forwardable()
+                && !self.flags.contains(TicketFlags::Forwardable),
+            proxiable_refused: kdc_options.is_proxiable()
+                && !self.flags.contains(TicketFlags::Proxiable),
+        }
+    }
+}
+
+/// Where a ticket issued by the KDC falls short of what the client requested. KDCs
+/// are free to shorten lifetimes and refuse options according to their policy, so a
+/// downgrade is not an error, but a client may want to report or act on it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TicketDowngrade {
+    /// The ticket ends before the requested end time.
+    pub end_time_reduced: bool,
+    /// The end time was reduced, but the ticket was made renewable in its place, as
+    /// requested with the renewable-ok option.
+    pub renewable_ok: bool,
+    /// A renewable ticket was requested but not issued.
+    pub renewable_refused: bool,
+    /// The ticket is renewable until before the requested renew time.
+    pub renew_until_reduced: bool,
+    /// A forwardable ticket was requested but not issued.
+    pub forwardable_refused: bool,
+    /// A proxiable ticket was requested but not issued.
+    pub proxiable_refused: bool,
+}
+
+impl TicketDowngrade {
+    /// If the ticket was issued exactly as requested.
+    pub fn is_honored(&self) -> bool {
+        *self == TicketDowngrade::default()
+    }
+}
+
+/// A serializable view of a [KdcReplyPart] for logging or persisting. Times are RFC3339
+/// strings, and the session key is omitted, only its encryption type is recorded.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdcReplyPartSummary {
+    pub key_type: EncryptionType,
+    pub nonce: u32,
+    pub key_expiration: Option<String>,
+    pub flags: u32,
+    pub auth_time: String,
+    pub start_time: Option<String>,
+    pub end_time: String,
+    pub renew_until: Option<String>,
+    pub server: Name,
+    pub client_addresses: Vec<HostAddress>,
+}
+
+#[cfg(feature = "serde")]
+impl From<&KdcReplyPart> for KdcReplyPartSummary {
+    fn from(reply_part: &KdcReplyPart) -> Self {
+        let rfc3339 = |t: SystemTime| humantime::format_rfc3339(t).to_string();
+
+        let key_type = match reply_part.key {
+            SessionKey::Aes256CtsHmacSha196 { .. } => EncryptionType::AES256_CTS_HMAC_SHA1_96,
+            #[cfg(feature = "des3")]
+            SessionKey::Des3CbcSha1 { .. } => EncryptionType::DES3_CBC_SHA1_KD,
+        };
+
+        KdcReplyPartSummary {
+            key_type,
+            nonce: reply_part.nonce,
+            key_expiration: reply_part.key_expiration.map(rfc3339),
+            flags: reply_part.flags.bits(),
+            auth_time: rfc3339(reply_part.auth_time),
+            start_time: reply_part.start_time.map(rfc3339),
+            end_time: rfc3339(reply_part.end_time),
+            renew_until: reply_part.renew_until.map(rfc3339),
+            server: reply_part.server.clone(),
+            client_addresses: reply_part.client_addresses.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncryptedData {
+    Aes256CtsHmacSha196 {
+        kvno: Option<u32>,
+        data: Vec<u8>,
+    },
+    #[cfg(feature = "des3")]
+    Des3CbcSha1 {
+        kvno: Option<u32>,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PreauthData {
+    pub(crate) pa_fx_fast: bool,
+    pub(crate) enc_timestamp: bool,
+    pub(crate) pa_fx_cookie: Option<Vec<u8>>,
+    pub(crate) pa_as_freshness: Option<Vec<u8>>,
+    pub(crate) etype_info2: Vec<EtypeInfo2>,
+    pub(crate) pw_salt: Option<Vec<u8>>,
+    pub(crate) padata_types: Vec<u32>,
+    pub(crate) pa_spake: bool,
+    pub(crate) spake_challenge: Option<SpakeChallenge>,
+    pub(crate) otp_challenge: Option<OtpChallenge>,
+    pub(crate) supported_enctypes: Option<Vec<EncryptionType>>,
+}
+
+impl PreauthData {
+    /// The types of padata the KDC sent, in the order it sent them, including those that
+    /// are otherwise ignored. This is empty if the data was not received from a KDC.
+    pub fn padata_types(&self) -> Vec<PaDataType> {
+        self.padata_types
+            .iter()
+            .filter_map(|padata_type| PaDataType::try_from(*padata_type).ok())
+            .collect()
+    }
+
+    // The strongest of the etype-info2 entries of `etypes`, such as the etypes of a
+    // request, with the salt of a PA-PW-SALT filled in if the entry has none.
+    pub(crate) fn preferred_etype_info2_of(
+        &self,
+        etypes: &[EncryptionType],
+    ) -> Result<EtypeInfo2, KrbError> {
+        let etype_info2 = self
+            .etype_info2
+            .iter()
+            .filter(|etype_info2| etypes.contains(&etype_info2.etype))
+            .max_by(|a, b| sort_cryptographic_strength(a, b));
+
+        let mut etype_info2 = match (etype_info2, &self.pw_salt) {
+            (Some(etype_info2), _) => etype_info2.clone(),
+            (None, Some(_)) if etypes.contains(&EncryptionType::AES256_CTS_HMAC_SHA1_96) => {
+                EtypeInfo2 {
+                    etype: EncryptionType::AES256_CTS_HMAC_SHA1_96,
+                    salt: None,
+                    s2kparams: None,
+                }
+            }
+            (None, _) => return Err(KrbError::PreauthMissingEtypeInfo2),
+        };
+
+        if etype_info2.salt.is_none() {
+            etype_info2.salt = self.pw_salt.clone();
+        }
+
+        Ok(etype_info2)
+    }
+
+    // Encrypted timestamp is the only preauth mechanism we can perform. If the KDC
+    // didn't offer it, name the mechanisms it did offer, leaving out the padata that
+    // only accompanies a mechanism.
+    pub(crate) fn require_enc_timestamp(&self) -> Result<(), KrbError> {
+        if self.enc_timestamp {
+            return Ok(());
+        }
+
+        let mechanisms: Vec<_> = self
+            .padata_types()
+            .into_iter()
+            .filter(|padata_type| {
+                !matches!(
+                    padata_type,
+                    PaDataType::PaPwSalt
+                        | PaDataType::PaAfs3Salt
+                        | PaDataType::PaEtypeInfo
+                        | PaDataType::PaEtypeInfo2
+                        | PaDataType::PaFxCookie
+                        | PaDataType::PaFxFast
+                        | PaDataType::PaPacRequest
+                        | PaDataType::EncpadataReqEncPaRep
+                        | PaDataType::PadataAsFreshness
+                        | PaDataType::PaSupportedEtypes
+                )
+            })
+            .collect();
+
+        if mechanisms.is_empty() {
+            Err(KrbError::PreauthUnsupported)
+        } else {
+            Err(KrbError::UnsupportedPreauthMechanism(mechanisms))
+        }
+    }
+
+    /// Whether the KDC offered SPAKE preauthentication.
+    pub fn spake_available(&self) -> bool {
+        self.pa_spake
+    }
+
+    /// The SPAKE challenge of the KDC, once it has been told the groups we support.
+    pub fn spake_challenge(&self) -> Option<&SpakeChallenge> {
+        self.spake_challenge.as_ref()
+    }
+
+    /// The challenge of a KDC that accepts one time passwords, if it sent a
+    /// PA-OTP-CHALLENGE.
+    pub fn otp_challenge(&self) -> Option<&OtpChallenge> {
+        self.otp_challenge.as_ref()
+    }
+
+    /// The freshness token of RFC 8070, if the KDC sent a PA-AS-FRESHNESS. This is
+    /// returned as is with the next request.
+    pub fn as_freshness(&self) -> Option<&[u8]> {
+        self.pa_as_freshness.as_deref()
+    }
+
+    /// The etypes that Active Directory has keys for on the account, if it sent a
+    /// PA-SUPPORTED-ENCTYPES. These need not be etypes that we support.
+    pub fn supported_enctypes(&self) -> Option<&[EncryptionType]> {
+        self.supported_enctypes.as_deref()
+    }
+
+    /// The raw values of the padata types the KDC sent that are not known to this crate.
+    pub fn unknown_padata_types(&self) -> Vec<u32> {
+        self.padata_types
+            .iter()
+            .copied()
+            .filter(|padata_type| PaDataType::try_from(*padata_type).is_err())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Name {
+    Principal {
+        name: String,
+        realm: String,
+    },
+    SrvInst {
+        service: String,
+        instance: String,
+        realm: String,
+    },
+    SrvHst {
+        service: String,
+        host: String,
+        realm: String,
+    },
+    /// A host based service whose host name is split into its components, such as the
+    /// labels of a DNS name.
+    SrvXHst {
+        components: Vec<String>,
+        realm: String,
+    },
+    /// A name of a type that we don't otherwise represent, or with more components
+    /// than its type usually has, such as an NT-PRINCIPAL with an instance.
+    Other {
+        name_type: i32,
+        components: Vec<String>,
+        realm: String,
+    },
     /*
-    if a.etype == EncryptionType::AES256_CTS_HMAC_SHA384_192 {
-        Ordering::Greater
-    } else if b.etype == EncryptionType::AES256_CTS_HMAC_SHA384_192 {
-        Ordering::Less
-    } else if a.etype == EncryptionType::AES128_CTS_HMAC_SHA256_128 {
-        Ordering::Greater
-    } else if b.etype == EncryptionType::AES128_CTS_HMAC_SHA256_128 {
-        Ordering::Less
-    } else
+    Uid {
+    }
     */
-    if a.etype == EncryptionType::AES256_CTS_HMAC_SHA1_96 {
-        Ordering::Greater
-    } else if b.etype == EncryptionType::AES256_CTS_HMAC_SHA1_96 {
-        Ordering::Less
-    } else if a.etype == EncryptionType::AES128_CTS_HMAC_SHA1_96 {
-        Ordering::Greater
-    } else if b.etype == EncryptionType::AES128_CTS_HMAC_SHA1_96 {
-        Ordering::Less
-    } else {
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EtypeInfo2 {
+    // The type of encryption for enc ts.
+    etype: EncryptionType,
+
+    // The salt as it was sent, which need not be utf-8.
+    salt: Option<Vec<u8>>,
+
+    // For AES HMAC SHA1:
+    //   The number of iterations is specified by the string-to-key parameters
+    //   supplied.  The parameter string is four octets indicating an unsigned
+    //   number in big-endian order.  This is the number of iterations to be
+    //   performed.  If the value is 00 00 00 00, the number of iterations to
+    //   be performed is 4,294,967,296 (2**32).  (Thus the minimum expressible
+    //   iteration count is 1.)
+    s2kparams: Option<Vec<u8>>,
+}
+
+impl EtypeInfo2 {
+    /// The salt to derive the key with, exactly as the KDC sent it.
+    pub fn salt(&self) -> Option<&[u8]> {
+        self.salt.as_deref()
+    }
+
+    /// The salt for display, with any bytes that aren't utf-8 replaced.
+    pub fn salt_str(&self) -> Option<Cow<'_, str>> {
+        self.salt.as_deref().map(String::from_utf8_lossy)
+    }
+}
+
+impl TryFrom<&EtypeInfo2> for KdcETypeInfo2Entry {
+    type Error = KrbError;
+
+    fn try_from(etype_info2: &EtypeInfo2) -> Result<Self, Self::Error> {
+        let salt = etype_info2.salt.clone().map(RawKerberosString);
+        let s2kparams = etype_info2
+            .s2kparams
+            .as_ref()
+            .map(|s2kparams| OctetString::new(s2kparams.as_slice()))
+            .transpose()
+            .map_err(|_| KrbError::DerEncodeEtypeInfo2)?;
+
+        Ok(KdcETypeInfo2Entry {
+            etype: etype_info2.etype as i32,
+            salt,
+            s2kparams,
+        })
+    }
+}
+
+fn sort_cryptographic_strength(a: &EtypeInfo2, b: &EtypeInfo2) -> Ordering {
+    cryptographic_strength(a.etype).cmp(&cryptographic_strength(b.etype))
+}
+
+/// The rank of an etype, strongest highest, following the MIT krb5 default preference
+/// order. Every etype has a distinct rank so that sorting by it is a total order, and new
+/// etypes must be ranked before this compiles.
+pub(crate) fn cryptographic_strength(etype: EncryptionType) -> u8 {
+    match etype {
+        // RFC 8009
+        EncryptionType::AES256_CTS_HMAC_SHA384_192 => 21,
+        EncryptionType::AES128_CTS_HMAC_SHA256_128 => 20,
+        // RFC 3962
+        EncryptionType::AES256_CTS_HMAC_SHA1_96 => 19,
+        EncryptionType::AES128_CTS_HMAC_SHA1_96 => 18,
+        // RFC 6803
+        EncryptionType::CAMELLIA256_CTS_CMAC => 17,
+        EncryptionType::CAMELLIA128_CTS_CMAC => 16,
+        // RFC 3961 and RFC 4757, both deprecated by RFC 8429.
+        EncryptionType::DES3_CBC_SHA1_KD => 15,
+        EncryptionType::RC4_HMAC => 14,
+        EncryptionType::RC4_HMAC_EXP => 13,
         // Everything else is trash.
-        Ordering::Equal
+        EncryptionType::DES3_CBC_SHA1 => 12,
+        EncryptionType::DES3_CBC_MD5 => 11,
+        EncryptionType::DES_CBC_MD5 => 10,
+        EncryptionType::DES_CBC_MD4 => 9,
+        EncryptionType::DES_CBC_CRC => 8,
+        // The PKINIT algorithm identifiers aren't usable as an etype at all.
+        EncryptionType::DES3_CBC_ENV => 7,
+        EncryptionType::RSA_ES_OAEP_ENV => 6,
+        EncryptionType::RSA_ENV => 5,
+        EncryptionType::RC2_CBC_ENV => 4,
+        EncryptionType::SHA1_RSA_CMS => 3,
+        EncryptionType::MD5_RSA_CMS => 2,
+        EncryptionType::DSA_SHA1_CMS => 1,
     }
 }
 
+// The bits of the msDS-SupportedEncryptionTypes mask that MS-KILE 2.2.7 defines for
+// etypes. The others describe features of the KDC, such as FAST and claims.
+const SUPPORTED_ENCTYPES_BITS: [(u32, EncryptionType); 5] = [
+    (0x01, EncryptionType::DES_CBC_CRC),
+    (0x02, EncryptionType::DES_CBC_MD5),
+    (0x04, EncryptionType::RC4_HMAC),
+    (0x08, EncryptionType::AES128_CTS_HMAC_SHA1_96),
+    (0x10, EncryptionType::AES256_CTS_HMAC_SHA1_96),
+];
+
+// PA-SUPPORTED-ENCTYPES is a 32 bit little endian mask, rather than DER.
+fn padata_supported_enctypes(padata_value: &[u8]) -> Option<Vec<EncryptionType>> {
+    let Ok(mask) = <[u8; 4]>::try_from(padata_value).map(u32::from_le_bytes) else {
+        trace!(
+            len = padata_value.len(),
+            "Ignoring malformed PA-SUPPORTED-ENCTYPES"
+        );
+        return None;
+    };
+
+    Some(
+        SUPPORTED_ENCTYPES_BITS
+            .iter()
+            .filter(|(bit, _)| mask & bit != 0)
+            .map(|(_, etype)| *etype)
+            .collect(),
+    )
+}
+
 impl TryFrom<Vec<PaData>> for PreauthData {
     type Error = KrbError;
 
@@ -364,13 +1353,22 @@ impl TryFrom<Vec<PaData>> for PreauthData {
         let mut pa_fx_fast = false;
         let mut enc_timestamp = false;
         let mut pa_fx_cookie = None;
+        let mut pa_as_freshness = None;
         let mut etype_info2 = Vec::with_capacity(0);
+        let mut pw_salt = None;
+        let mut padata_types = Vec::with_capacity(pavec.len());
+        let mut pa_spake = false;
+        let mut spake_challenge = None;
+        let mut otp_challenge = None;
+        let mut supported_enctypes = None;
 
         for PaData {
             padata_type,
             padata_value,
         } in pavec
         {
+            padata_types.push(padata_type);
+
             let Ok(padt) = padata_type.try_into() else {
                 // padatatype that we don't support
                 continue;
@@ -390,13 +1388,12 @@ impl TryFrom<Vec<PaData>> for PreauthData {
                         };
 
                         // Only proceed with what we support.
-                        match etype {
-                            EncryptionType::AES256_CTS_HMAC_SHA1_96 => {}
-                            _ => continue,
-                        };
+                        if !supported_etypes().contains(&etype) {
+                            continue;
+                        }
 
                         // I think at this point we should ignore any etypes we don't support.
-                        let salt = einfo2.salt.map(|s| s.into());
+                        let salt = einfo2.salt.map(|s| s.0);
                         let s2kparams = einfo2.s2kparams.map(|v| v.as_bytes().to_vec());
 
                         etype_info2.push(EtypeInfo2 {
@@ -408,6 +1405,43 @@ impl TryFrom<Vec<PaData>> for PreauthData {
                 }
                 PaDataType::PaFxFast => pa_fx_fast = true,
                 PaDataType::PaFxCookie => pa_fx_cookie = Some(padata_value.as_bytes().to_vec()),
+                PaDataType::PadataAsFreshness => {
+                    pa_as_freshness = Some(padata_value.as_bytes().to_vec())
+                }
+                // PA-PW-SALT takes precedence over PA-AFS3-SALT if both are sent.
+                PaDataType::PaPwSalt => pw_salt = Some(padata_value.as_bytes().to_vec()),
+                PaDataType::PaAfs3Salt => {
+                    pw_salt = pw_salt.or_else(|| Some(padata_value.as_bytes().to_vec()))
+                }
+                PaDataType::PadataSpake => {
+                    pa_spake = true;
+                    // SPAKE is first offered with an empty value, and the challenge is
+                    // only sent once the client has said which groups it supports. We
+                    // can't perform SPAKE yet, so a challenge we can't read doesn't
+                    // prevent the other mechanisms from being used.
+                    if !padata_value.is_empty() {
+                        match PaSpake::from_der(padata_value.as_bytes()) {
+                            Ok(PaSpake::Challenge(challenge)) => {
+                                spake_challenge = Some(challenge.into())
+                            }
+                            Ok(pa_spake) => trace!(?pa_spake, "Ignoring unexpected PA-SPAKE"),
+                            Err(err) => trace!(?err, "Unable to decode PA-SPAKE"),
+                        }
+                    }
+                }
+                PaDataType::PaOtpChallenge => {
+                    // As for SPAKE, a challenge we can't read leaves the other
+                    // mechanisms to be used.
+                    match PaOtpChallenge::from_der(padata_value.as_bytes()) {
+                        Ok(challenge) => otp_challenge = Some(challenge.into()),
+                        Err(err) => trace!(?err, "Unable to decode PA-OTP-CHALLENGE"),
+                    }
+                }
+                PaDataType::PaSupportedEtypes => {
+                    // This only informs the client, so a value we can't read isn't an
+                    // error.
+                    supported_enctypes = padata_supported_enctypes(padata_value.as_bytes())
+                }
                 _ => {
                     // Ignore unsupported pa data types.
                 }
@@ -415,13 +1449,20 @@ impl TryFrom<Vec<PaData>> for PreauthData {
         }
 
         // Sort the etype_info by cryptographic strength.
-        etype_info2.sort_unstable_by(sort_cryptographic_strength);
+        etype_info2.sort_by(sort_cryptographic_strength);
 
         Ok(PreauthData {
             pa_fx_fast,
             pa_fx_cookie,
+            pa_as_freshness,
             enc_timestamp,
             etype_info2,
+            pw_salt,
+            padata_types,
+            pa_spake,
+            spake_challenge,
+            otp_challenge,
+            supported_enctypes,
         })
     }
 }
@@ -437,23 +1478,29 @@ impl TryFrom<Vec<PaData>> for Preauth {
             padata_value,
         } in pavec
         {
-            let Ok(padt) = padata_type.try_into() else {
-                // padatatype that we don't support
-                continue;
-            };
-
-            match padt {
-                PaDataType::PaEncTimestamp => {
+            match padata_type.try_into() {
+                Ok(PaDataType::PaEncTimestamp) => {
                     let enc_timestamp = KdcEncryptedData::from_der(padata_value.as_bytes())
                         .map_err(|_| KrbError::DerDecodePaData)
                         .and_then(EncryptedData::try_from)?;
                     preauth.enc_timestamp = Some(enc_timestamp);
                 }
-                PaDataType::PaFxCookie => {
+                Ok(PaDataType::PaFxCookie) => {
                     preauth.pa_fx_cookie = Some(padata_value.as_bytes().to_vec())
                 }
+                Ok(PaDataType::PadataAsFreshness) => {
+                    preauth.pa_as_freshness = Some(padata_value.as_bytes().to_vec())
+                }
+                Ok(PaDataType::PaPacRequest) => {
+                    let pac_request = PaPacRequest::from_der(padata_value.as_bytes())
+                        .map_err(|_| KrbError::DerDecodePaData)?;
+                    preauth.pa_pac_request = Some(pac_request.include_pac);
+                }
                 _ => {
-                    // Ignore unsupported pa data types.
+                    // Keep the padata types we don't interpret so they can be inspected.
+                    preauth
+                        .additional_padata
+                        .push((padata_type, padata_value.into_bytes()));
                 }
             };
         }
@@ -462,13 +1509,51 @@ impl TryFrom<Vec<PaData>> for Preauth {
     }
 }
 
+/// des3-cbc-sha1-kd pads the plaintext to the block size with zeros, and the length of
+/// the message is not recorded. Since every kerberos message is DER, use the length of the
+/// outer TLV to drop the padding.
+#[cfg(feature = "des3")]
+fn trim_des3_padding(mut data: Vec<u8>) -> Result<Vec<u8>, KrbError> {
+    use der::{Header, Reader, SliceReader};
+
+    let len = {
+        let mut reader = SliceReader::new(&data).map_err(|_| KrbError::InsufficientData)?;
+        let header = Header::decode(&mut reader).map_err(|_| KrbError::InsufficientData)?;
+        (reader.position() + header.length)
+            .and_then(usize::try_from)
+            .map_err(|_| KrbError::InsufficientData)?
+    };
+
+    if len > data.len() {
+        return Err(KrbError::InsufficientData);
+    }
+
+    data.truncate(len);
+    Ok(data)
+}
+
 impl EncryptedData {
+    /// The etype the data is encrypted with.
+    pub fn etype(&self) -> EncryptionType {
+        match self {
+            EncryptedData::Aes256CtsHmacSha196 { .. } => EncryptionType::AES256_CTS_HMAC_SHA1_96,
+            #[cfg(feature = "des3")]
+            EncryptedData::Des3CbcSha1 { .. } => EncryptionType::DES3_CBC_SHA1_KD,
+        }
+    }
+
     fn decrypt_data(&self, base_key: &DerivedKey, key_usage: i32) -> Result<Vec<u8>, KrbError> {
         match (self, base_key) {
             (
                 EncryptedData::Aes256CtsHmacSha196 { kvno: _, data },
                 DerivedKey::Aes256CtsHmacSha196 { k, .. },
             ) => decrypt_aes256_cts_hmac_sha1_96(&k, &data, key_usage),
+            #[cfg(feature = "des3")]
+            (EncryptedData::Des3CbcSha1 { kvno: _, data }, DerivedKey::Des3CbcSha1 { k, .. }) => {
+                decrypt_des3_cbc_sha1(k, data, key_usage).and_then(trim_des3_padding)
+            }
+            #[cfg(feature = "des3")]
+            _ => Err(KrbError::UnsupportedEncryption),
         }
     }
 
@@ -476,25 +1561,71 @@ impl EncryptedData {
         // RFC 4120 The key usage value for encrypting this field is 3 in an AS-REP
         // message, using the client's long-term key or another key selected
         // via pre-authentication mechanisms.
-        let data = self.decrypt_data(base_key, 3)?;
+        self.decrypt_enc_kdc_rep_with_usage(base_key, 3)
+    }
 
-        let tagged_kdc_enc_part = TaggedEncKdcRepPart::from_der(&data).map_err(|e| {
-            println!("{:#?}", e);
-            KrbError::DerDecodeEncKdcRepPart
-        })?;
+    /// Decrypt the enc-part of an AS-REP with `key_usage` rather than the usage of 3
+    /// from RFC 4120, for KDCs that disagree with it.
+    #[instrument(level = "trace", skip_all)]
+    pub fn decrypt_enc_kdc_rep_with_usage(
+        &self,
+        base_key: &DerivedKey,
+        key_usage: i32,
+    ) -> Result<KdcReplyPart, KrbError> {
+        self.decrypt_enc_kdc_rep_raw(base_key, key_usage)
+            .and_then(KdcReplyPart::try_from)
+    }
 
-        // RFC states we should relax the tag check on these.
+    /// Decrypt the enc-part of an AS-REP to the EncKdcRepPart as the KDC sent it, before
+    /// the conversion to a [KdcReplyPart] drops the fields it doesn't model, such as the
+    /// last-req. The `key_usage` is 3 unless the KDC disagrees with RFC 4120.
+    pub fn decrypt_enc_kdc_rep_raw(
+        &self,
+        base_key: &DerivedKey,
+        key_usage: i32,
+    ) -> Result<RawKdcReplyPart, KrbError> {
+        let data = self.decrypt_data(base_key, key_usage)?;
+        decode_enc_kdc_rep_part(&data).map(RawKdcReplyPart)
+    }
 
-        let kdc_enc_part = match tagged_kdc_enc_part {
-            TaggedEncKdcRepPart::EncTgsRepPart(part) | TaggedEncKdcRepPart::EncAsRepPart(part) => {
-                part
-            }
-        };
+    pub fn decrypt_enc_tgs_rep(&self, session_key: &SessionKey) -> Result<KdcReplyPart, KrbError> {
+        // RFC 4120 The key usage value for encrypting this field is 8 in a TGS-REP
+        // message, using the session key from the TGT.
+        self.decrypt_enc_tgs_rep_with_usage(session_key, 8)
+    }
 
-        KdcReplyPart::try_from(kdc_enc_part)
+    /// Decrypt the enc-part of a TGS-REP with `key_usage` rather than the usage of 8
+    /// from RFC 4120. This is 9 when the request carried a subkey in its
+    /// authenticator, and `session_key` is then that subkey.
+    #[instrument(level = "trace", skip_all)]
+    pub fn decrypt_enc_tgs_rep_with_usage(
+        &self,
+        session_key: &SessionKey,
+        key_usage: i32,
+    ) -> Result<KdcReplyPart, KrbError> {
+        let data = session_key.decrypt_data(self, key_usage)?;
+        decode_enc_kdc_rep_part(&data).and_then(KdcReplyPart::try_from)
     }
 
     pub fn decrypt_pa_enc_timestamp(&self, base_key: &DerivedKey) -> Result<SystemTime, KrbError> {
+        let (stime, pausec) = self.decrypt_pa_enc_timestamp_full(base_key)?;
+
+        let usecs = pausec
+            .map(|s| Duration::from_micros(s as u64))
+            .unwrap_or_default();
+
+        let stime = stime + usecs;
+
+        Ok(stime)
+    }
+
+    /// Decrypt the PA-ENC-TIMESTAMP, returning the timestamp in whole seconds and the
+    /// microseconds (pausec) exactly as the client sent them.
+    #[instrument(level = "trace", skip_all)]
+    pub fn decrypt_pa_enc_timestamp_full(
+        &self,
+        base_key: &DerivedKey,
+    ) -> Result<(SystemTime, Option<u32>), KrbError> {
         // https://www.rfc-editor.org/rfc/rfc4120#section-5.2.7.2
         let data = self.decrypt_data(base_key, 1)?;
 
@@ -503,15 +1634,55 @@ impl EncryptedData {
         trace!(?paenctsenc);
 
         let stime = paenctsenc.patimestamp.to_system_time();
-        let usecs = paenctsenc
-            .pausec
-            .map(|s| Duration::from_micros(s as u64))
-            .unwrap_or_default();
 
-        let stime = stime + usecs;
+        Ok((stime, paenctsenc.pausec))
+    }
 
-        Ok(stime)
+    /// Decrypt the PA-ENC-TIMESTAMP as [Self::decrypt_pa_enc_timestamp_full] does, and
+    /// check that it is within [DEFAULT_MAX_SKEW] of `now`.
+    pub fn decrypt_pa_enc_timestamp_checked(
+        &self,
+        base_key: &DerivedKey,
+        now: SystemTime,
+    ) -> Result<(SystemTime, Option<u32>), KrbError> {
+        self.decrypt_pa_enc_timestamp_checked_with_skew(base_key, now, DEFAULT_MAX_SKEW)
     }
+
+    /// As [Self::decrypt_pa_enc_timestamp_checked], but accepting a timestamp up to
+    /// `max_skew` from `now`.
+    pub fn decrypt_pa_enc_timestamp_checked_with_skew(
+        &self,
+        base_key: &DerivedKey,
+        now: SystemTime,
+        max_skew: Duration,
+    ) -> Result<(SystemTime, Option<u32>), KrbError> {
+        let (stime, pausec) = self.decrypt_pa_enc_timestamp_full(base_key)?;
+        let timestamp = stime + Duration::from_micros(pausec.unwrap_or_default() as u64);
+
+        let offset = timestamp
+            .duration_since(now)
+            .or_else(|_| now.duration_since(timestamp))
+            .map_err(|_| KrbError::ClockSkew)?;
+
+        if offset > max_skew {
+            return Err(KrbError::ClockSkew);
+        }
+
+        Ok((stime, pausec))
+    }
+}
+
+fn decode_enc_kdc_rep_part(data: &[u8]) -> Result<EncKdcRepPart, KrbError> {
+    let tagged_kdc_enc_part =
+        TaggedEncKdcRepPart::from_der(data).map_err(|_| KrbError::DerDecodeEncKdcRepPart)?;
+
+    // RFC 4120 5.4.2 Implementations should accept either tag in either reply, as some
+    // send an EncTGSRepPart in an AS-REP.
+    let kdc_enc_part = match tagged_kdc_enc_part {
+        TaggedEncKdcRepPart::EncTgsRepPart(part) | TaggedEncKdcRepPart::EncAsRepPart(part) => part,
+    };
+
+    Ok(kdc_enc_part)
 }
 
 impl TryFrom<KdcEncryptedData> for EncryptedData {
@@ -528,6 +1699,12 @@ impl TryFrom<KdcEncryptedData> for EncryptedData {
                 let data = enc_data.cipher.into_bytes();
                 Ok(EncryptedData::Aes256CtsHmacSha196 { kvno, data })
             }
+            #[cfg(feature = "des3")]
+            EncryptionType::DES3_CBC_SHA1_KD => {
+                let kvno = enc_data.kvno;
+                let data = enc_data.cipher.into_bytes();
+                Ok(EncryptedData::Des3CbcSha1 { kvno, data })
+            }
             _ => Err(KrbError::UnsupportedEncryption),
         }
     }
@@ -541,11 +1718,17 @@ impl TryInto<KdcEncryptedData> for EncryptedData {
             EncryptedData::Aes256CtsHmacSha196 { kvno, data } => Ok(KdcEncryptedData {
                 etype: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
                 kvno,
-                cipher: OctetString::new(data).map_err(|e| {
-                    println!("{:#?}", e);
+                cipher: OctetString::new(data).map_err(|err| {
+                    tracing::error!(?err, "Unable to encode ciphertext");
                     KrbError::UnsupportedEncryption // TODO
                 })?,
             }),
+            #[cfg(feature = "des3")]
+            EncryptedData::Des3CbcSha1 { kvno, data } => Ok(KdcEncryptedData {
+                etype: EncryptionType::DES3_CBC_SHA1_KD as i32,
+                kvno,
+                cipher: OctetString::new(data).map_err(|_| KrbError::UnsupportedEncryption)?,
+            }),
         }
     }
 }
@@ -554,6 +1737,9 @@ impl TryFrom<Asn1Ticket> for Ticket {
     type Error = KrbError;
 
     fn try_from(tkt: Asn1Ticket) -> Result<Self, Self::Error> {
+        // The decoder only accepts DER, so encoding the ticket again yields the bytes that
+        // were received.
+        let der = tkt.to_der().map_err(|_| KrbError::DerEncodeTicket)?;
         let Asn1Ticket(tkt) = tkt;
 
         let service = Name::try_from((tkt.sname, tkt.realm))?;
@@ -564,6 +1750,7 @@ impl TryFrom<Asn1Ticket> for Ticket {
             tkt_vno,
             service,
             enc_part,
+            der: Some(der),
         })
     }
 }
@@ -572,10 +1759,15 @@ impl TryInto<Asn1Ticket> for Ticket {
     type Error = KrbError;
 
     fn try_into(self) -> Result<Asn1Ticket, KrbError> {
+        if let Some(der) = &self.der {
+            return Asn1Ticket::from_der(der).map_err(|_| KrbError::DerDecodeTicket);
+        }
+
+        let (sname, realm) = (&self.service).try_into()?;
         let t = crate::asn1::tagged_ticket::Ticket {
             tkt_vno: self.tkt_vno,
-            realm: (&self.service).try_into()?,
-            sname: (&self.service).try_into()?,
+            realm,
+            sname,
             enc_part: self.enc_part.try_into()?,
         };
         Ok(Asn1Ticket::new(t))
@@ -601,6 +1793,13 @@ impl TryFrom<EncKdcRepPart> for KdcReplyPart {
         let auth_time = enc_kdc_rep_part.auth_time.to_system_time();
         let end_time = enc_kdc_rep_part.end_time.to_system_time();
 
+        let client_addresses = enc_kdc_rep_part
+            .client_addresses
+            .unwrap_or_default()
+            .into_iter()
+            .map(HostAddress::from)
+            .collect();
+
         Ok(KdcReplyPart {
             key,
             nonce,
@@ -611,6 +1810,47 @@ impl TryFrom<EncKdcRepPart> for KdcReplyPart {
             end_time,
             renew_until,
             server,
+            client_addresses,
+        })
+    }
+}
+
+// The last-req of the reply part isn't kept, so it is encoded empty.
+impl TryFrom<&KdcReplyPart> for EncKdcRepPart {
+    type Error = KrbError;
+
+    fn try_from(reply_part: &KdcReplyPart) -> Result<Self, Self::Error> {
+        let kerberos_time = |time| {
+            KerberosTime::from_system_time(time).map_err(|_| KrbError::DerEncodeKerberosTime)
+        };
+
+        let (server_name, server_realm) = (&reply_part.server).try_into()?;
+
+        let client_addresses = if reply_part.client_addresses.is_empty() {
+            None
+        } else {
+            Some(
+                reply_part
+                    .client_addresses
+                    .iter()
+                    .map(KdcHostAddress::try_from)
+                    .collect::<Result<Vec<_>, _>>()?,
+            )
+        };
+
+        Ok(EncKdcRepPart {
+            key: (&reply_part.key).try_into()?,
+            last_req: Vec::with_capacity(0),
+            nonce: reply_part.nonce,
+            key_expiration: reply_part.key_expiration.map(kerberos_time).transpose()?,
+            flags: reply_part.flags,
+            auth_time: kerberos_time(reply_part.auth_time)?,
+            start_time: reply_part.start_time.map(kerberos_time).transpose()?,
+            end_time: kerberos_time(reply_part.end_time)?,
+            renew_till: reply_part.renew_until.map(kerberos_time).transpose()?,
+            server_realm,
+            server_name,
+            client_addresses,
         })
     }
 }
@@ -631,11 +1871,40 @@ impl TryFrom<KdcEncryptionKey> for SessionKey {
                     Err(KrbError::InvalidEncryptionKey)
                 }
             }
+            #[cfg(feature = "des3")]
+            EncryptionType::DES3_CBC_SHA1_KD => {
+                if kdc_key.key_value.as_bytes().len() == DES3_KEY_LEN {
+                    let mut k = [0u8; DES3_KEY_LEN];
+                    k.copy_from_slice(kdc_key.key_value.as_bytes());
+                    Ok(SessionKey::Des3CbcSha1 { k })
+                } else {
+                    Err(KrbError::InvalidEncryptionKey)
+                }
+            }
             _ => Err(KrbError::UnsupportedEncryption),
         }
     }
 }
 
+impl TryFrom<&SessionKey> for KdcEncryptionKey {
+    type Error = KrbError;
+
+    fn try_from(session_key: &SessionKey) -> Result<Self, Self::Error> {
+        let (key_type, key_value) = match session_key {
+            SessionKey::Aes256CtsHmacSha196 { k } => {
+                (EncryptionType::AES256_CTS_HMAC_SHA1_96, k.as_slice())
+            }
+            #[cfg(feature = "des3")]
+            SessionKey::Des3CbcSha1 { k } => (EncryptionType::DES3_CBC_SHA1_KD, k.as_slice()),
+        };
+
+        Ok(KdcEncryptionKey {
+            key_type: key_type as i32,
+            key_value: OctetString::new(key_value).map_err(|_| KrbError::DerEncodeOctetString)?,
+        })
+    }
+}
+
 impl Name {
     pub fn principal(name: &str, realm: &str) -> Self {
         Self::Principal {
@@ -647,15 +1916,59 @@ impl Name {
     pub fn service_krbtgt(realm: &str) -> Self {
         Self::SrvInst {
             service: "krbtgt".to_string(),
+            instance: realm.to_string(),
             realm: realm.to_string(),
         }
     }
 
-    pub fn is_service_krbtgt(&self, check_realm: &str) -> bool {
-        match self {
-            Self::SrvInst { service, realm } => service == "krbtgt" && check_realm == realm,
-            _ => false,
-        }
+    /// The cross realm ticket granting service `krbtgt/TARGET@REALM`, issued by the KDC
+    /// of `realm` for use with the KDC of `target_realm`.
+    pub fn service_krbtgt_cross_realm(target_realm: &str, realm: &str) -> Self {
+        Self::SrvInst {
+            service: "krbtgt".to_string(),
+            instance: target_realm.to_string(),
+            realm: realm.to_string(),
+        }
+    }
+
+    /// The host based service `service/host@REALM`, such as `HTTP/www.example.com`.
+    pub fn service_host(service: &str, host: &str, realm: &str) -> Self {
+        Self::SrvHst {
+            service: service.to_string(),
+            host: host.to_string(),
+            realm: realm.to_string(),
+        }
+    }
+
+    /// The service instance `service/instance@REALM`, such as `kadmin/admin`.
+    pub fn service_instance(service: &str, instance: &str, realm: &str) -> Self {
+        Self::SrvInst {
+            service: service.to_string(),
+            instance: instance.to_string(),
+            realm: realm.to_string(),
+        }
+    }
+
+    pub fn is_service_krbtgt(&self, check_realm: &str) -> bool {
+        match self {
+            Self::SrvInst {
+                service,
+                instance,
+                realm,
+            } => service == "krbtgt" && check_realm == instance && check_realm == realm,
+            _ => false,
+        }
+    }
+
+    /// If this name is a ticket granting service (`krbtgt/TARGET@REALM`) return the
+    /// realm of the KDC that the ticket is valid for.
+    pub fn krbtgt_target_realm(&self) -> Option<&str> {
+        match self {
+            Self::SrvInst {
+                service, instance, ..
+            } if service == "krbtgt" => Some(instance.as_str()),
+            _ => None,
+        }
     }
 
     /// If the name is a PRINCIPAL then return it's name and realm compontents. If
@@ -666,6 +1979,81 @@ impl Name {
             _ => Err(KrbError::NameNotPrincipal),
         }
     }
+
+    /// The realm of the name, whatever its type.
+    pub fn realm(&self) -> &str {
+        match self {
+            Name::Principal { realm, .. }
+            | Name::SrvInst { realm, .. }
+            | Name::SrvHst { realm, .. }
+            | Name::SrvXHst { realm, .. }
+            | Name::Other { realm, .. } => realm.as_str(),
+        }
+    }
+
+    /// The components of a service name, such as `["HTTP", "www.example.com"]`, without
+    /// the realm. A PRINCIPAL has no service components, so this is empty.
+    pub fn service_components(&self) -> Vec<&str> {
+        match self {
+            Name::Principal { .. } => Vec::new(),
+            Name::SrvInst {
+                service, instance, ..
+            } => vec![service.as_str(), instance.as_str()],
+            Name::SrvHst { service, host, .. } => vec![service.as_str(), host.as_str()],
+            Name::SrvXHst { components, .. } | Name::Other { components, .. } => {
+                components.iter().map(String::as_str).collect()
+            }
+        }
+    }
+}
+
+/// Split a name in its string form, `component/component@REALM`, into its components
+/// and realm. A backslash escapes a separator or backslash, or stands for a control
+/// character as `\n`, `\t`, `\b` and `\0`, as MIT krb5 writes names.
+pub(crate) fn parse_name_components(name: &str) -> Result<(Vec<String>, String), KrbError> {
+    let mut components = Vec::new();
+    let mut current = String::new();
+    let mut in_realm = false;
+    let mut chars = name.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => current.push(match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('b') => '\x08',
+                Some('0') => '\0',
+                Some(c) => c,
+                None => return Err(KrbError::MalformedPrincipalName),
+            }),
+            '/' | '@' if in_realm => return Err(KrbError::MalformedPrincipalName),
+            '/' | '@' => {
+                components.push(std::mem::take(&mut current));
+                in_realm = c == '@';
+            }
+            c => current.push(c),
+        }
+    }
+
+    let realm = current;
+    if !in_realm || realm.is_empty() || components.iter().any(String::is_empty) {
+        return Err(KrbError::MalformedPrincipalName);
+    }
+
+    Ok((components, realm))
+}
+
+/// Encode a name component or realm. NUL and other control characters are rejected,
+/// since they are valid in an Ia5String but would be truncated or misinterpreted by the
+/// C implementations that names are often passed on to.
+fn kerberos_string(component: &str) -> Result<KerberosString, KrbError> {
+    if component.chars().any(|c| c.is_ascii_control()) {
+        return Err(KrbError::InvalidPrincipalName);
+    }
+
+    Ia5String::new(component)
+        .map(KerberosString)
+        .map_err(|_| KrbError::InvalidPrincipalName)
 }
 
 impl TryInto<Realm> for &Name {
@@ -673,20 +2061,12 @@ impl TryInto<Realm> for &Name {
 
     fn try_into(self) -> Result<Realm, KrbError> {
         match self {
-            Name::Principal { name, realm } => {
-                let realm = KerberosString(Ia5String::new(realm).unwrap());
-                Ok(realm)
-            }
-            Name::SrvInst { service, realm } => {
-                let realm = KerberosString(Ia5String::new(realm).unwrap());
-                Ok(realm)
-            }
-            Name::SrvHst {
-                service,
-                host,
-                realm,
-            } => {
-                let realm = KerberosString(Ia5String::new(realm).unwrap());
+            Name::Principal { realm, .. }
+            | Name::SrvInst { realm, .. }
+            | Name::SrvHst { realm, .. }
+            | Name::SrvXHst { realm, .. }
+            | Name::Other { realm, .. } => {
+                let realm = kerberos_string(realm)?;
                 Ok(realm)
             }
         }
@@ -699,21 +2079,17 @@ impl TryInto<PrincipalName> for &Name {
     fn try_into(self) -> Result<PrincipalName, KrbError> {
         match self {
             Name::Principal { name, realm } => {
-                let name_string = vec![
-                    KerberosString(Ia5String::new(name).unwrap()),
-                    KerberosString(Ia5String::new(realm).unwrap()),
-                ];
+                let name_string = vec![kerberos_string(name)?, kerberos_string(realm)?];
 
                 Ok(PrincipalName {
                     name_type: 1,
                     name_string,
                 })
             }
-            Name::SrvInst { service, realm } => {
-                let name_string = vec![
-                    KerberosString(Ia5String::new(service).unwrap()),
-                    KerberosString(Ia5String::new(realm).unwrap()),
-                ];
+            Name::SrvInst {
+                service, instance, ..
+            } => {
+                let name_string = vec![kerberos_string(service)?, kerberos_string(instance)?];
 
                 Ok(PrincipalName {
                     name_type: 2,
@@ -726,9 +2102,9 @@ impl TryInto<PrincipalName> for &Name {
                 realm,
             } => {
                 let name_string = vec![
-                    KerberosString(Ia5String::new(service).unwrap()),
-                    KerberosString(Ia5String::new(host).unwrap()),
-                    KerberosString(Ia5String::new(realm).unwrap()),
+                    kerberos_string(service)?,
+                    kerberos_string(host)?,
+                    kerberos_string(realm)?,
                 ];
 
                 Ok(PrincipalName {
@@ -736,6 +2112,34 @@ impl TryInto<PrincipalName> for &Name {
                     name_string,
                 })
             }
+            Name::SrvXHst { components, realm } => {
+                let name_string = components
+                    .iter()
+                    .chain(std::iter::once(realm))
+                    .map(|component| kerberos_string(component))
+                    .collect::<Result<_, _>>()?;
+
+                Ok(PrincipalName {
+                    name_type: 4,
+                    name_string,
+                })
+            }
+            Name::Other {
+                name_type,
+                components,
+                realm,
+            } => {
+                let name_string = components
+                    .iter()
+                    .chain(std::iter::once(realm))
+                    .map(|component| kerberos_string(component))
+                    .collect::<Result<_, _>>()?;
+
+                Ok(PrincipalName {
+                    name_type: *name_type,
+                    name_string,
+                })
+            }
         }
     }
 }
@@ -746,8 +2150,8 @@ impl TryInto<(PrincipalName, Realm)> for &Name {
     fn try_into(self) -> Result<(PrincipalName, Realm), KrbError> {
         match self {
             Name::Principal { name, realm } => {
-                let name_string = vec![KerberosString(Ia5String::new(&name).unwrap())];
-                let realm = KerberosString(Ia5String::new(realm).unwrap());
+                let name_string = vec![kerberos_string(name)?];
+                let realm = kerberos_string(realm)?;
 
                 Ok((
                     PrincipalName {
@@ -757,9 +2161,13 @@ impl TryInto<(PrincipalName, Realm)> for &Name {
                     realm,
                 ))
             }
-            Name::SrvInst { service, realm } => {
-                let name_string = vec![KerberosString(Ia5String::new(&service).unwrap())];
-                let realm = KerberosString(Ia5String::new(realm).unwrap());
+            Name::SrvInst {
+                service,
+                instance,
+                realm,
+            } => {
+                let name_string = vec![kerberos_string(service)?, kerberos_string(instance)?];
+                let realm = kerberos_string(realm)?;
 
                 Ok((
                     PrincipalName {
@@ -774,11 +2182,8 @@ impl TryInto<(PrincipalName, Realm)> for &Name {
                 host,
                 realm,
             } => {
-                let name_string = vec![
-                    KerberosString(Ia5String::new(&service).unwrap()),
-                    KerberosString(Ia5String::new(&host).unwrap()),
-                ];
-                let realm = KerberosString(Ia5String::new(realm).unwrap());
+                let name_string = vec![kerberos_string(service)?, kerberos_string(host)?];
+                let realm = kerberos_string(realm)?;
 
                 Ok((
                     PrincipalName {
@@ -788,6 +2193,40 @@ impl TryInto<(PrincipalName, Realm)> for &Name {
                     realm,
                 ))
             }
+            Name::SrvXHst { components, realm } => {
+                let name_string = components
+                    .iter()
+                    .map(|component| kerberos_string(component))
+                    .collect::<Result<_, _>>()?;
+                let realm = kerberos_string(realm)?;
+
+                Ok((
+                    PrincipalName {
+                        name_type: 4,
+                        name_string,
+                    },
+                    realm,
+                ))
+            }
+            Name::Other {
+                name_type,
+                components,
+                realm,
+            } => {
+                let name_string = components
+                    .iter()
+                    .map(|component| kerberos_string(component))
+                    .collect::<Result<_, _>>()?;
+                let realm = kerberos_string(realm)?;
+
+                Ok((
+                    PrincipalName {
+                        name_type: *name_type,
+                        name_string,
+                    },
+                    realm,
+                ))
+            }
         }
     }
 }
@@ -802,26 +2241,72 @@ impl TryFrom<PrincipalName> for Name {
         } = princ;
         match name_type {
             1 => {
-                let name = name_string.get(0).unwrap().into();
-                let realm = name_string.get(1).unwrap().into();
+                let name = name_string
+                    .first()
+                    .ok_or(KrbError::MalformedPrincipalName)?
+                    .into();
+                let realm = name_string
+                    .get(1)
+                    .ok_or(KrbError::MalformedPrincipalName)?
+                    .into();
                 Ok(Name::Principal { name, realm })
             }
             2 => {
-                let service = name_string.get(0).unwrap().into();
-                let realm = name_string.get(1).unwrap().into();
-                Ok(Name::SrvInst { service, realm })
+                let service = name_string
+                    .first()
+                    .ok_or(KrbError::MalformedPrincipalName)?
+                    .into();
+                let instance: String = name_string
+                    .get(1)
+                    .ok_or(KrbError::MalformedPrincipalName)?
+                    .into();
+                let realm = instance.clone();
+                Ok(Name::SrvInst {
+                    service,
+                    instance,
+                    realm,
+                })
             }
             3 => {
-                let service = name_string.get(0).unwrap().into();
-                let host = name_string.get(1).unwrap().into();
-                let realm = name_string.get(2).unwrap().into();
+                let service = name_string
+                    .first()
+                    .ok_or(KrbError::MalformedPrincipalName)?
+                    .into();
+                let host = name_string
+                    .get(1)
+                    .ok_or(KrbError::MalformedPrincipalName)?
+                    .into();
+                let realm = name_string
+                    .get(2)
+                    .ok_or(KrbError::MalformedPrincipalName)?
+                    .into();
                 Ok(Name::SrvHst {
                     service,
                     host,
                     realm,
                 })
             }
-            _ => todo!(),
+            4 => {
+                let (realm, components) = name_string
+                    .split_last()
+                    .filter(|(_, components)| !components.is_empty())
+                    .ok_or(KrbError::MalformedPrincipalName)?;
+                Ok(Name::SrvXHst {
+                    components: components.iter().map(Into::into).collect(),
+                    realm: realm.into(),
+                })
+            }
+            name_type => {
+                let (realm, components) = name_string
+                    .split_last()
+                    .filter(|(_, components)| !components.is_empty())
+                    .ok_or(KrbError::MalformedPrincipalName)?;
+                Ok(Name::Other {
+                    name_type,
+                    components: components.iter().map(Into::into).collect(),
+                    realm: realm.into(),
+                })
+            }
         }
     }
 }
@@ -836,26 +2321,48 @@ impl TryFrom<(PrincipalName, Realm)> for Name {
         } = princ;
 
         let realm = realm.into();
+        let mut components: Vec<String> = name_string.iter().map(Into::into).collect();
 
-        match name_type {
-            1 => {
-                let name = name_string.get(0).unwrap().into();
-                Ok(Name::Principal { name, realm })
-            }
-            2 => {
-                let service = name_string.get(0).unwrap().into();
-                Ok(Name::SrvInst { service, realm })
+        // Names with fewer components than their type requires are malformed, but a
+        // name with more is kept whole rather than dropping the extra components.
+        let required = match name_type {
+            2 | 3 => 2,
+            _ => 1,
+        };
+
+        if components.len() < required {
+            return Err(KrbError::MalformedPrincipalName);
+        }
+
+        match (name_type, components.len()) {
+            (1, 1) => Ok(Name::Principal {
+                name: components.remove(0),
+                realm,
+            }),
+            (2, 2) => {
+                let instance = components.remove(1);
+                let service = components.remove(0);
+                Ok(Name::SrvInst {
+                    service,
+                    instance,
+                    realm,
+                })
             }
-            3 => {
-                let service = name_string.get(0).unwrap().into();
-                let host = name_string.get(1).unwrap().into();
+            (3, 2) => {
+                let host = components.remove(1);
+                let service = components.remove(0);
                 Ok(Name::SrvHst {
                     service,
                     host,
                     realm,
                 })
             }
-            _ => todo!(),
+            (4, _) => Ok(Name::SrvXHst { components, realm }),
+            (name_type, _) => Ok(Name::Other {
+                name_type,
+                components,
+                realm,
+            }),
         }
     }
 }
@@ -864,4 +2371,1336 @@ impl Preauth {
     pub fn enc_timestamp(&self) -> Option<&EncryptedData> {
         self.enc_timestamp.as_ref()
     }
+
+    /// The RFC 8070 freshness token sent with the request. This is empty when the
+    /// client only asked the KDC for a token.
+    pub fn as_freshness(&self) -> Option<&[u8]> {
+        self.pa_as_freshness.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        default_salt, DerivedKey, EncryptedData, HostAddress, KdcOptions, KdcReplyPart,
+        KerberosRequest, LastRequest, Name, PreauthData, SessionKey, Ticket,
+    };
+    use crate::asn1::constants::encryption_types::EncryptionType;
+    use crate::asn1::constants::PaDataType;
+    use crate::asn1::enc_kdc_rep_part::EncKdcRepPart;
+    use crate::asn1::encryption_key::EncryptionKey;
+    use crate::asn1::etype_info2::ETypeInfo2Entry;
+    use crate::asn1::host_address::HostAddress as KdcHostAddress;
+    use crate::asn1::kerberos_string::{KerberosString, RawKerberosString};
+    use crate::asn1::kerberos_time::KerberosTime;
+    use crate::asn1::krb_kdc_req::KrbKdcReq;
+    use crate::asn1::last_req::LastReqItem;
+    use crate::asn1::pa_data::PaData;
+    use crate::asn1::pa_enc_ts_enc::PaEncTsEnc;
+    use crate::asn1::principal_name::PrincipalName;
+    use crate::asn1::tagged_enc_kdc_rep_part::TaggedEncKdcRepPart;
+    use crate::asn1::ticket_flags::TicketFlags;
+    use crate::asn1::{Ia5String, OctetString};
+    use crate::clock::{Clock, SystemClock};
+    use crate::crypto::supported_etypes;
+    use crate::error::KrbError;
+    use crate::test_helpers::{reply_part_valid_between, session_key, user_key};
+    use der::flagset::FlagSet;
+    use der::Encode;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_pa_enc_ts_enc_from_system_time() {
+        let user_key = user_key();
+
+        let time = SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 750_000);
+        let paenctsenc = PaEncTsEnc::from_system_time(time).expect("Failed to build PaEncTsEnc");
+        assert_eq!(paenctsenc.pausec, Some(750));
+
+        let enc_timestamp = user_key
+            .encrypt_pa_enc_timestamp(&paenctsenc)
+            .expect("Failed to encrypt timestamp");
+        let (stime, pausec) = enc_timestamp
+            .decrypt_pa_enc_timestamp_full(&user_key)
+            .expect("Failed to decrypt timestamp");
+
+        assert_eq!(
+            stime,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+        assert_eq!(pausec, Some(750));
+    }
+
+    #[cfg(feature = "des3")]
+    #[test]
+    fn test_from_encrypted_reply_etype_info2() {
+        let etype_info2 = vec![
+            ETypeInfo2Entry {
+                etype: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                salt: Some(RawKerberosString(b"EXAMPLE.COMaes256".to_vec())),
+                s2kparams: Some(
+                    OctetString::new(0x2000u32.to_be_bytes()).expect("Invalid OCTET STRING"),
+                ),
+            },
+            ETypeInfo2Entry {
+                etype: EncryptionType::DES3_CBC_SHA1_KD as i32,
+                salt: Some(RawKerberosString(b"EXAMPLE.COMdes3".to_vec())),
+                s2kparams: None,
+            },
+        ];
+
+        let pa_data = PreauthData::try_from(vec![PaData {
+            padata_type: PaDataType::PaEtypeInfo2 as u32,
+            padata_value: OctetString::new(etype_info2.to_der().expect("Failed to encode"))
+                .expect("Invalid OCTET STRING"),
+        }])
+        .expect("Failed to parse padata");
+
+        // We preferred aes256, but the KDC replied in des3, so the key is derived with
+        // the salt of the des3 entry rather than the first one.
+        let enc_part = EncryptedData::Des3CbcSha1 {
+            kvno: None,
+            data: Vec::new(),
+        };
+
+        let base_key = DerivedKey::from_encrypted_reply(
+            &enc_part,
+            Some(&pa_data.etype_info2),
+            "EXAMPLE.COM",
+            "testuser",
+            "password",
+        )
+        .expect("Failed to derive key");
+
+        assert_eq!(
+            base_key,
+            DerivedKey::new_des3_cbc_sha1("password", "EXAMPLE.COMdes3")
+                .expect("Failed to derive key")
+        );
+    }
+
+    #[test]
+    fn test_default_salt() {
+        assert_eq!(
+            default_salt("EXAMPLE.COM", &Name::principal("testuser", "EXAMPLE.COM")),
+            "EXAMPLE.COMtestuser"
+        );
+
+        let service = Name::SrvHst {
+            service: "host".to_string(),
+            host: "kdc.example.com".to_string(),
+            realm: "EXAMPLE.COM".to_string(),
+        };
+        assert_eq!(
+            default_salt("EXAMPLE.COM", &service),
+            "EXAMPLE.COMhostkdc.example.com"
+        );
+    }
+
+    #[test]
+    fn test_etype_cryptographic_strength_order() {
+        use crate::proto::cryptographic_strength;
+
+        let mut etypes = vec![
+            EncryptionType::DES_CBC_MD5,
+            EncryptionType::AES128_CTS_HMAC_SHA1_96,
+            EncryptionType::RSA_ENV,
+            EncryptionType::RC4_HMAC,
+            EncryptionType::AES256_CTS_HMAC_SHA384_192,
+            EncryptionType::DES3_CBC_SHA1_KD,
+            EncryptionType::CAMELLIA256_CTS_CMAC,
+            EncryptionType::DES_CBC_CRC,
+            EncryptionType::AES256_CTS_HMAC_SHA1_96,
+            EncryptionType::AES128_CTS_HMAC_SHA256_128,
+        ];
+        etypes.sort_by_key(|etype| std::cmp::Reverse(cryptographic_strength(*etype)));
+
+        // Strongest first, the RFC 8009 etypes ahead of RFC 3962, then camellia, des3,
+        // rc4, single des and finally the PKINIT identifiers.
+        assert_eq!(
+            etypes,
+            vec![
+                EncryptionType::AES256_CTS_HMAC_SHA384_192,
+                EncryptionType::AES128_CTS_HMAC_SHA256_128,
+                EncryptionType::AES256_CTS_HMAC_SHA1_96,
+                EncryptionType::AES128_CTS_HMAC_SHA1_96,
+                EncryptionType::CAMELLIA256_CTS_CMAC,
+                EncryptionType::DES3_CBC_SHA1_KD,
+                EncryptionType::RC4_HMAC,
+                EncryptionType::DES_CBC_MD5,
+                EncryptionType::DES_CBC_CRC,
+                EncryptionType::RSA_ENV,
+            ]
+        );
+
+        // Each etype has a rank of its own, so no two compare equal.
+        let mut ranks: Vec<u8> = (0..=i32::from(EncryptionType::CAMELLIA256_CTS_CMAC))
+            .filter_map(|etype| EncryptionType::try_from(etype).ok())
+            .map(cryptographic_strength)
+            .collect();
+        let count = ranks.len();
+        ranks.sort_unstable();
+        ranks.dedup();
+        assert_eq!(ranks.len(), count);
+    }
+
+    #[test]
+    fn test_select_and_derive_prefers_aes256() {
+        let etype_info2 = vec![
+            ETypeInfo2Entry {
+                etype: EncryptionType::AES128_CTS_HMAC_SHA1_96 as i32,
+                salt: Some(RawKerberosString(b"EXAMPLE.COMaes128".to_vec())),
+                s2kparams: None,
+            },
+            ETypeInfo2Entry {
+                etype: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                salt: Some(RawKerberosString(b"EXAMPLE.COMtestuser".to_vec())),
+                s2kparams: Some(
+                    OctetString::new(0x1000u32.to_be_bytes()).expect("Invalid OCTET STRING"),
+                ),
+            },
+        ];
+
+        let pavec = vec![PaData {
+            padata_type: PaDataType::PaEtypeInfo2 as u32,
+            padata_value: OctetString::new(etype_info2.to_der().expect("Failed to encode"))
+                .expect("Invalid OCTET STRING"),
+        }];
+
+        let pa_data = PreauthData::try_from(pavec).expect("Failed to parse padata");
+
+        let selected = DerivedKey::select_and_derive(
+            &pa_data,
+            supported_etypes(),
+            "EXAMPLE.COM",
+            "testuser",
+            "password",
+        )
+        .expect("Failed to select etype");
+        let expected = user_key();
+
+        assert!(matches!(
+            (selected, expected),
+            (
+                DerivedKey::Aes256CtsHmacSha196 { k, i, s },
+                DerivedKey::Aes256CtsHmacSha196 {
+                    k: expected_k,
+                    i: expected_i,
+                    s: expected_s,
+                },
+            ) if k == expected_k && i == expected_i && s == expected_s
+        ));
+    }
+
+    #[test]
+    fn test_select_and_derive_only_requested_etypes() {
+        // The KDC's first and strongest entry is aes256, which the client didn't request.
+        let etype_info2 = vec![
+            ETypeInfo2Entry {
+                etype: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                salt: Some(RawKerberosString(b"EXAMPLE.COMaes256".to_vec())),
+                s2kparams: None,
+            },
+            #[cfg(feature = "des3")]
+            ETypeInfo2Entry {
+                etype: EncryptionType::DES3_CBC_SHA1_KD as i32,
+                salt: Some(RawKerberosString(b"EXAMPLE.COMdes3".to_vec())),
+                s2kparams: None,
+            },
+        ];
+        let pa_data = PreauthData::try_from(vec![
+            PaData {
+                padata_type: PaDataType::PaPwSalt as u32,
+                padata_value: OctetString::new("example.com".as_bytes())
+                    .expect("Invalid OCTET STRING"),
+            },
+            PaData {
+                padata_type: PaDataType::PaEtypeInfo2 as u32,
+                padata_value: OctetString::new(etype_info2.to_der().expect("Failed to encode"))
+                    .expect("Invalid OCTET STRING"),
+            },
+        ])
+        .expect("Failed to parse padata");
+
+        // Nor does the PA-PW-SALT fall back to aes256 when it wasn't requested.
+        assert!(matches!(
+            DerivedKey::select_and_derive(
+                &pa_data,
+                &[EncryptionType::AES128_CTS_HMAC_SHA1_96],
+                "EXAMPLE.COM",
+                "testuser",
+                "password"
+            ),
+            Err(KrbError::PreauthMissingEtypeInfo2)
+        ));
+
+        #[cfg(feature = "des3")]
+        {
+            let selected = DerivedKey::select_and_derive(
+                &pa_data,
+                &[EncryptionType::DES3_CBC_SHA1_KD],
+                "EXAMPLE.COM",
+                "testuser",
+                "password",
+            )
+            .expect("Failed to derive key");
+            assert!(
+                matches!(&selected, DerivedKey::Des3CbcSha1 { s, .. } if s == b"EXAMPLE.COMdes3")
+            );
+        }
+    }
+
+    #[test]
+    fn test_etype_info2_non_utf8_salt() {
+        // Some AD deployments have salts that aren't utf-8, and the key must be derived
+        // from the bytes that were sent rather than a lossy string.
+        let salt = b"EXAMPLE.COM\xfftestuser";
+        let etype_info2 = vec![ETypeInfo2Entry {
+            etype: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+            salt: Some(RawKerberosString(salt.to_vec())),
+            s2kparams: Some(
+                OctetString::new(0x1000u32.to_be_bytes()).expect("Invalid OCTET STRING"),
+            ),
+        }];
+        let preauth = PreauthData::try_from(vec![
+            PaData {
+                padata_type: PaDataType::PaEncTimestamp as u32,
+                padata_value: OctetString::new([]).expect("Invalid OCTET STRING"),
+            },
+            PaData {
+                padata_type: PaDataType::PaEtypeInfo2 as u32,
+                padata_value: OctetString::new(etype_info2.to_der().expect("Failed to encode"))
+                    .expect("Invalid OCTET STRING"),
+            },
+        ])
+        .expect("Failed to parse padata");
+
+        assert_eq!(preauth.etype_info2[0].salt(), Some(salt.as_slice()));
+        assert_eq!(
+            preauth.etype_info2[0].salt_str().as_deref(),
+            Some("EXAMPLE.COM\u{fffd}testuser")
+        );
+
+        let user_key = DerivedKey::select_and_derive(
+            &preauth,
+            supported_etypes(),
+            "EXAMPLE.COM",
+            "testuser",
+            "password",
+        )
+        .expect("Failed to derive key");
+        let expected = DerivedKey::Aes256CtsHmacSha196 {
+            k: crate::crypto::derive_key_aes256_cts_hmac_sha1_96(b"password", salt, 0x1000)
+                .expect("Failed to derive key"),
+            i: 0x1000,
+            s: salt.to_vec(),
+        };
+        assert!(user_key == expected);
+        assert!(matches!(&user_key, DerivedKey::Aes256CtsHmacSha196 { s, .. } if s == salt));
+
+        // The salt is sent on unchanged when the etype-info2 is encoded again.
+        let entry = ETypeInfo2Entry::try_from(&preauth.etype_info2[0])
+            .expect("Failed to encode etype-info2");
+        assert_eq!(entry.salt, Some(RawKerberosString(salt.to_vec())));
+    }
+
+    #[test]
+    fn test_select_and_derive_pw_salt() {
+        // A principal provisioned with an AFS style salt, where the KDC only sends
+        // PA-PW-SALT.
+        let pavec = vec![
+            PaData {
+                padata_type: PaDataType::PaEncTimestamp as u32,
+                padata_value: OctetString::new([]).expect("Invalid OCTET STRING"),
+            },
+            PaData {
+                padata_type: PaDataType::PaPwSalt as u32,
+                padata_value: OctetString::new("example.com".as_bytes())
+                    .expect("Invalid OCTET STRING"),
+            },
+        ];
+
+        let pa_data = PreauthData::try_from(pavec).expect("Failed to parse padata");
+        assert!(pa_data.etype_info2.is_empty());
+
+        let selected = DerivedKey::select_and_derive(
+            &pa_data,
+            supported_etypes(),
+            "EXAMPLE.COM",
+            "testuser",
+            "password",
+        )
+        .expect("Failed to derive key");
+
+        assert!(matches!(
+            selected,
+            DerivedKey::Aes256CtsHmacSha196 { s, .. } if s == b"example.com"
+        ));
+
+        // When the etype-info2 carries a salt, that is preferred.
+        let etype_info2 = vec![ETypeInfo2Entry {
+            etype: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+            salt: Some(RawKerberosString(b"EXAMPLE.COMtestuser".to_vec())),
+            s2kparams: None,
+        }];
+
+        let pavec = vec![
+            PaData {
+                padata_type: PaDataType::PaPwSalt as u32,
+                padata_value: OctetString::new("example.com".as_bytes())
+                    .expect("Invalid OCTET STRING"),
+            },
+            PaData {
+                padata_type: PaDataType::PaEtypeInfo2 as u32,
+                padata_value: OctetString::new(etype_info2.to_der().expect("Failed to encode"))
+                    .expect("Invalid OCTET STRING"),
+            },
+        ];
+
+        let pa_data = PreauthData::try_from(pavec).expect("Failed to parse padata");
+        let selected = DerivedKey::select_and_derive(
+            &pa_data,
+            supported_etypes(),
+            "EXAMPLE.COM",
+            "testuser",
+            "password",
+        )
+        .expect("Failed to derive key");
+
+        assert!(matches!(
+            selected,
+            DerivedKey::Aes256CtsHmacSha196 { s, .. } if s == b"EXAMPLE.COMtestuser"
+        ));
+    }
+
+    #[test]
+    fn test_from_etype_info2_min_iter_count() {
+        // A KDC that asks for a single PBKDF2 iteration, as a downgrade attack would.
+        let etype_info2 = vec![ETypeInfo2Entry {
+            etype: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+            salt: None,
+            s2kparams: Some(OctetString::new(1u32.to_be_bytes()).expect("Invalid OCTET STRING")),
+        }];
+        let pa_data = PreauthData::try_from(vec![
+            PaData {
+                padata_type: PaDataType::PaEncTimestamp as u32,
+                padata_value: OctetString::new([]).expect("Invalid OCTET STRING"),
+            },
+            PaData {
+                padata_type: PaDataType::PaEtypeInfo2 as u32,
+                padata_value: OctetString::new(etype_info2.to_der().expect("Failed to encode"))
+                    .expect("Invalid OCTET STRING"),
+            },
+        ])
+        .expect("Failed to parse padata");
+        let etype_info2 = pa_data
+            .preferred_etype_info2_of(supported_etypes())
+            .expect("No etype-info2");
+
+        assert!(matches!(
+            DerivedKey::from_etype_info2(&etype_info2, "EXAMPLE.COM", "testuser", "password"),
+            Err(KrbError::IterationCountTooLow)
+        ));
+        assert!(matches!(
+            DerivedKey::select_and_derive(
+                &pa_data,
+                supported_etypes(),
+                "EXAMPLE.COM",
+                "testuser",
+                "password"
+            ),
+            Err(KrbError::IterationCountTooLow)
+        ));
+        assert!(matches!(
+            KerberosRequest::build_as(
+                Name::principal("testuser", "EXAMPLE.COM"),
+                Name::service_krbtgt("EXAMPLE.COM"),
+                SystemClock.now() + Duration::from_secs(3600),
+            )
+            .preauth_enc_ts_with_passphrase(
+                &pa_data,
+                Duration::from_secs(1_700_000_000),
+                "password"
+            ),
+            Err(KrbError::IterationCountTooLow)
+        ));
+
+        // The caller can choose to accept the weak key.
+        let user_key = DerivedKey::from_etype_info2_with_min_iter(
+            &etype_info2,
+            &Name::principal("testuser", "EXAMPLE.COM"),
+            "password",
+            1,
+        )
+        .expect("Failed to derive key");
+        assert!(matches!(
+            user_key,
+            DerivedKey::Aes256CtsHmacSha196 { i: 1, .. }
+        ));
+
+        // The default salt of a service principal is made from all of its components.
+        let user_key = DerivedKey::from_etype_info2_with_min_iter(
+            &etype_info2,
+            &Name::service_host("host", "foo", "EXAMPLE.COM"),
+            "password",
+            1,
+        )
+        .expect("Failed to derive key");
+        assert!(matches!(
+            user_key,
+            DerivedKey::Aes256CtsHmacSha196 { s, .. } if s == b"EXAMPLE.COMhostfoo"
+        ));
+
+        assert_eq!(crate::DEFAULT_MIN_PBKDF2_SHA1_ITER, 4096);
+    }
+
+    #[test]
+    fn test_derived_key_eq() {
+        let key_a = user_key();
+        let key_b = user_key();
+        assert_eq!(key_a, key_b);
+
+        let key_c = DerivedKey::new_aes256_cts_hmac_sha1_96("password", "EXAMPLE.COMotheruser")
+            .expect("Failed to derive key");
+        assert_ne!(key_a, key_c);
+
+        let session_key = SessionKey::Aes256CtsHmacSha196 { k: [0x11; 32] };
+        assert_eq!(
+            session_key,
+            SessionKey::Aes256CtsHmacSha196 { k: [0x11; 32] }
+        );
+        assert_ne!(
+            session_key,
+            SessionKey::Aes256CtsHmacSha196 { k: [0x12; 32] }
+        );
+    }
+
+    #[test]
+    fn test_pa_enc_timestamp_pausec() {
+        let user_key = user_key();
+
+        let paenctsenc = PaEncTsEnc {
+            patimestamp: KerberosTime::from_unix_duration(Duration::from_secs(1_700_000_000))
+                .expect("Invalid KerberosTime"),
+            pausec: Some(123456),
+        };
+
+        let enc_timestamp = user_key
+            .encrypt_pa_enc_timestamp(&paenctsenc)
+            .expect("Failed to encrypt timestamp");
+
+        let (stime, pausec) = enc_timestamp
+            .decrypt_pa_enc_timestamp_full(&user_key)
+            .expect("Failed to decrypt timestamp");
+
+        assert_eq!(
+            stime,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+        assert_eq!(pausec, Some(123456));
+
+        let stime = enc_timestamp
+            .decrypt_pa_enc_timestamp(&user_key)
+            .expect("Failed to decrypt timestamp");
+
+        assert_eq!(
+            stime,
+            SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456)
+        );
+    }
+
+    #[test]
+    fn test_pa_enc_timestamp_max_skew() {
+        assert_eq!(crate::DEFAULT_MAX_SKEW, Duration::from_secs(300));
+
+        let user_key = user_key();
+
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let paenctsenc = PaEncTsEnc::from_system_time(time).expect("Failed to build PaEncTsEnc");
+        let enc_timestamp = user_key
+            .encrypt_pa_enc_timestamp(&paenctsenc)
+            .expect("Failed to encrypt timestamp");
+
+        // Within the default skew, either side of our time.
+        for now in [
+            time + Duration::from_secs(300),
+            time - Duration::from_secs(300),
+        ] {
+            assert!(enc_timestamp
+                .decrypt_pa_enc_timestamp_checked(&user_key, now)
+                .is_ok());
+        }
+
+        let now = time + Duration::from_secs(301);
+        assert!(matches!(
+            enc_timestamp.decrypt_pa_enc_timestamp_checked(&user_key, now),
+            Err(KrbError::ClockSkew)
+        ));
+
+        // An override is honored in both directions.
+        assert!(enc_timestamp
+            .decrypt_pa_enc_timestamp_checked_with_skew(&user_key, now, Duration::from_secs(600))
+            .is_ok());
+        let now = time + Duration::from_secs(60);
+        assert!(matches!(
+            enc_timestamp.decrypt_pa_enc_timestamp_checked_with_skew(
+                &user_key,
+                now,
+                Duration::from_secs(30)
+            ),
+            Err(KrbError::ClockSkew)
+        ));
+    }
+
+    #[cfg(feature = "des3")]
+    #[test]
+    fn test_pa_enc_timestamp_des3() {
+        let user_key = DerivedKey::new_des3_cbc_sha1("password", "ATHENA.MIT.EDUraeburn")
+            .expect("Failed to derive user key");
+
+        let paenctsenc = PaEncTsEnc {
+            patimestamp: KerberosTime::from_unix_duration(Duration::from_secs(1_700_000_000))
+                .expect("Invalid KerberosTime"),
+            pausec: Some(123456),
+        };
+
+        let enc_timestamp = user_key
+            .encrypt_pa_enc_timestamp(&paenctsenc)
+            .expect("Failed to encrypt timestamp");
+
+        // The zero padding must be removed for the timestamp to decode.
+        let stime = enc_timestamp
+            .decrypt_pa_enc_timestamp(&user_key)
+            .expect("Failed to decrypt timestamp");
+
+        assert_eq!(
+            stime,
+            SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456)
+        );
+    }
+
+    #[test]
+    fn test_reply_part_is_valid_at() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+        // Valid from the auth time.
+        let reply_part = reply_part_valid_between(None, 2_000);
+        assert!(reply_part.is_valid_at(at(1_000)));
+        assert!(reply_part.is_valid_at(at(1_500)));
+        assert!(!reply_part.is_valid_at(at(999)));
+
+        // Postdated, not yet valid.
+        let reply_part = reply_part_valid_between(Some(1_500), 2_000);
+        assert!(!reply_part.is_valid_at(at(1_200)));
+        assert!(reply_part.is_valid_at(at(1_500)));
+
+        // Expired.
+        let reply_part = reply_part_valid_between(None, 2_000);
+        assert!(!reply_part.is_valid_at(at(2_000)));
+        assert!(!reply_part.is_valid_at(at(3_000)));
+    }
+
+    #[test]
+    fn test_enc_kdc_rep_part_encrypt_round_trip() {
+        let kerberos_time = |secs| KerberosTime::from_unix_duration(Duration::from_secs(secs));
+        let enc_kdc_rep_part = |nonce| {
+            let (server_name, server_realm) = (&Name::service_krbtgt("EXAMPLE.COM"))
+                .try_into()
+                .expect("Invalid name");
+            EncKdcRepPart {
+                key: EncryptionKey {
+                    key_type: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                    key_value: OctetString::new([0x55; 32]).expect("Invalid OCTET STRING"),
+                },
+                last_req: Vec::with_capacity(0),
+                nonce,
+                key_expiration: None,
+                flags: FlagSet::<TicketFlags>::new(0).expect("Invalid ticket flags"),
+                auth_time: kerberos_time(1_000).expect("Invalid KerberosTime"),
+                start_time: None,
+                end_time: kerberos_time(2_000).expect("Invalid KerberosTime"),
+                renew_till: None,
+                server_realm,
+                server_name,
+                client_addresses: None,
+            }
+        };
+
+        let user_key = user_key();
+        let reply_part =
+            KdcReplyPart::try_from(enc_kdc_rep_part(1)).expect("Failed to convert reply part");
+        let enc_part = user_key
+            .encrypt_enc_as_rep_part(&reply_part)
+            .expect("Failed to encrypt reply part");
+        let reply_part = enc_part
+            .decrypt_enc_kdc_rep(&user_key)
+            .expect("Failed to decrypt reply part");
+        assert_eq!(reply_part.server(), &Name::service_krbtgt("EXAMPLE.COM"));
+        assert!(reply_part.is_valid_at(SystemTime::UNIX_EPOCH + Duration::from_secs(1_500)));
+
+        // The TGS-REP part is encrypted in the session key, with a different usage.
+        let session_key = SessionKey::Aes256CtsHmacSha196 { k: [0x11; 32] };
+        let reply_part =
+            KdcReplyPart::try_from(enc_kdc_rep_part(2)).expect("Failed to convert reply part");
+        let enc_part = session_key
+            .encrypt_enc_tgs_rep_part(&reply_part)
+            .expect("Failed to encrypt reply part");
+        assert!(enc_part.decrypt_enc_tgs_rep(&session_key).is_ok());
+        assert!(enc_part
+            .decrypt_enc_tgs_rep_with_usage(&session_key, 3)
+            .is_err());
+    }
+
+    #[test]
+    fn test_enc_kdc_rep_part_raw_last_req() {
+        let kerberos_time = |secs| KerberosTime::from_unix_duration(Duration::from_secs(secs));
+        let (server_name, server_realm) = (&Name::service_krbtgt("EXAMPLE.COM"))
+            .try_into()
+            .expect("Invalid name");
+
+        // The time of the last initial request, which KdcReplyPart doesn't keep.
+        let last_req = vec![LastReqItem {
+            lr_type: 1,
+            lr_value: kerberos_time(500).expect("Invalid KerberosTime"),
+        }];
+
+        let user_key = user_key();
+        let enc_part = user_key
+            .encrypt_enc_kdc_rep_part(EncKdcRepPart {
+                key: EncryptionKey {
+                    key_type: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                    key_value: OctetString::new([0x55; 32]).expect("Invalid OCTET STRING"),
+                },
+                last_req,
+                nonce: 1,
+                key_expiration: None,
+                flags: FlagSet::<TicketFlags>::new(0).expect("Invalid ticket flags"),
+                auth_time: kerberos_time(1_000).expect("Invalid KerberosTime"),
+                start_time: None,
+                end_time: kerberos_time(2_000).expect("Invalid KerberosTime"),
+                renew_till: None,
+                server_realm,
+                server_name,
+                client_addresses: None,
+            })
+            .expect("Failed to encrypt reply part");
+
+        let raw_part = enc_part
+            .decrypt_enc_kdc_rep_raw(&user_key, 3)
+            .expect("Failed to decrypt reply part");
+        assert_eq!(
+            raw_part.last_req(),
+            vec![LastRequest {
+                lr_type: 1,
+                lr_value: SystemTime::UNIX_EPOCH + Duration::from_secs(500),
+            }]
+        );
+        assert_eq!(raw_part.nonce(), 1);
+
+        // The raw part converts to the lossy reply part.
+        let reply_part =
+            KdcReplyPart::try_from(raw_part).expect("Failed to convert raw reply part");
+        assert_eq!(reply_part.server(), &Name::service_krbtgt("EXAMPLE.COM"));
+
+        // The lossy conversion still works on the same enc-part.
+        let reply_part = enc_part
+            .decrypt_enc_kdc_rep(&user_key)
+            .expect("Failed to decrypt reply part");
+        assert_eq!(reply_part.server(), &Name::service_krbtgt("EXAMPLE.COM"));
+    }
+
+    #[test]
+    fn test_reply_part_renewable_ok_downgrade() {
+        let now = SystemClock.now();
+        let hour = Duration::from_secs(3600);
+        let kerberos_time = |t| KerberosTime::from_system_time(t).expect("Invalid KerberosTime");
+        let (server_name, server_realm) = (&Name::service_krbtgt("EXAMPLE.COM"))
+            .try_into()
+            .expect("Invalid name");
+
+        // A day long ticket, accepting a renewable ticket if that's too long.
+        let as_req = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now + 24 * hour,
+        )
+        .kdc_options(KdcOptions::default().forwardable(true).renewable_ok(true))
+        .build(&SystemClock)
+        .expect("Failed to build request");
+
+        // The KDC caps the lifetime at ten hours, renewable up to the requested end time,
+        // and refuses to make it forwardable.
+        let reply_part = KdcReplyPart::try_from(EncKdcRepPart {
+            key: EncryptionKey {
+                key_type: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                key_value: OctetString::new([0x55; 32]).expect("Invalid OCTET STRING"),
+            },
+            last_req: Vec::with_capacity(0),
+            nonce: 1,
+            key_expiration: None,
+            flags: FlagSet::from(TicketFlags::Renewable),
+            auth_time: kerberos_time(now),
+            start_time: None,
+            end_time: kerberos_time(now + 10 * hour),
+            renew_till: Some(kerberos_time(now + 24 * hour)),
+            server_realm,
+            server_name,
+            client_addresses: None,
+        })
+        .expect("Failed to build reply part");
+
+        let downgrade = reply_part.downgrade(&as_req);
+        assert!(!downgrade.is_honored());
+        assert!(downgrade.end_time_reduced);
+        assert!(downgrade.renewable_ok);
+        assert!(downgrade.forwardable_refused);
+        assert!(!downgrade.renewable_refused);
+        assert!(!downgrade.renew_until_reduced);
+
+        // The same ticket is exactly what a ten hour, renewable request asked for.
+        let as_req = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now + 10 * hour,
+        )
+        .renew_until(Some(now + 24 * hour))
+        .build(&SystemClock)
+        .expect("Failed to build request");
+        assert!(reply_part.downgrade(&as_req).is_honored());
+    }
+
+    #[test]
+    fn test_reply_part_client_addresses() {
+        let session_key = session_key();
+        let kerberos_time = KerberosTime::from_unix_duration(Duration::from_secs(1_000))
+            .expect("Invalid KerberosTime");
+        let (server_name, server_realm) = (&Name::service_krbtgt("EXAMPLE.COM"))
+            .try_into()
+            .expect("Invalid name");
+
+        let enc_part = TaggedEncKdcRepPart::EncTgsRepPart(EncKdcRepPart {
+            key: EncryptionKey {
+                key_type: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                key_value: OctetString::new([0x55; 32]).expect("Invalid OCTET STRING"),
+            },
+            last_req: Vec::with_capacity(0),
+            nonce: 1,
+            key_expiration: None,
+            flags: FlagSet::<TicketFlags>::new(0).expect("Invalid ticket flags"),
+            auth_time: kerberos_time,
+            start_time: None,
+            end_time: kerberos_time,
+            renew_till: None,
+            server_realm,
+            server_name,
+            client_addresses: Some(vec![KdcHostAddress {
+                addr_type: 2,
+                address: OctetString::new([192, 0, 2, 1]).expect("Invalid OCTET STRING"),
+            }]),
+        })
+        .to_der()
+        .expect("Failed to encode");
+
+        let reply_part = session_key
+            .encrypt_data(&enc_part, 8)
+            .and_then(|enc_part| enc_part.decrypt_enc_tgs_rep(&session_key))
+            .expect("Failed to decrypt reply part");
+
+        assert_eq!(
+            reply_part.client_addresses(),
+            &[HostAddress::Ip(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))]
+        );
+    }
+
+    #[test]
+    fn test_host_address_from_ip() {
+        let ipv4 = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let host_address = HostAddress::try_from(ipv4).expect("Invalid host address");
+        assert_eq!(host_address, HostAddress::Ip(ipv4));
+        let kdc_host_address =
+            KdcHostAddress::try_from(&host_address).expect("Invalid host address");
+        assert_eq!(kdc_host_address.addr_type, 2);
+        assert_eq!(kdc_host_address.address.as_bytes(), &[192, 0, 2, 1]);
+
+        let ipv6 = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        let host_address = HostAddress::try_from(ipv6).expect("Invalid host address");
+        assert_eq!(host_address, HostAddress::Ip(ipv6));
+        let kdc_host_address =
+            KdcHostAddress::try_from(&host_address).expect("Invalid host address");
+        assert_eq!(kdc_host_address.addr_type, 24);
+        assert_eq!(
+            kdc_host_address.address.as_bytes(),
+            &[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]
+        );
+        assert_eq!(HostAddress::from(kdc_host_address), host_address);
+
+        // ::ffff:192.0.2.1 is sent as the plain IPv4 address.
+        let mapped = IpAddr::V6(Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped());
+        let host_address = HostAddress::try_from(mapped).expect("Invalid host address");
+        assert_eq!(host_address, HostAddress::Ip(ipv4));
+        let kdc_host_address =
+            KdcHostAddress::try_from(&host_address).expect("Invalid host address");
+        assert_eq!(kdc_host_address.addr_type, 2);
+        assert_eq!(kdc_host_address.address.as_bytes(), &[192, 0, 2, 1]);
+
+        // And is understood as IPv4 when a peer sends it as IPv6.
+        let kdc_host_address = KdcHostAddress {
+            addr_type: 24,
+            address: OctetString::new(Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped().octets())
+                .expect("Invalid OCTET STRING"),
+        };
+        assert_eq!(HostAddress::from(kdc_host_address), HostAddress::Ip(ipv4));
+
+        for ip in [
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            IpAddr::V4(Ipv4Addr::new(224, 0, 0, 1)),
+            IpAddr::V6(Ipv4Addr::new(192, 0, 2, 1).to_ipv6_compatible()),
+        ] {
+            assert!(matches!(
+                HostAddress::try_from(ip),
+                Err(KrbError::InvalidHostAddress)
+            ));
+        }
+
+        assert!(HostAddress::try_from(IpAddr::V6(Ipv6Addr::LOCALHOST)).is_ok());
+    }
+
+    #[test]
+    fn test_tgs_rep_part_key_usage() {
+        let session_key = session_key();
+        let kerberos_time = KerberosTime::from_unix_duration(Duration::from_secs(1_000))
+            .expect("Invalid KerberosTime");
+        let (server_name, server_realm) = (&Name::principal("someservice", "EXAMPLE.COM"))
+            .try_into()
+            .expect("Invalid name");
+
+        let enc_part = TaggedEncKdcRepPart::EncTgsRepPart(EncKdcRepPart {
+            key: EncryptionKey {
+                key_type: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                key_value: OctetString::new([0x55; 32]).expect("Invalid OCTET STRING"),
+            },
+            last_req: Vec::with_capacity(0),
+            nonce: 1,
+            key_expiration: None,
+            flags: FlagSet::<TicketFlags>::new(0).expect("Invalid ticket flags"),
+            auth_time: kerberos_time,
+            start_time: None,
+            end_time: kerberos_time,
+            renew_till: None,
+            server_realm,
+            server_name,
+            client_addresses: None,
+        })
+        .to_der()
+        .expect("Failed to encode");
+
+        // The standard usage of 8 for a TGS-REP.
+        let reply_part = session_key
+            .encrypt_data(&enc_part, 8)
+            .and_then(|enc_part| enc_part.decrypt_enc_tgs_rep(&session_key))
+            .expect("Failed to decrypt reply part");
+        assert_eq!(
+            reply_part.server(),
+            &Name::principal("someservice", "EXAMPLE.COM")
+        );
+
+        // Encrypted in an authenticator subkey, with usage 9.
+        let enc_part = session_key
+            .encrypt_data(&enc_part, 9)
+            .expect("Failed to encrypt");
+        assert!(matches!(
+            enc_part.decrypt_enc_tgs_rep(&session_key),
+            Err(KrbError::MessageAuthenticationFailed)
+        ));
+        let reply_part = enc_part
+            .decrypt_enc_tgs_rep_with_usage(&session_key, 9)
+            .expect("Failed to decrypt reply part");
+        assert_eq!(
+            reply_part.server(),
+            &Name::principal("someservice", "EXAMPLE.COM")
+        );
+    }
+
+    #[test]
+    fn test_ticket_der_round_trip() {
+        let ticket = Ticket {
+            tkt_vno: 5,
+            service: Name::SrvHst {
+                service: "HTTP".to_string(),
+                host: "www.example.com".to_string(),
+                realm: "EXAMPLE.COM".to_string(),
+            },
+            enc_part: EncryptedData::Aes256CtsHmacSha196 {
+                kvno: Some(3),
+                data: vec![0x42; 64],
+            },
+            der: None,
+        };
+
+        let der = ticket.to_der().expect("Failed to encode ticket");
+        // Ticket is [APPLICATION 1]
+        assert_eq!(der[0], 0x61);
+
+        let decoded = Ticket::from_der(&der).expect("Failed to decode ticket");
+        assert_eq!(decoded.service(), ticket.service());
+        assert_eq!(decoded.kvno(), Some(3));
+    }
+
+    #[test]
+    fn test_ticket_der_preserved() {
+        use crate::asn1::encrypted_data::EncryptedData as KdcEncryptedData;
+        use crate::asn1::tagged_ticket::{TaggedTicket, Ticket as KdcTicket};
+
+        // A ticket from another KDC, with a name we would refuse to encode ourselves.
+        let der = TaggedTicket::new(KdcTicket {
+            tkt_vno: 5,
+            realm: KerberosString(Ia5String::new("EXAMPLE.COM").expect("Invalid IA5String")),
+            sname: PrincipalName {
+                name_type: 2,
+                name_string: vec![
+                    KerberosString(Ia5String::new("HTTP").expect("Invalid IA5String")),
+                    KerberosString(
+                        Ia5String::new("www\x01.example.com").expect("Invalid IA5String"),
+                    ),
+                ],
+            },
+            enc_part: KdcEncryptedData {
+                etype: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                kvno: Some(7),
+                cipher: OctetString::new(vec![0x42; 64]).expect("Invalid OCTET STRING"),
+            },
+        })
+        .to_der()
+        .expect("Failed to encode");
+
+        let ticket = Ticket::from_der(&der).expect("Failed to decode ticket");
+        assert_eq!(ticket.kvno(), Some(7));
+        assert_eq!(ticket.to_der().expect("Failed to encode ticket"), der);
+
+        // Including when the ticket is carried in another message.
+        let tagged: TaggedTicket = ticket.clone().try_into().expect("Failed to convert ticket");
+        assert_eq!(tagged.to_der().expect("Failed to encode"), der);
+
+        // A ticket that we build ourselves still checks its names.
+        let rebuilt = Ticket {
+            der: None,
+            ..ticket
+        };
+        assert!(matches!(
+            rebuilt.to_der(),
+            Err(KrbError::InvalidPrincipalName)
+        ));
+    }
+
+    #[test]
+    fn test_preauth_supported_enctypes() {
+        let pa_data = |padata_value: &[u8]| {
+            PreauthData::try_from(vec![
+                PaData {
+                    padata_type: PaDataType::PaEncTimestamp as u32,
+                    padata_value: OctetString::new(b"").expect("Invalid OCTET STRING"),
+                },
+                PaData {
+                    padata_type: PaDataType::PaSupportedEtypes as u32,
+                    padata_value: OctetString::new(padata_value).expect("Invalid OCTET STRING"),
+                },
+            ])
+            .expect("Failed to parse padata")
+        };
+
+        // RC4-HMAC, AES128 and AES256, and the FAST supported bit, which isn't an etype.
+        let ad_pa_data = pa_data(&[0x1c, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            ad_pa_data.supported_enctypes(),
+            Some(
+                [
+                    EncryptionType::RC4_HMAC,
+                    EncryptionType::AES128_CTS_HMAC_SHA1_96,
+                    EncryptionType::AES256_CTS_HMAC_SHA1_96,
+                ]
+                .as_slice()
+            )
+        );
+        // It's a hint, not a mechanism.
+        assert!(ad_pa_data.require_enc_timestamp().is_ok());
+
+        // A mask that isn't 32 bits is ignored.
+        assert!(pa_data(&[0x1c]).supported_enctypes().is_none());
+        assert!(PreauthData::default().supported_enctypes().is_none());
+    }
+
+    #[test]
+    fn test_ticket_new_encrypted() {
+        use crate::proto::TicketTimes;
+
+        let service = Name::SrvHst {
+            service: "HTTP".to_string(),
+            host: "www.example.com".to_string(),
+            realm: "EXAMPLE.COM".to_string(),
+        };
+        let client = Name::principal("testuser", "EXAMPLE.COM");
+        let service_key =
+            DerivedKey::new_aes256_cts_hmac_sha1_96("password", "EXAMPLE.COMHTTPwww.example.com")
+                .expect("Failed to derive service key");
+        let session_key = SessionKey::Aes256CtsHmacSha196 { k: [0x11; 32] };
+
+        let auth_time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let times = TicketTimes {
+            auth_time,
+            start_time: None,
+            end_time: auth_time + Duration::from_secs(3600),
+            renew_until: Some(auth_time + Duration::from_secs(86400)),
+        };
+
+        let ticket = Ticket::new_encrypted(
+            &service,
+            &client,
+            &session_key,
+            TicketFlags::Renewable as u32,
+            times,
+            &service_key,
+        )
+        .expect("Failed to mint ticket");
+        assert_eq!(ticket.service(), &service);
+
+        // The ticket survives being sent to the client and on to the service.
+        let ticket = Ticket::from_der(&ticket.to_der().expect("Failed to encode"))
+            .expect("Failed to decode");
+        let ticket_part = ticket
+            .decrypt(&service_key)
+            .expect("Failed to decrypt ticket");
+
+        assert_eq!(ticket_part.client(), &client);
+        assert!(matches!(
+            ticket_part.session_key(),
+            SessionKey::Aes256CtsHmacSha196 { k } if *k == [0x11; 32]
+        ));
+        assert_eq!(ticket_part.renew_until(), times.renew_until);
+        assert!(ticket_part.is_valid_at(auth_time));
+        assert!(!ticket_part.is_valid_at(times.end_time));
+
+        // Only the service can decrypt it.
+        let other_key = DerivedKey::new_aes256_cts_hmac_sha1_96("password", "EXAMPLE.COMother")
+            .expect("Failed to derive key");
+        assert!(ticket.decrypt(&other_key).is_err());
+    }
+
+    #[test]
+    fn test_name_control_characters() {
+        let name = Name::principal("test\0user", "EXAMPLE.COM");
+        let result: Result<(PrincipalName, KerberosString), _> = (&name).try_into();
+        assert!(matches!(result, Err(KrbError::InvalidPrincipalName)));
+        let result: Result<PrincipalName, _> = (&name).try_into();
+        assert!(matches!(result, Err(KrbError::InvalidPrincipalName)));
+
+        let name = Name::principal("testuser", "EXAMPLE.COM\n");
+        let result: Result<KerberosString, _> = (&name).try_into();
+        assert!(matches!(result, Err(KrbError::InvalidPrincipalName)));
+
+        // Not representable in an Ia5String at all.
+        let name = Name::principal("tëstuser", "EXAMPLE.COM");
+        let result: Result<(PrincipalName, KerberosString), _> = (&name).try_into();
+        assert!(matches!(result, Err(KrbError::InvalidPrincipalName)));
+
+        // A request for the name can't be encoded.
+        let as_req = KerberosRequest::build_as(
+            Name::principal("test\0user", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            SystemClock.now() + Duration::from_secs(3600),
+        )
+        .build(&SystemClock)
+        .expect("Failed to build request");
+        let result: Result<KrbKdcReq, _> = as_req.try_into();
+        assert!(matches!(result, Err(KrbError::InvalidPrincipalName)));
+    }
+
+    #[test]
+    fn test_name_srv_xhst() {
+        let kerberos_string = |s| KerberosString(Ia5String::new(s).expect("Invalid IA5String"));
+
+        let princ = || PrincipalName {
+            name_type: 4,
+            name_string: vec![
+                kerberos_string("host"),
+                kerberos_string("www"),
+                kerberos_string("example"),
+                kerberos_string("com"),
+            ],
+        };
+        let realm = || kerberos_string("EXAMPLE.COM");
+
+        let name = Name::try_from((princ(), realm())).expect("Failed to decode name");
+        assert_eq!(
+            name,
+            Name::SrvXHst {
+                components: vec![
+                    "host".to_string(),
+                    "www".to_string(),
+                    "example".to_string(),
+                    "com".to_string(),
+                ],
+                realm: "EXAMPLE.COM".to_string(),
+            }
+        );
+
+        let (encoded_princ, encoded_realm): (PrincipalName, _) =
+            (&name).try_into().expect("Failed to encode name");
+        assert_eq!(encoded_princ, princ());
+        assert_eq!(encoded_realm, realm());
+
+        let princ = PrincipalName {
+            name_type: 4,
+            name_string: Vec::with_capacity(0),
+        };
+        assert!(matches!(
+            Name::try_from((princ, realm())),
+            Err(KrbError::MalformedPrincipalName)
+        ));
+    }
+
+    #[test]
+    fn test_name_realm_and_service_components() {
+        let name = Name::principal("testuser", "EXAMPLE.COM");
+        assert_eq!(name.realm(), "EXAMPLE.COM");
+        assert!(name.service_components().is_empty());
+
+        let name = Name::service_krbtgt_cross_realm("OTHER.COM", "EXAMPLE.COM");
+        assert_eq!(name.realm(), "EXAMPLE.COM");
+        assert_eq!(name.service_components(), ["krbtgt", "OTHER.COM"]);
+
+        let name = Name::SrvHst {
+            service: "HTTP".to_string(),
+            host: "www.example.com".to_string(),
+            realm: "EXAMPLE.COM".to_string(),
+        };
+        assert_eq!(name.realm(), "EXAMPLE.COM");
+        assert_eq!(name.service_components(), ["HTTP", "www.example.com"]);
+
+        let name = Name::SrvXHst {
+            components: vec!["host".to_string(), "www".to_string()],
+            realm: "EXAMPLE.COM".to_string(),
+        };
+        assert_eq!(name.realm(), "EXAMPLE.COM");
+        assert_eq!(name.service_components(), ["host", "www"]);
+    }
+
+    #[test]
+    fn test_name_service_constructors() {
+        let kerberos_string = |s| KerberosString(Ia5String::new(s).expect("Invalid IA5String"));
+
+        let name = Name::service_host("HTTP", "www.example.com", "EXAMPLE.COM");
+        let (princ, realm): (PrincipalName, KerberosString) =
+            (&name).try_into().expect("Failed to encode name");
+        assert_eq!(princ.name_type, 3);
+        assert_eq!(
+            princ.name_string,
+            [kerberos_string("HTTP"), kerberos_string("www.example.com")]
+        );
+        assert_eq!(realm, kerberos_string("EXAMPLE.COM"));
+
+        let name = Name::service_instance("kadmin", "admin", "EXAMPLE.COM");
+        let (princ, realm): (PrincipalName, KerberosString) =
+            (&name).try_into().expect("Failed to encode name");
+        assert_eq!(princ.name_type, 2);
+        assert_eq!(
+            princ.name_string,
+            [kerberos_string("kadmin"), kerberos_string("admin")]
+        );
+        assert_eq!(realm, kerberos_string("EXAMPLE.COM"));
+
+        assert_eq!(
+            Name::service_instance("krbtgt", "EXAMPLE.COM", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM")
+        );
+    }
+
+    #[test]
+    fn test_name_missing_components() {
+        let kerberos_string = |s| KerberosString(Ia5String::new(s).expect("Invalid IA5String"));
+
+        let princ = PrincipalName {
+            name_type: 2,
+            name_string: Vec::with_capacity(0),
+        };
+        assert!(matches!(
+            Name::try_from(princ),
+            Err(KrbError::MalformedPrincipalName)
+        ));
+
+        let princ = PrincipalName {
+            name_type: 3,
+            name_string: vec![kerberos_string("HTTP"), kerberos_string("www.example.com")],
+        };
+        assert!(matches!(
+            Name::try_from(princ),
+            Err(KrbError::MalformedPrincipalName)
+        ));
+
+        let princ = PrincipalName {
+            name_type: 1,
+            name_string: Vec::with_capacity(0),
+        };
+        let realm = kerberos_string("EXAMPLE.COM");
+        assert!(matches!(
+            Name::try_from((princ, realm)),
+            Err(KrbError::MalformedPrincipalName)
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json_round_trip() {
+        use crate::proto::EtypeInfo2;
+
+        let name = Name::SrvHst {
+            service: "HTTP".to_string(),
+            host: "www.example.com".to_string(),
+            realm: "EXAMPLE.COM".to_string(),
+        };
+        let json = serde_json::to_string(&name).expect("Failed to serialize name");
+        let decoded: Name = serde_json::from_str(&json).expect("Failed to deserialize name");
+        assert_eq!(decoded, name);
+
+        let etype_info2 = vec![ETypeInfo2Entry {
+            etype: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+            salt: Some(RawKerberosString(b"EXAMPLE.COMtestuser".to_vec())),
+            s2kparams: Some(
+                OctetString::new(0x1000u32.to_be_bytes()).expect("Invalid OCTET STRING"),
+            ),
+        }];
+        let pavec = vec![PaData {
+            padata_type: PaDataType::PaEtypeInfo2 as u32,
+            padata_value: OctetString::new(etype_info2.to_der().expect("Failed to encode"))
+                .expect("Invalid OCTET STRING"),
+        }];
+        let pa_data = PreauthData::try_from(pavec).expect("Failed to parse padata");
+        let etype_info2 = &pa_data.etype_info2[0];
+
+        let json = serde_json::to_string(etype_info2).expect("Failed to serialize etype info");
+        let decoded: EtypeInfo2 =
+            serde_json::from_str(&json).expect("Failed to deserialize etype info");
+        assert_eq!(&decoded, etype_info2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_reply_part_summary() {
+        use crate::proto::KdcReplyPartSummary;
+
+        let reply_part = reply_part_valid_between(Some(1_500), 2_000);
+        let summary = KdcReplyPartSummary::from(&reply_part);
+
+        assert_eq!(summary.key_type, EncryptionType::AES256_CTS_HMAC_SHA1_96);
+        assert_eq!(summary.auth_time, "1970-01-01T00:16:40Z");
+        assert_eq!(summary.start_time.as_deref(), Some("1970-01-01T00:25:00Z"));
+        assert_eq!(summary.end_time, "1970-01-01T00:33:20Z");
+
+        let json = serde_json::to_string(&summary).expect("Failed to serialize summary");
+        assert!(!json.contains("key_value"));
+        let decoded: KdcReplyPartSummary =
+            serde_json::from_str(&json).expect("Failed to deserialize summary");
+        assert_eq!(decoded, summary);
+    }
 }
@@ -0,0 +1,293 @@
+use crate::asn1::pa_otp::{AlgorithmIdentifier, OtpTokenInfo as KdcOtpTokenInfo, PaOtpChallenge};
+use crate::asn1::OctetString;
+use crate::error::KrbError;
+use der::asn1::ObjectIdentifier;
+
+// OTPFlags are KerberosFlags, numbered from the most significant bit of the first octet.
+// Bit 0 is reserved.
+const OTP_FLAG_NEXT_OTP: u8 = 0x40;
+const OTP_FLAG_COMBINE: u8 = 0x20;
+const OTP_FLAG_COLLECT_PIN: u8 = 0x10;
+const OTP_FLAG_DO_NOT_COLLECT_PIN: u8 = 0x08;
+const OTP_FLAG_MUST_ENCRYPT_NONCE: u8 = 0x04;
+const OTP_FLAG_SEPARATE_PIN_REQUIRED: u8 = 0x02;
+
+/// The representations of an OTP value defined by RFC 6560.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpFormat {
+    Decimal = 0,
+    Hexadecimal = 1,
+    Alphanumeric = 2,
+    Binary = 3,
+    Base64 = 4,
+}
+
+impl TryFrom<i32> for OtpFormat {
+    type Error = KrbError;
+
+    fn try_from(format: i32) -> Result<Self, Self::Error> {
+        match format {
+            0 => Ok(OtpFormat::Decimal),
+            1 => Ok(OtpFormat::Hexadecimal),
+            2 => Ok(OtpFormat::Alphanumeric),
+            3 => Ok(OtpFormat::Binary),
+            4 => Ok(OtpFormat::Base64),
+            _ => Err(KrbError::InvalidEnumValue(
+                std::any::type_name::<OtpFormat>().to_string(),
+                format,
+            )),
+        }
+    }
+}
+
+/// The PA-OTP-CHALLENGE of a KDC that accepts one time passwords as defined in RFC 6560.
+///
+/// Answering the challenge is not yet supported. The PA-OTP-REQUEST carries the OTP
+/// value in the clear and relies on a FAST tunnel to protect it, which this crate
+/// doesn't provide.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtpChallenge {
+    nonce: Vec<u8>,
+    service: Option<String>,
+    token_info: Vec<OtpTokenInfo>,
+    salt: Option<Vec<u8>>,
+    s2kparams: Option<Vec<u8>>,
+}
+
+impl OtpChallenge {
+    /// The nonce that the client returns, encrypted, in its PA-OTP-REQUEST.
+    pub fn nonce(&self) -> &[u8] {
+        &self.nonce
+    }
+
+    /// The name of the OTP service, which may be shown to the user.
+    pub fn service(&self) -> Option<&str> {
+        self.service.as_deref()
+    }
+
+    /// The tokens that the KDC will accept, of which the client uses one.
+    pub fn token_info(&self) -> &[OtpTokenInfo] {
+        &self.token_info
+    }
+
+    /// The salt to derive a key from the OTP value with, if the KDC sent one, exactly as
+    /// it was sent.
+    pub fn salt(&self) -> Option<&[u8]> {
+        self.salt.as_deref()
+    }
+
+    /// The string-to-key parameters to derive a key from the OTP value with, if the
+    /// KDC sent them.
+    pub fn s2kparams(&self) -> Option<&[u8]> {
+        self.s2kparams.as_deref()
+    }
+}
+
+/// A token that the KDC will accept a one time password from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtpTokenInfo {
+    flags: u8,
+    vendor: Option<String>,
+    challenge: Option<Vec<u8>>,
+    length: Option<i32>,
+    format: Option<i32>,
+    token_id: Option<Vec<u8>>,
+    alg_id: Option<String>,
+    supported_hash_algs: Vec<String>,
+    iteration_count: Option<i32>,
+}
+
+impl OtpTokenInfo {
+    /// The KDC requires the next OTP value of the token, rather than the current one.
+    pub fn next_otp(&self) -> bool {
+        self.flags & OTP_FLAG_NEXT_OTP != 0
+    }
+
+    /// The KDC requires the current and next OTP values, combined.
+    pub fn combine(&self) -> bool {
+        self.flags & OTP_FLAG_COMBINE != 0
+    }
+
+    /// The client should ask the user for the PIN of the token.
+    pub fn collect_pin(&self) -> bool {
+        self.flags & OTP_FLAG_COLLECT_PIN != 0
+    }
+
+    /// The client should not ask the user for the PIN of the token.
+    pub fn do_not_collect_pin(&self) -> bool {
+        self.flags & OTP_FLAG_DO_NOT_COLLECT_PIN != 0
+    }
+
+    /// The KDC requires the nonce to be encrypted in a key derived from the OTP value.
+    pub fn must_encrypt_nonce(&self) -> bool {
+        self.flags & OTP_FLAG_MUST_ENCRYPT_NONCE != 0
+    }
+
+    /// The PIN must be sent separately from the OTP value.
+    pub fn separate_pin_required(&self) -> bool {
+        self.flags & OTP_FLAG_SEPARATE_PIN_REQUIRED != 0
+    }
+
+    pub fn vendor(&self) -> Option<&str> {
+        self.vendor.as_deref()
+    }
+
+    /// The challenge to give the token, for challenge and response tokens.
+    pub fn challenge(&self) -> Option<&[u8]> {
+        self.challenge.as_deref()
+    }
+
+    /// The length of the OTP value.
+    pub fn length(&self) -> Option<i32> {
+        self.length
+    }
+
+    /// The format of the OTP value, if it is one that RFC 6560 defines.
+    pub fn format(&self) -> Option<OtpFormat> {
+        self.format
+            .and_then(|format| OtpFormat::try_from(format).ok())
+    }
+
+    pub fn token_id(&self) -> Option<&[u8]> {
+        self.token_id.as_deref()
+    }
+
+    /// The URI of the OTP algorithm of the token.
+    pub fn alg_id(&self) -> Option<&str> {
+        self.alg_id.as_deref()
+    }
+
+    /// The object identifiers of the hash algorithms that the KDC accepts, in dotted
+    /// decimal form, in its order of preference.
+    pub fn supported_hash_algs(&self) -> &[String] {
+        &self.supported_hash_algs
+    }
+
+    /// The iteration count to use with the hash algorithm.
+    pub fn iteration_count(&self) -> Option<i32> {
+        self.iteration_count
+    }
+}
+
+impl From<PaOtpChallenge> for OtpChallenge {
+    fn from(challenge: PaOtpChallenge) -> Self {
+        OtpChallenge {
+            nonce: challenge.nonce.into_bytes(),
+            service: challenge.otp_service,
+            token_info: challenge
+                .otp_token_info
+                .into_iter()
+                .map(OtpTokenInfo::from)
+                .collect(),
+            salt: challenge.salt.map(|salt| salt.0),
+            s2kparams: challenge.s2kparams.map(OctetString::into_bytes),
+        }
+    }
+}
+
+impl From<KdcOtpTokenInfo> for OtpTokenInfo {
+    fn from(token_info: KdcOtpTokenInfo) -> Self {
+        let supported_hash_algs = token_info
+            .supported_hash_alg
+            .unwrap_or_default()
+            .iter()
+            .filter_map(algorithm_oid)
+            .collect();
+
+        OtpTokenInfo {
+            flags: token_info
+                .flags
+                .raw_bytes()
+                .first()
+                .copied()
+                .unwrap_or_default(),
+            vendor: token_info.otp_vendor,
+            challenge: token_info.otp_challenge.map(OctetString::into_bytes),
+            length: token_info.otp_length,
+            format: token_info.otp_format,
+            token_id: token_info.otp_token_id.map(OctetString::into_bytes),
+            alg_id: token_info.otp_alg_id,
+            supported_hash_algs,
+            iteration_count: token_info.iteration_count,
+        }
+    }
+}
+
+// The object identifier of the algorithm in dotted decimal form. An algorithm that isn't
+// identified by a well formed object identifier is skipped.
+fn algorithm_oid(algorithm: &AlgorithmIdentifier) -> Option<String> {
+    algorithm
+        .algorithm
+        .decode_as::<ObjectIdentifier>()
+        .ok()
+        .map(|oid| oid.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asn1::constants::PaDataType;
+    use crate::asn1::kerberos_string::RawKerberosString;
+    use crate::asn1::pa_data::PaData;
+    use crate::asn1::pa_otp::PaOtpChallenge;
+    use crate::asn1::OctetString;
+    use crate::proto::{OtpFormat, PreauthData};
+    use der::{Decode, Encode};
+
+    #[test]
+    fn test_preauth_otp_challenge() {
+        // A PA-OTP-CHALLENGE for a single HOTP token, which asks for the PIN and accepts
+        // SHA-256.
+        let challenge = hex::decode(concat!(
+            "308187a00a04080102030405060708a1050c034f5450a2723070306ea007030500100000",
+            "00a1090c074578616d706c65a303020106a403020100a5090407746f6b656e2d31a62a0c",
+            "2875726e3a696574663a706172616d733a786d6c3a6e733a6b657970726f763a70736b63",
+            "3a686f7470a711300f300d06096086480165030402010500a804020203e8"
+        ))
+        .expect("Invalid hex");
+
+        let pa_data = PreauthData::try_from(vec![PaData {
+            padata_type: PaDataType::PaOtpChallenge as u32,
+            padata_value: OctetString::new(challenge.as_slice()).expect("Invalid OCTET STRING"),
+        }])
+        .expect("Failed to parse padata");
+
+        let otp_challenge = pa_data.otp_challenge().expect("No OTP challenge");
+        assert_eq!(otp_challenge.nonce(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(otp_challenge.service(), Some("OTP"));
+        assert!(otp_challenge.salt().is_none());
+
+        let [token] = otp_challenge.token_info() else {
+            unreachable!();
+        };
+        assert!(token.collect_pin());
+        assert!(!token.must_encrypt_nonce());
+        assert_eq!(token.vendor(), Some("Example"));
+        assert_eq!(token.length(), Some(6));
+        assert_eq!(token.format(), Some(OtpFormat::Decimal));
+        assert_eq!(token.token_id(), Some(b"token-1".as_slice()));
+        assert_eq!(
+            token.alg_id(),
+            Some("urn:ietf:params:xml:ns:keyprov:pskc:hotp")
+        );
+        assert_eq!(token.supported_hash_algs(), &["2.16.840.1.101.3.4.2.1"]);
+        assert_eq!(token.iteration_count(), Some(1000));
+
+        // The salt is kept as the bytes that were sent, which need not be utf-8.
+        let mut challenge =
+            PaOtpChallenge::from_der(&challenge).expect("Failed to decode challenge");
+        challenge.salt = Some(RawKerberosString(b"EXAMPLE.COM\xfftestuser".to_vec()));
+
+        let pa_data = PreauthData::try_from(vec![PaData {
+            padata_type: PaDataType::PaOtpChallenge as u32,
+            padata_value: OctetString::new(challenge.to_der().expect("Failed to encode"))
+                .expect("Invalid OCTET STRING"),
+        }])
+        .expect("Failed to parse padata");
+
+        let otp_challenge = pa_data.otp_challenge().expect("No OTP challenge");
+        assert_eq!(
+            otp_challenge.salt(),
+            Some(b"EXAMPLE.COM\xfftestuser".as_slice())
+        );
+    }
+}
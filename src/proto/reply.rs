@@ -8,7 +8,7 @@ use crate::asn1::{
     encryption_key::EncryptionKey as KdcEncryptionKey,
     etype_info2::ETypeInfo2Entry as KdcETypeInfo2Entry,
     kdc_rep::KdcRep,
-    kerberos_string::KerberosString,
+    kerberos_string::{KerberosString, RawKerberosString},
     kerberos_time::KerberosTime,
     krb_error::KrbError as KdcKrbError,
     krb_error::MethodData,
@@ -18,21 +18,24 @@ use crate::asn1::{
     transited_encoding::TransitedEncoding,
     Ia5String, OctetString,
 };
-use crate::constants::{AES_256_KEY_LEN, PKBDF2_SHA1_ITER};
+use crate::clock::ClockOffset;
+use crate::constants::{AES_256_KEY_LEN, PKBDF2_SHA1_ITER, RFC_PKBDF2_SHA1_ITER};
 use crate::crypto::{
     decrypt_aes256_cts_hmac_sha1_96, derive_key_aes256_cts_hmac_sha1_96,
-    encrypt_aes256_cts_hmac_sha1_96,
+    encrypt_aes256_cts_hmac_sha1_96, supported_etypes,
 };
 use crate::error::KrbError;
 use der::{flagset::FlagSet, Decode, Encode};
 use rand::{thread_rng, Rng};
 
 use std::time::{Duration, SystemTime};
-use tracing::trace;
+use tracing::{error, instrument, trace};
 
-use super::{DerivedKey, EncryptedData, EtypeInfo2, KdcPrimaryKey, Name, PreauthData, Ticket};
+use super::{
+    DerivedKey, EncryptedData, EtypeInfo2, KdcPrimaryKey, KdcReplyPart, Name, PreauthData, Ticket,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum KerberosReply {
     AS(AuthenticationReply),
     TGS(TicketGrantReply),
@@ -40,7 +43,7 @@ pub enum KerberosReply {
     ERR(ErrorReply),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AuthenticationReply {
     pub name: Name,
     pub enc_part: EncryptedData,
@@ -48,35 +51,42 @@ pub struct AuthenticationReply {
     pub ticket: Ticket,
 }
 
-#[derive(Debug)]
-pub struct TicketGrantReply {}
+#[derive(Debug, Clone)]
+pub struct TicketGrantReply {
+    pub name: Name,
+    pub enc_part: EncryptedData,
+    pub ticket: Ticket,
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PreauthReply {
     pub pa_data: PreauthData,
     pub service: Name,
     pub stime: SystemTime,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ErrorReply {
     code: KrbErrorCode,
     service: Name,
     error_text: Option<String>,
     stime: SystemTime,
+    // The encryption types the KDC will accept, sent with KDC_ERR_ETYPE_NOSUPP.
+    etype_info2: Vec<EtypeInfo2>,
 }
 
 pub struct KerberosReplyPreauthBuilder {
     pa_fx_cookie: Option<Vec<u8>>,
+    pa_as_freshness: Option<Vec<u8>>,
     aes256_cts_hmac_sha1_96_iter_count: u32,
-    salt: Option<String>,
+    salt: Option<Vec<u8>>,
     service: Name,
     stime: SystemTime,
 }
 
 pub struct KerberosReplyAuthenticationBuilder {
     aes256_cts_hmac_sha1_96_iter_count: u32,
-    salt: Option<String>,
+    salt: Option<Vec<u8>>,
     client: Name,
     server: Name,
 
@@ -89,10 +99,55 @@ pub struct KerberosReplyAuthenticationBuilder {
 }
 
 impl KerberosReply {
+    /// Encode the reply as the DER message that a KDC sends, without the record marking
+    /// of the TCP transport.
+    pub fn to_der(&self) -> Result<Vec<u8>, KrbError> {
+        let krb_kdc_rep: KrbKdcRep = self.clone().try_into()?;
+        krb_kdc_rep.to_der().map_err(|_| KrbError::DerEncodeKdcRep)
+    }
+
+    pub fn from_der(data: &[u8]) -> Result<Self, KrbError> {
+        let krb_kdc_rep = KrbKdcRep::from_der(data).map_err(|_| KrbError::DerDecodeKdcRep)?;
+        KerberosReply::try_from(krb_kdc_rep)
+    }
+
+    /// Check that this is a reply to an AS-REQ, which is either an AS-REP, a request
+    /// for preauthentication or an error.
+    pub fn expect_as_rep(self) -> Result<Self, KrbError> {
+        match self {
+            KerberosReply::AS(_) | KerberosReply::PA(_) | KerberosReply::ERR(_) => Ok(self),
+            KerberosReply::TGS(_) => Err(KrbError::UnexpectedReplyType),
+        }
+    }
+
+    /// Check that this is a reply to a TGS-REQ, which is either a TGS-REP or an error.
+    pub fn expect_tgs_rep(self) -> Result<Self, KrbError> {
+        match self {
+            KerberosReply::TGS(_) | KerberosReply::ERR(_) => Ok(self),
+            KerberosReply::AS(_) | KerberosReply::PA(_) => Err(KrbError::UnexpectedReplyType),
+        }
+    }
+
+    /// The realm this reply concerns, so that a client with requests to several realms
+    /// can route it. This is the client's realm for an AS-REP or TGS-REP, and the
+    /// realm of the service that replied for a request for preauthentication or an
+    /// error.
+    pub fn realm(&self) -> Option<&str> {
+        let name = match self {
+            KerberosReply::AS(AuthenticationReply { name, .. })
+            | KerberosReply::TGS(TicketGrantReply { name, .. }) => name,
+            KerberosReply::PA(PreauthReply { service, .. })
+            | KerberosReply::ERR(ErrorReply { service, .. }) => service,
+        };
+
+        Some(name.realm())
+    }
+
     pub fn preauth_builder(service: Name, stime: SystemTime) -> KerberosReplyPreauthBuilder {
         let aes256_cts_hmac_sha1_96_iter_count: u32 = PKBDF2_SHA1_ITER;
         KerberosReplyPreauthBuilder {
             pa_fx_cookie: None,
+            pa_as_freshness: None,
             aes256_cts_hmac_sha1_96_iter_count,
             salt: None,
             service,
@@ -136,6 +191,7 @@ impl KerberosReply {
                 "Client and Server do not have overlapping encryption type support.".to_string(),
             ),
             stime,
+            etype_info2: Vec::with_capacity(0),
         })
     }
 
@@ -147,6 +203,7 @@ impl KerberosReply {
                 "Preauthentication Failed - Check your password is correct.".to_string(),
             ),
             stime,
+            etype_info2: Vec::with_capacity(0),
         })
     }
 
@@ -158,6 +215,7 @@ impl KerberosReply {
                 "Preauthentication Failed - Client Name was not a valid Principal.".to_string(),
             ),
             stime,
+            etype_info2: Vec::with_capacity(0),
         })
     }
 
@@ -167,6 +225,7 @@ impl KerberosReply {
             service,
             error_text: Some("Preauthentication Failed - Check your realm is correct.".to_string()),
             stime,
+            etype_info2: Vec::with_capacity(0),
         })
     }
 
@@ -178,6 +237,7 @@ impl KerberosReply {
                 "Preauthentication Failed - Check your username is correct.".to_string(),
             ),
             stime,
+            etype_info2: Vec::with_capacity(0),
         })
     }
 
@@ -190,6 +250,17 @@ impl KerberosReply {
                     .to_string(),
             ),
             stime,
+            etype_info2: Vec::with_capacity(0),
+        })
+    }
+
+    pub fn error_service_unknown(service: Name, stime: SystemTime) -> KerberosReply {
+        KerberosReply::ERR(ErrorReply {
+            code: KrbErrorCode::KdcErrSPrincipalUnknown,
+            service,
+            error_text: Some("Server not found in Kerberos database".to_string()),
+            stime,
+            etype_info2: Vec::with_capacity(0),
         })
     }
 
@@ -199,6 +270,7 @@ impl KerberosReply {
             service,
             error_text: Some("No Key Available".to_string()),
             stime,
+            etype_info2: Vec::with_capacity(0),
         })
     }
 
@@ -208,6 +280,7 @@ impl KerberosReply {
             service,
             error_text: Some("Clock Skew too great".to_string()),
             stime,
+            etype_info2: Vec::with_capacity(0),
         })
     }
 
@@ -217,10 +290,121 @@ impl KerberosReply {
             service,
             error_text: Some("Internal Server Error".to_string()),
             stime,
+            etype_info2: Vec::with_capacity(0),
+        })
+    }
+
+    /// The KDC_ERR_PREAUTH_REQUIRED reply to an AS-REQ without preauthentication. It
+    /// asks for PA-ENC-TIMESTAMP, gives the etype-info2 of each of the supported
+    /// `etypes` with the principal's salt, and carries a PA-FX-COOKIE that the client
+    /// will return with its next request. AES keys are described with the iteration
+    /// count of RFC 3962, as [DerivedKey::new_aes256_cts_hmac_sha1_96] uses. Use
+    /// [KerberosReply::preauth_builder] for keys derived with another count.
+    pub fn preauth_required(
+        service: Name,
+        stime: SystemTime,
+        principal_salt: &str,
+        etypes: &[EncryptionType],
+        cookie: Vec<u8>,
+    ) -> KerberosReply {
+        let etype_info2 = etypes
+            .iter()
+            .filter(|etype| supported_etypes().contains(etype))
+            .map(|etype| {
+                let s2kparams = match etype {
+                    EncryptionType::AES256_CTS_HMAC_SHA1_96 => {
+                        Some(RFC_PKBDF2_SHA1_ITER.to_be_bytes().to_vec())
+                    }
+                    _ => None,
+                };
+
+                EtypeInfo2 {
+                    etype: *etype,
+                    salt: Some(principal_salt.as_bytes().to_vec()),
+                    s2kparams,
+                }
+            })
+            .collect();
+
+        KerberosReply::PA(PreauthReply {
+            pa_data: PreauthData {
+                pa_fx_fast: false,
+                enc_timestamp: true,
+                pa_fx_cookie: Some(cookie),
+                pa_as_freshness: None,
+                etype_info2,
+                pw_salt: None,
+                padata_types: Vec::with_capacity(0),
+                pa_spake: false,
+                spake_challenge: None,
+                otp_challenge: None,
+                supported_enctypes: None,
+            },
+            service,
+            stime,
         })
     }
 }
 
+impl ErrorReply {
+    pub fn code(&self) -> KrbErrorCode {
+        self.code
+    }
+
+    pub fn service(&self) -> &Name {
+        &self.service
+    }
+
+    pub fn error_text(&self) -> Option<&str> {
+        self.error_text.as_deref()
+    }
+
+    /// The time of the KDC when it sent the error, from its stime and susec.
+    pub fn stime(&self) -> SystemTime {
+        self.stime
+    }
+
+    /// The offset of the KDC's clock from `now`, if the KDC rejected the request
+    /// because the clocks are too far apart (KRB_AP_ERR_SKEW). The request can be
+    /// retried with a clock corrected by the offset, such as an
+    /// [OffsetClock](crate::clock::OffsetClock).
+    pub fn clock_offset(&self, now: SystemTime) -> Option<ClockOffset> {
+        (self.code == KrbErrorCode::KrbApErrSkew).then(|| ClockOffset::between(now, self.stime))
+    }
+
+    /// The encryption types and their parameters that the KDC would accept. This is only
+    /// present when the KDC rejected all of the encryption types the client offered
+    /// (KDC_ERR_ETYPE_NOSUPP), and allows the client to retry with one that is supported.
+    pub fn etype_info2(&self) -> &[EtypeInfo2] {
+        &self.etype_info2
+    }
+}
+
+impl AuthenticationReply {
+    /// Decrypt the reply with the client's long term key.
+    ///
+    /// Before decrypting, the client name and realm of the reply are checked against
+    /// `client_name`, the client that the request was made for. This catches a reply
+    /// that was issued for a different client.
+    #[instrument(level = "trace", skip_all)]
+    pub fn decrypt(&self, client_name: &Name, key: &DerivedKey) -> Result<KdcReplyPart, KrbError> {
+        if &self.name != client_name {
+            return Err(KrbError::ClientNameMismatch);
+        }
+
+        self.enc_part.decrypt_enc_kdc_rep(key)
+    }
+
+    /// Check that `key`, derived from the client's password, decrypts the reply.
+    ///
+    /// This only proves the password is correct if the reply came from the real KDC.
+    /// To guard against a spoofed KDC, the ticket must also be verified, for example by
+    /// using it to authenticate to a service whose key we hold.
+    pub fn verify_password(&self, client_name: &Name, key: &DerivedKey) -> Result<(), KrbError> {
+        self.decrypt(client_name, key).map(|_| ())
+    }
+}
+
 impl KerberosReplyPreauthBuilder {
     pub fn set_key_params(mut self, dk: &DerivedKey) -> Self {
         match dk {
@@ -229,6 +413,11 @@ impl KerberosReplyPreauthBuilder {
                 self.aes256_cts_hmac_sha1_96_iter_count = *i;
                 self
             }
+            #[cfg(feature = "des3")]
+            DerivedKey::Des3CbcSha1 { s, .. } => {
+                self.salt = Some(s.clone());
+                self
+            }
         }
     }
 
@@ -237,6 +426,12 @@ impl KerberosReplyPreauthBuilder {
         self
     }
 
+    /// Issue an RFC 8070 freshness token, which the client returns with its next request.
+    pub fn set_pa_as_freshness(mut self, as_freshness: Option<Vec<u8>>) -> Self {
+        self.pa_as_freshness = as_freshness;
+        self
+    }
+
     pub fn build(self) -> KerberosReply {
         let aes256_cts_hmac_sha1_96_iter_count = Some(
             self.aes256_cts_hmac_sha1_96_iter_count
@@ -249,11 +444,18 @@ impl KerberosReplyPreauthBuilder {
                 pa_fx_fast: false,
                 enc_timestamp: true,
                 pa_fx_cookie: self.pa_fx_cookie,
+                pa_as_freshness: self.pa_as_freshness,
                 etype_info2: vec![EtypeInfo2 {
                     etype: EncryptionType::AES256_CTS_HMAC_SHA1_96,
                     salt: self.salt,
                     s2kparams: aes256_cts_hmac_sha1_96_iter_count,
                 }],
+                pw_salt: None,
+                padata_types: Vec::with_capacity(0),
+                pa_spake: false,
+                spake_challenge: None,
+                otp_challenge: None,
+                supported_enctypes: None,
             },
             service: self.service,
             stime: self.stime,
@@ -263,7 +465,7 @@ impl KerberosReplyPreauthBuilder {
 
 impl KerberosReplyAuthenticationBuilder {
     pub fn set_salt(mut self, salt: Option<String>) -> Self {
-        self.salt = salt;
+        self.salt = salt.map(String::into_bytes);
         self
     }
 
@@ -272,6 +474,7 @@ impl KerberosReplyAuthenticationBuilder {
         self
     }
 
+    #[instrument(level = "trace", skip_all)]
     pub fn build(
         self,
         user_key: &DerivedKey,
@@ -288,12 +491,14 @@ impl KerberosReplyAuthenticationBuilder {
             key_value,
         };
 
-        let auth_time = KerberosTime::from_system_time(self.auth_time).unwrap();
-        let start_time = KerberosTime::from_system_time(self.auth_time).unwrap();
-        let end_time = KerberosTime::from_system_time(self.auth_time).unwrap();
-        let renew_till = self
-            .renew_until
-            .map(|t| KerberosTime::from_system_time(t).unwrap());
+        let kerberos_time = |time| {
+            KerberosTime::from_system_time(time).map_err(|_| KrbError::DerEncodeKerberosTime)
+        };
+
+        let auth_time = kerberos_time(self.auth_time)?;
+        let start_time = kerberos_time(self.auth_time)?;
+        let end_time = kerberos_time(self.auth_time)?;
+        let renew_till = self.renew_until.map(kerberos_time).transpose()?;
 
         let mut flags = FlagSet::<TicketFlags>::new(0b0).expect("Failed to build FlagSet");
         if renew_till.is_some() {
@@ -319,14 +524,10 @@ impl KerberosReplyAuthenticationBuilder {
             client_addresses: None,
         };
 
-        let data = enc_kdc_rep_part
-            .to_der()
-            .map_err(|_| KrbError::DerEncodeEncKdcRepPart)?;
-
         let (etype_info2, enc_part) = match user_key {
-            DerivedKey::Aes256CtsHmacSha196 { i, s, k } => {
-                let data = encrypt_aes256_cts_hmac_sha1_96(&k, &data, 3)?;
-                let enc_part = EncryptedData::Aes256CtsHmacSha196 { kvno: None, data };
+            DerivedKey::Aes256CtsHmacSha196 { i, s, .. } => {
+                // RFC 4120 5.4.2 The encrypted part of an AS-REP is an EncASRepPart.
+                let enc_part = user_key.encrypt_enc_kdc_rep_part(enc_kdc_rep_part)?;
 
                 let ei = EtypeInfo2 {
                     etype: EncryptionType::AES256_CTS_HMAC_SHA1_96,
@@ -336,6 +537,9 @@ impl KerberosReplyAuthenticationBuilder {
 
                 (ei, enc_part)
             }
+            // The KDC only issues aes256 keys.
+            #[cfg(feature = "des3")]
+            DerivedKey::Des3CbcSha1 { .. } => return Err(KrbError::UnsupportedEncryption),
         };
 
         let transited = TransitedEncoding {
@@ -373,6 +577,7 @@ impl KerberosReplyAuthenticationBuilder {
             tkt_vno: 5,
             service: self.server,
             enc_part: ticket_enc_part,
+            der: None,
         };
 
         let name = self.client;
@@ -401,12 +606,18 @@ impl TryFrom<KrbKdcRep> for KerberosReply {
     type Error = KrbError;
 
     fn try_from(rep: KrbKdcRep) -> Result<Self, KrbError> {
-        match rep {
-            KrbKdcRep::AsRep(kdc_rep) | KrbKdcRep::TgsRep(kdc_rep) => {
-                KerberosReply::try_from(kdc_rep)
-            }
-            KrbKdcRep::ErrRep(err_rep) => KerberosReply::try_from(err_rep),
+        // As for requests, the msg_type must agree with the application tag.
+        let (kdc_rep, expected_msg_type) = match rep {
+            KrbKdcRep::AsRep(kdc_rep) => (kdc_rep, KrbMessageType::KrbAsRep),
+            KrbKdcRep::TgsRep(kdc_rep) => (kdc_rep, KrbMessageType::KrbTgsRep),
+            KrbKdcRep::ErrRep(err_rep) => return KerberosReply::try_from(err_rep),
+        };
+
+        if kdc_rep.msg_type != u8::from(expected_msg_type) {
+            return Err(KrbError::InvalidMessageType);
         }
+
+        KerberosReply::try_from(kdc_rep)
     }
 }
 
@@ -458,11 +669,28 @@ impl TryFrom<KdcKrbError> for KerberosReply {
             code => {
                 let error_text = rep.error_text.as_ref().map(|s| s.into());
 
+                // The KDC may tell us which etypes it would have accepted. This is only
+                // advisory, so if we can't understand it we still return the error.
+                let etype_info2 = match (code, rep.error_data) {
+                    (KrbErrorCode::KdcErrEtypeNosupp, Some(edata)) => {
+                        MethodData::from_der(edata.as_bytes())
+                            .map_err(|_| KrbError::DerDecodePaData)
+                            .and_then(PreauthData::try_from)
+                            .map(|pa_data| pa_data.etype_info2)
+                            .unwrap_or_else(|err| {
+                                trace!(?err, "Unable to decode etype-info2 from e-data");
+                                Vec::with_capacity(0)
+                            })
+                    }
+                    _ => Vec::with_capacity(0),
+                };
+
                 Ok(KerberosReply::ERR(ErrorReply {
                     code,
                     service,
                     error_text,
                     stime,
+                    etype_info2,
                 }))
             }
         }
@@ -487,10 +715,7 @@ impl TryInto<KrbKdcRep> for KerberosReply {
                             .iter()
                             .map(|einfo| {
                                 let etype = einfo.etype as i32;
-                                let salt = einfo
-                                    .salt
-                                    .as_ref()
-                                    .map(|data| KerberosString(Ia5String::new(data).unwrap()));
+                                let salt = einfo.salt.clone().map(RawKerberosString);
                                 let s2kparams = einfo
                                     .s2kparams
                                     .as_ref()
@@ -506,8 +731,8 @@ impl TryInto<KrbKdcRep> for KerberosReply {
                         let etype_padata_value = etype_padata_vec
                             .to_der()
                             .and_then(OctetString::new)
-                            .map_err(|e| {
-                                println!("{:#?}", e);
+                            .map_err(|err| {
+                                error!(?err, "Unable to encode etype-info2");
                                 KrbError::DerEncodeOctetString
                             })?;
 
@@ -515,7 +740,7 @@ impl TryInto<KrbKdcRep> for KerberosReply {
                             PaData {
                                 padata_type: PaDataType::PaEncTimestamp as u32,
                                 padata_value: OctetString::new(&[]).map_err(|err| {
-                                    println!("{:#?}", err);
+                                    error!(?err, "Unable to encode PA-ENC-TIMESTAMP");
                                     KrbError::DerEncodeOctetString
                                 })?,
                             },
@@ -529,20 +754,38 @@ impl TryInto<KrbKdcRep> for KerberosReply {
                     None => None,
                 };
 
+                let (cname, crealm) = (&name).try_into()?;
+
                 let as_rep = KdcRep {
                     pvno: 5,
                     msg_type: KrbMessageType::KrbAsRep as u8,
                     padata: pa_data,
-                    crealm: (&name).try_into()?,
-                    cname: (&name).try_into()?,
+                    crealm,
+                    cname,
                     ticket: ticket.try_into()?,
                     enc_part: enc_part.try_into()?,
                 };
 
                 Ok(KrbKdcRep::AsRep(as_rep))
             }
-            KerberosReply::TGS(TicketGrantReply {}) => {
-                todo!();
+            KerberosReply::TGS(TicketGrantReply {
+                name,
+                enc_part,
+                ticket,
+            }) => {
+                let (cname, crealm) = (&name).try_into()?;
+
+                let tgs_rep = KdcRep {
+                    pvno: 5,
+                    msg_type: KrbMessageType::KrbTgsRep as u8,
+                    padata: None,
+                    crealm,
+                    cname,
+                    ticket: ticket.try_into()?,
+                    enc_part: enc_part.try_into()?,
+                };
+
+                Ok(KrbKdcRep::TgsRep(tgs_rep))
             }
             KerberosReply::PA(PreauthReply {
                 pa_data,
@@ -557,10 +800,7 @@ impl TryInto<KrbKdcRep> for KerberosReply {
                     .iter()
                     .map(|einfo| {
                         let etype = einfo.etype as i32;
-                        let salt = einfo
-                            .salt
-                            .as_ref()
-                            .map(|data| KerberosString(Ia5String::new(data).unwrap()));
+                        let salt = einfo.salt.clone().map(RawKerberosString);
                         let s2kparams = einfo
                             .s2kparams
                             .as_ref()
@@ -579,7 +819,7 @@ impl TryInto<KrbKdcRep> for KerberosReply {
                     .and_then(OctetString::new)
                     .map_err(|_| KrbError::DerEncodeOctetString)?;
 
-                let pavec = vec![
+                let mut pavec = vec![
                     PaData {
                         padata_type: PaDataType::PaEncTimestamp as u32,
                         padata_value: OctetString::new(&[])
@@ -591,6 +831,22 @@ impl TryInto<KrbKdcRep> for KerberosReply {
                     },
                 ];
 
+                if let Some(cookie) = pa_data.pa_fx_cookie {
+                    pavec.push(PaData {
+                        padata_type: PaDataType::PaFxCookie as u32,
+                        padata_value: OctetString::new(cookie)
+                            .map_err(|_| KrbError::DerEncodeOctetString)?,
+                    });
+                }
+
+                if let Some(as_freshness) = pa_data.pa_as_freshness {
+                    pavec.push(PaData {
+                        padata_type: PaDataType::PadataAsFreshness as u32,
+                        padata_value: OctetString::new(as_freshness)
+                            .map_err(|_| KrbError::DerEncodeOctetString)?,
+                    });
+                }
+
                 let error_data = pavec
                     .to_der()
                     .and_then(OctetString::new)
@@ -601,13 +857,15 @@ impl TryInto<KrbKdcRep> for KerberosReply {
                     .map(KerberosString)
                     .ok();
 
-                let stime = stime
+                // The whole seconds go in stime, and the fractional part in susec.
+                let since_epoch = stime
                     .duration_since(SystemTime::UNIX_EPOCH)
-                    // We need to stip the fractional part.
-                    .map(|t| Duration::from_secs(t.as_secs()))
                     .unwrap_or_default();
+                let susec = since_epoch.subsec_micros();
 
-                let stime = KerberosTime::from_unix_duration(stime).unwrap();
+                let stime =
+                    KerberosTime::from_unix_duration(Duration::from_secs(since_epoch.as_secs()))
+                        .map_err(|_| KrbError::DerEncodeKerberosTime)?;
 
                 let (service_name, service_realm) = (&service).try_into()?;
 
@@ -617,7 +875,7 @@ impl TryInto<KrbKdcRep> for KerberosReply {
                     ctime: None,
                     cusec: None,
                     stime,
-                    susec: 0,
+                    susec,
                     error_code,
                     crealm: None,
                     cname: None,
@@ -634,20 +892,46 @@ impl TryInto<KrbKdcRep> for KerberosReply {
                 service,
                 error_text,
                 stime,
+                etype_info2,
             }) => {
                 let error_code = code as i32;
 
+                let error_data = if etype_info2.is_empty() {
+                    None
+                } else {
+                    let etype_padata_value = etype_info2
+                        .iter()
+                        .map(KdcETypeInfo2Entry::try_from)
+                        .collect::<Result<Vec<_>, _>>()?
+                        .to_der()
+                        .and_then(OctetString::new)
+                        .map_err(|_| KrbError::DerEncodeOctetString)?;
+
+                    let pavec = vec![PaData {
+                        padata_type: PaDataType::PaEtypeInfo2 as u32,
+                        padata_value: etype_padata_value,
+                    }];
+
+                    pavec
+                        .to_der()
+                        .and_then(OctetString::new)
+                        .map(Some)
+                        .map_err(|_| KrbError::DerEncodeOctetString)?
+                };
+
                 let error_text = error_text
                     .as_ref()
                     .and_then(|et| Ia5String::new(&et).map(KerberosString).ok());
 
-                let stime = stime
+                // The whole seconds go in stime, and the fractional part in susec.
+                let since_epoch = stime
                     .duration_since(SystemTime::UNIX_EPOCH)
-                    // We need to stip the fractional part.
-                    .map(|t| Duration::from_secs(t.as_secs()))
                     .unwrap_or_default();
+                let susec = since_epoch.subsec_micros();
 
-                let stime = KerberosTime::from_unix_duration(stime).unwrap();
+                let stime =
+                    KerberosTime::from_unix_duration(Duration::from_secs(since_epoch.as_secs()))
+                        .map_err(|_| KrbError::DerEncodeKerberosTime)?;
 
                 let (service_name, service_realm) = (&service).try_into()?;
 
@@ -657,14 +941,14 @@ impl TryInto<KrbKdcRep> for KerberosReply {
                     ctime: None,
                     cusec: None,
                     stime,
-                    susec: 0,
+                    susec,
                     error_code,
                     crealm: None,
                     cname: None,
                     service_realm,
                     service_name,
                     error_text,
-                    error_data: None,
+                    error_data,
                 };
 
                 Ok(KrbKdcRep::ErrRep(krb_error))
@@ -687,7 +971,13 @@ impl TryFrom<KdcRep> for KerberosReply {
 
         match msg_type {
             KrbMessageType::KrbAsRep => {
-                let enc_part = EncryptedData::try_from(rep.enc_part)?;
+                // The KDC may have chosen any of the etypes we requested, so say which it
+                // chose if we can't use it.
+                let etype = rep.enc_part.etype;
+                let enc_part = EncryptedData::try_from(rep.enc_part).map_err(|err| match err {
+                    KrbError::UnsupportedEncryption => KrbError::UnsupportedReplyEncryption(etype),
+                    err => err,
+                })?;
                 trace!(?enc_part);
 
                 let pa_data = rep
@@ -707,9 +997,696 @@ impl TryFrom<KdcRep> for KerberosReply {
                 }))
             }
             KrbMessageType::KrbTgsRep => {
-                todo!();
+                let enc_part = EncryptedData::try_from(rep.enc_part)?;
+                trace!(?enc_part);
+
+                let name = (rep.cname, rep.crealm).try_into()?;
+                let ticket = Ticket::try_from(rep.ticket)?;
+
+                Ok(KerberosReply::TGS(TicketGrantReply {
+                    name,
+                    enc_part,
+                    ticket,
+                }))
             }
             _ => Err(KrbError::InvalidMessageDirection),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::asn1::constants::encryption_types::EncryptionType;
+    use crate::asn1::constants::{KrbErrorCode, KrbMessageType, PaDataType};
+    use crate::asn1::etype_info2::ETypeInfo2Entry;
+    use crate::asn1::kerberos_string::{KerberosString, RawKerberosString};
+    use crate::asn1::kerberos_time::KerberosTime;
+    use crate::asn1::krb_error::KrbError as KdcKrbError;
+    use crate::asn1::krb_kdc_rep::KrbKdcRep;
+    use crate::asn1::pa_data::PaData;
+    use crate::asn1::principal_name::PrincipalName;
+    use crate::asn1::tagged_enc_kdc_rep_part::TaggedEncKdcRepPart;
+    use crate::asn1::{Ia5String, OctetString};
+    use crate::clock::{Clock, ClockOffset, OffsetClock, SystemClock};
+    use crate::crypto::supported_etypes;
+    use crate::error::KrbError;
+    use crate::proto::{
+        decode_token, encode_token, DerivedKey, EncryptedData, KerberosReply, Name, PreauthData,
+        PreauthReply, TicketGrantReply,
+    };
+    use crate::test_helpers::{primary_key, user_key, FixedClock};
+    use der::{Decode, Encode};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_as_rep_decrypt_client_name_mismatch() {
+        let now = SystemClock.now();
+        let user_key = user_key();
+        let primary_key = primary_key();
+
+        let reply = KerberosReply::authentication_builder(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now,
+            1,
+        )
+        .build(&user_key, &primary_key)
+        .expect("Failed to build reply");
+
+        let KerberosReply::AS(mut as_rep) = reply else {
+            unreachable!();
+        };
+
+        let client_name = Name::principal("testuser", "EXAMPLE.COM");
+        assert!(as_rep.decrypt(&client_name, &user_key).is_ok());
+
+        // The reply may be decrypted with the key, but was not issued for the client.
+        assert!(matches!(
+            as_rep.decrypt(&Name::principal("mallory", "EXAMPLE.COM"), &user_key),
+            Err(KrbError::ClientNameMismatch)
+        ));
+        assert!(matches!(
+            as_rep.decrypt(&Name::principal("testuser", "OTHER.COM"), &user_key),
+            Err(KrbError::ClientNameMismatch)
+        ));
+
+        // A reply for a different client, even with no etype-info2 to supply the salt.
+        as_rep.name = Name::principal("mallory", "EXAMPLE.COM");
+        as_rep.pa_data = None;
+        assert!(matches!(
+            as_rep.decrypt(&client_name, &user_key),
+            Err(KrbError::ClientNameMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_as_rep_decrypt_non_default_salt() {
+        let now = SystemClock.now();
+        // An account whose key was derived with a salt other than its default one.
+        let user_key = DerivedKey::new_aes256_cts_hmac_sha1_96("password", "EXAMPLE.COMrenamed")
+            .expect("Failed to derive user key");
+        let primary_key = primary_key();
+
+        let client_name = Name::principal("testuser", "EXAMPLE.COM");
+        let reply = KerberosReply::authentication_builder(
+            client_name.clone(),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now,
+            1,
+        )
+        .build(&user_key, &primary_key)
+        .expect("Failed to build reply");
+
+        let KerberosReply::AS(mut as_rep) = reply else {
+            unreachable!();
+        };
+
+        // The KDC didn't say which salt it used, but the reply is still for the client.
+        as_rep.pa_data = None;
+        assert!(as_rep.decrypt(&client_name, &user_key).is_ok());
+    }
+
+    #[test]
+    fn test_as_rep_verify_password() {
+        let now = SystemClock.now();
+        let user_key = user_key();
+        let primary_key = primary_key();
+
+        let reply = KerberosReply::authentication_builder(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now,
+            1,
+        )
+        .build(&user_key, &primary_key)
+        .expect("Failed to build reply");
+
+        let KerberosReply::AS(as_rep) = reply else {
+            unreachable!();
+        };
+
+        let client_name = Name::principal("testuser", "EXAMPLE.COM");
+        assert!(as_rep.verify_password(&client_name, &user_key).is_ok());
+
+        let wrong_key =
+            DerivedKey::new_aes256_cts_hmac_sha1_96("wrong password", "EXAMPLE.COMtestuser")
+                .expect("Failed to derive user key");
+        assert!(matches!(
+            as_rep.verify_password(&client_name, &wrong_key),
+            Err(KrbError::MessageAuthenticationFailed)
+        ));
+    }
+
+    #[test]
+    fn test_as_rep_enc_part_is_enc_as_rep_part() {
+        let now = SystemClock.now();
+        let user_key = user_key();
+        let primary_key = primary_key();
+
+        let reply = KerberosReply::authentication_builder(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now,
+            1,
+        )
+        .build(&user_key, &primary_key)
+        .expect("Failed to build reply");
+
+        let KerberosReply::AS(as_rep) = reply else {
+            unreachable!();
+        };
+        // RFC 4120 5.4.2 The KDC sends an EncASRepPart, [APPLICATION 25], in an AS-REP.
+        let data = match (&as_rep.enc_part, &user_key) {
+            (
+                EncryptedData::Aes256CtsHmacSha196 { data, .. },
+                DerivedKey::Aes256CtsHmacSha196 { k, .. },
+            ) => crate::crypto::decrypt_aes256_cts_hmac_sha1_96(k, data, 3)
+                .expect("Failed to decrypt enc-part"),
+            #[cfg(feature = "des3")]
+            _ => unreachable!(),
+        };
+        assert_eq!(data[0], 0x79);
+        assert!(matches!(
+            TaggedEncKdcRepPart::from_der(&data),
+            Ok(TaggedEncKdcRepPart::EncAsRepPart(_))
+        ));
+    }
+
+    #[test]
+    fn test_as_rep_multi_component_cname() {
+        let now = SystemClock.now();
+        // An NT-PRINCIPAL with an instance, as MIT krb5 uses for admin principals.
+        let client = Name::Other {
+            name_type: 1,
+            components: vec!["testuser".to_string(), "admin".to_string()],
+            realm: "EXAMPLE.COM".to_string(),
+        };
+        let user_key =
+            DerivedKey::new_aes256_cts_hmac_sha1_96("password", "EXAMPLE.COMtestuseradmin")
+                .expect("Failed to derive user key");
+        let primary_key = primary_key();
+
+        let reply = KerberosReply::authentication_builder(
+            client.clone(),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now,
+            1,
+        )
+        .build(&user_key, &primary_key)
+        .expect("Failed to build reply");
+
+        let kdc_rep: KrbKdcRep = reply.try_into().expect("Failed to encode reply");
+        let kdc_rep = kdc_rep.to_der().expect("Failed to encode reply");
+        let kdc_rep = KrbKdcRep::from_der(&kdc_rep).expect("Failed to decode reply");
+
+        let KrbKdcRep::AsRep(as_rep) = &kdc_rep else {
+            unreachable!();
+        };
+        assert_eq!(as_rep.cname.name_type, 1);
+        assert_eq!(as_rep.cname.name_string.len(), 2);
+
+        let KerberosReply::AS(as_rep) =
+            KerberosReply::try_from(kdc_rep).expect("Failed to decode reply")
+        else {
+            unreachable!();
+        };
+        assert_eq!(as_rep.name, client);
+        assert!(as_rep.verify_password(&client, &user_key).is_ok());
+
+        // A name type we don't otherwise represent, such as NT-ENTERPRISE.
+        let kerberos_string = |s| KerberosString(Ia5String::new(s).expect("Invalid IA5String"));
+        let princ = PrincipalName {
+            name_type: 10,
+            name_string: vec![kerberos_string("testuser@example.com")],
+        };
+        assert_eq!(
+            Name::try_from((princ, kerberos_string("EXAMPLE.COM"))).expect("Invalid name"),
+            Name::Other {
+                name_type: 10,
+                components: vec!["testuser@example.com".to_string()],
+                realm: "EXAMPLE.COM".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_reply_invalid_pvno() {
+        let now = SystemClock.now();
+        let user_key = user_key();
+        let primary_key = primary_key();
+
+        let kdc_rep = |msg_type: KrbMessageType| {
+            let reply = KerberosReply::authentication_builder(
+                Name::principal("testuser", "EXAMPLE.COM"),
+                Name::service_krbtgt("EXAMPLE.COM"),
+                now,
+                1,
+            )
+            .build(&user_key, &primary_key)
+            .expect("Failed to build reply");
+
+            let KrbKdcRep::AsRep(mut kdc_rep) = reply.try_into().expect("Failed to encode reply")
+            else {
+                unreachable!();
+            };
+            kdc_rep.pvno = 4;
+            kdc_rep.msg_type = msg_type as u8;
+            kdc_rep
+        };
+
+        let KrbKdcRep::ErrRep(mut err_rep) =
+            KerberosReply::error_no_etypes(Name::service_krbtgt("EXAMPLE.COM"), now)
+                .try_into()
+                .expect("Failed to encode error reply")
+        else {
+            unreachable!();
+        };
+        err_rep.pvno = 4;
+
+        for rep in [
+            KrbKdcRep::AsRep(kdc_rep(KrbMessageType::KrbAsRep)),
+            KrbKdcRep::TgsRep(kdc_rep(KrbMessageType::KrbTgsRep)),
+            KrbKdcRep::ErrRep(err_rep),
+        ] {
+            assert!(matches!(
+                KerberosReply::try_from(rep),
+                Err(KrbError::InvalidPvno)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_reply_unexpected_type() {
+        let now = SystemClock.now();
+        let user_key = user_key();
+        let primary_key = primary_key();
+
+        let reply = KerberosReply::authentication_builder(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now,
+            1,
+        )
+        .build(&user_key, &primary_key)
+        .expect("Failed to build reply");
+
+        let KrbKdcRep::AsRep(mut kdc_rep) = reply.try_into().expect("Failed to encode reply")
+        else {
+            unreachable!();
+        };
+        kdc_rep.msg_type = KrbMessageType::KrbTgsRep as u8;
+
+        // A TGS-REP arriving in reply to an AS-REQ.
+        let tgs_rep = KrbKdcRep::TgsRep(kdc_rep)
+            .to_der()
+            .expect("Failed to encode TGS-REP");
+        let decode = || {
+            let kdc_rep = KrbKdcRep::from_der(&tgs_rep).expect("Failed to decode TGS-REP");
+            KerberosReply::try_from(kdc_rep).expect("Failed to decode TGS-REP")
+        };
+
+        assert!(matches!(
+            decode().expect_as_rep(),
+            Err(KrbError::UnexpectedReplyType)
+        ));
+        assert!(matches!(
+            decode().expect_tgs_rep(),
+            Ok(KerberosReply::TGS(_))
+        ));
+
+        // Preauthentication is only part of the AS exchange.
+        let preauth =
+            || KerberosReply::preauth_builder(Name::service_krbtgt("EXAMPLE.COM"), now).build();
+        assert!(matches!(
+            preauth().expect_as_rep(),
+            Ok(KerberosReply::PA(_))
+        ));
+        assert!(matches!(
+            preauth().expect_tgs_rep(),
+            Err(KrbError::UnexpectedReplyType)
+        ));
+    }
+
+    #[test]
+    fn test_as_rep_unsupported_reply_etype() {
+        let user_key = user_key();
+        let primary_key = primary_key();
+
+        let reply = KerberosReply::authentication_builder(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            SystemClock.now(),
+            1,
+        )
+        .build(&user_key, &primary_key)
+        .expect("Failed to build reply");
+
+        let KrbKdcRep::AsRep(mut kdc_rep) = reply.try_into().expect("Failed to encode reply")
+        else {
+            unreachable!();
+        };
+
+        // We asked for aes256 and aes128, and the KDC chose aes128.
+        kdc_rep.enc_part.etype = EncryptionType::AES128_CTS_HMAC_SHA1_96 as i32;
+
+        assert!(matches!(
+            KerberosReply::try_from(KrbKdcRep::AsRep(kdc_rep)),
+            Err(KrbError::UnsupportedReplyEncryption(17))
+        ));
+    }
+
+    #[test]
+    fn test_reply_der_round_trip() {
+        let now = SystemClock.now();
+        let user_key = user_key();
+        let primary_key = primary_key();
+
+        let as_rep = KerberosReply::authentication_builder(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now,
+            0x1234_5678,
+        )
+        .build(&user_key, &primary_key)
+        .expect("Failed to build reply");
+
+        let der = as_rep.to_der().expect("Failed to encode reply");
+        let KerberosReply::AS(decoded) = KerberosReply::from_der(&der).expect("Failed to decode")
+        else {
+            unreachable!();
+        };
+        assert_eq!(decoded.name, Name::principal("testuser", "EXAMPLE.COM"));
+        let reply_part = decoded
+            .decrypt(&Name::principal("testuser", "EXAMPLE.COM"), &user_key)
+            .expect("Failed to decrypt reply");
+        assert_eq!(reply_part.server(), &Name::service_krbtgt("EXAMPLE.COM"));
+        assert_eq!(
+            KerberosReply::AS(decoded)
+                .to_der()
+                .expect("Failed to encode reply"),
+            der
+        );
+
+        let err_rep =
+            KerberosReply::error_client_username(Name::service_krbtgt("EXAMPLE.COM"), now);
+        let der = err_rep.to_der().expect("Failed to encode reply");
+        let KerberosReply::ERR(decoded) = KerberosReply::from_der(&der).expect("Failed to decode")
+        else {
+            unreachable!();
+        };
+        assert_eq!(decoded.code(), KrbErrorCode::KdcErrCPrincipalUnknown);
+        assert_eq!(
+            decoded.error_text(),
+            Some("Preauthentication Failed - Check your username is correct.")
+        );
+
+        assert!(matches!(
+            KerberosReply::from_der(&der[..der.len() - 1]),
+            Err(KrbError::DerDecodeKdcRep)
+        ));
+    }
+
+    #[test]
+    fn test_krb_error_skew_stime() {
+        let stime = SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+        let service = Name::service_krbtgt("EXAMPLE.COM");
+
+        let der = KerberosReply::error_clock_skew(service.clone(), stime)
+            .to_der()
+            .expect("Failed to encode reply");
+        let KerberosReply::ERR(err_rep) = KerberosReply::from_der(&der).expect("Failed to decode")
+        else {
+            unreachable!();
+        };
+        assert_eq!(err_rep.code(), KrbErrorCode::KrbApErrSkew);
+        assert_eq!(err_rep.stime(), stime);
+
+        // Our clock is five minutes slow, so retrying with the offset matches the KDC.
+        let now = stime - Duration::from_secs(300);
+        let offset = err_rep
+            .clock_offset(now)
+            .expect("No offset for a skew error");
+        assert_eq!(offset, ClockOffset::Ahead(Duration::from_secs(300)));
+
+        assert_eq!(OffsetClock::new(FixedClock(now), offset).now(), stime);
+
+        let offset = err_rep.clock_offset(stime + Duration::from_secs(60));
+        assert_eq!(offset, Some(ClockOffset::Behind(Duration::from_secs(60))));
+
+        // The time of any other error says nothing about our clock.
+        let KerberosReply::ERR(err_rep) = KerberosReply::error_preauth_failed(service, stime)
+        else {
+            unreachable!();
+        };
+        assert!(err_rep.clock_offset(now).is_none());
+    }
+
+    #[test]
+    fn test_error_etype_nosupp_etype_info2() {
+        let etype_info2 = vec![
+            ETypeInfo2Entry {
+                // rc4-hmac, which we don't support and so don't surface.
+                etype: 23,
+                salt: None,
+                s2kparams: None,
+            },
+            ETypeInfo2Entry {
+                etype: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                salt: Some(RawKerberosString(b"EXAMPLE.COMtestuser".to_vec())),
+                s2kparams: Some(
+                    OctetString::new(0x1000u32.to_be_bytes()).expect("Invalid OCTET STRING"),
+                ),
+            },
+        ];
+        let pavec = vec![PaData {
+            padata_type: PaDataType::PaEtypeInfo2 as u32,
+            padata_value: OctetString::new(etype_info2.to_der().expect("Failed to encode"))
+                .expect("Invalid OCTET STRING"),
+        }];
+        let error_data = OctetString::new(pavec.to_der().expect("Failed to encode"))
+            .expect("Invalid OCTET STRING");
+        let expected = PreauthData::try_from(pavec)
+            .expect("Failed to parse padata")
+            .etype_info2;
+
+        let (service_name, service_realm) = (&Name::service_krbtgt("EXAMPLE.COM"))
+            .try_into()
+            .expect("Invalid name");
+
+        let krb_error = KdcKrbError {
+            pvno: 5,
+            msg_type: KrbMessageType::KrbError as u8,
+            ctime: None,
+            cusec: None,
+            stime: KerberosTime::from_unix_duration(Duration::from_secs(1_000))
+                .expect("Invalid KerberosTime"),
+            susec: 0,
+            error_code: KrbErrorCode::KdcErrEtypeNosupp as i32,
+            crealm: None,
+            cname: None,
+            service_realm,
+            service_name,
+            error_text: None,
+            error_data: Some(error_data),
+        };
+
+        let reply = KerberosReply::try_from(KrbKdcRep::ErrRep(krb_error))
+            .expect("Failed to decode error reply");
+        let KerberosReply::ERR(err_rep) = reply else {
+            unreachable!();
+        };
+
+        assert_eq!(err_rep.code(), KrbErrorCode::KdcErrEtypeNosupp);
+        assert_eq!(err_rep.etype_info2().len(), 1);
+        assert_eq!(err_rep.etype_info2(), expected.as_slice());
+
+        // The etype info survives being sent back out.
+        let krb_kdc_rep: KrbKdcRep = KerberosReply::ERR(err_rep)
+            .try_into()
+            .expect("Failed to encode error reply");
+        let KerberosReply::ERR(err_rep) =
+            KerberosReply::try_from(krb_kdc_rep).expect("Failed to decode error reply")
+        else {
+            unreachable!();
+        };
+        assert_eq!(err_rep.etype_info2(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_preauth_required_reply() {
+        let now = SystemClock.now();
+
+        let reply = KerberosReply::preauth_required(
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now,
+            "EXAMPLE.COMsalt",
+            &[
+                EncryptionType::AES256_CTS_HMAC_SHA1_96,
+                EncryptionType::RC4_HMAC,
+            ],
+            b"cookie".to_vec(),
+        );
+
+        let bytes = encode_token(reply).expect("Failed to encode reply");
+
+        let KerberosReply::PA(PreauthReply { pa_data, .. }) =
+            decode_token(&bytes).expect("Failed to decode reply")
+        else {
+            unreachable!();
+        };
+
+        assert_eq!(
+            pa_data.padata_types(),
+            vec![
+                PaDataType::PaEncTimestamp,
+                PaDataType::PaEtypeInfo2,
+                PaDataType::PaFxCookie
+            ]
+        );
+        assert!(pa_data.enc_timestamp);
+        assert_eq!(pa_data.pa_fx_cookie.as_deref(), Some(b"cookie".as_slice()));
+
+        // Only the supported etype is offered.
+        assert_eq!(pa_data.etype_info2.len(), 1);
+        let etype_info2 = pa_data
+            .preferred_etype_info2_of(supported_etypes())
+            .expect("No etype-info2 in reply");
+
+        // The client uses the salt of the etype-info2 rather than the default salt.
+        let user_key =
+            DerivedKey::from_etype_info2(&etype_info2, "EXAMPLE.COM", "testuser", "password")
+                .expect("Failed to derive user key");
+        match &user_key {
+            DerivedKey::Aes256CtsHmacSha196 { i, s, .. } => {
+                assert_eq!(s, b"EXAMPLE.COMsalt");
+                assert_eq!(*i, 4096);
+            }
+            #[cfg(feature = "des3")]
+            _ => unreachable!(),
+        }
+        assert!(
+            user_key
+                == DerivedKey::new_aes256_cts_hmac_sha1_96("password", "EXAMPLE.COMsalt")
+                    .expect("Failed to derive key")
+        );
+    }
+
+    #[test]
+    fn test_kerberos_reply_realm() {
+        let now = SystemClock.now();
+        let user_key = user_key();
+        let primary_key = primary_key();
+
+        let reply = KerberosReply::authentication_builder(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now,
+            1,
+        )
+        .build(&user_key, &primary_key)
+        .expect("Failed to build reply");
+        assert_eq!(reply.realm(), Some("EXAMPLE.COM"));
+
+        let KerberosReply::AS(as_rep) = reply else {
+            unreachable!();
+        };
+
+        // A cross realm TGS-REP names the client in its home realm.
+        let reply = KerberosReply::TGS(TicketGrantReply {
+            name: Name::principal("testuser", "EXAMPLE.COM"),
+            enc_part: as_rep.enc_part,
+            ticket: as_rep.ticket,
+        });
+        assert_eq!(reply.realm(), Some("EXAMPLE.COM"));
+
+        let reply =
+            KerberosReply::preauth_builder(Name::service_krbtgt("PREAUTH.EXAMPLE.COM"), now)
+                .build();
+        assert_eq!(reply.realm(), Some("PREAUTH.EXAMPLE.COM"));
+
+        let reply =
+            KerberosReply::error_preauth_failed(Name::service_krbtgt("ERROR.EXAMPLE.COM"), now);
+        assert_eq!(reply.realm(), Some("ERROR.EXAMPLE.COM"));
+    }
+
+    #[test]
+    fn test_preauth_reply_stime_microseconds() {
+        let stime = SystemTime::UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789);
+
+        let krb_kdc_rep: KrbKdcRep =
+            KerberosReply::preauth_builder(Name::service_krbtgt("EXAMPLE.COM"), stime)
+                .build()
+                .try_into()
+                .expect("Failed to encode preauth reply");
+        let KrbKdcRep::ErrRep(krb_error) = &krb_kdc_rep else {
+            unreachable!();
+        };
+        assert_eq!(krb_error.susec, 123_456);
+
+        let KerberosReply::PA(pa_rep) =
+            KerberosReply::try_from(krb_kdc_rep).expect("Failed to decode preauth reply")
+        else {
+            unreachable!();
+        };
+        assert_eq!(
+            pa_rep.stime,
+            SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456)
+        );
+    }
+
+    #[test]
+    fn test_preauth_reply_padata_types() {
+        let empty = || OctetString::new(b"").expect("Invalid OCTET STRING");
+        // A KDC that only offers PKINIT, which we don't support, and an unassigned type.
+        let pavec = vec![
+            PaData {
+                padata_type: PaDataType::PaPkAsReq as u32,
+                padata_value: empty(),
+            },
+            PaData {
+                padata_type: 9999,
+                padata_value: empty(),
+            },
+            PaData {
+                padata_type: PaDataType::PaFxCookie as u32,
+                padata_value: OctetString::new(b"cookie").expect("Invalid OCTET STRING"),
+            },
+        ];
+        let error_data = OctetString::new(pavec.to_der().expect("Failed to encode"))
+            .expect("Invalid OCTET STRING");
+
+        let (service_name, service_realm) = (&Name::service_krbtgt("EXAMPLE.COM"))
+            .try_into()
+            .expect("Invalid name");
+
+        let krb_error = KdcKrbError {
+            pvno: 5,
+            msg_type: KrbMessageType::KrbError as u8,
+            ctime: None,
+            cusec: None,
+            stime: KerberosTime::from_unix_duration(Duration::from_secs(1_000))
+                .expect("Invalid KerberosTime"),
+            susec: 0,
+            error_code: KrbErrorCode::KdcErrPreauthRequired as i32,
+            crealm: None,
+            cname: None,
+            service_realm,
+            service_name,
+            error_text: None,
+            error_data: Some(error_data),
+        };
+
+        let reply = KerberosReply::try_from(KrbKdcRep::ErrRep(krb_error))
+            .expect("Failed to decode error reply");
+        let KerberosReply::PA(pa_rep) = reply else {
+            unreachable!();
+        };
+
+        assert!(!pa_rep.pa_data.enc_timestamp);
+        assert_eq!(
+            pa_rep.pa_data.padata_types(),
+            vec![PaDataType::PaPkAsReq, PaDataType::PaFxCookie]
+        );
+        assert_eq!(pa_rep.pa_data.unknown_padata_types(), vec![9999]);
+    }
+}
@@ -1,56 +1,111 @@
 use crate::asn1::{
+    ap_req::{ApReq, TaggedApReq},
+    authenticator::{Authenticator, TaggedAuthenticator},
+    checksum::Checksum,
     constants::{
         encryption_types::EncryptionType, message_types::KrbMessageType, pa_data_types::PaDataType,
     },
     encrypted_data::EncryptedData as KdcEncryptedData,
     kdc_req::KdcReq,
     kdc_req_body::KdcReqBody,
+    kerberos_string::KerberosString,
     kerberos_time::KerberosTime,
     krb_kdc_req::KrbKdcReq,
     pa_data::PaData,
     pa_enc_ts_enc::PaEncTsEnc,
-    BitString, OctetString,
+    pa_for_user::PaForUser,
+    pa_pac_request::PaPacRequest,
+    BitString, Ia5String, OctetString,
+};
+use crate::clock::Clock;
+use crate::constants::DEFAULT_MIN_PBKDF2_SHA1_ITER;
+use crate::crypto::{
+    derive_key_aes256_cts_hmac_sha1_96, encrypt_aes256_cts_hmac_sha1_96, supported_etypes,
 };
-use crate::crypto::{derive_key_aes256_cts_hmac_sha1_96, encrypt_aes256_cts_hmac_sha1_96};
 use crate::error::KrbError;
-use der::Encode;
+use der::{Decode, Encode};
 use rand::{thread_rng, Rng};
 
 use std::time::{Duration, SystemTime};
-use tracing::trace;
+use tracing::{instrument, trace};
 
-use super::{DerivedKey, EncryptedData, Name, Preauth, PreauthData};
+use super::authorization_data::{decrypt_authorization_data, encrypt_authorization_data};
+use super::spake::encode_spake_support;
+use super::{
+    default_salt, make_checksum, AuthorizationData, DerivedKey, EncryptedData, EtypePolicy,
+    ForUser, KdcOptions, Name, Preauth, PreauthData, PreauthReply, SessionKey, Ticket,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum KerberosRequest {
     AS(AuthenticationRequest),
     TGS(TicketGrantRequest),
 }
 
-#[derive(Debug)]
-pub struct TicketGrantRequest {}
+#[derive(Debug, Clone)]
+pub struct TicketGrantRequest {
+    pub nonce: u32,
+    pub service_name: Name,
+    pub from: Option<SystemTime>,
+    pub until: SystemTime,
+    pub renew: Option<SystemTime>,
+    pub kdc_options: KdcOptions,
+    pub etypes: Vec<EncryptionType>,
+    pub additional_tickets: Vec<Ticket>,
+    /// The user that an S4U2Self request is on behalf of.
+    pub for_user: Option<ForUser>,
+    /// The authorization data to add to the issued ticket, encrypted in the session key.
+    pub enc_authorization_data: Option<EncryptedData>,
+    pub ticket: Ticket,
+    pub authenticator: EncryptedData,
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AuthenticationRequest {
     pub nonce: u32,
     pub client_name: Name,
     pub service_name: Name,
+    /// The realm of the KDC that the request is for.
+    pub realm: String,
     pub from: Option<SystemTime>,
     pub until: SystemTime,
     pub renew: Option<SystemTime>,
+    pub kdc_options: KdcOptions,
     pub preauth: Preauth,
+    /// The etypes that we support, of those the client asked for.
     pub etypes: Vec<EncryptionType>,
+    // Every etype the client asked for, in its order of preference.
+    requested_etypes: Vec<i32>,
+    // Whether the cname is left out of the encoded request.
+    omit_client_name: bool,
 }
 
 #[derive(Debug)]
 pub struct KerberosAuthenticationBuilder {
     client_name: Name,
+    service_name: Name,
+    realm: Option<String>,
+    nonce: Option<u32>,
+    from: Option<SystemTime>,
+    until: SystemTime,
+    renew: Option<SystemTime>,
+    kdc_options: KdcOptions,
+    preauth: Preauth,
+    etypes: Vec<EncryptionType>,
+    omit_client_name: bool,
+}
+
+#[derive(Debug)]
+pub struct KerberosTicketGrantBuilder {
     service_name: Name,
     from: Option<SystemTime>,
     until: SystemTime,
     renew: Option<SystemTime>,
-    preauth: Option<Preauth>,
+    kdc_options: KdcOptions,
     etypes: Vec<EncryptionType>,
+    additional_tickets: Vec<Ticket>,
+    for_user: Option<Name>,
+    authorization_data: Vec<AuthorizationData>,
 }
 
 impl KerberosRequest {
@@ -59,20 +114,224 @@ impl KerberosRequest {
         service_name: Name,
         until: SystemTime,
     ) -> KerberosAuthenticationBuilder {
-        let etypes = vec![EncryptionType::AES256_CTS_HMAC_SHA1_96];
+        let etypes = supported_etypes().to_vec();
 
         KerberosAuthenticationBuilder {
             client_name,
+            service_name,
+            realm: None,
+            nonce: None,
+            from: None,
+            until,
+            renew: None,
+            kdc_options: KdcOptions::default().renewable(true),
+            preauth: Preauth::default(),
+            etypes,
+            omit_client_name: false,
+        }
+    }
+
+    pub fn build_tgs(service_name: Name, until: SystemTime) -> KerberosTicketGrantBuilder {
+        let etypes = supported_etypes().to_vec();
+
+        KerberosTicketGrantBuilder {
             service_name,
             from: None,
             until,
             renew: None,
-            preauth: None,
+            kdc_options: KdcOptions::default().renewable(true),
             etypes,
+            additional_tickets: Vec::with_capacity(0),
+            for_user: None,
+            authorization_data: Vec::with_capacity(0),
+        }
+    }
+
+    /// Request a ticket to `service_name` on behalf of `user`, without the user's
+    /// credentials (S4U2Self). The request must be built with the service's own
+    /// ticket granting ticket, and the KDC must permit the service protocol
+    /// transition.
+    pub fn build_s4u2self(
+        service_name: Name,
+        user: Name,
+        until: SystemTime,
+    ) -> KerberosTicketGrantBuilder {
+        KerberosTicketGrantBuilder {
+            for_user: Some(user),
+            ..Self::build_tgs(service_name, until)
+        }
+    }
+
+    /// Request a ticket to `service_name` on behalf of the client of `user_ticket`
+    /// (S4U2Proxy). `user_ticket` is a forwardable ticket that the user, or an
+    /// S4U2Self request, issued to the delegating service. The request must be built
+    /// with the delegating service's own ticket granting ticket, and the KDC must
+    /// permit the service to delegate to `service_name`.
+    pub fn build_s4u2proxy(
+        service_name: Name,
+        user_ticket: Ticket,
+        until: SystemTime,
+    ) -> KerberosTicketGrantBuilder {
+        let builder = Self::build_tgs(service_name, until);
+
+        KerberosTicketGrantBuilder {
+            kdc_options: builder.kdc_options.cname_in_addl_tkt(true),
+            additional_tickets: vec![user_ticket],
+            ..builder
+        }
+    }
+
+    /// The nonce of the request, which the KDC must return in the encrypted part of
+    /// its reply.
+    pub fn nonce(&self) -> u32 {
+        match self {
+            KerberosRequest::AS(req) => req.nonce,
+            KerberosRequest::TGS(req) => req.nonce,
+        }
+    }
+}
+
+/// Generate a random, non-zero request nonce.
+fn random_nonce() -> u32 {
+    loop {
+        // BUG IN MIT KRB5 - If the value is greater than i32 max you get:
+        // Jun 28 03:47:41 3e79497ab6b5 krb5kdc[1](Error): ASN.1 value too large - while dispatching (tcp)
+        let nonce: u32 = thread_rng().gen();
+        let nonce = nonce & 0x7fff_ffff;
+
+        // Some KDCs treat a zero nonce specially, so never send one.
+        if nonce != 0 {
+            return nonce;
         }
     }
 }
 
+/// Build and encode the authenticator of an AP-REQ for `client_name`, timestamped with
+/// the current time of `clock`.
+pub(super) fn encode_authenticator(
+    client_name: &Name,
+    cksum: Option<Checksum>,
+    clock: &impl Clock,
+) -> Result<Vec<u8>, KrbError> {
+    // Strip any excess time.
+    let now = clock
+        .now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|_| KrbError::DerEncodeKerberosTime)?;
+    let cusec = now.subsec_micros();
+    let ctime = KerberosTime::from_unix_duration(Duration::from_secs(now.as_secs()))
+        .map_err(|_| KrbError::DerEncodeKerberosTime)?;
+
+    let (cname, crealm) = client_name.try_into()?;
+
+    let authenticator = TaggedAuthenticator::new(Authenticator {
+        authenticator_vno: 5,
+        crealm,
+        cname,
+        cksum,
+        cusec,
+        ctime,
+        subkey: None,
+        seq_number: None,
+        authorization_data: None,
+    });
+
+    trace!(?authenticator);
+
+    authenticator
+        .to_der()
+        .map_err(|_| KrbError::DerEncodeAuthenticator)
+}
+
+/// Reject ticket times that the KDC would refuse, so that the caller gets a clear error
+/// rather than an opaque one from the KDC.
+fn validate_ticket_times(
+    now: SystemTime,
+    from: Option<SystemTime>,
+    until: SystemTime,
+    renew: Option<SystemTime>,
+) -> Result<(), KrbError> {
+    if until <= now {
+        return Err(KrbError::InvalidTicketTimes);
+    }
+
+    if let Some(from) = from {
+        if until <= from {
+            return Err(KrbError::InvalidTicketTimes);
+        }
+    }
+
+    if let Some(renew) = renew {
+        if renew < until {
+            return Err(KrbError::InvalidTicketTimes);
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the KDC-REQ-BODY of a TGS-REQ. This is used both when computing the
+/// authenticator checksum and when encoding the request, so both must agree
+/// byte for byte.
+#[allow(clippy::too_many_arguments)]
+fn tgs_req_body(
+    nonce: u32,
+    service_name: &Name,
+    from: Option<SystemTime>,
+    until: SystemTime,
+    renew: Option<SystemTime>,
+    kdc_options: KdcOptions,
+    etypes: &[EncryptionType],
+    additional_tickets: &[Ticket],
+    enc_authorization_data: Option<&EncryptedData>,
+) -> Result<KdcReqBody, KrbError> {
+    // In a TGS-REQ the realm is the realm of the server, and the client is
+    // identified by the ticket instead.
+    let (sname, realm) = service_name.try_into()?;
+
+    let from = from
+        .map(KerberosTime::from_system_time)
+        .transpose()
+        .map_err(|_| KrbError::DerEncodeKerberosTime)?;
+    let till =
+        KerberosTime::from_system_time(until).map_err(|_| KrbError::DerEncodeKerberosTime)?;
+    let rtime = renew
+        .map(KerberosTime::from_system_time)
+        .transpose()
+        .map_err(|_| KrbError::DerEncodeKerberosTime)?;
+
+    let additional_tickets = if additional_tickets.is_empty() {
+        None
+    } else {
+        additional_tickets
+            .iter()
+            .cloned()
+            .map(|ticket| ticket.try_into())
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some)?
+    };
+
+    let enc_authorization_data = enc_authorization_data
+        .cloned()
+        .map(|enc_authorization_data| enc_authorization_data.try_into())
+        .transpose()?;
+
+    Ok(KdcReqBody {
+        kdc_options: kdc_options.to_bit_string()?,
+        cname: None,
+        realm,
+        sname: Some(sname),
+        from,
+        till,
+        rtime,
+        nonce,
+        etype: etypes.iter().map(|e| *e as i32).collect(),
+        addresses: None,
+        enc_authorization_data,
+        additional_tickets,
+    })
+}
+
 impl TryInto<KrbKdcReq> for KerberosRequest {
     type Error = KrbError;
 
@@ -82,76 +341,102 @@ impl TryInto<KrbKdcReq> for KerberosRequest {
                 nonce,
                 client_name,
                 service_name,
+                realm,
                 from,
                 until,
                 renew,
+                kdc_options,
                 preauth,
                 etypes,
+                requested_etypes: _,
+                omit_client_name,
             }) => {
-                let padata = if preauth.pa_fx_cookie.is_some() || preauth.enc_timestamp.is_some() {
-                    let mut padata_inner = Vec::with_capacity(2);
-
-                    if let Some(fx_cookie) = &preauth.pa_fx_cookie {
-                        let padata_value = OctetString::new(fx_cookie.clone())
-                            .map_err(|_| KrbError::DerEncodeOctetString)?;
-                        padata_inner.push(PaData {
-                            padata_type: PaDataType::PaFxCookie as u32,
-                            padata_value,
-                        })
-                    }
-
-                    if let Some(enc_data) = &preauth.enc_timestamp {
-                        let padata_value = match enc_data {
-                            EncryptedData::Aes256CtsHmacSha196 { kvno, data } => {
-                                let cipher = OctetString::new(data.clone())
-                                    .map_err(|_| KrbError::DerEncodeOctetString)?;
-                                KdcEncryptedData {
-                                    etype: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
-                                    kvno: None,
-                                    cipher,
-                                }
-                            }
-                        };
-
-                        // Need to encode the padata value now.
-                        let padata_value = padata_value
-                            .to_der()
-                            .and_then(OctetString::new)
-                            .map_err(|_| KrbError::DerEncodeOctetString)?;
-
-                        padata_inner.push(PaData {
-                            padata_type: PaDataType::PaEncTimestamp as u32,
-                            padata_value,
-                        })
-                    }
-
-                    /*
+                // RFC 4120 doesn't give the padata an order, but keep it stable: the
+                // cookie and freshness token of the conversation, the preauth itself,
+                // the PAC request and then any the caller added, in the order they were
+                // added.
+                let mut padata_inner = Vec::with_capacity(4 + preauth.additional_padata.len());
+
+                if let Some(fx_cookie) = &preauth.pa_fx_cookie {
+                    let padata_value = OctetString::new(fx_cookie.clone())
+                        .map_err(|_| KrbError::DerEncodeOctetString)?;
+                    padata_inner.push(PaData {
+                        padata_type: PaDataType::PaFxCookie as u32,
+                        padata_value,
+                    })
+                }
+
+                if let Some(as_freshness) = &preauth.pa_as_freshness {
+                    let padata_value = OctetString::new(as_freshness.clone())
+                        .map_err(|_| KrbError::DerEncodeOctetString)?;
                     padata_inner.push(PaData {
                         padata_type: PaDataType::PadataAsFreshness as u32,
-                        padata_value: OctetString::new(&[]).map_err(|_| KrbError::DerEncodeOctetString)?,
-                    });
+                        padata_value,
+                    })
+                }
+
+                if let Some(enc_data) = &preauth.enc_timestamp {
+                    let padata_value: KdcEncryptedData = enc_data.clone().try_into()?;
+
+                    // Need to encode the padata value now.
+                    let padata_value = padata_value
+                        .to_der()
+                        .and_then(OctetString::new)
+                        .map_err(|_| KrbError::DerEncodeOctetString)?;
 
                     padata_inner.push(PaData {
-                        padata_type: PaDataType::EncpadataReqEncPaRep as u32,
-                        padata_value: OctetString::new(&[]).map_err(|_| KrbError::DerEncodeOctetString)?,
-                    });
-                    */
+                        padata_type: PaDataType::PaEncTimestamp as u32,
+                        padata_value,
+                    })
+                }
+
+                if let Some(include_pac) = preauth.pa_pac_request {
+                    let padata_value = PaPacRequest { include_pac }
+                        .to_der()
+                        .and_then(OctetString::new)
+                        .map_err(|_| KrbError::DerEncodeOctetString)?;
 
-                    Some(padata_inner)
-                } else {
-                    None
-                };
+                    padata_inner.push(PaData {
+                        padata_type: PaDataType::PaPacRequest as u32,
+                        padata_value,
+                    })
+                }
 
-                let (cname, realm) = (&client_name).try_into().unwrap();
-                let sname = (&service_name).try_into().unwrap();
+                for (padata_type, padata_value) in &preauth.additional_padata {
+                    let padata_value = OctetString::new(padata_value.clone())
+                        .map_err(|_| KrbError::DerEncodeOctetString)?;
+
+                    padata_inner.push(PaData {
+                        padata_type: *padata_type,
+                        padata_value,
+                    })
+                }
+
+                /*
+                padata_inner.push(PaData {
+                    padata_type: PaDataType::EncpadataReqEncPaRep as u32,
+                    padata_value: OctetString::new(&[]).map_err(|_| KrbError::DerEncodeOctetString)?,
+                });
+                */
+
+                let padata = (!padata_inner.is_empty()).then_some(padata_inner);
+
+                // Only the realm of the request is sent, which RFC 4120 defines as the
+                // realm of both the client and the service in an AS-REQ.
+                let (cname, _client_realm) = (&client_name).try_into()?;
+                let cname = (!omit_client_name).then_some(cname);
+                let (sname, _service_realm) = (&service_name).try_into()?;
+                let realm = Ia5String::new(&realm)
+                    .map(KerberosString)
+                    .map_err(|_| KrbError::DerEncodeKdcReqBody)?;
 
                 Ok(KrbKdcReq::AsReq(KdcReq {
                     pvno: 5,
                     msg_type: KrbMessageType::KrbAsReq as u8,
                     padata,
                     req_body: KdcReqBody {
-                        kdc_options: BitString::from_bytes(&[0x00, 0x80, 0x00, 0x00]).unwrap(),
-                        cname: Some(cname),
+                        kdc_options: kdc_options.to_bit_string()?,
+                        cname,
                         // Per the RFC this is the "servers realm" in an AsReq but also the clients. So it's really
                         // not clear if the sname should have the realm or not or if this can be divergent between
                         // the client and server realm. What a clownshow, completely of their own making by trying
@@ -177,8 +462,71 @@ impl TryInto<KrbKdcReq> for KerberosRequest {
                     },
                 }))
             }
-            KerberosRequest::TGS(TicketGrantRequest {}) => {
-                todo!()
+            KerberosRequest::TGS(TicketGrantRequest {
+                nonce,
+                service_name,
+                from,
+                until,
+                renew,
+                kdc_options,
+                etypes,
+                additional_tickets,
+                for_user,
+                enc_authorization_data,
+                ticket,
+                authenticator,
+            }) => {
+                let req_body = tgs_req_body(
+                    nonce,
+                    &service_name,
+                    from,
+                    until,
+                    renew,
+                    kdc_options,
+                    &etypes,
+                    &additional_tickets,
+                    enc_authorization_data.as_ref(),
+                )?;
+
+                let ap_req = TaggedApReq::new(ApReq {
+                    pvno: 5,
+                    msg_type: KrbMessageType::KrbApReq as u8,
+                    ap_options: BitString::from_bytes(&[0x00, 0x00, 0x00, 0x00])
+                        .map_err(|_| KrbError::DerEncodeApReq)?,
+                    ticket: ticket.try_into()?,
+                    authenticator: authenticator.try_into()?,
+                });
+
+                let padata_value = ap_req
+                    .to_der()
+                    .and_then(OctetString::new)
+                    .map_err(|_| KrbError::DerEncodeApReq)?;
+
+                let mut padata = vec![PaData {
+                    padata_type: PaDataType::PaTgsReq as u32,
+                    padata_value,
+                }];
+
+                if let Some(for_user) = &for_user {
+                    let padata_value = PaForUser::try_from(for_user)?
+                        .to_der()
+                        .and_then(OctetString::new)
+                        .map_err(|_| KrbError::DerEncodePaForUser)?;
+
+                    padata.push(PaData {
+                        padata_type: PaDataType::PaForUser as u32,
+                        padata_value,
+                    });
+                }
+
+                let padata = Some(padata);
+
+                Ok(KrbKdcReq::TgsReq(KdcReq {
+                    pvno: 5,
+                    msg_type: KrbMessageType::KrbTgsReq as u8,
+                    padata,
+                    req_body,
+                }))
             }
         }
     }
@@ -188,25 +536,121 @@ impl TryFrom<KrbKdcReq> for KerberosRequest {
     type Error = KrbError;
 
     fn try_from(req: KrbKdcReq) -> Result<Self, KrbError> {
-        match req {
-            KrbKdcReq::TgsReq(kdc_req) | KrbKdcReq::AsReq(kdc_req) => {
-                KerberosRequest::try_from(kdc_req)
-            }
+        // The msg_type must agree with the application tag, or an AS-REQ could be
+        // handled as a TGS-REQ and the other way around.
+        let (kdc_req, expected_msg_type) = match req {
+            KrbKdcReq::AsReq(kdc_req) => (kdc_req, KrbMessageType::KrbAsReq),
+            KrbKdcReq::TgsReq(kdc_req) => (kdc_req, KrbMessageType::KrbTgsReq),
+        };
+
+        if kdc_req.msg_type != u8::from(expected_msg_type) {
+            return Err(KrbError::InvalidMessageType);
         }
+
+        KerberosRequest::try_from(kdc_req)
     }
 }
 
 impl KerberosAuthenticationBuilder {
+    /// Request a ticket granting ticket for `realm`, setting the service to `krbtgt/REALM`.
+    pub fn for_tgt(mut self, realm: &str) -> Self {
+        self.service_name = Name::service_krbtgt(realm);
+        self
+    }
+
+    /// Send the request to the KDC of `realm`. By default this is the client's realm.
+    pub fn realm(mut self, realm: &str) -> Self {
+        self.realm = Some(realm.to_string());
+        self
+    }
+
+    /// Override the randomly generated nonce. This should only be used for tests
+    /// or replaying a known request.
+    pub fn nonce(mut self, nonce: u32) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
     pub fn from(mut self, from: Option<SystemTime>) -> Self {
         self.from = from;
         self
     }
 
+    /// Request a ticket that is renewable until `renew`, which also requests the
+    /// renewable option. The renew time is dropped if the renewable option is later
+    /// cleared with `kdc_options`.
     pub fn renew_until(mut self, renew: Option<SystemTime>) -> Self {
+        if renew.is_some() {
+            self.kdc_options = self.kdc_options.renewable(true);
+        }
         self.renew = renew;
         self
     }
 
+    /// Set the options requested of the KDC. By default only a renewable ticket
+    /// is requested. Without the renewable option, no renew time is sent.
+    pub fn kdc_options(mut self, kdc_options: KdcOptions) -> Self {
+        self.kdc_options = kdc_options;
+        self
+    }
+
+    /// Request a ticket valid for `lifetime`, starting from the time set with `from`,
+    /// or the current time of `clock` if the ticket is not postdated.
+    pub fn lifetime(mut self, lifetime: Duration, clock: &impl Clock) -> Self {
+        self.until = self.from.unwrap_or_else(|| clock.now()) + lifetime;
+        self
+    }
+
+    /// Request a ticket that is renewable for `renewable` from the time set with `from`,
+    /// or the current time of `clock` if the ticket is not postdated.
+    pub fn renewable_for(mut self, renewable: Duration, clock: &impl Clock) -> Self {
+        self.kdc_options = self.kdc_options.renewable(true);
+        self.renew = Some(self.from.unwrap_or_else(|| clock.now()) + renewable);
+        self
+    }
+
+    /// Only advertise the etypes that `policy` permits, in its order of preference.
+    pub fn etype_policy(mut self, policy: &EtypePolicy) -> Self {
+        self.etypes = policy.etypes();
+        self
+    }
+
+    /// Add a PA-ENC-TIMESTAMP with a key derived from `passphrase`, for the strongest
+    /// etype the KDC offered in its etype-info2 that we request, and with the salt and
+    /// string-to-key parameters the KDC sent for that etype. The derived key is returned
+    /// to decrypt the reply with.
+    ///
+    /// Only the etypes of [crate::crypto::supported_etypes] can be requested, so a KDC
+    /// that only offers another etype, such as aes128-cts-hmac-sha1-96, is not answered
+    /// with a key in an etype it didn't ask for.
+    pub fn preauth_enc_ts_with_passphrase(
+        self,
+        pa_data: &PreauthData,
+        epoch_seconds: Duration,
+        passphrase: &str,
+    ) -> Result<(Self, DerivedKey), KrbError> {
+        // Before the key is derived, as a KDC that doesn't offer an encrypted timestamp
+        // may not send etype-info2 either.
+        pa_data.require_enc_timestamp()?;
+
+        let etype_info2 = pa_data.preferred_etype_info2_of(&self.etypes)?;
+
+        let salt = match &etype_info2.salt {
+            Some(salt) => salt.clone(),
+            None => default_salt(self.client_name.realm(), &self.client_name).into_bytes(),
+        };
+
+        let user_key = DerivedKey::from_etype_info2_with_salt(
+            &etype_info2,
+            &salt,
+            passphrase,
+            DEFAULT_MIN_PBKDF2_SHA1_ITER,
+        )?;
+
+        self.preauth_enc_ts(pa_data, epoch_seconds, &user_key)
+            .map(|builder| (builder, user_key))
+    }
+
     pub fn preauth_enc_ts(
         mut self,
         pa_data: &PreauthData,
@@ -214,65 +658,383 @@ impl KerberosAuthenticationBuilder {
         user_key: &DerivedKey,
     ) -> Result<Self, KrbError> {
         // Major TODO: Can we actually use a reasonable amount of iterations?
-        if !pa_data.enc_timestamp {
-            return Err(KrbError::PreauthUnsupported);
-        }
+        pa_data.require_enc_timestamp()?;
 
-        // Strip any excess time.
-        let usecs = epoch_seconds.subsec_micros();
-        let epoch_seconds = Duration::from_secs(epoch_seconds.as_secs());
-
-        let patimestamp = KerberosTime::from_unix_duration(epoch_seconds)
-            .map_err(|_| KrbError::PreauthInvalidUnixTs)?;
-
-        let paenctsenc = PaEncTsEnc {
-            patimestamp,
-            pausec: Some(usecs),
-        };
+        let paenctsenc = PaEncTsEnc::from_system_time(SystemTime::UNIX_EPOCH + epoch_seconds)?;
 
         trace!(?paenctsenc);
 
         let enc_timestamp = user_key.encrypt_pa_enc_timestamp(&paenctsenc)?;
 
-        // fx cookie always has to be sent.
-        let pa_fx_cookie = pa_data.pa_fx_cookie.clone();
+        // fx cookie always has to be sent, and so does the freshness token if the KDC
+        // issued one.
+        self.preauth.pa_fx_cookie = pa_data.pa_fx_cookie.clone();
+        self.preauth.pa_as_freshness = pa_data.pa_as_freshness.clone();
+        self.preauth.enc_timestamp = Some(enc_timestamp);
 
-        self.preauth = Some(Preauth {
-            enc_timestamp: Some(enc_timestamp),
-            pa_fx_cookie,
-        });
+        Ok(self)
+    }
+
+    /// Ask the KDC to include a PAC in the ticket, or to leave it out, with a
+    /// PA-PAC-REQUEST as defined in MS-KILE.
+    pub fn pac_request(mut self, include_pac: bool) -> Self {
+        self.preauth.pa_pac_request = Some(include_pac);
+        self
+    }
+
+    /// Send an additional padata entry, for padata types that this library doesn't
+    /// produce itself. The padata_value is sent as is.
+    pub fn additional_padata(mut self, padata_type: u32, padata_value: Vec<u8>) -> Self {
+        self.preauth
+            .additional_padata
+            .push((padata_type, padata_value));
+        self
+    }
 
+    /// Tell a KDC that offered SPAKE preauthentication which groups we support, so
+    /// that its next reply carries a [SpakeChallenge](super::SpakeChallenge). Responding
+    /// to the challenge is not yet supported.
+    pub fn spake_support(mut self) -> Result<Self, KrbError> {
+        let spake_support = encode_spake_support()?;
+        self.preauth.additional_padata.push(spake_support);
         Ok(self)
     }
 
-    pub fn build(self) -> KerberosRequest {
+    /// Send an empty PA-AS-FRESHNESS to ask a KDC that supports RFC 8070 for a freshness
+    /// token. The token arrives with the request for preauthentication, and is sent back
+    /// by [Self::preauth_enc_ts].
+    pub fn request_as_freshness(mut self) -> Self {
+        self.preauth.pa_as_freshness = Some(Vec::new());
+        self
+    }
+
+    /// Send the request without a client name, for flows where the KDC identifies the
+    /// client by other means, such as anonymous PKINIT. The realm is still sent, and is
+    /// taken from the client name unless set with [Self::realm].
+    pub fn omit_client_name(mut self, omit_client_name: bool) -> Self {
+        self.omit_client_name = omit_client_name;
+        self
+    }
+
+    /// Build the request. The requested ticket times are checked against the
+    /// current time of `clock`.
+    #[instrument(level = "trace", skip_all)]
+    pub fn build(self, clock: &impl Clock) -> Result<KerberosRequest, KrbError> {
         let KerberosAuthenticationBuilder {
             client_name,
             service_name,
+            realm,
+            nonce,
             from,
             until,
             renew,
+            kdc_options,
             preauth,
             etypes,
+            omit_client_name,
         } = self;
 
-        // BUG IN MIT KRB5 - If the value is greater than i32 max you get:
-        // Jun 28 03:47:41 3e79497ab6b5 krb5kdc[1](Error): ASN.1 value too large - while dispatching (tcp)
-        let nonce: u32 = thread_rng().gen();
-        let nonce = nonce & 0x7fff_ffff;
+        // The KDC ignores or rejects an rtime without the renewable option.
+        let renew = renew.filter(|_| kdc_options.is_renewable());
 
-        let preauth = preauth.unwrap_or_default();
+        validate_ticket_times(clock.now(), from, until, renew)?;
 
-        KerberosRequest::AS(AuthenticationRequest {
+        // An etype policy may leave nothing that we can use.
+        if etypes.is_empty() {
+            return Err(KrbError::UnsupportedEncryption);
+        }
+
+        let nonce = nonce.unwrap_or_else(random_nonce);
+
+        let realm = realm.unwrap_or_else(|| client_name.realm().to_string());
+
+        let requested_etypes = etypes.iter().map(|etype| *etype as i32).collect();
+
+        Ok(KerberosRequest::AS(AuthenticationRequest {
             nonce,
             client_name,
             service_name,
+            realm,
             from,
             until,
             renew,
+            kdc_options,
             preauth,
             etypes,
-        })
+            requested_etypes,
+            omit_client_name,
+        }))
+    }
+}
+
+impl AuthenticationRequest {
+    pub fn client_name(&self) -> &Name {
+        &self.client_name
+    }
+
+    pub fn service_name(&self) -> &Name {
+        &self.service_name
+    }
+
+    /// Every etype the client asked for, in its order of preference, including those
+    /// that we don't support. A client asking for only weak etypes, such as RC4-HMAC
+    /// (23), may be trying to obtain a reply that is easier to crack offline.
+    pub fn etypes(&self) -> &[i32] {
+        &self.requested_etypes
+    }
+
+    pub fn kdc_options(&self) -> KdcOptions {
+        self.kdc_options
+    }
+
+    pub fn from(&self) -> Option<SystemTime> {
+        self.from
+    }
+
+    pub fn until(&self) -> SystemTime {
+        self.until
+    }
+
+    pub fn renew(&self) -> Option<SystemTime> {
+        self.renew
+    }
+
+    /// Whether the client asked for a PAC in the ticket, if it sent a PA-PAC-REQUEST.
+    pub fn pac_request(&self) -> Option<bool> {
+        self.preauth.pa_pac_request
+    }
+
+    /// Build the follow-up to this request after the KDC replied with `pa_rep`, adding
+    /// a PA-ENC-TIMESTAMP as [KerberosAuthenticationBuilder::preauth_enc_ts_with_passphrase]
+    /// does and returning the cookie of the conversation. The nonce, names, ticket times,
+    /// options and etypes of this request are kept. The derived key is returned to
+    /// decrypt the reply with.
+    pub fn with_preauth_from(
+        &self,
+        pa_rep: &PreauthReply,
+        passphrase: &str,
+        clock: &impl Clock,
+    ) -> Result<(KerberosRequest, DerivedKey), KrbError> {
+        let epoch_seconds = clock
+            .now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|_| KrbError::PreauthInvalidUnixTs)?;
+
+        // Any timestamp, cookie and freshness token from an earlier attempt are replaced.
+        let preauth = Preauth {
+            enc_timestamp: None,
+            pa_fx_cookie: None,
+            pa_as_freshness: None,
+            ..self.preauth.clone()
+        };
+
+        let builder = KerberosAuthenticationBuilder {
+            client_name: self.client_name.clone(),
+            service_name: self.service_name.clone(),
+            realm: Some(self.realm.clone()),
+            nonce: Some(self.nonce),
+            from: self.from,
+            until: self.until,
+            renew: self.renew,
+            kdc_options: self.kdc_options,
+            preauth,
+            etypes: self.etypes.clone(),
+            omit_client_name: self.omit_client_name,
+        };
+
+        let (builder, user_key) =
+            builder.preauth_enc_ts_with_passphrase(&pa_rep.pa_data, epoch_seconds, passphrase)?;
+
+        builder.build(clock).map(|request| (request, user_key))
+    }
+}
+
+impl TicketGrantRequest {
+    /// Decrypt the authorization data that the client asked to be added to the issued
+    /// ticket, with the session key of the ticket granting ticket it presented.
+    pub fn authorization_data(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Vec<AuthorizationData>, KrbError> {
+        self.enc_authorization_data
+            .as_ref()
+            .map(|enc_authorization_data| {
+                decrypt_authorization_data(enc_authorization_data, session_key)
+            })
+            .unwrap_or_else(|| Ok(Vec::with_capacity(0)))
+    }
+}
+
+impl KerberosTicketGrantBuilder {
+    pub fn from(mut self, from: Option<SystemTime>) -> Self {
+        self.from = from;
+        self
+    }
+
+    /// Request a ticket that is renewable until `renew`, which also requests the
+    /// renewable option. The renew time is dropped if the renewable option is later
+    /// cleared with `kdc_options`.
+    pub fn renew_until(mut self, renew: Option<SystemTime>) -> Self {
+        if renew.is_some() {
+            self.kdc_options = self.kdc_options.renewable(true);
+        }
+        self.renew = renew;
+        self
+    }
+
+    /// Set the options requested of the KDC. By default only a renewable ticket
+    /// is requested. Without the renewable option, no renew time is sent.
+    pub fn kdc_options(mut self, kdc_options: KdcOptions) -> Self {
+        self.kdc_options = kdc_options;
+        self
+    }
+
+    /// Request a ticket valid for `lifetime`, starting from the time set with `from`,
+    /// or the current time of `clock` if the ticket is not postdated.
+    pub fn lifetime(mut self, lifetime: Duration, clock: &impl Clock) -> Self {
+        self.until = self.from.unwrap_or_else(|| clock.now()) + lifetime;
+        self
+    }
+
+    /// Request a ticket that is renewable for `renewable` from the time set with `from`,
+    /// or the current time of `clock` if the ticket is not postdated.
+    pub fn renewable_for(mut self, renewable: Duration, clock: &impl Clock) -> Self {
+        self.kdc_options = self.kdc_options.renewable(true);
+        self.renew = Some(self.from.unwrap_or_else(|| clock.now()) + renewable);
+        self
+    }
+
+    /// Request a user-to-user ticket, encrypted in the session key of `ticket` rather
+    /// than the service's long term key. `ticket` is the ticket granting ticket of the
+    /// service, which it must have provided to the client.
+    pub fn user_to_user(mut self, ticket: Ticket) -> Self {
+        self.kdc_options = self.kdc_options.enc_tkt_in_skey(true);
+        self.additional_tickets = vec![ticket];
+        self
+    }
+
+    /// Add a ticket to the additional tickets of the request. The KDC only uses the
+    /// additional tickets if either the enc-tkt-in-skey or cname-in-addl-tkt option
+    /// is also requested.
+    pub fn additional_ticket(mut self, ticket: Ticket) -> Self {
+        self.additional_tickets.push(ticket);
+        self
+    }
+
+    /// Ask the KDC to add `authorization_data` to the issued ticket, such as to restrict
+    /// what a delegated ticket may be used for. It is sent encrypted in the session key.
+    pub fn authorization_data(mut self, authorization_data: Vec<AuthorizationData>) -> Self {
+        self.authorization_data = authorization_data;
+        self
+    }
+
+    /// Only advertise the etypes that `policy` permits, in its order of preference.
+    pub fn etype_policy(mut self, policy: &EtypePolicy) -> Self {
+        self.etypes = policy.etypes();
+        self
+    }
+
+    /// Build the request, authenticating to the KDC as `client_name` with the
+    /// ticket granting ticket and the session key that was issued alongside it.
+    /// The authenticator timestamp is taken from `clock`.
+    #[instrument(level = "trace", skip_all)]
+    pub fn build(
+        self,
+        client_name: &Name,
+        ticket: &Ticket,
+        session_key: &SessionKey,
+        clock: &impl Clock,
+    ) -> Result<KerberosRequest, KrbError> {
+        let KerberosTicketGrantBuilder {
+            service_name,
+            from,
+            until,
+            renew,
+            kdc_options,
+            etypes,
+            additional_tickets,
+            for_user,
+            authorization_data,
+        } = self;
+
+        // The KDC ignores or rejects an rtime without the renewable option.
+        let renew = renew.filter(|_| kdc_options.is_renewable());
+
+        validate_ticket_times(clock.now(), from, until, renew)?;
+
+        // An etype policy may leave nothing that we can use.
+        if etypes.is_empty() {
+            return Err(KrbError::UnsupportedEncryption);
+        }
+
+        // An additional ticket is only meaningful with an option that tells the KDC what
+        // to do with it, and those options can't be honoured without one.
+        if additional_tickets.is_empty() == kdc_options.uses_additional_ticket() {
+            return Err(KrbError::InvalidAdditionalTickets);
+        }
+
+        let nonce = random_nonce();
+
+        let for_user = for_user
+            .map(|user| ForUser::new(user, session_key))
+            .transpose()?;
+
+        let enc_authorization_data = if authorization_data.is_empty() {
+            None
+        } else {
+            Some(encrypt_authorization_data(
+                &authorization_data,
+                session_key,
+            )?)
+        };
+
+        // RFC 4120 7.5.1 - The authenticator of a TGS-REQ carries a checksum of
+        // the req-body keyed with the session key, key usage 6.
+        let req_body = tgs_req_body(
+            nonce,
+            &service_name,
+            from,
+            until,
+            renew,
+            kdc_options,
+            &etypes,
+            &additional_tickets,
+            enc_authorization_data.as_ref(),
+        )?;
+        let req_body = req_body
+            .to_der()
+            .map_err(|_| KrbError::DerEncodeKdcReqBody)?;
+
+        let (checksum_type, checksum) = make_checksum(session_key, 6, &req_body)?;
+        let checksum = OctetString::new(checksum).map_err(|_| KrbError::DerEncodeOctetString)?;
+
+        let authenticator = encode_authenticator(
+            client_name,
+            Some(Checksum {
+                checksum_type,
+                checksum,
+            }),
+            clock,
+        )?;
+
+        // RFC 4120 The TGS-REQ authenticator is encrypted in the session key with
+        // key usage 7.
+        let authenticator = session_key.encrypt_data(&authenticator, 7)?;
+
+        Ok(KerberosRequest::TGS(TicketGrantRequest {
+            nonce,
+            service_name,
+            from,
+            until,
+            renew,
+            kdc_options,
+            etypes,
+            additional_tickets,
+            for_user,
+            enc_authorization_data,
+            ticket: ticket.clone(),
+            authenticator,
+        }))
     }
 }
 
@@ -290,6 +1052,8 @@ impl TryFrom<KdcReq> for KerberosRequest {
 
         match msg_type {
             KrbMessageType::KrbAsReq => {
+                let requested_etypes = req.req_body.etype.clone();
+
                 // Filter and use only the finest of etypes.
                 let mut etypes = req
                     .req_body
@@ -315,18 +1079,22 @@ impl TryFrom<KdcReq> for KerberosRequest {
                 let cname = req.req_body.cname.ok_or(KrbError::MissingClientName)?;
                 let realm = req.req_body.realm;
 
-                let client_name: Name = (cname, realm).try_into().unwrap();
+                let client_name: Name = (cname, realm.clone()).try_into()?;
 
                 // Is realm from .realm? In the service? Who knows! The krb spec is cooked.
+                // RFC 4120 says that it's the realm of the server, and in the AS exchange
+                // also of the client.
                 let service_name: Name = req
                     .req_body
                     .sname
                     .ok_or(KrbError::MissingServiceNameWithRealm)
-                    .and_then(|s| s.try_into())?;
+                    .and_then(|s| (s, realm.clone()).try_into())?;
+                let realm = realm.into();
 
                 let from = req.req_body.from.map(|t| t.to_system_time());
                 let until = req.req_body.till.to_system_time();
                 let renew = req.req_body.rtime.map(|t| t.to_system_time());
+                let kdc_options = KdcOptions::from_bit_string(&req.req_body.kdc_options);
                 let nonce = req.req_body.nonce;
 
                 // addresses,
@@ -337,17 +1105,1130 @@ impl TryFrom<KdcReq> for KerberosRequest {
                     nonce,
                     client_name,
                     service_name,
+                    realm,
                     from,
                     until,
                     renew,
+                    kdc_options,
                     etypes,
                     preauth,
+                    requested_etypes,
+                    omit_client_name: false,
                 }))
             }
             KrbMessageType::KrbTgsReq => {
-                todo!();
+                let etypes = req
+                    .req_body
+                    .etype
+                    .iter()
+                    .filter_map(|etype| {
+                        EncryptionType::try_from(*etype)
+                            .ok()
+                            .and_then(|etype| match etype {
+                                EncryptionType::AES256_CTS_HMAC_SHA1_96 => Some(etype),
+                                _ => None,
+                            })
+                    })
+                    .collect();
+
+                let padata = req.padata.unwrap_or_default();
+
+                let for_user = padata
+                    .iter()
+                    .find(|pa| pa.padata_type == PaDataType::PaForUser as u32)
+                    .map(|pa| {
+                        PaForUser::from_der(pa.padata_value.as_bytes())
+                            .map_err(|_| KrbError::DerDecodePaForUser)
+                            .and_then(ForUser::try_from)
+                    })
+                    .transpose()?;
+
+                let ap_req = padata
+                    .into_iter()
+                    .find(|pa| pa.padata_type == PaDataType::PaTgsReq as u32)
+                    .ok_or(KrbError::MissingApReq)
+                    .and_then(|pa| {
+                        TaggedApReq::from_der(pa.padata_value.as_bytes())
+                            .map_err(|_| KrbError::DerDecodeApReq)
+                    })?;
+
+                let TaggedApReq(ap_req) = ap_req;
+                trace!(?ap_req);
+
+                let ticket = Ticket::try_from(ap_req.ticket)?;
+                let authenticator = EncryptedData::try_from(ap_req.authenticator)?;
+
+                let sname = req
+                    .req_body
+                    .sname
+                    .ok_or(KrbError::MissingServiceNameWithRealm)?;
+                let service_name: Name = (sname, req.req_body.realm).try_into()?;
+
+                let from = req.req_body.from.map(|t| t.to_system_time());
+                let until = req.req_body.till.to_system_time();
+                let renew = req.req_body.rtime.map(|t| t.to_system_time());
+                let kdc_options = KdcOptions::from_bit_string(&req.req_body.kdc_options);
+                let nonce = req.req_body.nonce;
+                let additional_tickets = req
+                    .req_body
+                    .additional_tickets
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(Ticket::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let enc_authorization_data = req
+                    .req_body
+                    .enc_authorization_data
+                    .map(EncryptedData::try_from)
+                    .transpose()?;
+
+                Ok(KerberosRequest::TGS(TicketGrantRequest {
+                    nonce,
+                    service_name,
+                    from,
+                    until,
+                    renew,
+                    kdc_options,
+                    etypes,
+                    additional_tickets,
+                    for_user,
+                    enc_authorization_data,
+                    ticket,
+                    authenticator,
+                }))
             }
             _ => Err(KrbError::InvalidMessageDirection),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::asn1::constants::encryption_types::EncryptionType;
+    use crate::asn1::constants::{KrbMessageType, PaDataType};
+    use crate::asn1::enc_ticket_part::{EncTicketPart, TaggedEncTicketPart};
+    use crate::asn1::encryption_key::EncryptionKey;
+    use crate::asn1::etype_info2::ETypeInfo2Entry;
+    use crate::asn1::kerberos_string::RawKerberosString;
+    use crate::asn1::kerberos_time::KerberosTime;
+    use crate::asn1::krb_kdc_req::KrbKdcReq;
+    use crate::asn1::pa_data::PaData;
+    use crate::asn1::pa_for_user::PaForUser;
+    use crate::asn1::ticket_flags::TicketFlags;
+    use crate::asn1::transited_encoding::TransitedEncoding;
+    use crate::asn1::OctetString;
+    use crate::clock::{Clock, SystemClock};
+    use crate::error::KrbError;
+    use crate::proto::{
+        decode_token, encode_token, ApOptions, ApRequest, DerivedKey, KdcOptions,
+        KerberosAuthenticationBuilder, KerberosReply, KerberosRequest, Name, PreauthData,
+        SessionKey, Ticket,
+    };
+    use crate::test_helpers::{primary_key, session_key, ticket, user_key};
+    use der::flagset::FlagSet;
+    use der::{Decode, Encode};
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_preauth_enc_ts_uses_offered_etype_info2() {
+        let etype_info2_entry = |etype: EncryptionType, salt: &str| ETypeInfo2Entry {
+            etype: etype as i32,
+            salt: Some(RawKerberosString(salt.as_bytes().to_vec())),
+            s2kparams: Some(
+                OctetString::new(0x2000u32.to_be_bytes()).expect("Invalid OCTET STRING"),
+            ),
+        };
+        let pa_data = |etype_info2: Vec<ETypeInfo2Entry>| {
+            PreauthData::try_from(vec![
+                PaData {
+                    padata_type: PaDataType::PaEncTimestamp as u32,
+                    padata_value: OctetString::new([]).expect("Invalid OCTET STRING"),
+                },
+                PaData {
+                    padata_type: PaDataType::PaEtypeInfo2 as u32,
+                    padata_value: OctetString::new(etype_info2.to_der().expect("Failed to encode"))
+                        .expect("Invalid OCTET STRING"),
+                },
+            ])
+            .expect("Failed to parse padata")
+        };
+        let build_as = || {
+            KerberosRequest::build_as(
+                Name::principal("testuser", "EXAMPLE.COM"),
+                Name::service_krbtgt("EXAMPLE.COM"),
+                SystemClock.now() + Duration::from_secs(3600),
+            )
+        };
+        let epoch_seconds = Duration::from_secs(1_700_000_000);
+
+        // Each etype has its own salt and iterations, so the timestamp must be encrypted
+        // with the key of the entry that was selected, not a key derived up front.
+        let pa_data_aes = pa_data(vec![
+            etype_info2_entry(EncryptionType::AES128_CTS_HMAC_SHA1_96, "EXAMPLE.COMaes128"),
+            etype_info2_entry(EncryptionType::AES256_CTS_HMAC_SHA1_96, "EXAMPLE.COMaes256"),
+        ]);
+
+        let (builder, user_key) = build_as()
+            .preauth_enc_ts_with_passphrase(&pa_data_aes, epoch_seconds, "password")
+            .expect("Failed to add preauth");
+
+        assert!(matches!(
+            &user_key,
+            DerivedKey::Aes256CtsHmacSha196 { i: 0x2000, s, .. } if s == b"EXAMPLE.COMaes256"
+        ));
+
+        let KerberosRequest::AS(as_req) = builder
+            .build(&SystemClock)
+            .expect("Failed to build request")
+        else {
+            unreachable!();
+        };
+        let enc_timestamp = as_req.preauth.enc_timestamp().expect("No PA-ENC-TIMESTAMP");
+        assert_eq!(
+            enc_timestamp
+                .decrypt_pa_enc_timestamp(&user_key)
+                .expect("Failed to decrypt timestamp"),
+            SystemTime::UNIX_EPOCH + epoch_seconds
+        );
+
+        let default_key = crate::test_helpers::user_key();
+        assert!(enc_timestamp
+            .decrypt_pa_enc_timestamp(&default_key)
+            .is_err());
+
+        // aes128 is not supported, so when it is all the KDC offers, preauth fails rather
+        // than falling back to a key the KDC didn't ask for.
+        let pa_data_aes128 = pa_data(vec![etype_info2_entry(
+            EncryptionType::AES128_CTS_HMAC_SHA1_96,
+            "EXAMPLE.COMaes128",
+        )]);
+        assert!(matches!(
+            build_as().preauth_enc_ts_with_passphrase(&pa_data_aes128, epoch_seconds, "password"),
+            Err(KrbError::PreauthMissingEtypeInfo2)
+        ));
+
+        #[cfg(feature = "des3")]
+        {
+            let pa_data_des3 = pa_data(vec![etype_info2_entry(
+                EncryptionType::DES3_CBC_SHA1_KD,
+                "EXAMPLE.COMdes3",
+            )]);
+
+            let (builder, user_key) = build_as()
+                .preauth_enc_ts_with_passphrase(&pa_data_des3, epoch_seconds, "password")
+                .expect("Failed to add preauth");
+            assert!(
+                matches!(&user_key, DerivedKey::Des3CbcSha1 { s, .. } if s == b"EXAMPLE.COMdes3")
+            );
+
+            let KerberosRequest::AS(as_req) = builder
+                .build(&SystemClock)
+                .expect("Failed to build request")
+            else {
+                unreachable!();
+            };
+            assert!(matches!(
+                as_req.preauth.enc_timestamp(),
+                Some(crate::proto::EncryptedData::Des3CbcSha1 { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_preauth_unsupported_mechanism() {
+        // A KDC that requires PKINIT, so only offers PA-PK-AS-REQ alongside the
+        // etype-info2 and cookie.
+        let etype_info2 = vec![ETypeInfo2Entry {
+            etype: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+            salt: None,
+            s2kparams: None,
+        }];
+
+        let pavec = vec![
+            PaData {
+                padata_type: PaDataType::PaPkAsReq as u32,
+                padata_value: OctetString::new([]).expect("Invalid OCTET STRING"),
+            },
+            PaData {
+                padata_type: PaDataType::PaEtypeInfo2 as u32,
+                padata_value: OctetString::new(etype_info2.to_der().expect("Failed to encode"))
+                    .expect("Invalid OCTET STRING"),
+            },
+            PaData {
+                padata_type: PaDataType::PaFxCookie as u32,
+                padata_value: OctetString::new("cookie".as_bytes()).expect("Invalid OCTET STRING"),
+            },
+        ];
+
+        let pa_data = PreauthData::try_from(pavec).expect("Failed to parse padata");
+
+        let builder = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            SystemClock.now() + Duration::from_secs(3600),
+        );
+
+        let err = builder
+            .preauth_enc_ts_with_passphrase(
+                &pa_data,
+                Duration::from_secs(1_700_000_000),
+                "password",
+            )
+            .expect_err("Preauth was built for an unsupported mechanism");
+
+        assert!(matches!(
+            &err,
+            KrbError::UnsupportedPreauthMechanism(mechanisms)
+                if mechanisms == &[PaDataType::PaPkAsReq]
+        ));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_as_req_for_tgt() {
+        let now = SystemClock.now();
+        let as_req = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::principal("someservice", "EXAMPLE.COM"),
+            now + Duration::from_secs(3600),
+        )
+        .for_tgt("EXAMPLE.COM")
+        .build(&SystemClock)
+        .expect("Failed to build request");
+
+        let KrbKdcReq::AsReq(kdc_req) = as_req.try_into().expect("Failed to encode request") else {
+            unreachable!();
+        };
+
+        let sname = kdc_req.req_body.sname.expect("Missing sname");
+        assert_eq!(sname.name_type, 2);
+        let components: Vec<&str> = sname.name_string.iter().map(|s| s.0.as_str()).collect();
+        assert_eq!(components, vec!["krbtgt", "EXAMPLE.COM"]);
+        assert_eq!(kdc_req.req_body.realm.0.as_str(), "EXAMPLE.COM");
+    }
+
+    #[test]
+    fn test_as_req_realms() {
+        let until = SystemClock.now() + Duration::from_secs(3600);
+
+        let as_req = |builder: KerberosAuthenticationBuilder| {
+            let request = builder.build(&SystemClock).expect("Failed to build AS-REQ");
+            let KrbKdcReq::AsReq(kdc_req) = request.try_into().expect("Failed to encode AS-REQ")
+            else {
+                unreachable!();
+            };
+            kdc_req
+        };
+
+        // alice@A.COM asking her KDC for a cross realm TGT to B.COM.
+        let kdc_req = as_req(KerberosRequest::build_as(
+            Name::principal("alice", "A.COM"),
+            Name::service_krbtgt_cross_realm("B.COM", "A.COM"),
+            until,
+        ));
+
+        assert_eq!(kdc_req.req_body.realm.as_str(), "A.COM");
+        let cname = kdc_req.req_body.cname.as_ref().expect("No name");
+        assert_eq!(cname.name_string.len(), 1);
+        assert_eq!(cname.name_string[0].as_str(), "alice");
+        let sname = kdc_req.req_body.sname.as_ref().expect("No name");
+        assert_eq!(sname.name_string.len(), 2);
+        assert_eq!(sname.name_string[0].as_str(), "krbtgt");
+        assert_eq!(sname.name_string[1].as_str(), "B.COM");
+
+        let KerberosRequest::AS(request) =
+            KerberosRequest::try_from(KrbKdcReq::AsReq(kdc_req)).expect("Failed to decode AS-REQ")
+        else {
+            unreachable!();
+        };
+        assert_eq!(request.realm, "A.COM");
+        assert_eq!(request.client_name, Name::principal("alice", "A.COM"));
+        assert_eq!(
+            request.service_name,
+            Name::service_krbtgt_cross_realm("B.COM", "A.COM")
+        );
+
+        // The realm of the request can be set explicitly.
+        let kdc_req = as_req(
+            KerberosRequest::build_as(
+                Name::principal("alice", "A.COM"),
+                Name::service_krbtgt("B.COM"),
+                until,
+            )
+            .realm("B.COM"),
+        );
+        assert_eq!(kdc_req.req_body.realm.as_str(), "B.COM");
+    }
+
+    #[test]
+    fn test_as_req_omit_client_name() {
+        let request = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            SystemClock.now() + Duration::from_secs(3600),
+        )
+        .omit_client_name(true)
+        .build(&SystemClock)
+        .expect("Failed to build AS-REQ");
+
+        let kdc_req: KrbKdcReq = request.try_into().expect("Failed to encode AS-REQ");
+        let blob = kdc_req.to_der().expect("Failed to encode AS-REQ");
+
+        let KrbKdcReq::AsReq(kdc_req) = KrbKdcReq::from_der(&blob).expect("Failed to decode")
+        else {
+            unreachable!();
+        };
+
+        // The cname is absent, but the realm is still there for the KDC.
+        assert!(kdc_req.req_body.cname.is_none());
+        assert_eq!(kdc_req.req_body.realm.as_str(), "EXAMPLE.COM");
+        assert!(kdc_req.req_body.sname.is_some());
+
+        // Our KDC needs the client name to look up its key.
+        assert!(matches!(
+            KerberosRequest::try_from(KrbKdcReq::AsReq(kdc_req)),
+            Err(KrbError::MissingClientName)
+        ));
+    }
+
+    #[test]
+    fn test_kdc_req_msg_type_mismatch() {
+        let request = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            SystemClock.now() + Duration::from_secs(3600),
+        )
+        .build(&SystemClock)
+        .expect("Failed to build AS-REQ");
+
+        let kdc_req: KrbKdcReq = request.try_into().expect("Failed to encode AS-REQ");
+        let blob = kdc_req.to_der().expect("Failed to encode AS-REQ");
+        let decode = || {
+            let KrbKdcReq::AsReq(kdc_req) = KrbKdcReq::from_der(&blob).expect("Failed to decode")
+            else {
+                unreachable!();
+            };
+            assert_eq!(kdc_req.msg_type, KrbMessageType::KrbAsReq as u8);
+            kdc_req
+        };
+
+        // An AS-REQ body in a TGS-REQ, and a TGS-REQ msg_type in an AS-REQ.
+        let tgs_req = KrbKdcReq::TgsReq(decode())
+            .to_der()
+            .expect("Failed to encode TGS-REQ");
+        let mut kdc_req = decode();
+        kdc_req.msg_type = KrbMessageType::KrbTgsReq as u8;
+        let as_req = KrbKdcReq::AsReq(kdc_req)
+            .to_der()
+            .expect("Failed to encode AS-REQ");
+
+        for blob in [tgs_req, as_req] {
+            assert!(matches!(
+                decode_token::<KerberosRequest>(&blob),
+                Err(KrbError::InvalidMessageType)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_as_req_invalid_ticket_times() {
+        let now = SystemClock.now();
+        let hour = Duration::from_secs(3600);
+        let build_as = |until| {
+            KerberosRequest::build_as(
+                Name::principal("testuser", "EXAMPLE.COM"),
+                Name::service_krbtgt("EXAMPLE.COM"),
+                until,
+            )
+        };
+
+        // until before from.
+        let result = build_as(now + hour)
+            .from(Some(now + 2 * hour))
+            .build(&SystemClock);
+        assert!(matches!(result, Err(KrbError::InvalidTicketTimes)));
+
+        // until equal to from.
+        let result = build_as(now + hour)
+            .from(Some(now + hour))
+            .build(&SystemClock);
+        assert!(matches!(result, Err(KrbError::InvalidTicketTimes)));
+
+        // until in the past.
+        let result = build_as(now - hour).build(&SystemClock);
+        assert!(matches!(result, Err(KrbError::InvalidTicketTimes)));
+
+        // renew before until.
+        let result = build_as(now + 2 * hour)
+            .renew_until(Some(now + hour))
+            .build(&SystemClock);
+        assert!(matches!(result, Err(KrbError::InvalidTicketTimes)));
+
+        let result = build_as(now + hour)
+            .from(Some(now))
+            .renew_until(Some(now + hour))
+            .build(&SystemClock);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_as_req_rtime_requires_renewable() {
+        let now = SystemClock.now();
+        let hour = Duration::from_secs(3600);
+        let encode = |builder: KerberosAuthenticationBuilder| {
+            let as_req = builder
+                .build(&SystemClock)
+                .expect("Failed to build request");
+            let KrbKdcReq::AsReq(kdc_req) = as_req.try_into().expect("Failed to encode request")
+            else {
+                unreachable!();
+            };
+            let kdc_options = KdcOptions::from_bit_string(&kdc_req.req_body.kdc_options);
+            (kdc_options, kdc_req.req_body.rtime)
+        };
+        let build_as = || {
+            KerberosRequest::build_as(
+                Name::principal("testuser", "EXAMPLE.COM"),
+                Name::service_krbtgt("EXAMPLE.COM"),
+                now + hour,
+            )
+        };
+
+        // Setting a renew time requests the renewable option.
+        let (kdc_options, rtime) = encode(
+            build_as()
+                .kdc_options(KdcOptions::default().forwardable(true))
+                .renew_until(Some(now + 2 * hour)),
+        );
+        assert_eq!(
+            kdc_options,
+            KdcOptions::default().forwardable(true).renewable(true)
+        );
+        assert!(rtime.is_some());
+
+        // Clearing the renewable option drops the renew time.
+        let (kdc_options, rtime) = encode(
+            build_as()
+                .renew_until(Some(now + 2 * hour))
+                .kdc_options(KdcOptions::default().forwardable(true)),
+        );
+        assert_eq!(kdc_options, KdcOptions::default().forwardable(true));
+        assert!(rtime.is_none());
+
+        // A renew time that would be invalid is not checked once it's dropped.
+        let (_, rtime) = encode(
+            build_as()
+                .renew_until(Some(now))
+                .kdc_options(KdcOptions::default()),
+        );
+        assert!(rtime.is_none());
+    }
+
+    #[derive(Clone, Default)]
+    struct TraceCapture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for TraceCapture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().expect("Poisoned lock").extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace_redacts_keys() {
+        let capture = TraceCapture::default();
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let keys = tracing::subscriber::with_default(subscriber, || {
+            let now = SystemClock.now();
+            let user_key = user_key();
+            let primary_key = primary_key();
+
+            let request = KerberosRequest::build_as(
+                Name::principal("testuser", "EXAMPLE.COM"),
+                Name::service_krbtgt("EXAMPLE.COM"),
+                now + Duration::from_secs(3600),
+            )
+            .build(&SystemClock)
+            .expect("Failed to build request");
+            let KerberosRequest::AS(auth_req) =
+                decode_token(&encode_token(request).expect("Failed to encode"))
+                    .expect("Failed to decode")
+            else {
+                unreachable!();
+            };
+
+            let reply = KerberosReply::authentication_builder(
+                auth_req.client_name,
+                auth_req.service_name,
+                now,
+                auth_req.nonce,
+            )
+            .build(&user_key, &primary_key)
+            .expect("Failed to build reply");
+            let KerberosReply::AS(as_rep) =
+                decode_token(&encode_token(reply).expect("Failed to encode"))
+                    .expect("Failed to decode")
+            else {
+                unreachable!();
+            };
+
+            let reply_part = as_rep
+                .decrypt(&Name::principal("testuser", "EXAMPLE.COM"), &user_key)
+                .expect("Failed to decrypt reply");
+
+            let user_key = match user_key {
+                DerivedKey::Aes256CtsHmacSha196 { k, .. } => k,
+                #[cfg(feature = "des3")]
+                _ => unreachable!(),
+            };
+            let session_key = match reply_part.session_key() {
+                SessionKey::Aes256CtsHmacSha196 { k } => k,
+                #[cfg(feature = "des3")]
+                _ => unreachable!(),
+            };
+            [user_key, *session_key]
+        });
+
+        let output = String::from_utf8(capture.0.lock().expect("Poisoned lock").clone())
+            .expect("Invalid UTF-8");
+
+        // The decrypted reply part was traced, but not the session key within it.
+        assert!(output.contains("decrypt_enc_kdc_rep_with_usage"));
+        assert!(output.contains("EncryptionKey { key_type: 18, .. }"));
+
+        for key in keys {
+            let key_debug = format!("{:?}", key);
+            assert!(!output.contains(key_debug.trim_matches(['[', ']'])));
+            assert!(!output.contains(&hex::encode(key)));
+            assert!(!output.contains(&hex::encode_upper(key)));
+        }
+    }
+
+    #[test]
+    fn test_as_req_accessors() {
+        // An AS-REQ from MIT kinit, without preauthentication.
+        let as_req = hex::decode("6a81b23081afa103020105a20302010aa31a3018300aa10402020096a2020400300aa10402020095a2020400a48186308183a00703050000000010a1143012a003020101a10b30091b0777696c6c69616da20b1b094b4b4443502e444556a31e301ca003020102a11530131b066b72627467741b094b4b4443502e444556a511180f32303234303431373034313534395aa70602047fbda7aea81a301802011202011102011402011302011002011702011902011a").expect("Invalid hex");
+
+        let KerberosRequest::AS(auth_req) = decode_token(&as_req).expect("Failed to decode AS-REQ")
+        else {
+            unreachable!();
+        };
+
+        assert_eq!(
+            auth_req.client_name(),
+            &Name::principal("william", "KKDCP.DEV")
+        );
+        assert_eq!(auth_req.service_name(), &Name::service_krbtgt("KKDCP.DEV"));
+        assert_eq!(auth_req.etypes(), &[18, 17, 20, 19, 16, 23, 25, 26]);
+        assert_eq!(
+            auth_req.kdc_options(),
+            KdcOptions::default().renewable_ok(true)
+        );
+        assert_eq!(auth_req.from(), None);
+        assert_eq!(
+            auth_req.until(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_713_327_349)
+        );
+        assert_eq!(auth_req.renew(), None);
+    }
+
+    #[test]
+    fn test_as_req_multiple_padata() {
+        let now = SystemClock.now();
+        let user_key = user_key();
+
+        let pa_data = PreauthData {
+            enc_timestamp: true,
+            pa_fx_cookie: Some(b"cookie".to_vec()),
+            ..Default::default()
+        };
+
+        let request = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now + Duration::from_secs(3600),
+        )
+        .pac_request(true)
+        .additional_padata(PaDataType::EncpadataReqEncPaRep as u32, Vec::new())
+        .preauth_enc_ts(
+            &pa_data,
+            now.duration_since(SystemTime::UNIX_EPOCH)
+                .expect("Time before the epoch"),
+            &user_key,
+        )
+        .and_then(|builder| builder.build(&SystemClock))
+        .expect("Failed to build request");
+
+        let bytes = encode_token(request).expect("Failed to encode request");
+
+        let KrbKdcReq::AsReq(kdc_req) = KrbKdcReq::from_der(&bytes).expect("Failed to decode")
+        else {
+            unreachable!();
+        };
+        let padata = kdc_req.padata.expect("No padata was sent");
+
+        assert_eq!(
+            padata.iter().map(|pa| pa.padata_type).collect::<Vec<_>>(),
+            vec![
+                PaDataType::PaFxCookie as u32,
+                PaDataType::PaEncTimestamp as u32,
+                PaDataType::PaPacRequest as u32,
+                PaDataType::EncpadataReqEncPaRep as u32,
+            ]
+        );
+        assert_eq!(padata[0].padata_value.as_bytes(), b"cookie");
+        assert_eq!(
+            padata[2].padata_value.as_bytes(),
+            &[0x30, 0x05, 0xa0, 0x03, 0x01, 0x01, 0xff]
+        );
+        assert!(padata[3].padata_value.as_bytes().is_empty());
+
+        let KerberosRequest::AS(auth_req) = decode_token(&bytes).expect("Failed to decode AS-REQ")
+        else {
+            unreachable!();
+        };
+        assert_eq!(auth_req.pac_request(), Some(true));
+
+        // The padata survives being decoded and sent on again.
+        let bytes = encode_token(KerberosRequest::AS(auth_req)).expect("Failed to encode");
+        let KrbKdcReq::AsReq(kdc_req) = KrbKdcReq::from_der(&bytes).expect("Failed to decode")
+        else {
+            unreachable!();
+        };
+        assert_eq!(kdc_req.padata, Some(padata));
+    }
+
+    #[test]
+    fn test_as_req_with_preauth_from() {
+        let now = SystemClock.now();
+
+        let KerberosRequest::AS(as_req) = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now + Duration::from_secs(3600),
+        )
+        .nonce(0x1234_5678)
+        .pac_request(false)
+        .build(&SystemClock)
+        .expect("Failed to build request") else {
+            unreachable!();
+        };
+
+        let KerberosReply::PA(pa_rep) = KerberosReply::preauth_required(
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now,
+            "EXAMPLE.COMtestuser",
+            &[EncryptionType::AES256_CTS_HMAC_SHA1_96],
+            b"cookie".to_vec(),
+        ) else {
+            unreachable!();
+        };
+
+        let (request, user_key) = as_req
+            .with_preauth_from(&pa_rep, "password", &SystemClock)
+            .expect("Failed to build follow-up request");
+
+        let KerberosRequest::AS(follow_up) = request.clone() else {
+            unreachable!();
+        };
+        assert_eq!(follow_up.nonce, as_req.nonce);
+        assert_eq!(follow_up.client_name(), as_req.client_name());
+        assert_eq!(follow_up.service_name(), as_req.service_name());
+        assert_eq!(follow_up.until(), as_req.until());
+        assert_eq!(follow_up.renew(), as_req.renew());
+        assert_eq!(follow_up.etypes(), as_req.etypes());
+        assert_eq!(follow_up.pac_request(), Some(false));
+
+        follow_up
+            .preauth
+            .enc_timestamp()
+            .expect("No timestamp was added")
+            .decrypt_pa_enc_timestamp_checked(&user_key, SystemClock.now())
+            .expect("Failed to decrypt timestamp");
+
+        let bytes = encode_token(request).expect("Failed to encode request");
+        let KrbKdcReq::AsReq(kdc_req) = KrbKdcReq::from_der(&bytes).expect("Failed to decode")
+        else {
+            unreachable!();
+        };
+        let padata = kdc_req.padata.expect("No padata was sent");
+        assert_eq!(padata[0].padata_type, PaDataType::PaFxCookie as u32);
+        assert_eq!(padata[0].padata_value.as_bytes(), b"cookie");
+    }
+
+    #[test]
+    fn test_as_req_echoes_as_freshness() {
+        let now = SystemClock.now();
+
+        let request = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now + Duration::from_secs(3600),
+        )
+        .request_as_freshness()
+        .build(&SystemClock)
+        .expect("Failed to build request");
+
+        // The first request asks for a token with an empty PA-AS-FRESHNESS.
+        let bytes = encode_token(request).expect("Failed to encode request");
+        let KerberosRequest::AS(as_req) = decode_token(&bytes).expect("Failed to decode AS-REQ")
+        else {
+            unreachable!();
+        };
+        assert_eq!(as_req.preauth.as_freshness(), Some([].as_slice()));
+
+        let token = b"\x00freshness token\xff".to_vec();
+        let user_key = user_key();
+
+        let reply = KerberosReply::preauth_builder(Name::service_krbtgt("EXAMPLE.COM"), now)
+            .set_key_params(&user_key)
+            .set_pa_as_freshness(Some(token.clone()))
+            .build();
+
+        let bytes = encode_token(reply).expect("Failed to encode reply");
+        let KerberosReply::PA(pa_rep) = decode_token(&bytes).expect("Failed to decode reply")
+        else {
+            unreachable!();
+        };
+        assert_eq!(pa_rep.pa_data.as_freshness(), Some(token.as_slice()));
+
+        let (request, _user_key) = as_req
+            .with_preauth_from(&pa_rep, "password", &SystemClock)
+            .expect("Failed to build follow-up request");
+
+        let bytes = encode_token(request).expect("Failed to encode request");
+        let KrbKdcReq::AsReq(kdc_req) = KrbKdcReq::from_der(&bytes).expect("Failed to decode")
+        else {
+            unreachable!();
+        };
+        let padata = kdc_req.padata.expect("No padata was sent");
+        assert_eq!(
+            padata.iter().map(|pa| pa.padata_type).collect::<Vec<_>>(),
+            vec![
+                PaDataType::PadataAsFreshness as u32,
+                PaDataType::PaEncTimestamp as u32,
+            ]
+        );
+        assert_eq!(padata[0].padata_value.as_bytes(), token.as_slice());
+    }
+
+    #[test]
+    fn test_tgs_req_additional_ticket() {
+        let session_key = session_key();
+        let client = Name::principal("service", "EXAMPLE.COM");
+        let tgt = ticket(Name::service_krbtgt("EXAMPLE.COM"));
+        let evidence_ticket = || ticket(Name::principal("service", "EXAMPLE.COM"));
+
+        let build_tgs = || {
+            KerberosRequest::build_tgs(
+                Name::principal("backend", "EXAMPLE.COM"),
+                SystemClock.now() + Duration::from_secs(3600),
+            )
+        };
+
+        let request = build_tgs()
+            .kdc_options(KdcOptions::default().cname_in_addl_tkt(true))
+            .additional_ticket(evidence_ticket())
+            .build(&client, &tgt, &session_key, &SystemClock)
+            .expect("Failed to build TGS-REQ");
+
+        let KrbKdcReq::TgsReq(kdc_req) = request.try_into().expect("Failed to encode TGS-REQ")
+        else {
+            unreachable!();
+        };
+
+        // cname-in-addl-tkt is bit 14
+        assert_eq!(
+            kdc_req.req_body.kdc_options.raw_bytes(),
+            &[0x00, 0x02, 0x00, 0x00]
+        );
+        let additional_tickets = kdc_req
+            .req_body
+            .additional_tickets
+            .expect("No additional tickets");
+        assert_eq!(additional_tickets.len(), 1);
+        assert_eq!(
+            Ticket::try_from(
+                additional_tickets
+                    .into_iter()
+                    .next()
+                    .expect("No additional ticket")
+            )
+            .expect("Invalid ticket")
+            .service(),
+            &Name::principal("service", "EXAMPLE.COM")
+        );
+
+        // The additional ticket must come with an option that uses it, and the reverse.
+        assert!(matches!(
+            build_tgs().additional_ticket(evidence_ticket()).build(
+                &client,
+                &tgt,
+                &session_key,
+                &SystemClock
+            ),
+            Err(KrbError::InvalidAdditionalTickets)
+        ));
+        assert!(matches!(
+            build_tgs()
+                .kdc_options(KdcOptions::default().enc_tkt_in_skey(true))
+                .build(&client, &tgt, &session_key, &SystemClock),
+            Err(KrbError::InvalidAdditionalTickets)
+        ));
+    }
+
+    #[test]
+    fn test_s4u2self_request() {
+        let session_key = session_key();
+        let service = Name::principal("service", "EXAMPLE.COM");
+        let tgt = ticket(Name::service_krbtgt("EXAMPLE.COM"));
+
+        let request = KerberosRequest::build_s4u2self(
+            service.clone(),
+            Name::principal("testuser", "USERS.EXAMPLE.COM"),
+            SystemClock.now() + Duration::from_secs(3600),
+        )
+        .build(&service, &tgt, &session_key, &SystemClock)
+        .expect("Failed to build S4U2Self request");
+
+        let KrbKdcReq::TgsReq(kdc_req) = request.try_into().expect("Failed to encode TGS-REQ")
+        else {
+            unreachable!();
+        };
+
+        let pa_for_user = kdc_req
+            .padata
+            .as_ref()
+            .expect("No padata")
+            .iter()
+            .find(|pa| pa.padata_type == PaDataType::PaForUser as u32)
+            .map(|pa| {
+                PaForUser::from_der(pa.padata_value.as_bytes())
+                    .expect("Failed to decode PA-FOR-USER")
+            })
+            .expect("No PA-FOR-USER");
+
+        assert_eq!(pa_for_user.user_name.name_type, 1);
+        assert_eq!(pa_for_user.user_name.name_string.len(), 1);
+        assert_eq!(pa_for_user.user_name.name_string[0].as_str(), "testuser");
+        assert_eq!(pa_for_user.user_realm.as_str(), "USERS.EXAMPLE.COM");
+        assert_eq!(pa_for_user.auth_package.as_str(), "Kerberos");
+
+        // The KDC sees the same user, with a checksum keyed by the TGT session key.
+        let KerberosRequest::TGS(tgs_req) =
+            KerberosRequest::try_from(kdc_req).expect("Failed to decode TGS-REQ")
+        else {
+            unreachable!();
+        };
+        let for_user = tgs_req.for_user.expect("No PA-FOR-USER");
+        assert_eq!(
+            for_user.user(),
+            &Name::principal("testuser", "USERS.EXAMPLE.COM")
+        );
+        assert!(for_user.verify(&session_key).is_ok());
+        assert!(matches!(
+            for_user.verify(&SessionKey::Aes256CtsHmacSha196 { k: [0xbb; 32] }),
+            Err(KrbError::MessageAuthenticationFailed)
+        ));
+    }
+
+    #[test]
+    fn test_tgs_req_enc_authorization_data() {
+        use crate::proto::AuthorizationData;
+
+        let session_key = session_key();
+        let client = Name::principal("testuser", "EXAMPLE.COM");
+        let tgt = ticket(Name::service_krbtgt("EXAMPLE.COM"));
+
+        // AD-IF-RELEVANT wrapping an empty sequence of elements.
+        let authorization_data = vec![AuthorizationData::new(1, vec![0x30, 0x00])];
+
+        let request = KerberosRequest::build_tgs(
+            Name::principal("service", "EXAMPLE.COM"),
+            SystemClock.now() + Duration::from_secs(3600),
+        )
+        .authorization_data(authorization_data.clone())
+        .build(&client, &tgt, &session_key, &SystemClock)
+        .expect("Failed to build TGS-REQ");
+
+        let KrbKdcReq::TgsReq(kdc_req) = request.try_into().expect("Failed to encode TGS-REQ")
+        else {
+            unreachable!();
+        };
+        assert!(kdc_req.req_body.enc_authorization_data.is_some());
+
+        let KerberosRequest::TGS(tgs_req) =
+            KerberosRequest::try_from(kdc_req).expect("Failed to decode TGS-REQ")
+        else {
+            unreachable!();
+        };
+        assert_eq!(
+            tgs_req
+                .authorization_data(&session_key)
+                .expect("Failed to decrypt authorization data"),
+            authorization_data
+        );
+        assert!(tgs_req
+            .authorization_data(&SessionKey::Aes256CtsHmacSha196 { k: [0xbb; 32] })
+            .is_err());
+    }
+
+    #[test]
+    fn test_s4u2proxy_request() {
+        let session_key = session_key();
+        let service = Name::principal("service", "EXAMPLE.COM");
+        let tgt = ticket(Name::service_krbtgt("EXAMPLE.COM"));
+        // The ticket that the user issued to the delegating service.
+        let user_ticket = ticket(service.clone());
+
+        let request = KerberosRequest::build_s4u2proxy(
+            Name::principal("backend", "EXAMPLE.COM"),
+            user_ticket,
+            SystemClock.now() + Duration::from_secs(3600),
+        )
+        .build(&service, &tgt, &session_key, &SystemClock)
+        .expect("Failed to build S4U2Proxy request");
+
+        let KrbKdcReq::TgsReq(kdc_req) = request.try_into().expect("Failed to encode TGS-REQ")
+        else {
+            unreachable!();
+        };
+
+        // renewable is bit 8, cname-in-addl-tkt is bit 14
+        assert_eq!(
+            kdc_req.req_body.kdc_options.raw_bytes(),
+            &[0x00, 0x82, 0x00, 0x00]
+        );
+
+        let additional_tickets = kdc_req
+            .req_body
+            .additional_tickets
+            .expect("No additional tickets");
+        assert_eq!(additional_tickets.len(), 1);
+        assert_eq!(
+            Ticket::try_from(
+                additional_tickets
+                    .into_iter()
+                    .next()
+                    .expect("No additional ticket")
+            )
+            .expect("Invalid ticket")
+            .service(),
+            &service
+        );
+    }
+
+    #[test]
+    fn test_user_to_user() {
+        let session_key = session_key();
+        let client = Name::principal("testuser", "EXAMPLE.COM");
+        let tgt = ticket(Name::service_krbtgt("EXAMPLE.COM"));
+        let acceptor_tgt = ticket(Name::principal("acceptor", "EXAMPLE.COM"));
+
+        let request = KerberosRequest::build_tgs(
+            Name::principal("acceptor", "EXAMPLE.COM"),
+            SystemClock.now() + Duration::from_secs(3600),
+        )
+        .user_to_user(acceptor_tgt)
+        .build(&client, &tgt, &session_key, &SystemClock)
+        .expect("Failed to build TGS-REQ");
+
+        let KrbKdcReq::TgsReq(kdc_req) = request.try_into().expect("Failed to encode TGS-REQ")
+        else {
+            unreachable!();
+        };
+
+        // renewable and enc-tkt-in-skey
+        assert_eq!(
+            kdc_req.req_body.kdc_options.raw_bytes(),
+            &[0x00, 0x80, 0x00, 0x08]
+        );
+        assert_eq!(
+            kdc_req.req_body.additional_tickets.as_ref().map(Vec::len),
+            Some(1)
+        );
+
+        let KerberosRequest::TGS(request) =
+            KerberosRequest::try_from(kdc_req).expect("Failed to decode TGS-REQ")
+        else {
+            unreachable!();
+        };
+        assert_eq!(
+            request.kdc_options,
+            KdcOptions::default().renewable(true).enc_tkt_in_skey(true)
+        );
+        assert_eq!(request.additional_tickets.len(), 1);
+        assert_eq!(
+            request.additional_tickets[0].service(),
+            &Name::principal("acceptor", "EXAMPLE.COM")
+        );
+
+        // The acceptor decrypts the ticket with its TGT session key.
+        let (cname, crealm) = (&client).try_into().expect("Invalid name");
+        let enc_ticket_part = TaggedEncTicketPart(EncTicketPart {
+            flags: FlagSet::<TicketFlags>::new(0).expect("Invalid ticket flags"),
+            key: EncryptionKey {
+                key_type: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+                key_value: OctetString::new([0x55; 32]).expect("Invalid OCTET STRING"),
+            },
+            crealm,
+            cname,
+            transited: TransitedEncoding {
+                tr_type: 1,
+                contents: OctetString::new(b"").expect("Invalid OCTET STRING"),
+            },
+            auth_time: KerberosTime::from_unix_duration(Duration::from_secs(1_000))
+                .expect("Invalid KerberosTime"),
+            start_time: None,
+            end_time: KerberosTime::from_unix_duration(Duration::from_secs(2_000))
+                .expect("Invalid KerberosTime"),
+            renew_till: None,
+            client_addresses: None,
+            authorization_data: None,
+        })
+        .to_der()
+        .expect("Failed to encode");
+
+        let u2u_ticket = Ticket {
+            tkt_vno: 5,
+            service: Name::principal("acceptor", "EXAMPLE.COM"),
+            enc_part: session_key
+                .encrypt_data(&enc_ticket_part, 2)
+                .expect("Failed to encrypt"),
+            der: None,
+        };
+
+        let ticket_part = u2u_ticket
+            .decrypt_with_session_key(&session_key)
+            .expect("Failed to decrypt ticket");
+        assert_eq!(ticket_part.client(), &client);
+
+        let ap_req = ApRequest::builder(u2u_ticket)
+            .use_session_key(true)
+            .build(&client, ticket_part.session_key(), &SystemClock)
+            .and_then(|ap_req| ap_req.to_der())
+            .expect("Failed to build AP-REQ");
+
+        let ap_req = ApRequest::from_der(&ap_req).expect("Failed to decode AP-REQ");
+        assert!(ap_req.use_session_key());
+
+        // A ticket in the service's long term key, which names its kvno, can't be used
+        // with use-session-key.
+        let result = ApRequest::builder(ticket(Name::principal("acceptor", "EXAMPLE.COM")))
+            .ap_options(ApOptions::default().use_session_key(true))
+            .build(&client, ticket_part.session_key(), &SystemClock);
+        assert!(matches!(result, Err(KrbError::InvalidApOptions)));
+    }
+}
@@ -0,0 +1,111 @@
+use super::{make_checksum, verify_checksum, Name, SessionKey};
+use crate::asn1::{
+    checksum::Checksum, kerberos_string::KerberosString, pa_for_user::PaForUser,
+    principal_name::PrincipalName, realm::Realm, Ia5String, OctetString,
+};
+use crate::error::KrbError;
+
+// MS-SFU 2.2.1 The checksum is keyed with the session key of the ticket granting
+// ticket, with the key usage KERB_NON_KERB_CKSUM_SALT.
+const PA_FOR_USER_KEY_USAGE: i32 = 17;
+const AUTH_PACKAGE: &str = "Kerberos";
+
+/// The user that a service requests a ticket to itself on behalf of, with the
+/// PA-FOR-USER of an S4U2Self (protocol transition) request.
+#[derive(Debug, Clone)]
+pub struct ForUser {
+    user: Name,
+    checksum_type: i32,
+    checksum: Vec<u8>,
+}
+
+impl ForUser {
+    pub(crate) fn new(user: Name, session_key: &SessionKey) -> Result<Self, KrbError> {
+        let (user_name, user_realm) = (&user).try_into()?;
+        let data = checksum_data(&user_name, &user_realm);
+        let (checksum_type, checksum) = make_checksum(session_key, PA_FOR_USER_KEY_USAGE, &data)?;
+
+        Ok(ForUser {
+            user,
+            checksum_type,
+            checksum,
+        })
+    }
+
+    pub fn user(&self) -> &Name {
+        &self.user
+    }
+
+    /// Verify the checksum with the session key of the ticket granting ticket that
+    /// the request was made with.
+    pub fn verify(&self, session_key: &SessionKey) -> Result<(), KrbError> {
+        let (user_name, user_realm) = (&self.user).try_into()?;
+        let data = checksum_data(&user_name, &user_realm);
+
+        verify_checksum(
+            session_key,
+            PA_FOR_USER_KEY_USAGE,
+            &data,
+            self.checksum_type,
+            &self.checksum,
+        )
+    }
+}
+
+// MS-SFU 2.2.1 The checksum is over the name type as a little endian 32 bit integer,
+// followed by each name component, the realm and the auth package, without separators.
+fn checksum_data(user_name: &PrincipalName, user_realm: &Realm) -> Vec<u8> {
+    let mut data = user_name.name_type.to_le_bytes().to_vec();
+    for component in &user_name.name_string {
+        data.extend_from_slice(component.as_str().as_bytes());
+    }
+    data.extend_from_slice(user_realm.as_str().as_bytes());
+    data.extend_from_slice(AUTH_PACKAGE.as_bytes());
+    data
+}
+
+impl TryFrom<&ForUser> for PaForUser {
+    type Error = KrbError;
+
+    fn try_from(for_user: &ForUser) -> Result<Self, Self::Error> {
+        let (user_name, user_realm) = (&for_user.user).try_into()?;
+        let checksum = OctetString::new(for_user.checksum.clone())
+            .map_err(|_| KrbError::DerEncodeOctetString)?;
+        let auth_package = Ia5String::new(AUTH_PACKAGE)
+            .map(KerberosString)
+            .map_err(|_| KrbError::DerEncodePaForUser)?;
+
+        Ok(PaForUser {
+            user_name,
+            user_realm,
+            cksum: Checksum {
+                checksum_type: for_user.checksum_type,
+                checksum,
+            },
+            auth_package,
+        })
+    }
+}
+
+impl TryFrom<PaForUser> for ForUser {
+    type Error = KrbError;
+
+    fn try_from(pa_for_user: PaForUser) -> Result<Self, Self::Error> {
+        // The auth package is case insensitive.
+        if !pa_for_user
+            .auth_package
+            .as_str()
+            .eq_ignore_ascii_case(AUTH_PACKAGE)
+        {
+            return Err(KrbError::DerDecodePaForUser);
+        }
+
+        let user = (pa_for_user.user_name, pa_for_user.user_realm).try_into()?;
+
+        Ok(ForUser {
+            user,
+            checksum_type: pa_for_user.cksum.checksum_type,
+            checksum: pa_for_user.cksum.checksum.as_bytes().to_vec(),
+        })
+    }
+}
@@ -0,0 +1,142 @@
+use crate::asn1::constants::pa_data_types::PaDataType;
+use crate::asn1::pa_spake::{PaSpake, SpakeChallenge as KdcSpakeChallenge, SpakeSupport};
+use crate::error::KrbError;
+use der::Encode;
+
+/// The groups over which the SPAKE preauthentication of
+/// draft-ietf-kitten-krb-spake-preauth can be performed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpakeGroup {
+    Edwards25519 = 1,
+    P256 = 2,
+    P384 = 3,
+    P521 = 4,
+}
+
+impl TryFrom<i32> for SpakeGroup {
+    type Error = KrbError;
+
+    fn try_from(group: i32) -> Result<Self, Self::Error> {
+        match group {
+            1 => Ok(SpakeGroup::Edwards25519),
+            2 => Ok(SpakeGroup::P256),
+            3 => Ok(SpakeGroup::P384),
+            4 => Ok(SpakeGroup::P521),
+            _ => Err(KrbError::InvalidEnumValue(
+                std::any::type_name::<SpakeGroup>().to_string(),
+                group,
+            )),
+        }
+    }
+}
+
+/// The groups we offer to the KDC, in order of preference.
+pub(crate) fn supported_spake_groups() -> &'static [SpakeGroup] {
+    &[SpakeGroup::Edwards25519]
+}
+
+/// The challenge of a KDC that has chosen the group for a SPAKE exchange.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpakeChallenge {
+    group: i32,
+    pubkey: Vec<u8>,
+    factor_types: Vec<i32>,
+}
+
+impl SpakeChallenge {
+    /// The group the KDC chose, if it is one of those we support.
+    pub fn group(&self) -> Option<SpakeGroup> {
+        SpakeGroup::try_from(self.group)
+            .ok()
+            .filter(|group| supported_spake_groups().contains(group))
+    }
+
+    /// The public key of the KDC, encoded as defined for its group.
+    pub fn pubkey(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    /// The types of the second factors the KDC will accept.
+    pub fn factor_types(&self) -> &[i32] {
+        &self.factor_types
+    }
+}
+
+impl From<KdcSpakeChallenge> for SpakeChallenge {
+    fn from(challenge: KdcSpakeChallenge) -> Self {
+        SpakeChallenge {
+            group: challenge.group,
+            pubkey: challenge.pubkey.into_bytes(),
+            factor_types: challenge
+                .factors
+                .iter()
+                .map(|factor| factor.factor_type)
+                .collect(),
+        }
+    }
+}
+
+/// The PA-SPAKE support message, which tells the KDC the groups we support so that it
+/// can choose one for its challenge.
+pub(crate) fn encode_spake_support() -> Result<(u32, Vec<u8>), KrbError> {
+    let groups = supported_spake_groups()
+        .iter()
+        .map(|group| *group as i32)
+        .collect();
+
+    PaSpake::Support(SpakeSupport { groups })
+        .to_der()
+        .map(|padata_value| (PaDataType::PadataSpake as u32, padata_value))
+        .map_err(|_| KrbError::DerEncodePaSpake)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asn1::constants::PaDataType;
+    use crate::asn1::pa_data::PaData;
+    use crate::asn1::OctetString;
+    use crate::proto::{PreauthData, SpakeGroup};
+
+    #[test]
+    fn test_preauth_spake_challenge() {
+        // A SPAKEChallenge for edwards25519 with SF-NONE as the only second factor.
+        let pubkey = [0x42; 32];
+        let mut challenge = vec![
+            0xa1, 0x36, 0x30, 0x34, 0xa0, 0x03, 0x02, 0x01, 0x01, 0xa1, 0x22, 0x04, 0x20,
+        ];
+        challenge.extend_from_slice(&pubkey);
+        challenge.extend_from_slice(&[
+            0xa2, 0x09, 0x30, 0x07, 0x30, 0x05, 0xa0, 0x03, 0x02, 0x01, 0x01,
+        ]);
+
+        let pavec = vec![
+            PaData {
+                padata_type: PaDataType::PadataSpake as u32,
+                padata_value: OctetString::new(challenge).expect("Invalid OCTET STRING"),
+            },
+            PaData {
+                padata_type: PaDataType::PaEncTimestamp as u32,
+                padata_value: OctetString::new([]).expect("Invalid OCTET STRING"),
+            },
+        ];
+
+        let pa_data = PreauthData::try_from(pavec).expect("Failed to parse padata");
+        assert!(pa_data.spake_available());
+        assert!(pa_data.enc_timestamp);
+
+        let challenge = pa_data.spake_challenge().expect("No SPAKE challenge");
+        assert_eq!(challenge.group(), Some(SpakeGroup::Edwards25519));
+        assert_eq!(challenge.pubkey(), &pubkey);
+        assert_eq!(challenge.factor_types(), &[1]);
+
+        // SPAKE is first offered without a challenge.
+        let pavec = vec![PaData {
+            padata_type: PaDataType::PadataSpake as u32,
+            padata_value: OctetString::new([]).expect("Invalid OCTET STRING"),
+        }];
+
+        let pa_data = PreauthData::try_from(pavec).expect("Failed to parse padata");
+        assert!(pa_data.spake_available());
+        assert!(pa_data.spake_challenge().is_none());
+    }
+}
@@ -0,0 +1,208 @@
+use super::{ApRequest, KerberosReply, KerberosRequest};
+use crate::asn1::{krb_kdc_rep::KrbKdcRep, krb_kdc_req::KrbKdcReq};
+use crate::error::KrbError;
+use der::{Decode, Encode, Reader, SliceReader};
+use tracing::instrument;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// A Kerberos message that can be carried as a bare DER token, without the record
+/// marking of the TCP transport, as LDAP SASL GSSAPI or HTTP Negotiate do.
+pub trait KerberosToken: private::Sealed + Sized {
+    fn encode_token(self) -> Result<Vec<u8>, KrbError>;
+
+    fn decode_token(bytes: &[u8]) -> Result<Self, KrbError>;
+}
+
+/// Encode `msg` as a bare DER token.
+#[instrument(level = "trace", skip_all)]
+pub fn encode_token<T: KerberosToken>(msg: T) -> Result<Vec<u8>, KrbError> {
+    msg.encode_token()
+}
+
+/// Decode a bare DER token, as produced by [encode_token].
+#[instrument(level = "trace", skip_all)]
+pub fn decode_token<T: KerberosToken>(bytes: &[u8]) -> Result<T, KrbError> {
+    T::decode_token(bytes)
+}
+
+/// Decode each of the tokens in `buf`, as when messages have been captured back to
+/// back. Iteration stops after the first error, since the start of the next message
+/// can't be found.
+pub fn decode_all<T: KerberosToken>(buf: &[u8]) -> impl Iterator<Item = Result<T, KrbError>> + '_ {
+    let mut reader = SliceReader::new(buf).map_err(|_| KrbError::InsufficientData);
+    let mut failed = false;
+
+    std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+
+        let result = match &mut reader {
+            Ok(reader) if reader.is_finished() => return None,
+            Ok(reader) => reader
+                .tlv_bytes()
+                .map_err(|_| KrbError::InsufficientData)
+                .and_then(T::decode_token),
+            Err(err) => Err(err.clone()),
+        };
+
+        failed = result.is_err();
+        Some(result)
+    })
+}
+
+/// Encode `msg` as a base64 DER token, as used by SPNEGO and HTTP Negotiate.
+#[cfg(feature = "base64")]
+pub fn encode_token_base64<T: KerberosToken>(msg: T) -> Result<String, KrbError> {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    msg.encode_token().map(|bytes| STANDARD.encode(bytes))
+}
+
+/// Decode a base64 DER token, as produced by [encode_token_base64].
+#[cfg(feature = "base64")]
+pub fn decode_token_base64<T: KerberosToken>(token: &str) -> Result<T, KrbError> {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    let bytes = STANDARD
+        .decode(token.trim())
+        .map_err(|_| KrbError::MalformedBase64)?;
+    T::decode_token(&bytes)
+}
+
+impl private::Sealed for KerberosRequest {}
+
+impl KerberosToken for KerberosRequest {
+    fn encode_token(self) -> Result<Vec<u8>, KrbError> {
+        let krb_kdc_req: KrbKdcReq = self.try_into()?;
+        krb_kdc_req.to_der().map_err(|_| KrbError::DerEncodeKdcReq)
+    }
+
+    fn decode_token(bytes: &[u8]) -> Result<Self, KrbError> {
+        let krb_kdc_req = KrbKdcReq::from_der(bytes).map_err(|_| KrbError::DerDecodeKdcReq)?;
+        KerberosRequest::try_from(krb_kdc_req)
+    }
+}
+
+impl private::Sealed for KerberosReply {}
+
+impl KerberosToken for KerberosReply {
+    fn encode_token(self) -> Result<Vec<u8>, KrbError> {
+        let krb_kdc_rep: KrbKdcRep = self.try_into()?;
+        krb_kdc_rep.to_der().map_err(|_| KrbError::DerEncodeKdcRep)
+    }
+
+    fn decode_token(bytes: &[u8]) -> Result<Self, KrbError> {
+        KerberosReply::from_der(bytes)
+    }
+}
+
+impl private::Sealed for ApRequest {}
+
+impl KerberosToken for ApRequest {
+    fn encode_token(self) -> Result<Vec<u8>, KrbError> {
+        self.to_der()
+    }
+
+    fn decode_token(bytes: &[u8]) -> Result<Self, KrbError> {
+        ApRequest::from_der(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asn1::constants::KrbErrorCode;
+    use crate::asn1::krb_kdc_req::KrbKdcReq;
+    use crate::clock::{Clock, SystemClock};
+    use crate::error::KrbError;
+    use crate::proto::{
+        decode_all, decode_token, encode_token, KerberosReply, KerberosRequest, Name,
+    };
+    use crate::test_helpers::{primary_key, user_key};
+    use der::Decode;
+    use std::time::Duration;
+
+    #[test]
+    fn test_as_req_token() {
+        let now = SystemClock.now();
+        let as_req = KerberosRequest::build_as(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now + Duration::from_secs(3600),
+        )
+        .build(&SystemClock)
+        .expect("Failed to build request");
+
+        let KerberosRequest::AS(expected) = as_req.clone() else {
+            unreachable!();
+        };
+
+        // A bare DER token, without the record mark of the TCP transport.
+        let token = encode_token(as_req).expect("Failed to encode token");
+        assert!(KrbKdcReq::from_der(&token).is_ok());
+
+        let KerberosRequest::AS(as_req) =
+            decode_token::<KerberosRequest>(&token).expect("Failed to decode token")
+        else {
+            unreachable!();
+        };
+        assert_eq!(as_req.nonce, expected.nonce);
+        assert_eq!(as_req.client_name, expected.client_name);
+        assert_eq!(as_req.service_name, expected.service_name);
+        assert_eq!(as_req.realm, expected.realm);
+
+        assert!(decode_token::<KerberosRequest>(&token[1..]).is_err());
+        assert!(decode_token::<KerberosReply>(&token).is_err());
+
+        #[cfg(feature = "base64")]
+        {
+            let token = crate::proto::encode_token_base64(KerberosRequest::AS(as_req))
+                .expect("Failed to encode token");
+            assert!(crate::proto::decode_token_base64::<KerberosRequest>(&token).is_ok());
+            assert!(matches!(
+                crate::proto::decode_token_base64::<KerberosRequest>("not base64!"),
+                Err(KrbError::MalformedBase64)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decode_all_replies() {
+        let now = SystemClock.now();
+        let user_key = user_key();
+        let primary_key = primary_key();
+
+        let as_rep = KerberosReply::authentication_builder(
+            Name::principal("testuser", "EXAMPLE.COM"),
+            Name::service_krbtgt("EXAMPLE.COM"),
+            now,
+            1,
+        )
+        .build(&user_key, &primary_key)
+        .expect("Failed to build reply");
+        let err_rep = KerberosReply::error_preauth_failed(Name::service_krbtgt("EXAMPLE.COM"), now);
+
+        let mut buf = encode_token(as_rep).expect("Failed to encode reply");
+        buf.extend(encode_token(err_rep).expect("Failed to encode reply"));
+
+        let mut replies = decode_all::<KerberosReply>(&buf);
+        assert!(matches!(replies.next(), Some(Ok(KerberosReply::AS(_)))));
+        let Some(Ok(KerberosReply::ERR(err_rep))) = replies.next() else {
+            unreachable!();
+        };
+        assert_eq!(err_rep.code(), KrbErrorCode::KdcErrPreauthFailed);
+        assert!(replies.next().is_none());
+
+        // A truncated message ends the iteration.
+        let mut replies = decode_all::<KerberosReply>(&buf[..buf.len() - 1]);
+        assert!(matches!(replies.next(), Some(Ok(KerberosReply::AS(_)))));
+        assert!(matches!(
+            replies.next(),
+            Some(Err(KrbError::InsufficientData))
+        ));
+        assert!(replies.next().is_none());
+    }
+}
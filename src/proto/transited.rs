@@ -0,0 +1,197 @@
+use crate::asn1::{transited_encoding::TransitedEncoding as KdcTransitedEncoding, OctetString};
+use crate::error::KrbError;
+
+// RFC 4120 3.3.3.2 The only registered transited encoding type.
+const DOMAIN_X500_COMPRESS: i32 = 1;
+
+/// The realms that were traversed on the way to the realm that issued a ticket, not
+/// including the realm of the client or the issuing realm.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TransitedEncoding {
+    realms: Vec<String>,
+}
+
+impl TransitedEncoding {
+    pub fn new(realms: Vec<String>) -> Self {
+        TransitedEncoding { realms }
+    }
+
+    /// The transited realms, in the order they were traversed.
+    pub fn realms(&self) -> &[String] {
+        &self.realms
+    }
+
+    /// Encode the realms as DOMAIN-X500-COMPRESS. A domain style realm that ends with
+    /// the previous realm is written as its prefix followed by a '.', and an X.500
+    /// style realm that starts with the previous realm is written as the remainder.
+    pub fn compress(&self) -> String {
+        let mut encoded = Vec::with_capacity(self.realms.len());
+        let mut previous: Option<&str> = None;
+
+        for realm in &self.realms {
+            let component = match previous {
+                Some(previous) if realm.starts_with('/') => {
+                    match realm
+                        .strip_prefix(previous)
+                        .filter(|rest| rest.starts_with('/'))
+                    {
+                        Some(rest) => escape(rest),
+                        // An X.500 name that doesn't extend the previous one is marked
+                        // as absolute with a leading space.
+                        None => format!(" {}", escape(realm)),
+                    }
+                }
+                Some(previous) => match realm
+                    .strip_suffix(previous)
+                    .and_then(|prefix| prefix.strip_suffix('.'))
+                    .filter(|prefix| !prefix.is_empty())
+                {
+                    Some(prefix) => format!("{}.", escape(prefix)),
+                    None => escape(realm),
+                },
+                None => escape(realm),
+            };
+
+            encoded.push(component);
+            previous = Some(realm);
+        }
+
+        encoded.join(",")
+    }
+
+    /// Decode DOMAIN-X500-COMPRESS encoded realms.
+    pub fn decompress(contents: &str) -> Result<Self, KrbError> {
+        if contents.is_empty() {
+            return Ok(TransitedEncoding::default());
+        }
+
+        let mut realms: Vec<String> = Vec::new();
+
+        for component in split_components(contents) {
+            // A null subfield means that every realm between its neighbours was
+            // traversed, which can't be expressed as a list of realms.
+            if component.is_empty() {
+                return Err(KrbError::MalformedTransitedEncoding);
+            }
+
+            let realm = match (realms.last(), component.strip_prefix(' ')) {
+                (_, Some(absolute)) => absolute.to_string(),
+                (Some(previous), None) if component.starts_with('/') => {
+                    format!("{}{}", previous, component)
+                }
+                (Some(previous), None) if component.ends_with('.') => {
+                    format!("{}{}", component, previous)
+                }
+                (_, None) => component,
+            };
+
+            realms.push(realm);
+        }
+
+        Ok(TransitedEncoding { realms })
+    }
+}
+
+fn escape(realm: &str) -> String {
+    realm.replace('\\', "\\\\").replace(',', "\\,")
+}
+
+// Split on the commas that are not escaped with a backslash, removing the escapes.
+fn split_components(contents: &str) -> Vec<String> {
+    let mut components = Vec::new();
+    let mut component = String::new();
+    let mut chars = contents.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => component.extend(chars.next()),
+            ',' => components.push(std::mem::take(&mut component)),
+            c => component.push(c),
+        }
+    }
+    components.push(component);
+
+    components
+}
+
+impl TryFrom<KdcTransitedEncoding> for TransitedEncoding {
+    type Error = KrbError;
+
+    fn try_from(transited: KdcTransitedEncoding) -> Result<Self, Self::Error> {
+        if transited.tr_type != DOMAIN_X500_COMPRESS {
+            return Err(KrbError::UnsupportedTransitedType(transited.tr_type));
+        }
+
+        let contents = std::str::from_utf8(transited.contents.as_bytes())
+            .map_err(|_| KrbError::MalformedTransitedEncoding)?;
+
+        Self::decompress(contents)
+    }
+}
+
+impl TryFrom<&TransitedEncoding> for KdcTransitedEncoding {
+    type Error = KrbError;
+
+    fn try_from(transited: &TransitedEncoding) -> Result<Self, Self::Error> {
+        let contents = OctetString::new(transited.compress().into_bytes())
+            .map_err(|_| KrbError::DerEncodeOctetString)?;
+
+        Ok(KdcTransitedEncoding {
+            tr_type: DOMAIN_X500_COMPRESS,
+            contents,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransitedEncoding;
+    use crate::asn1::transited_encoding::TransitedEncoding as KdcTransitedEncoding;
+
+    #[test]
+    fn test_transited_round_trip() {
+        let transited = TransitedEncoding::new(vec![
+            "A.COM".to_string(),
+            "B.COM".to_string(),
+            "C.COM".to_string(),
+        ]);
+
+        let encoded = KdcTransitedEncoding::try_from(&transited).unwrap();
+        assert_eq!(encoded.tr_type, 1);
+        assert_eq!(encoded.contents.as_bytes(), b"A.COM,B.COM,C.COM");
+
+        assert_eq!(TransitedEncoding::try_from(encoded).unwrap(), transited);
+    }
+
+    #[test]
+    fn test_transited_domain_x500_compress() {
+        // The examples from RFC 4120 3.3.3.2
+        let domain = TransitedEncoding::new(vec![
+            "EDU".to_string(),
+            "MIT.EDU".to_string(),
+            "ATHENA.MIT.EDU".to_string(),
+            "WASHINGTON.EDU".to_string(),
+            "CS.WASHINGTON.EDU".to_string(),
+        ]);
+        assert_eq!(domain.compress(), "EDU,MIT.,ATHENA.,WASHINGTON.EDU,CS.");
+        assert_eq!(
+            TransitedEncoding::decompress("EDU,MIT.,ATHENA.,WASHINGTON.EDU,CS.").unwrap(),
+            domain
+        );
+
+        let x500 = TransitedEncoding::new(vec![
+            "/COM".to_string(),
+            "/COM/HP".to_string(),
+            "/COM/HP/APOLLO".to_string(),
+            "/COM/DEC".to_string(),
+        ]);
+        assert_eq!(x500.compress(), "/COM,/HP,/APOLLO, /COM/DEC");
+        assert_eq!(
+            TransitedEncoding::decompress("/COM,/HP,/APOLLO, /COM/DEC").unwrap(),
+            x500
+        );
+
+        // Every realm between EDU and MIT.EDU, which we can't list.
+        assert!(TransitedEncoding::decompress("EDU,,MIT.EDU").is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::clock::Clock;
+use crate::error::KrbError;
+use crate::proto::Name;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+/// A record of the authenticators and preauthentication timestamps that have already
+/// been accepted, so that a KDC or service can reject them when they are replayed.
+pub trait ReplayCache {
+    /// Record that `principal` authenticated with the timestamp `ctime` and `cusec`,
+    /// returning `KrbError::Replay` if it has done so before. `ctime` is in whole
+    /// seconds, as sent in an authenticator.
+    fn check_and_insert(
+        &self,
+        principal: &Name,
+        ctime: SystemTime,
+        cusec: u32,
+    ) -> Result<(), KrbError>;
+}
+
+/// A `ReplayCache` held in memory. Entries are kept for `window`, which must be at
+/// least the clock skew that is accepted, since a timestamp outside the skew is
+/// rejected before the cache is consulted.
+#[derive(Debug)]
+pub struct MemoryReplayCache {
+    window: Duration,
+    clock: Box<dyn Clock + Send + Sync>,
+    entries: Mutex<BTreeMap<(SystemTime, u32), Vec<Name>>>,
+}
+
+impl MemoryReplayCache {
+    pub fn new(window: Duration, clock: impl Clock + Send + Sync + 'static) -> Self {
+        MemoryReplayCache {
+            window,
+            clock: Box::new(clock),
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl ReplayCache for MemoryReplayCache {
+    fn check_and_insert(
+        &self,
+        principal: &Name,
+        ctime: SystemTime,
+        cusec: u32,
+    ) -> Result<(), KrbError> {
+        let cutoff = self
+            .clock
+            .now()
+            .checked_sub(self.window)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+
+        // A timestamp older than the window may have been evicted, so we can't tell if
+        // it's a replay and must assume that it is.
+        if ctime < cutoff {
+            return Err(KrbError::Replay);
+        }
+
+        // A panic can't leave the entries inconsistent, so a poisoned lock is safe to
+        // keep using.
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+        *entries = entries.split_off(&(cutoff, 0));
+
+        let principals = entries.entry((ctime, cusec)).or_default();
+        if principals.contains(principal) {
+            return Err(KrbError::Replay);
+        }
+        principals.push(principal.clone());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryReplayCache, ReplayCache};
+    use crate::error::KrbError;
+    use crate::proto::Name;
+    use crate::test_helpers::FixedClock;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_memory_replay_cache() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let cache = MemoryReplayCache::new(Duration::from_secs(300), FixedClock(now));
+        let client = Name::principal("testuser", "EXAMPLE.COM");
+
+        assert!(cache.check_and_insert(&client, now, 123).is_ok());
+        assert!(matches!(
+            cache.check_and_insert(&client, now, 123),
+            Err(KrbError::Replay)
+        ));
+
+        // The same timestamp from another client, or a new one from the same client.
+        assert!(cache
+            .check_and_insert(&Name::principal("other", "EXAMPLE.COM"), now, 123)
+            .is_ok());
+        assert!(cache.check_and_insert(&client, now, 124).is_ok());
+
+        // Older than the window, so it could have been evicted.
+        assert!(matches!(
+            cache.check_and_insert(&client, now - Duration::from_secs(301), 0),
+            Err(KrbError::Replay)
+        ));
+    }
+}
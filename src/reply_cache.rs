@@ -0,0 +1,249 @@
+use crate::asn1::constants::message_types::KrbMessageType;
+use crate::clock::Clock;
+use crate::constants::DEFAULT_REPLY_CACHE_MAX_ENTRIES;
+use crate::proto::{AuthenticationRequest, KerberosReply, Name, Preauth};
+use std::collections::VecDeque;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug)]
+struct CachedReply {
+    client: Name,
+    nonce: u32,
+    msg_type: u8,
+    // A client may send a new request with the same nonce, such as once it has been
+    // asked for preauthentication, so the preauth must match for it to be a
+    // retransmission.
+    preauth: Preauth,
+    inserted: SystemTime,
+    reply: KerberosReply,
+}
+
+/// A cache of the replies a KDC has sent, so that a retransmitted request is answered
+/// with the same reply rather than being processed again, which would otherwise see
+/// its preauthentication timestamp as a replay. Replies are keyed on the client name,
+/// nonce and message type of the request, and are kept for `ttl`. Once the cache holds
+/// its maximum number of replies, the oldest is evicted to make room for a new one.
+#[derive(Debug)]
+pub struct MemoryReplyCache {
+    ttl: Duration,
+    max_entries: usize,
+    clock: Box<dyn Clock + Send + Sync>,
+    entries: Mutex<VecDeque<CachedReply>>,
+}
+
+impl MemoryReplyCache {
+    /// A cache that holds at most [DEFAULT_REPLY_CACHE_MAX_ENTRIES] replies.
+    pub fn new(ttl: Duration, clock: impl Clock + Send + Sync + 'static) -> Self {
+        Self::with_max_entries(ttl, DEFAULT_REPLY_CACHE_MAX_ENTRIES, clock)
+    }
+
+    /// As [Self::new], but holding at most `max_entries` replies.
+    pub fn with_max_entries(
+        ttl: Duration,
+        max_entries: usize,
+        clock: impl Clock + Send + Sync + 'static,
+    ) -> Self {
+        MemoryReplyCache {
+            ttl,
+            max_entries,
+            clock: Box::new(clock),
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The reply that was sent to an identical AS-REQ, if it was sent within the ttl.
+    pub fn get_as_rep(&self, auth_req: &AuthenticationRequest) -> Option<KerberosReply> {
+        let msg_type = KrbMessageType::KrbAsReq as u8;
+        let entries = self.entries();
+
+        entries
+            .iter()
+            .find(|entry| {
+                entry.msg_type == msg_type
+                    && entry.nonce == auth_req.nonce
+                    && entry.client == auth_req.client_name
+                    && entry.preauth == auth_req.preauth
+            })
+            .map(|entry| entry.reply.clone())
+    }
+
+    /// Record the reply that was sent to an AS-REQ, replacing any reply to an earlier
+    /// request with the same nonce from the same client.
+    pub fn insert_as_rep(&self, auth_req: &AuthenticationRequest, reply: &KerberosReply) {
+        let msg_type = KrbMessageType::KrbAsReq as u8;
+        let inserted = self.clock.now();
+        let mut entries = self.entries();
+
+        entries.retain(|entry| {
+            !(entry.msg_type == msg_type
+                && entry.nonce == auth_req.nonce
+                && entry.client == auth_req.client_name)
+        });
+
+        entries.push_back(CachedReply {
+            client: auth_req.client_name.clone(),
+            nonce: auth_req.nonce,
+            msg_type,
+            preauth: auth_req.preauth.clone(),
+            inserted,
+            reply: reply.clone(),
+        });
+
+        while entries.len() > self.max_entries {
+            entries.pop_front();
+        }
+    }
+
+    // The entries that are still within the ttl, with the expired ones evicted. They
+    // are in the order they were inserted, so the oldest are at the front.
+    fn entries(&self) -> std::sync::MutexGuard<'_, VecDeque<CachedReply>> {
+        let cutoff = self
+            .clock
+            .now()
+            .checked_sub(self.ttl)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+
+        // A panic can't leave the entries inconsistent, so a poisoned lock is safe to
+        // keep using.
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+        while entries.front().is_some_and(|entry| entry.inserted < cutoff) {
+            entries.pop_front();
+        }
+
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryReplyCache;
+    use crate::clock::Clock;
+    use crate::proto::{encode_token, KerberosReply, KerberosRequest, Name, PreauthData};
+    use crate::test_helpers::{primary_key, user_key};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime};
+
+    #[derive(Debug, Clone)]
+    struct SharedClock(Arc<Mutex<SystemTime>>);
+
+    impl Clock for SharedClock {
+        fn now(&self) -> SystemTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_memory_reply_cache() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = SharedClock(Arc::new(Mutex::new(now)));
+        let cache = MemoryReplyCache::new(Duration::from_secs(30), clock.clone());
+
+        let client_name = Name::principal("testuser", "EXAMPLE.COM");
+        let service_name = Name::service_krbtgt("EXAMPLE.COM");
+        let user_key = user_key();
+        let primary_key = primary_key();
+
+        let build_as = || {
+            KerberosRequest::build_as(
+                client_name.clone(),
+                service_name.clone(),
+                now + Duration::from_secs(3600),
+            )
+            .nonce(1234)
+        };
+
+        let KerberosRequest::AS(auth_req) =
+            build_as().build(&clock).expect("Failed to build request")
+        else {
+            unreachable!();
+        };
+
+        assert!(cache.get_as_rep(&auth_req).is_none());
+
+        let reply = KerberosReply::authentication_builder(
+            client_name.clone(),
+            service_name.clone(),
+            now,
+            1234,
+        )
+        .build(&user_key, &primary_key)
+        .expect("Failed to build reply");
+        cache.insert_as_rep(&auth_req, &reply);
+
+        // The retransmission gets exactly the reply that was sent the first time.
+        let cached = cache.get_as_rep(&auth_req).expect("Reply was not cached");
+        assert_eq!(
+            encode_token(cached).unwrap(),
+            encode_token(reply.clone()).unwrap()
+        );
+
+        // The same nonce with preauth is a new request.
+        let pa_data = PreauthData {
+            enc_timestamp: true,
+            ..Default::default()
+        };
+        let KerberosRequest::AS(preauth_req) = build_as()
+            .preauth_enc_ts(&pa_data, Duration::from_secs(1_700_000_000), &user_key)
+            .and_then(|builder| builder.build(&clock))
+            .expect("Failed to build request")
+        else {
+            unreachable!();
+        };
+        assert!(cache.get_as_rep(&preauth_req).is_none());
+
+        // Another client with the same nonce.
+        let mut other_req = auth_req.clone();
+        other_req.client_name = Name::principal("other", "EXAMPLE.COM");
+        assert!(cache.get_as_rep(&other_req).is_none());
+
+        *clock.0.lock().unwrap() = now + Duration::from_secs(31);
+        assert!(cache.get_as_rep(&auth_req).is_none());
+    }
+
+    #[test]
+    fn test_memory_reply_cache_max_entries() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = SharedClock(Arc::new(Mutex::new(now)));
+        let cache = MemoryReplyCache::with_max_entries(Duration::from_secs(30), 2, clock.clone());
+
+        let client_name = Name::principal("testuser", "EXAMPLE.COM");
+        let service_name = Name::service_krbtgt("EXAMPLE.COM");
+        let user_key = user_key();
+        let primary_key = primary_key();
+
+        let requests = (1..=3)
+            .map(|nonce| {
+                let KerberosRequest::AS(auth_req) = KerberosRequest::build_as(
+                    client_name.clone(),
+                    service_name.clone(),
+                    now + Duration::from_secs(3600),
+                )
+                .nonce(nonce)
+                .build(&clock)
+                .expect("Failed to build request") else {
+                    unreachable!();
+                };
+                auth_req
+            })
+            .collect::<Vec<_>>();
+
+        for auth_req in &requests {
+            let reply = KerberosReply::authentication_builder(
+                client_name.clone(),
+                service_name.clone(),
+                now,
+                auth_req.nonce,
+            )
+            .build(&user_key, &primary_key)
+            .expect("Failed to build reply");
+            cache.insert_as_rep(auth_req, &reply);
+        }
+
+        // The oldest reply made room for the newest, though it is within the ttl.
+        assert!(cache.get_as_rep(&requests[0]).is_none());
+        assert!(cache.get_as_rep(&requests[1]).is_some());
+        assert!(cache.get_as_rep(&requests[2]).is_some());
+    }
+}
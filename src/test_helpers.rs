@@ -0,0 +1,80 @@
+//! Keys and tickets shared by the tests of several modules.
+
+use crate::asn1::constants::encryption_types::EncryptionType;
+use crate::asn1::enc_kdc_rep_part::EncKdcRepPart;
+use crate::asn1::encryption_key::EncryptionKey;
+use crate::asn1::kerberos_time::KerberosTime;
+use crate::asn1::ticket_flags::TicketFlags;
+use crate::asn1::OctetString;
+use crate::clock::Clock;
+use crate::proto::{
+    DerivedKey, EncryptedData, KdcPrimaryKey, KdcReplyPart, Name, SessionKey, Ticket,
+};
+use der::flagset::FlagSet;
+use std::time::{Duration, SystemTime};
+
+/// A clock that is stopped at the time it holds.
+#[derive(Debug)]
+pub(crate) struct FixedClock(pub(crate) SystemTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+}
+
+/// The key of `testuser@EXAMPLE.COM` with the password "password" and the default salt.
+pub(crate) fn user_key() -> DerivedKey {
+    DerivedKey::new_aes256_cts_hmac_sha1_96("password", "EXAMPLE.COMtestuser")
+        .expect("Failed to derive user key")
+}
+
+pub(crate) fn primary_key() -> KdcPrimaryKey {
+    KdcPrimaryKey::try_from([0x55; 32].as_slice()).expect("Failed to load primary key")
+}
+
+pub(crate) fn session_key() -> SessionKey {
+    SessionKey::Aes256CtsHmacSha196 { k: [0xaa; 32] }
+}
+
+/// A ticket for `service` with an enc-part that no key decrypts, for requests that only
+/// need to carry a ticket.
+pub(crate) fn ticket(service: Name) -> Ticket {
+    Ticket {
+        tkt_vno: 5,
+        service,
+        enc_part: EncryptedData::Aes256CtsHmacSha196 {
+            kvno: Some(1),
+            data: vec![0x42; 64],
+        },
+        der: None,
+    }
+}
+
+/// A reply part for a krbtgt ticket issued at 1000 seconds after the epoch, which is
+/// valid from `start_time` until `end_time`, in seconds after the epoch.
+pub(crate) fn reply_part_valid_between(start_time: Option<u64>, end_time: u64) -> KdcReplyPart {
+    let kerberos_time = |secs| KerberosTime::from_unix_duration(Duration::from_secs(secs));
+    let (server_name, server_realm) = (&Name::service_krbtgt("EXAMPLE.COM"))
+        .try_into()
+        .expect("Invalid name");
+
+    KdcReplyPart::try_from(EncKdcRepPart {
+        key: EncryptionKey {
+            key_type: EncryptionType::AES256_CTS_HMAC_SHA1_96 as i32,
+            key_value: OctetString::new([0x55; 32]).expect("Invalid OCTET STRING"),
+        },
+        last_req: Vec::with_capacity(0),
+        nonce: 1,
+        key_expiration: None,
+        flags: FlagSet::<TicketFlags>::new(0).expect("Invalid ticket flags"),
+        auth_time: kerberos_time(1_000).expect("Invalid KerberosTime"),
+        start_time: start_time.map(|secs| kerberos_time(secs).expect("Invalid KerberosTime")),
+        end_time: kerberos_time(end_time).expect("Invalid KerberosTime"),
+        renew_till: None,
+        server_realm,
+        server_name,
+        client_addresses: None,
+    })
+    .expect("Failed to build reply part")
+}